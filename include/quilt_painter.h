@@ -0,0 +1,120 @@
+#ifndef QUILT_PAINTER_H
+#define QUILT_PAINTER_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Quilt layout: tile grid, quilt size in pixels and display pixel aspect.
+ */
+typedef struct QuiltLayout {
+  uint32_t columns;
+  uint32_t rows;
+  uint32_t width;
+  uint32_t height;
+  float pixel_aspect;
+} QuiltLayout;
+
+/**
+ * Render settings for `quilt_make`, matching the painter options of the same names.
+ */
+typedef struct QuiltRenderOptions {
+  float fov;
+  float zoom;
+  float scale;
+  uint8_t bg[3];
+  /**
+   * Limit in view pixels on how far depth moves a point; 0 for none.
+   */
+  float max_parallax;
+  /**
+   * Split samples between neighbouring pixels instead of using the nearest.
+   */
+  bool subpixel;
+  /**
+   * Pixels each texture sample covers, 1-3.
+   */
+  uint32_t splat_radius;
+} QuiltRenderOptions;
+
+/**
+ * An 8-bit RGB image owned by the library. Release it with `quilt_free`.
+ */
+typedef struct QuiltImage {
+  uint32_t width;
+  uint32_t height;
+  uint8_t *data;
+  uintptr_t len;
+} QuiltImage;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * The message for the last failed call on this thread, or null. Valid until the next failing
+ * call on the same thread.
+ */
+const char *quilt_last_error(void);
+
+/**
+ * Looks up the layout of a named device, e.g. "portrait" or "Looking Glass Go".
+ *
+ * # Safety
+ * `device` must be a NUL-terminated string and `out` writable.
+ */
+bool quilt_settings_for_device(const char *device, struct QuiltLayout *out);
+
+/**
+ * The painter defaults.
+ */
+struct QuiltRenderOptions quilt_render_options_default(void);
+
+/**
+ * Renders a quilt from an RGB texture and a depth map, also RGB with depth in the red channel.
+ * Returns null on failure.
+ *
+ * # Safety
+ * `layout` and `options` must be valid, and each image pointer must point to
+ * `width * height * 3` readable bytes.
+ */
+struct QuiltImage *quilt_make(const struct QuiltLayout *layout,
+                              const uint8_t *texture,
+                              uint32_t texture_width,
+                              uint32_t texture_height,
+                              const uint8_t *depth,
+                              uint32_t depth_width,
+                              uint32_t depth_height,
+                              const struct QuiltRenderOptions *options);
+
+/**
+ * Generates a depth map for an image file with ComfyUI, using the depth cache in `cache_dir`
+ * if it isn't null. On success, `texture_out` and `depth_out` receive images to release with
+ * `quilt_free`.
+ *
+ * # Safety
+ * The strings must be NUL-terminated (`cache_dir` may be null) and the out pointers writable.
+ */
+bool depth_generate(const char *input_path,
+                    const char *comfy_url,
+                    const char *cache_dir,
+                    struct QuiltImage **texture_out,
+                    struct QuiltImage **depth_out);
+
+/**
+ * Releases an image returned by this library. Null is ignored.
+ *
+ * # Safety
+ * `image` must be null or an image from this library that hasn't been freed.
+ */
+void quilt_free(struct QuiltImage *image);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* QUILT_PAINTER_H */
@@ -0,0 +1,222 @@
+//! Node.js bindings, so web backends can render quilts in-process instead of spawning the
+//! binaries. Both functions run on the libuv thread pool and return promises.
+
+use image::{ImageBuffer, ImageOutputFormat, Rgb};
+use napi::bindgen_prelude::{AsyncTask, Buffer};
+use napi::{Env, Error, Result, Task};
+use napi_derive::napi;
+use quilt_painter::background::Background;
+use quilt_painter::debug::NullDebugFlags;
+use quilt_painter::depth_gen::{generate_depth, DepthConfig, DEFAULT_COMFY_URL};
+use quilt_painter::encode::png_encoder;
+use quilt_painter::image_types::RgbdImage;
+use quilt_painter::quilt::{
+    get_quilt_settings, render_quilt, QuiltSettings, RenderOptions, Sampling,
+};
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+fn to_napi(e: impl std::fmt::Display) -> Error {
+    Error::from_reason(e.to_string())
+}
+
+#[napi(object)]
+#[derive(Clone, Default)]
+pub struct QuiltOptions {
+    /// Device name, e.g. "portrait" or "Looking Glass Go"; or give columns, rows, width and height.
+    pub device: Option<String>,
+    pub columns: Option<u32>,
+    pub rows: Option<u32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Field of view in degrees [default: 60]
+    pub fov: Option<f64>,
+    /// Zoom towards the center of the image [default: 1.0]
+    pub zoom: Option<f64>,
+    /// Height scale [default: 1.0]
+    pub scale: Option<f64>,
+    /// black, sky, debug, an rgb triplet, gradient:TOP,BOTTOM or radial:CENTER,EDGE
+    /// [default: black]
+    pub bg: Option<String>,
+    pub max_parallax: Option<f64>,
+    /// "nearest" or "subpixel" [default: nearest]
+    pub sampling: Option<String>,
+    pub splat_radius: Option<u32>,
+    /// Output encoding, "png" or "jpeg" [default: png]
+    pub format: Option<String>,
+    /// JPEG quality, 1-100 [default: 90]
+    pub quality: Option<u8>,
+}
+
+impl QuiltOptions {
+    fn settings(&self) -> Result<QuiltSettings> {
+        if let Some(device) = &self.device {
+            return get_quilt_settings(device)
+                .copied()
+                .ok_or_else(|| to_napi(format!("Unknown device {device}")));
+        }
+        match (self.columns, self.rows, self.width, self.height) {
+            (Some(columns), Some(rows), Some(width), Some(height)) if columns > 0 && rows > 0 => {
+                Ok(QuiltSettings {
+                    columns,
+                    rows,
+                    resolution: (width, height),
+                    pixel_aspect: 1.0,
+                })
+            }
+            _ => Err(to_napi("give a device, or columns, rows, width and height")),
+        }
+    }
+
+    fn sampling(&self) -> Result<Sampling> {
+        match self.sampling.as_deref() {
+            None | Some("nearest") => Ok(Sampling::Nearest),
+            Some("subpixel") => Ok(Sampling::Subpixel),
+            Some(other) => Err(to_napi(format!("Unknown sampling {other}"))),
+        }
+    }
+}
+
+fn encode(
+    image: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    format: Option<&str>,
+    quality: Option<u8>,
+) -> Result<Vec<u8>> {
+    match format {
+        None | Some("png") => {
+            let mut encoder =
+                png_encoder(Vec::new(), image.width(), image.height(), false).map_err(to_napi)?;
+            encoder.write_image_rows(image.as_raw()).map_err(to_napi)?;
+            encoder.finish().map_err(to_napi)
+        }
+        Some("jpeg" | "jpg") => {
+            let mut out = Cursor::new(Vec::new());
+            image
+                .write_to(&mut out, ImageOutputFormat::Jpeg(quality.unwrap_or(90)))
+                .map_err(to_napi)?;
+            Ok(out.into_inner())
+        }
+        Some(other) => Err(to_napi(format!("Unknown format {other}"))),
+    }
+}
+
+pub struct MakeQuilt {
+    rgbd: Buffer,
+    options: QuiltOptions,
+}
+
+impl Task for MakeQuilt {
+    type Output = Vec<u8>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> Result<Vec<u8>> {
+        let options = &self.options;
+        let settings = options.settings()?;
+        let background: Background = options
+            .bg
+            .as_deref()
+            .unwrap_or("black")
+            .parse()
+            .map_err(to_napi)?;
+        let rgbd = image::load_from_memory(&self.rgbd).map_err(to_napi)?;
+        let (texture, depth) = RgbdImage(rgbd.to_rgb8()).split();
+        let render_options = RenderOptions {
+            fov_deg: options.fov.unwrap_or(60.0) as f32,
+            zoom: options.zoom.unwrap_or(1.0) as f32,
+            scale: options.scale.unwrap_or(1.0) as f32,
+            background,
+            max_parallax: options.max_parallax.map(|p| p as f32),
+            sampling: options.sampling()?,
+            splat_radius: options.splat_radius.unwrap_or(1).clamp(1, 3),
+            ..Default::default()
+        };
+        let quilt = render_quilt(
+            &settings,
+            &texture,
+            &depth,
+            &render_options,
+            Default::default(),
+            None,
+            &NullDebugFlags,
+        );
+        encode(&quilt, options.format.as_deref(), options.quality)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Vec<u8>) -> Result<Buffer> {
+        Ok(output.into())
+    }
+}
+
+/// Renders a quilt from an encoded RGBD image: the photo on the left half, its depth map on the
+/// right. Resolves to the encoded quilt.
+#[napi(js_name = "makeQuilt", ts_return_type = "Promise<Buffer>")]
+pub fn make_quilt_js(rgbd: Buffer, options: QuiltOptions) -> AsyncTask<MakeQuilt> {
+    AsyncTask::new(MakeQuilt { rgbd, options })
+}
+
+#[napi(object)]
+#[derive(Clone, Default)]
+pub struct DepthOptions {
+    /// ComfyUI server URL [default: http://127.0.0.1:8188]
+    pub comfy_url: Option<String>,
+    /// Depth cache directory; unset to always ask ComfyUI.
+    pub cache_dir: Option<String>,
+    /// Depth model checkpoint for the workflow.
+    pub depth_model: Option<String>,
+}
+
+static NEXT_UPLOAD: AtomicU64 = AtomicU64::new(0);
+
+pub struct GenerateDepth {
+    image: Buffer,
+    options: DepthOptions,
+}
+
+impl Task for GenerateDepth {
+    type Output = Vec<u8>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> Result<Vec<u8>> {
+        // generate_depth uploads from a file; the depth cache is keyed by content, not name.
+        let format = image::guess_format(&self.image).map_err(to_napi)?;
+        let extension = format.extensions_str().first().copied().unwrap_or("png");
+        let name = format!(
+            "quilt_painter_{}_{}.{}",
+            std::process::id(),
+            NEXT_UPLOAD.fetch_add(1, Ordering::Relaxed),
+            extension
+        );
+        let input_path = std::env::temp_dir().join(name);
+        std::fs::write(&input_path, &*self.image).map_err(to_napi)?;
+
+        let config = DepthConfig {
+            comfy_url: self
+                .options
+                .comfy_url
+                .clone()
+                .unwrap_or_else(|| DEFAULT_COMFY_URL.to_string()),
+            cache_dir: self.options.cache_dir.as_ref().map(PathBuf::from),
+            depth_model: self.options.depth_model.clone(),
+            ..DepthConfig::default()
+        };
+        let result = generate_depth(input_path.clone(), &config);
+        let _ = std::fs::remove_file(&input_path);
+        let (texture, depth) = result.map_err(to_napi)?;
+        encode(&RgbdImage::from((texture, depth)).0, None, None)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Vec<u8>) -> Result<Buffer> {
+        Ok(output.into())
+    }
+}
+
+/// Generates a depth map for an encoded image with ComfyUI. Resolves to an RGBD PNG, ready for
+/// `makeQuilt`.
+#[napi(js_name = "generateDepth", ts_return_type = "Promise<Buffer>")]
+pub fn generate_depth_js(image: Buffer, options: Option<DepthOptions>) -> AsyncTask<GenerateDepth> {
+    AsyncTask::new(GenerateDepth {
+        image,
+        options: options.unwrap_or_default(),
+    })
+}
@@ -0,0 +1,85 @@
+//! Animated GIF and APNG inputs. ComfyUI loads every frame of an animated upload as a batch, so
+//! such inputs are split into still frames first and each frame is treated as an image.
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, Frames, RgbaImage};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// The frames of a GIF or APNG, or `None` for any other image, including still PNGs.
+fn decode(path: &Path) -> Result<Option<Frames<'static>>, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(match extension(path).as_str() {
+        "gif" => Some(GifDecoder::new(reader)?.into_frames()),
+        "png" | "apng" => {
+            let decoder = PngDecoder::new(reader)?;
+            decoder.is_apng().then(|| decoder.apng().into_frames())
+        }
+        _ => None,
+    })
+}
+
+/// Whether the image at `path` has more than one frame.
+pub fn is_animated(path: &Path) -> Result<bool, Box<dyn Error>> {
+    Ok(match decode(path)? {
+        Some(frames) => frames.take(2).count() > 1,
+        None => false,
+    })
+}
+
+/// The frames of an animated image, fully composited; only the first with `first_only`.
+pub fn decode_frames(path: &Path, first_only: bool) -> Result<Vec<RgbaImage>, Box<dyn Error>> {
+    let frames = decode(path)?.ok_or_else(|| format!("{} is not animated", path.display()))?;
+    let count = if first_only { 1 } else { usize::MAX };
+    frames
+        .take(count)
+        .map(|frame| Ok(frame?.into_buffer()))
+        .collect()
+}
+
+/// Writes the frames of the animated image at `path` into `dir` as PNGs and returns their
+/// paths in order: `<stem>.png` for just the first frame, or `<stem>_f0000.png` onwards for all
+/// of them. Frames already in `dir` are reused.
+pub fn extract_frames(path: &Path, dir: &Path, all: bool) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let stem = path
+        .file_stem()
+        .ok_or("input path does not contain a file name")?
+        .to_string_lossy();
+    let frame_path = |index: usize| {
+        if all {
+            dir.join(format!("{stem}_f{index:04}.png"))
+        } else {
+            dir.join(format!("{stem}.png"))
+        }
+    };
+    if !all && frame_path(0).exists() {
+        return Ok(vec![frame_path(0)]);
+    }
+
+    std::fs::create_dir_all(dir)?;
+    let frames = decode_frames(path, !all)?;
+    tracing::debug!("Extracting {} frames of {}", frames.len(), path.display());
+    frames
+        .into_iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            let frame_path = frame_path(index);
+            if !frame_path.exists() {
+                // Depth models expect opaque images
+                image::DynamicImage::ImageRgba8(frame)
+                    .to_rgb8()
+                    .save(&frame_path)?;
+            }
+            Ok(frame_path)
+        })
+        .collect()
+}
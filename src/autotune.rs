@@ -0,0 +1,94 @@
+//! Picks `scale` and `zoom` from the depth map, for `--auto-tune`.
+//!
+//! Depth models differ widely in how much of the 0-255 range they use, so a fixed `--scale`
+//! gives some images barely any depth and tears others apart. Auto-tuning instead targets a
+//! parallax: how far, in view pixels, the nearest content moves against the farthest between the
+//! straight-on view and an outermost view.
+
+use crate::camera::view_theta;
+use crate::image_types::DepthImage;
+
+/// Default parallax target as a fraction of the view width.
+pub const DEFAULT_PARALLAX_FRACTION: f32 = 0.03;
+
+/// Percentiles of depth treated as the nearest and farthest content, so a few stray pixels
+/// don't decide the scale.
+const LOW_PERCENTILE: f32 = 0.02;
+const HIGH_PERCENTILE: f32 = 0.98;
+
+/// Bounds for the chosen values.
+const MAX_SCALE: f32 = 8.0;
+const MAX_ZOOM: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoTune {
+    pub scale: f32,
+    pub zoom: f32,
+}
+
+/// Chooses a scale that moves the depth range by `target_parallax` pixels (default
+/// `DEFAULT_PARALLAX_FRACTION` of `view_width`) at the outermost views, and the smallest zoom
+/// that keeps the image edges outside the view at that scale, with `focus_depth` on the display
+/// plane.
+pub fn auto_tune(
+    heightmap: &DepthImage,
+    fov_deg: f32,
+    view_width: u32,
+    target_parallax: Option<f32>,
+    focus_depth: f32,
+) -> AutoTune {
+    let (tex_width, tex_height) = heightmap.dimensions();
+    let angle = view_theta(1, 2, fov_deg).abs();
+    if tex_width == 0 || tex_height == 0 || view_width == 0 || angle < f32::EPSILON {
+        return AutoTune {
+            scale: 1.0,
+            zoom: 1.0,
+        };
+    }
+    let target = target_parallax.unwrap_or(view_width as f32 * DEFAULT_PARALLAX_FRACTION);
+
+    let mut histogram = [0u64; 256];
+    for p in heightmap.0.pixels() {
+        histogram[p[0] as usize] += 1;
+    }
+    let near = percentile(&histogram, HIGH_PERCENTILE);
+    let far = percentile(&histogram, LOW_PERCENTILE);
+    let depth_range = (near - far).max(1.0);
+
+    // Content at the image edges in front of the focus plane moves inwards in the outer views,
+    // and content behind it does at the opposite edge; whichever is farthest from the plane
+    // decides the zoom needed to hide the gap.
+    let mut edge_histogram = [0u64; 256];
+    for y in 0..tex_height {
+        edge_histogram[heightmap.0.get_pixel(0, y)[0] as usize] += 1;
+        edge_histogram[heightmap.0.get_pixel(tex_width - 1, y)[0] as usize] += 1;
+    }
+    let edge_depth = (percentile(&edge_histogram, HIGH_PERCENTILE) - focus_depth)
+        .max(focus_depth - percentile(&edge_histogram, LOW_PERCENTILE));
+
+    // A texture pixel at depth z moves sin(angle) * z * scale * zoom * (view / texture width)
+    // view pixels, so the target fixes scale * zoom.
+    let (sin, cos) = angle.sin_cos();
+    let half_width = tex_width as f32 / 2.0;
+    let scale_zoom = target * tex_width as f32 / (view_width as f32 * sin * depth_range);
+    // The edge stays outside the view while zoom * (cos * w/2 - sin * z_edge * scale) >= w/2
+    let zoom =
+        ((half_width + sin * edge_depth * scale_zoom) / (cos * half_width)).clamp(1.0, MAX_ZOOM);
+    let scale = (scale_zoom / zoom).min(MAX_SCALE);
+
+    AutoTune { scale, zoom }
+}
+
+/// The depth value below which `fraction` of the samples fall.
+fn percentile(histogram: &[u64; 256], fraction: f32) -> f32 {
+    let total: u64 = histogram.iter().sum();
+    let wanted = (total as f32 * fraction).ceil() as u64;
+    let mut seen = 0;
+    for (value, &count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen >= wanted.max(1) {
+            return value as f32;
+        }
+    }
+    255.0
+}
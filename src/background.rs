@@ -0,0 +1,256 @@
+//! What views show where no texture sample lands: a flat color, or a gradient drawn behind the
+//! scene in each view so the space behind the subject looks less like a solid wall.
+
+use image::{ImageBuffer, Pixel, Rgb};
+use serde::{Serialize, Serializer};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Background {
+    Solid(Rgb<u8>),
+    /// Top to bottom of each view.
+    Gradient {
+        top: Rgb<u8>,
+        bottom: Rgb<u8>,
+    },
+    /// From the middle of each view out to its corners.
+    Radial {
+        center: Rgb<u8>,
+        edge: Rgb<u8>,
+    },
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Solid(Rgb([0, 0, 0]))
+    }
+}
+
+impl From<Rgb<u8>> for Background {
+    fn from(color: Rgb<u8>) -> Self {
+        Background::Solid(color)
+    }
+}
+
+impl Background {
+    /// Rows `rows` of a background for a view `width` x `height`.
+    pub fn fill(
+        &self,
+        width: u32,
+        height: u32,
+        rows: std::ops::Range<u32>,
+    ) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        let band_height = rows.len() as u32;
+        match *self {
+            Background::Solid(color) => ImageBuffer::from_pixel(width, band_height, color),
+            Background::Gradient { top, bottom } => {
+                let mut img = ImageBuffer::new(width, band_height);
+                for (band_y, row) in img.rows_mut().enumerate() {
+                    let t = (rows.start as f32 + band_y as f32 + 0.5) / height as f32;
+                    let color = mix(top, bottom, t);
+                    for pixel in row {
+                        *pixel = color;
+                    }
+                }
+                img
+            }
+            Background::Radial { center, edge } => {
+                ImageBuffer::from_fn(width, band_height, |x, band_y| {
+                    let dx = (x as f32 + 0.5) / width as f32 - 0.5;
+                    let dy = (rows.start as f32 + band_y as f32 + 0.5) / height as f32 - 0.5;
+                    // 1 at the corners
+                    let t = (dx * dx + dy * dy).sqrt() / 0.5f32.sqrt();
+                    mix(center, edge, t)
+                })
+            }
+        }
+    }
+}
+
+fn mix(from: Rgb<u8>, to: Rgb<u8>, t: f32) -> Rgb<u8> {
+    let t = t.clamp(0.0, 1.0);
+    from.map2(&to, |a, b| {
+        (a as f32 + (b as f32 - a as f32) * t).round() as u8
+    })
+}
+
+/// `black`, `sky`, `debug`, an rgb triplet `r,g,b`, or hex `#rrggbb`.
+pub fn parse_color(arg: &str) -> Option<Rgb<u8>> {
+    match arg {
+        "black" => Some(Rgb([0, 0, 0])),
+        "sky" => Some(Rgb([128, (0.7 * 255.0) as u8, 255])),
+        "debug" => Some(Rgb([255, 0, 255])),
+        rgb if rgb.contains(',') => {
+            // parse 0,0,0
+            let parts: Vec<u8> = rgb
+                .split(',')
+                .map(|s| s.trim().parse::<u8>().ok())
+                .collect::<Option<_>>()?;
+            match parts[..] {
+                [r, g, b] => Some(Rgb([r, g, b])),
+                _ => None,
+            }
+        }
+        hex => {
+            // parse hex #rrggbb or rrggbb
+            let s = hex.trim_start_matches('#');
+            if s.len() != 6 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            let channel = |i: usize| u8::from_str_radix(&s[i..i + 2], 16).ok();
+            Some(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+        }
+    }
+}
+
+/// Exactly two colors from a comma-separated list, where each is a name or hex code or takes
+/// three entries as an rgb triplet: `sky,black`, `#ffffff,0,0,0`.
+fn parse_color_pair(list: &str) -> Option<(Rgb<u8>, Rgb<u8>)> {
+    let parts: Vec<&str> = list.split(',').map(str::trim).collect();
+    let mut colors = Vec::new();
+    let mut rest = &parts[..];
+    while !rest.is_empty() {
+        if let Some(color) = parse_color(rest[0]) {
+            colors.push(color);
+            rest = &rest[1..];
+        } else if rest.len() >= 3 && rest[..3].iter().all(|part| part.parse::<u8>().is_ok()) {
+            colors.push(parse_color(&rest[..3].join(","))?);
+            rest = &rest[3..];
+        } else {
+            return None;
+        }
+    }
+    match colors[..] {
+        [first, second] => Some((first, second)),
+        _ => None,
+    }
+}
+
+/// A color as for `parse_color`, `gradient:TOP,BOTTOM` or `radial:CENTER,EDGE`.
+impl FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let gradient = |spec: &str| {
+            parse_color_pair(spec).ok_or_else(|| format!("expected two colors, got '{}'", spec))
+        };
+        if let Some(spec) = s.strip_prefix("gradient:") {
+            let (top, bottom) = gradient(spec)?;
+            Ok(Background::Gradient { top, bottom })
+        } else if let Some(spec) = s.strip_prefix("radial:") {
+            let (center, edge) = gradient(spec)?;
+            Ok(Background::Radial { center, edge })
+        } else {
+            parse_color(s)
+                .map(Background::Solid)
+                .ok_or_else(|| format!("unknown background '{}'", s))
+        }
+    }
+}
+
+/// Solid backgrounds serialize as the bare `[r, g, b]` the render cache has always keyed on.
+impl Serialize for Background {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(untagged)]
+        enum Key {
+            Solid([u8; 3]),
+            Gradient { gradient: [[u8; 3]; 2] },
+            Radial { radial: [[u8; 3]; 2] },
+        }
+        let key = match *self {
+            Background::Solid(color) => Key::Solid(color.0),
+            Background::Gradient { top, bottom } => Key::Gradient {
+                gradient: [top.0, bottom.0],
+            },
+            Background::Radial { center, edge } => Key::Radial {
+                radial: [center.0, edge.0],
+            },
+        };
+        key.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+    const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("black"), Some(BLACK));
+        assert_eq!(parse_color("debug"), Some(Rgb([255, 0, 255])));
+        assert_eq!(parse_color("1, 2,3"), Some(Rgb([1, 2, 3])));
+        assert_eq!(parse_color("#ff8000"), Some(Rgb([255, 128, 0])));
+        assert_eq!(parse_color("FF8000"), Some(Rgb([255, 128, 0])));
+    }
+
+    #[test]
+    fn bad_colors() {
+        for bad in [
+            "",
+            "white",
+            "1,2",
+            "1,2,3,4",
+            "foo,bar,baz",
+            "300,0,0",
+            "-1,0,0",
+            "1,,3",
+            "#fff",
+            "#ff80001",
+            "gg0000",
+            "+f+f00",
+            "aééb",
+            "#aééb",
+        ] {
+            assert_eq!(parse_color(bad), None, "{bad:?} parsed");
+        }
+    }
+
+    #[test]
+    fn color_pairs() {
+        assert_eq!(parse_color_pair("sky,black").unwrap().1, BLACK);
+        assert_eq!(parse_color_pair("#ffffff, 0,0,0"), Some((WHITE, BLACK)));
+        assert_eq!(parse_color_pair("255,255,255,black"), Some((WHITE, BLACK)));
+        for bad in [
+            "",
+            "black",
+            "black,black,black",
+            "black,1,2",
+            "black,300,0,0",
+            "black,foo",
+        ] {
+            assert_eq!(parse_color_pair(bad), None, "{bad:?} parsed");
+        }
+    }
+
+    #[test]
+    fn backgrounds() {
+        assert_eq!("0,0,0".parse(), Ok(Background::Solid(BLACK)));
+        assert_eq!(
+            "gradient:#ffffff,black".parse(),
+            Ok(Background::Gradient {
+                top: WHITE,
+                bottom: BLACK
+            })
+        );
+        assert_eq!(
+            "radial:black,255,255,255".parse(),
+            Ok(Background::Radial {
+                center: BLACK,
+                edge: WHITE
+            })
+        );
+        for bad in [
+            "1,2",
+            "gradient:black",
+            "radial:",
+            "linear:black,white",
+            "aééb",
+        ] {
+            assert!(bad.parse::<Background>().is_err(), "{bad:?} parsed");
+        }
+    }
+}
@@ -1,29 +1,140 @@
-use clap::Parser;
-use quilt_painter::captions::CaptionConfig;
-#[cfg(feature = "captions")]
-use quilt_painter::captions::Position;
-use quilt_painter::depth_gen::{generate_depth, DepthConfig};
-use quilt_painter::quilt_gen::{generate_quilt, QuiltConfig};
-use rusqlite::{Connection, Result as SqlResult};
+use clap::{Parser, Subcommand};
+use clap_complete::Shell;
+use quilt_painter::camera::ViewSpacing;
+use quilt_painter::captions::{
+    parse_rgba, CaptionConfig, CaptionSpec, CaptionTarget, Length, Position, TextAlign,
+};
+use quilt_painter::cli;
+use quilt_painter::depth_gen::{generate_depth, DepthArgs, DepthConfig, DepthEdit, DepthOutputs};
+use quilt_painter::device_sync;
+use quilt_painter::dither::Dither;
+use quilt_painter::inpaint::{inpaint_back_layer, InpaintConfig, DEFAULT_INPAINT_PROMPT};
+use quilt_painter::interrupt;
+use quilt_painter::logging::{self, LogFormat};
+use quilt_painter::metadata::MetadataField;
+use quilt_painter::metrics::{self, METRICS};
+use quilt_painter::panorama::Projection;
+use quilt_painter::postfx::{self, Grain, PostFx, Sharpen};
+use quilt_painter::preview::Preview;
+use quilt_painter::quality::{parse_resolution, QualityGate};
+use quilt_painter::quilt::{
+    find_device, render_defaults, Easing, HoleFill, RenderDefaults, Sampling,
+};
+use quilt_painter::quilt_gen::{
+    fit_within, generate_quilt_deferred, FocusDepth, QuiltConfig, ZoomCenter,
+};
+use quilt_painter::save_queue::{SaveQueue, SaveResult};
+use quilt_painter::script::{ImageFacts, ParameterScript, ScriptParameters};
+use quilt_painter::temporal::TemporalDepthFilter;
+use quilt_painter::undistort::LensDistortion;
+use quilt_painter::watermark::WatermarkConfig;
+use quilt_painter::{animation, mpo};
+use rusqlite::{Connection, OptionalExtension, Result as SqlResult};
 use std::error::Error;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use walkdir::WalkDir;
 
+/// Finished quilts that may wait in memory while another is being written.
+const SAVE_QUEUE_CAPACITY: usize = 1;
+
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    #[arg(index = 1)]
-    input_dir: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    #[arg(index = 2)]
-    output_dir: PathBuf,
+    #[arg(index = 1, required = true)]
+    input_dir: Option<PathBuf>,
+
+    #[arg(index = 2, required = true)]
+    output_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    depth: DepthArgs,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Get depth maps from a depthd daemon, e.g. http://127.0.0.1:8190, instead of running the workflow on ComfyUI directly"
+    )]
+    depthd: Option<String>,
+
+    #[arg(
+        long,
+        help = "Also save the combined RGBD image as <stem>_rgbd.png in this directory"
+    )]
+    save_rgbd: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Also save the depth map as <stem>_depth.png in this directory"
+    )]
+    save_depth: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Write each depth map to a temporary PNG and wait for Enter before rendering, so it can be fixed by hand"
+    )]
+    pause_for_edit: bool,
+
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        help = "Run this command on each depth map before rendering and read it back when it exits; {} is replaced by the PNG's path, which is otherwise appended"
+    )]
+    depth_edit_cmd: Option<String>,
+
+    #[arg(
+        long,
+        help = "Inpaint what is behind the nearest subject with a second ComfyUI workflow and render it as a back layer, so wide viewcones reveal background instead of stretched edges"
+    )]
+    inpaint_behind: bool,
+
+    #[arg(long, default_value = DEFAULT_INPAINT_PROMPT, help = "Prompt for --inpaint-behind")]
+    inpaint_prompt: String,
+
+    #[arg(
+        long,
+        help = "Inpainting checkpoint for --inpaint-behind, e.g. 512-inpainting-ema.safetensors"
+    )]
+    inpaint_checkpoint: Option<String>,
+
+    #[arg(
+        long,
+        help = "ComfyUI API-format workflow for --inpaint-behind; its LoadImage gets the image with the subject transparent"
+    )]
+    inpaint_workflow: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DEPTH",
+        help = "Depth 0-255 above which pixels are the subject for --inpaint-behind [default: picked from the depth histogram]"
+    )]
+    subject_depth: Option<u8>,
 
-    #[arg(long, default_value = "http://127.0.0.1:8188")]
-    comfy_url: String,
+    #[arg(
+        long,
+        help = "Use <stem><suffix>.png next to the input as its depth map instead of ComfyUI [default: tries _depth and .depth]"
+    )]
+    depth_suffix: Option<String>,
 
-    #[arg(short, long, conflicts_with_all=["columns", "rows", "width", "height"])]
-    device: Option<String>,
+    #[arg(
+        short,
+        long = "device",
+        value_parser = cli::parse_device,
+        conflicts_with_all=["columns", "rows", "width", "height"],
+        help = "Target device. Repeat to render each image for several devices from one depth map"
+    )]
+    devices: Vec<String>,
 
     #[arg(long, help = "The number of columns of tiles in the output quilt.")]
     columns: Option<u32>,
@@ -47,79 +158,950 @@ struct Args {
     #[arg(
         long,
         default_value = "black",
-        help = "black, sky, debug or an rgb triplet"
+        value_parser = cli::parse_background,
+        help = "black, sky, debug, an rgb triplet, or gradient:TOP,BOTTOM or radial:CENTER,EDGE with two of those drawn behind the scene in each view"
     )]
     bg: String,
 
-    #[arg(long, default_value = "60", help = "field of view in degrees")]
+    #[arg(
+        long,
+        value_name = "COLOR",
+        value_parser = cli::parse_color,
+        help = "Color of the letterbox bars where the texture doesn't cover the view, if not --bg: black, sky, debug or an rgb triplet"
+    )]
+    matte: Option<String>,
+
+    #[arg(long, default_value = "60", value_parser = cli::parse_fov, help = "field of view in degrees (0-120)")]
     fov: f32,
 
-    #[arg(long, default_value = "1.05", help = "zoom towards center of image")]
-    zoom: f32,
+    #[arg(
+        long,
+        default_value = "flat",
+        value_enum,
+        help = "How the input maps onto the scene: flat, or equirectangular for a 360x180 panorama seen from inside"
+    )]
+    projection: Projection,
+
+    #[arg(
+        long,
+        default_value = "0",
+        allow_hyphen_values = true,
+        help = "Equirectangular only: heading of the view in degrees, positive to the right"
+    )]
+    pano_yaw: f32,
+
+    #[arg(
+        long,
+        default_value = "0",
+        allow_hyphen_values = true,
+        help = "Equirectangular only: elevation of the view in degrees above the horizon"
+    )]
+    pano_pitch: f32,
+
+    #[arg(
+        long,
+        default_value = "90",
+        help = "Equirectangular only: horizontal field of view of each view in degrees"
+    )]
+    pano_fov: f32,
+
+    #[arg(
+        long,
+        default_value = "linear",
+        value_name = "SPACING",
+        help = "Spread of the views across the field of view: linear, cosine (closer together towards the edges), or curve:P0,P1,... with positions rising from 0 to 1"
+    )]
+    view_spacing: ViewSpacing,
+
+    #[arg(
+        long,
+        help = "Mirror the input (texture and depth together) left to right"
+    )]
+    flip_h: bool,
+
+    #[arg(
+        long,
+        help = "Mirror the input (texture and depth together) top to bottom"
+    )]
+    flip_v: bool,
+
+    #[arg(
+        long,
+        value_name = "PROFILE|K1[,K2[,K3[,P1,P2]]]",
+        allow_hyphen_values = true,
+        help = "Take lens distortion out of the texture and depth before rendering: a profile (wide, action, fisheye) or Brown-Conrady coefficients over half the shorter side"
+    )]
+    undistort: Option<LensDistortion>,
+
+    #[arg(
+        long,
+        help = "Reverse the order of the views in the quilt, for displays or sources with mirrored geometry"
+    )]
+    mirror_views: bool,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Pixels of border around each view; views are rendered that much smaller to fit their tiles"
+    )]
+    tile_padding: u32,
+
+    #[arg(
+        long,
+        default_value = "black",
+        value_parser = cli::parse_color,
+        help = "Color of the border around each view: black, sky, debug or an rgb triplet"
+    )]
+    border_color: String,
+
+    #[arg(
+        long,
+        value_parser = cli::parse_zoom,
+        help = "zoom towards center of image [default: the device's, or 1.05]"
+    )]
+    zoom: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "X,Y|auto",
+        help = "Zoom towards this point, as fractions of the image width and height, or auto to find the subject [default: 0.5,0.5]"
+    )]
+    zoom_center: Option<ZoomCenter>,
+
+    #[arg(long, help = "enhance height [default: the device's, or 1.0]")]
+    scale: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "DEPTH|auto",
+        help = "Depth (0-255) that stays on the display plane, or auto for the subject's median depth [default: 0, the farthest]"
+    )]
+    focus_depth: Option<FocusDepth>,
+
+    #[arg(
+        long,
+        help = "resize multiplier relative to tile size [default: the device's, or 2.5]"
+    )]
+    resize: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "ALPHA",
+        help = "Treat the input as a frame sequence: visit files in name order and blend each depth map with the previous frames' (ALPHA is the new frame's weight, 0-1)"
+    )]
+    temporal_smoothing: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Rhai script run per image that may override fov, zoom, scale and caption (needs the scripting feature)"
+    )]
+    script: Option<PathBuf>,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "1.0",
+        help = "Also save a tangent-space normal map of the depth, with optional slope strength"
+    )]
+    export_normals: Option<f32>,
+
+    #[arg(
+        long,
+        help = "Also save the four views as a Leia (Lume Pad) 2x2 lightfield image, <name>_leia.jpg, with a JSON descriptor"
+    )]
+    export_leia: bool,
+
+    #[arg(
+        long,
+        value_name = "WHERE",
+        help = "Also make a small flat preview from the center view: file writes <name>-preview.jpg, exif embeds it as the thumbnail of a JPEG quilt"
+    )]
+    preview: Option<Preview>,
+
+    #[arg(
+        long,
+        value_name = "PNG[:POSITION[:OPACITY[:SCALE]]]",
+        help = "Composite a logo onto each view; SCALE is its width as a fraction of the view [default: bottom-right:1.0:0.15]"
+    )]
+    watermark: Option<WatermarkConfig>,
+
+    #[arg(
+        long,
+        help = "Draw the watermark once on the finished quilt instead of on every view"
+    )]
+    watermark_once: bool,
+
+    #[arg(
+        long,
+        help = "Also write a JSON layout descriptor (tile size, order, view count, viewcone) next to the quilt"
+    )]
+    descriptor: bool,
+
+    #[arg(
+        long,
+        help = "Pick --scale and --zoom from the depth map's range instead of using their values"
+    )]
+    auto_tune: bool,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Auto-tune parallax target: how far near content moves against far content in the outermost views [default: 3% of the view width]"
+    )]
+    auto_tune_parallax: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Flatten depth beyond what moves a pixel this far in the outermost views, to avoid stretching"
+    )]
+    max_parallax: Option<f32>,
+
+    #[arg(
+        long,
+        default_value = "nearest",
+        value_enum,
+        help = "Sample placement: nearest (fast) or subpixel (smoother edges, less crawling between views)"
+    )]
+    sampling: Sampling,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..=3),
+        help = "Pixels each texture sample covers (1-3); larger values fill pinholes when zoomed out or strongly displaced"
+    )]
+    splat_radius: u32,
+
+    #[arg(
+        long,
+        default_value = "none",
+        value_enum,
+        help = "Dither colors blended across gaps and between samples to hide banding: none, ordered or blue-noise"
+    )]
+    dither: Dither,
+
+    #[arg(
+        long,
+        default_value = "luminance:0.3333",
+        value_name = "CURVE",
+        help = "Color blend across gaps: linear, smoothstep, hold (no blend), or luminance[:SHARPNESS] to linger on the darker side"
+    )]
+    easing: Easing,
+
+    #[arg(
+        long,
+        default_value = "blend",
+        value_enum,
+        help = "How gaps opened by depth are filled: blend across them, or repeat the background side"
+    )]
+    hole_fill: HoleFill,
+
+    #[arg(
+        long,
+        help = "Histogram-match every view to the middle one after rendering, so color shifts from hole filling don't flicker between views"
+    )]
+    stabilize_views: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        value_parser = postfx::parse_look,
+        help = "Named bundle of color, sharpening, grain and vignette settings: natural, punchy, bw, vintage, or one from the user's looks.json; the effect options below override its parts"
+    )]
+    look: Option<PostFx>,
+
+    #[arg(
+        long,
+        value_name = "AMOUNT[:RADIUS]",
+        help = "Unsharp-mask every view after rendering, e.g. 0.5; a RADIUS of 20 or more boosts local contrast (clarity) instead [default radius: 1]"
+    )]
+    sharpen: Option<Sharpen>,
+
+    #[arg(
+        long,
+        value_name = "LEVELS",
+        help = "Add film grain of this standard deviation in 8-bit levels, e.g. 3, to hide banding in smooth gradients"
+    )]
+    grain: Option<f32>,
+
+    #[arg(
+        long,
+        requires = "grain",
+        help = "Use a different grain pattern in each view, so it fills the scene's depth instead of sitting on the display surface"
+    )]
+    grain_per_view: bool,
+
+    #[arg(
+        long,
+        value_name = "STRENGTH",
+        help = "Darken the corners of each view, from 0 (none) to 1 (black corners)"
+    )]
+    vignette: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Fade the outer PX pixels of each view to the background, hiding stretched edges in the outermost views"
+    )]
+    edge_fade: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "W/H",
+        help = "Display pixel width over height, for displays with non-square pixels [default: the device's, 1.0]"
+    )]
+    pixel_aspect: Option<f32>,
 
-    #[arg(long, default_value = "1.0", help = "enhance height")]
-    scale: f32,
+    #[arg(
+        long,
+        value_name = "ROWS",
+        help = "Render and write the quilt in bands of this many view rows to bound memory on very large outputs (PNG and JPEG only)"
+    )]
+    band_height: Option<u32>,
 
     #[arg(
         long,
-        default_value = "2.5",
-        help = "resize multiplier relative to tile size"
+        value_name = "TEXT[@POSITION[:SIZE[:COLOR[:DEPTH]]]]",
+        help = "Caption text to render on the image. Repeatable; each can set its own position, size, color and depth (pixels of shift between the outermost views, negative to float in front), e.g. --caption 'Lisbon@top-center' --caption '2024-06-01@bottom-right:3%:#ffd700'"
     )]
-    resize: f32,
+    caption: Vec<CaptionSpec>,
 
-    #[cfg(feature = "captions")]
-    #[arg(long, help = "Optional caption text to render on the image")]
-    caption: Option<String>,
+    #[arg(
+        long,
+        default_value = "16",
+        help = "Caption font size in pixels, or a percentage of the view height such as 4%"
+    )]
+    caption_size: Length,
 
-    #[cfg(feature = "captions")]
-    #[arg(long, default_value = "16", help = "Font size for caption in pixels")]
-    caption_size: u32,
+    #[arg(
+        long,
+        default_value = "10",
+        help = "Space between the caption and the view edges, in pixels or a percentage of the view height"
+    )]
+    caption_margin: Length,
 
-    #[cfg(feature = "captions")]
     #[arg(
         long,
         default_value = "bottom-center",
         value_enum,
-        help = "Caption position (top-left, top-center, top-right, bottom-left, bottom-center)"
+        help = "Caption position (top-left, top-center, top-right, bottom-left, bottom-center, bottom-right, center)"
     )]
     caption_position: Position,
 
-    #[cfg(not(feature = "captions"))]
-    caption: (),
-    #[cfg(not(feature = "captions"))]
-    caption_size: (),
-    #[cfg(not(feature = "captions"))]
-    caption_position: (),
+    #[arg(
+        long,
+        help = "Wrap captions to this width in pixels [default: view width minus margins]"
+    )]
+    caption_max_width: Option<u32>,
+
+    #[arg(
+        long,
+        default_value = "1.0",
+        help = "Caption line spacing as a multiple of the font's line height"
+    )]
+    caption_line_spacing: f32,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Alignment of wrapped caption lines (left, center, right) [default: follows position]"
+    )]
+    caption_align: Option<TextAlign>,
+
+    #[arg(
+        long,
+        value_name = "COLOR",
+        value_parser = parse_rgba,
+        help = "Box behind the caption as R,G,B[,A] or #RRGGBB[AA], e.g. 0,0,0,128"
+    )]
+    caption_bg: Option<[u8; 4]>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Black outline width around caption glyphs in pixels"
+    )]
+    caption_outline: u32,
+
+    #[arg(long, help = "Draw a drop shadow under the caption")]
+    caption_shadow: bool,
+
+    #[arg(
+        long = "caption-font",
+        value_name = "PATH",
+        help = "Fallback font for caption characters the bundled font lacks (e.g. Arabic, emoji). Repeatable."
+    )]
+    caption_fonts: Vec<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "view",
+        value_enum,
+        help = "Draw the caption on every view (view) or once on the stitched quilt (quilt)"
+    )]
+    caption_target: CaptionTarget,
+
+    #[arg(
+        long = "caption-from",
+        value_name = "KIND:NAME",
+        help = "Take the caption from image metadata, e.g. exif:DateTimeOriginal, iptc:Caption-Abstract or xmp:dc:title; --caption is the fallback. Repeatable, first found wins."
+    )]
+    caption_from: Vec<MetadataField>,
+
+    #[arg(
+        long,
+        default_value = "text",
+        value_enum,
+        help = "Log output: text, or json for one object per line with stage timings (level set by RUST_LOG)"
+    )]
+    log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        help = "Serve Prometheus metrics at http://ADDR/metrics while the batch runs, e.g. 127.0.0.1:9898"
+    )]
+    metrics_listen: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "WxH",
+        value_parser = parse_resolution,
+        help = "Skip images smaller than this in either orientation, such as thumbnails"
+    )]
+    min_resolution: Option<(u32, u32)>,
+
+    #[arg(
+        long,
+        value_name = "THRESHOLD",
+        help = "Skip images whose sharpness (variance of the Laplacian at 1024 pixels) is below this; around 100 separates sharp photos from blurry ones"
+    )]
+    skip_blurry: Option<f64>,
+
+    #[arg(
+        long,
+        help = "Turn every frame of animated GIFs and PNGs into a quilt, named <name>_f0000 onwards, instead of only the first"
+    )]
+    animate: bool,
+
+    #[arg(
+        long,
+        help = "Process only the images that failed on an earlier run, printing how each failed before"
+    )]
+    retry_failed: bool,
+
+    #[arg(
+        long,
+        default_value = "basename",
+        value_enum,
+        help = "How the m3u names quilts: basename (as the Looking Glass Go expects, with the playlist beside the copied output directory), relative to the playlist, or absolute"
+    )]
+    playlist_paths: PlaylistPaths,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Keep at most this many quilts in each playlist, for devices with little storage; see --playlist-rotation"
+    )]
+    playlist_max: Option<usize>,
+
+    #[arg(
+        long,
+        default_value = "drop-oldest",
+        value_enum,
+        requires = "playlist_max",
+        help = "How new quilts make room in a full playlist: drop-oldest, or random-replace to take the place of random earlier ones"
+    )]
+    playlist_rotation: Rotation,
+
+    #[arg(
+        long,
+        requires = "playlist_max",
+        help = "Delete the quilt files of quilts rotated out of the playlist, keeping the output directory bounded too"
+    )]
+    delete_rotated: bool,
+
+    #[arg(
+        long,
+        value_name = "SHELL",
+        exclusive = true,
+        help = "Print a completion script for bash, elvish, fish, powershell or zsh and exit"
+    )]
+    completions: Option<Shell>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Copy new and changed quilts and the playlist to a Looking Glass Go's storage
+    Sync(SyncArgs),
+    /// Summarize earlier runs: files by status, recent failures, cache hits and disk usage
+    Status(StatusArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct SyncArgs {
+    #[arg(index = 1, help = "Output directory of earlier runs")]
+    output_dir: PathBuf,
+
+    #[arg(
+        index = 2,
+        required_unless_present = "mtp",
+        help = "The device's storage, mounted over USB mass storage"
+    )]
+    target: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with = "target",
+        help = "Copy to the storage of the MTP device gvfs has mounted"
+    )]
+    mtp: bool,
+
+    #[arg(
+        long,
+        help = "Delete files from the device's copy of the output directory that are gone from it"
+    )]
+    delete: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatusArgs {
+    #[arg(
+        index = 1,
+        help = "Input directory of earlier runs, holding their index.db"
+    )]
+    input_dir: PathBuf,
+
+    #[arg(
+        long,
+        default_value = "10",
+        help = "How many of the latest failures to list"
+    )]
+    failures: usize,
+}
+
+/// `bytes` in the largest unit that keeps it at 1 or more, e.g. `3.2 GiB`.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
 }
 
-fn init_db(conn: &Connection) -> SqlResult<()> {
+/// `hits` as a share of all lookups, or `-` before any.
+fn hit_rate(hits: i64, misses: i64) -> String {
+    if hits + misses == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.0}%", 100.0 * hits as f64 / (hits + misses) as f64)
+    }
+}
+
+fn status(args: &StatusArgs) -> Result<(), Box<dyn Error>> {
+    let db_path = args.input_dir.join("index.db");
+    if !db_path.is_file() {
+        return Err(format!(
+            "no index.db in {}; has batch_depth run on it?",
+            args.input_dir.display()
+        )
+        .into());
+    }
+    let conn = Connection::open_with_flags(db_path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    // Databases from before error messages and run counts were kept are read as they are
+    let columns = table_columns(&conn, "processed_files")?;
+    let has_column = |column: &str| columns.iter().any(|c| c == column);
+    let has_runs = !table_columns(&conn, "runs")?.is_empty();
+
+    println!("Files by status:");
+    let mut stmt = conn.prepare(
+        "SELECT device, status, COUNT(*) FROM processed_files GROUP BY device, status ORDER BY device, status",
+    )?;
+    let counts = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })?
+        .collect::<SqlResult<Vec<_>>>()?;
+    if counts.is_empty() {
+        println!("  none yet");
+    }
+    for (device, status, count) in counts {
+        let device = if device.is_empty() {
+            "custom layout"
+        } else {
+            &device
+        };
+        println!(
+            "  {device}: {count} {}",
+            status.as_deref().unwrap_or("unknown")
+        );
+    }
+
+    println!("Latest failures:");
+    let error = if has_column("error") { "error" } else { "NULL" };
+    let stage = if has_column("stage") { "stage" } else { "NULL" };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT timestamp, path, {error}, {stage} FROM processed_files WHERE status = 'error' ORDER BY timestamp DESC LIMIT ?1"
+    ))?;
+    let failures = stmt
+        .query_map([args.failures as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })?
+        .collect::<SqlResult<Vec<_>>>()?;
+    if failures.is_empty() {
+        println!("  none");
+    }
+    for (timestamp, path, error, stage) in failures {
+        let stage = stage
+            .map(|stage| format!(" at {stage}"))
+            .unwrap_or_default();
+        println!(
+            "  {timestamp} {path}{stage}: {}",
+            error.as_deref().unwrap_or("no message recorded")
+        );
+    }
+
+    if has_runs {
+        let (runs, depth_hits, depth_misses, render_hits, render_misses): (i64, i64, i64, i64, i64) =
+            conn.query_row(
+                "SELECT COUNT(*), COALESCE(SUM(depth_cache_hits), 0), COALESCE(SUM(depth_cache_misses), 0),
+                    COALESCE(SUM(render_cache_hits), 0), COALESCE(SUM(render_cache_misses), 0) FROM runs",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
+            )?;
+        println!("Cache over {runs} runs:");
+        println!(
+            "  depth maps: {depth_hits} hits, {depth_misses} misses ({})",
+            hit_rate(depth_hits, depth_misses)
+        );
+        println!(
+            "  renders: {render_hits} hits, {render_misses} misses ({})",
+            hit_rate(render_hits, render_misses)
+        );
+        let last_run = conn
+            .query_row(
+                "SELECT finished, processed, failed, skipped FROM runs ORDER BY id DESC LIMIT 1",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, i64>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+        if let Some((finished, processed, failed, skipped)) = last_run {
+            println!(
+                "  last run finished {finished}: {processed} processed, {failed} failed, {skipped} skipped"
+            );
+        }
+    }
+
+    // Sizes of the quilts as they are now, as some may have been moved or deleted by hand
+    let mut stmt = conn.prepare(
+        "SELECT quiltfilename FROM processed_files WHERE status = 'success' AND quiltfilename != ''",
+    )?;
+    let quilts = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<SqlResult<Vec<_>>>()?;
+    let (mut quilt_bytes, mut missing) = (0, 0);
+    for quilt in &quilts {
+        match std::fs::metadata(quilt) {
+            Ok(metadata) => quilt_bytes += metadata.len(),
+            Err(_) => missing += 1,
+        }
+    }
+    println!("Disk usage:");
+    print!(
+        "  quilts: {} in {} files",
+        human_size(quilt_bytes),
+        quilts.len() - missing
+    );
+    if missing > 0 {
+        print!(", {missing} missing");
+    }
+    println!();
+    let cache_bytes: u64 = WalkDir::new(args.input_dir.join(".rgbd_cache"))
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum();
+    println!("  cache: {}", human_size(cache_bytes));
+    Ok(())
+}
+
+fn sync(args: &SyncArgs) -> Result<(), Box<dyn Error>> {
+    let target = match &args.target {
+        Some(target) => target.clone(),
+        None => device_sync::find_mtp_storage()?,
+    };
+    let report = device_sync::sync_output(&args.output_dir, &target, args.delete)?;
+    println!(
+        "Synced to {}: {} copied, {} unchanged, {} deleted",
+        target.display(),
+        report.copied,
+        report.unchanged,
+        report.deleted
+    );
+    Ok(())
+}
+
+/// How playlist entries name their quilts.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum PlaylistPaths {
+    /// The quilt's file name alone.
+    Basename,
+    /// The path from the playlist's directory, `<output dir name>/<file name>`.
+    Relative,
+    /// The full path.
+    Absolute,
+}
+
+impl PlaylistPaths {
+    /// The entry for the quilt saved as `quiltfilename` in `output_dir`.
+    fn entry(self, quiltfilename: &str, output_dir: &Path) -> String {
+        let path = Path::new(quiltfilename);
+        let file_name = path.file_name().unwrap_or_default();
+        let entry = match self {
+            PlaylistPaths::Basename => PathBuf::from(file_name),
+            PlaylistPaths::Relative => {
+                PathBuf::from(output_dir.file_name().unwrap_or_default()).join(file_name)
+            }
+            PlaylistPaths::Absolute => {
+                std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+            }
+        };
+        entry.to_string_lossy().into_owned()
+    }
+}
+
+/// Which quilts leave a playlist longer than `--playlist-max`.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Rotation {
+    /// The ones added first.
+    DropOldest,
+    /// Random earlier ones, each new quilt taking the place of one of them.
+    RandomReplace,
+}
+
+/// A bound on playlist length, from `--playlist-max` and the options that go with it.
+#[derive(Debug, Clone, Copy)]
+struct PlaylistLimit {
+    max: usize,
+    rotation: Rotation,
+    /// Also delete the quilt files of rotated out quilts.
+    delete: bool,
+}
+
+const PROCESSED_FILES_SCHEMA: &str = "(
+    path TEXT NOT NULL,
+    device TEXT NOT NULL DEFAULT '',
+    basename TEXT,
+    quiltfilename TEXT,
+    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+    status TEXT,
+    error TEXT,
+    stage TEXT,
+    PRIMARY KEY (path, device)
+)";
+
+/// `device` is the first device of this run, which rows from databases that predate per-device
+/// rows are taken to be for.
+fn init_db(conn: &Connection, device: &str) -> SqlResult<()> {
+    migrate_processed_files(conn, device)?;
+    conn.execute(
+        &format!("CREATE TABLE IF NOT EXISTS processed_files {PROCESSED_FILES_SCHEMA}"),
+        [],
+    )?;
+
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS processed_files (
-            path TEXT PRIMARY KEY,
-            basename TEXT,
-            quiltfilename TEXT,
-            timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
-            status TEXT
+        "CREATE TABLE IF NOT EXISTS playlist (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            path TEXT NOT NULL REFERENCES processed_files(path),
+            device TEXT NOT NULL DEFAULT '',
+            position INTEGER NOT NULL,
+            UNIQUE(position)
         )",
         [],
     )?;
+    if !table_columns(conn, "playlist")?
+        .iter()
+        .any(|c| c == "device")
+    {
+        conn.execute(
+            "ALTER TABLE playlist ADD COLUMN device TEXT NOT NULL DEFAULT ''",
+            [],
+        )?;
+        conn.execute("UPDATE playlist SET device = ?1", [device])?;
+    }
+    let columns = table_columns(conn, "processed_files")?;
+    for column in ["error", "stage"] {
+        if !columns.iter().any(|c| c == column) {
+            conn.execute(
+                &format!("ALTER TABLE processed_files ADD COLUMN {column} TEXT"),
+                [],
+            )?;
+        }
+    }
 
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS playlist (
+        "CREATE TABLE IF NOT EXISTS runs (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
-            path TEXT NOT NULL REFERENCES processed_files(path),
-            position INTEGER NOT NULL,
-            UNIQUE(position)
+            finished DATETIME DEFAULT CURRENT_TIMESTAMP,
+            processed INTEGER NOT NULL,
+            failed INTEGER NOT NULL,
+            skipped INTEGER NOT NULL,
+            depth_cache_hits INTEGER NOT NULL,
+            depth_cache_misses INTEGER NOT NULL,
+            render_cache_hits INTEGER NOT NULL,
+            render_cache_misses INTEGER NOT NULL
         )",
         [],
     )?;
+    migrate_device_aliases(conn)?;
+    Ok(())
+}
+
+/// Moves rows recorded under a device alias, as `--device` was written before it was
+/// normalized, to the device's full name.
+fn migrate_device_aliases(conn: &Connection) -> SqlResult<()> {
+    let recorded: Vec<String> = conn
+        .prepare("SELECT DISTINCT device FROM processed_files")?
+        .query_map([], |row| row.get(0))?
+        .collect::<SqlResult<_>>()?;
+    for device in recorded {
+        let Some(preset) = find_device(&device).filter(|preset| preset.name != device) else {
+            continue;
+        };
+        conn.execute(
+            "UPDATE OR IGNORE processed_files SET device = ?1 WHERE device = ?2",
+            (preset.name, &device),
+        )?;
+        conn.execute(
+            "UPDATE playlist SET device = ?1 WHERE device = ?2",
+            (preset.name, &device),
+        )?;
+    }
+    Ok(())
+}
+
+/// Records this run's counts, which `batch_depth status` adds up across runs.
+fn record_run(conn: &Connection) -> SqlResult<()> {
+    let count = |counter: &metrics::Counter| counter.get() as i64;
+    conn.execute(
+        "INSERT INTO runs (processed, failed, skipped, depth_cache_hits, depth_cache_misses, render_cache_hits, render_cache_misses)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        (
+            count(&METRICS.images_processed),
+            count(&METRICS.images_failed),
+            count(&METRICS.images_skipped),
+            count(&METRICS.depth_cache_hits),
+            count(&METRICS.depth_cache_misses),
+            count(&METRICS.render_cache_hits),
+            count(&METRICS.render_cache_misses),
+        ),
+    )?;
     Ok(())
 }
 
-fn get_playlist(conn: &Connection) -> SqlResult<Vec<(i64, String)>> {
-    let mut stmt = conn.prepare("SELECT position, quiltfilename FROM playlist JOIN processed_files ON playlist.path = processed_files.path ORDER BY position")?;
+fn table_columns(conn: &Connection, table: &str) -> SqlResult<Vec<String>> {
+    conn.prepare("SELECT name FROM pragma_table_info(?1)")?
+        .query_map([table], |row| row.get(0))?
+        .collect()
+}
+
+/// Adds the `device` column to a `processed_files` table keyed by path alone. SQLite can't
+/// change a primary key in place, so the rows are copied into a new table.
+fn migrate_processed_files(conn: &Connection, device: &str) -> SqlResult<()> {
+    let columns = table_columns(conn, "processed_files")?;
+    if columns.is_empty() || columns.iter().any(|c| c == "device") {
+        return Ok(());
+    }
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        &format!("CREATE TABLE processed_files_new {PROCESSED_FILES_SCHEMA}"),
+        [],
+    )?;
+    tx.execute(
+        "INSERT INTO processed_files_new (path, device, basename, quiltfilename, timestamp, status)
+            SELECT path, ?1, basename, quiltfilename, timestamp, status FROM processed_files",
+        [device],
+    )?;
+    tx.execute("DROP TABLE processed_files", [])?;
+    tx.execute(
+        "ALTER TABLE processed_files_new RENAME TO processed_files",
+        [],
+    )?;
+    tx.commit()
+}
+
+/// Moves rows from before full paths were recorded, keyed by file name alone, to the path of
+/// the one input with that name. Rows for a name several inputs share can't be told apart, so
+/// they are left as they are and only keep their output names from being given out again.
+fn migrate_legacy_paths(conn: &Connection, inputs: &[PathBuf]) -> SqlResult<()> {
+    let legacy: Vec<String> = conn
+        .prepare(
+            "SELECT DISTINCT path FROM processed_files
+            WHERE path NOT LIKE '%/%' AND path NOT LIKE '%\\%'",
+        )?
+        .query_map([], |row| row.get(0))?
+        .collect::<SqlResult<_>>()?;
+    if legacy.is_empty() {
+        return Ok(());
+    }
+    let mut by_name: std::collections::HashMap<String, Vec<&Path>> = Default::default();
+    for input in inputs {
+        if let Some(name) = input.file_name() {
+            by_name
+                .entry(name.to_string_lossy().into_owned())
+                .or_default()
+                .push(input);
+        }
+    }
+    let tx = conn.unchecked_transaction()?;
+    for name in legacy {
+        let Some([path]) = by_name.get(&name).map(Vec::as_slice) else {
+            continue;
+        };
+        let path = path.to_string_lossy();
+        // A row the full path already has is newer than the one keyed by file name
+        tx.execute(
+            "UPDATE OR IGNORE processed_files SET path = ?1 WHERE path = ?2",
+            (path.as_ref(), name.as_str()),
+        )?;
+        tx.execute(
+            "UPDATE playlist SET path = ?1 WHERE path = ?2",
+            (path.as_ref(), name.as_str()),
+        )?;
+    }
+    tx.commit()
+}
+
+fn get_playlist(conn: &Connection, device: &str) -> SqlResult<Vec<(i64, String)>> {
+    let mut stmt = conn.prepare("SELECT position, quiltfilename FROM playlist JOIN processed_files ON playlist.path = processed_files.path AND playlist.device = processed_files.device WHERE playlist.device = ?1 ORDER BY position")?;
     let playlist = stmt
-        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .query_map([device], |row| Ok((row.get(0)?, row.get(1)?)))?
         .collect::<SqlResult<Vec<_>>>()?;
     Ok(playlist)
 }
@@ -138,46 +1120,277 @@ fn generate_nonunique_simple_name(original_name: &str) -> String {
         .collect()
 }
 
-fn generate_simple_name(conn: &Connection, original_name: &str) -> Result<String, Box<dyn Error>> {
-    let simple = generate_nonunique_simple_name(original_name);
+/// The output name for the image at `path`: the name it was given on an earlier run, or its
+/// simplified file name with the first free `_NN` suffix if another image has that name. Names
+/// are stored with each image's rows, so re-runs keep them and deleted rows can't shift them.
+fn generate_simple_name(conn: &Connection, path: &Path) -> Result<String, Box<dyn Error>> {
+    let key = path.to_string_lossy();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
 
-    // Check if this name exists
-    let count: i32 = conn.query_row(
-        "SELECT COUNT(*) FROM processed_files WHERE basename LIKE ?1",
-        [format!("{simple}%")],
-        |row| row.get(0),
+    let existing: Option<String> = conn
+        .query_row(
+            "SELECT basename FROM processed_files WHERE path = ?1 AND basename != '' LIMIT 1",
+            [&key],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(existing) = existing {
+        return Ok(existing);
+    }
+
+    let simple = generate_nonunique_simple_name(&file_name);
+    let taken = |name: &str| -> SqlResult<bool> {
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM processed_files WHERE basename = ?1)",
+            [name],
+            |row| row.get(0),
+        )
+    };
+    let mut name = simple.clone();
+    let mut suffix = 1;
+    while taken(&name)? {
+        name = format!("{simple}_{suffix:02}");
+        suffix += 1;
+    }
+    Ok(name)
+}
+
+/// The file name an m3u line or stored quilt path names, which entries are matched by however
+/// their directories are written.
+fn entry_name(entry: &str) -> Option<String> {
+    Path::new(entry.trim())
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+/// Takes manual edits of `device`'s m3u at `m3u_path`, e.g. reordering on the device, back into
+/// the playlist table. Entries take the order they have in the file, ahead of quilts recorded
+/// since it was written. Quilts recorded before it was written but missing from it were removed
+/// by hand and leave the playlist. Returns the file's lines, none if there is no file yet.
+fn import_m3u_playlist(
+    conn: &Connection,
+    m3u_path: &Path,
+    device: &str,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let Ok(contents) = std::fs::read_to_string(m3u_path) else {
+        return Ok(Vec::new());
+    };
+    let written = std::fs::metadata(m3u_path)?
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as i64;
+    let lines: Vec<String> = contents.lines().map(str::to_string).collect();
+
+    // (path, quilt file name, position, when recorded), in playlist order
+    let rows: Vec<(String, Option<String>, i64, i64)> = conn
+        .prepare(
+            "SELECT playlist.path, quiltfilename, position, CAST(strftime('%s', timestamp) AS INTEGER)
+            FROM playlist JOIN processed_files ON playlist.path = processed_files.path AND playlist.device = processed_files.device
+            WHERE playlist.device = ?1 ORDER BY position",
+        )?
+        .query_map([device], |row| {
+            Ok((
+                row.get(0)?,
+                entry_name(&row.get::<_, String>(1)?),
+                row.get(2)?,
+                row.get::<_, Option<i64>>(3)?.unwrap_or(0),
+            ))
+        })?
+        .collect::<SqlResult<_>>()?;
+    let by_name: std::collections::HashMap<&str, usize> = rows
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (_, name, _, _))| Some((name.as_deref()?, i)))
+        .collect();
+    let mut listed = vec![false; rows.len()];
+    let mut order = Vec::new();
+    for line in &lines {
+        if let Some(&i) = entry_name(line).and_then(|name| by_name.get(name.as_str())) {
+            if !std::mem::replace(&mut listed[i], true) {
+                order.push(i);
+            }
+        }
+    }
+    let (newer, removed): (Vec<usize>, Vec<usize>) = (0..rows.len())
+        .filter(|&i| !listed[i])
+        .partition(|&i| rows[i].3 >= written);
+    order.extend(newer);
+
+    let transaction = conn.unchecked_transaction()?;
+    for &i in &removed {
+        println!("Removed from the playlist by hand: {}", rows[i].0);
+        conn.execute(
+            "DELETE FROM playlist WHERE path = ?1 AND device = ?2",
+            (&rows[i].0, device),
+        )?;
+    }
+    // The entries keep the positions they hold between them, which are unique across devices,
+    // so they are moved out of the way before taking them in the new order
+    let mut positions: Vec<i64> = order.iter().map(|&i| rows[i].2).collect();
+    positions.sort_unstable();
+    conn.execute(
+        "UPDATE playlist SET position = -1 - position WHERE device = ?1",
+        [device],
     )?;
+    for (&i, position) in order.iter().zip(positions) {
+        conn.execute(
+            "UPDATE playlist SET position = ?1 WHERE path = ?2 AND device = ?3",
+            (position, &rows[i].0, device),
+        )?;
+    }
+    transaction.commit()?;
+    Ok(lines)
+}
 
-    // Add number suffix if needed
-    let final_name = if count > 0 {
-        format!("{simple}_{count:02}")
-    } else {
-        simple
+/// Takes `device`'s playlist down to `limit.max` entries, as `limit.rotation` says. Rotated out
+/// quilts stay recorded as processed, so later runs don't make them again.
+fn rotate_playlist(
+    conn: &Connection,
+    device: &str,
+    limit: &PlaylistLimit,
+) -> Result<(), Box<dyn Error>> {
+    // (path, quilt file name, position), first added first
+    let rows: Vec<(String, String, i64)> = conn
+        .prepare(
+            "SELECT playlist.path, quiltfilename, position
+            FROM playlist JOIN processed_files ON playlist.path = processed_files.path AND playlist.device = processed_files.device
+            WHERE playlist.device = ?1 ORDER BY playlist.id",
+        )?
+        .query_map([device], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<SqlResult<_>>()?;
+    let excess = rows.len().saturating_sub(limit.max);
+    if excess == 0 {
+        return Ok(());
+    }
+
+    // Quilts to rotate out, and for random-replace the newest quilts that take their places
+    let (removed, incoming): (Vec<usize>, Vec<usize>) = match limit.rotation {
+        Rotation::DropOldest => ((0..excess).collect(), Vec::new()),
+        Rotation::RandomReplace => {
+            let incoming = excess.min(limit.max);
+            let older = rows.len() - incoming;
+            let last_older = &rows[older - 1].0;
+            let victims: Vec<usize> = conn
+                .prepare(
+                    "SELECT path FROM playlist
+                    WHERE device = ?1 AND id <= (SELECT id FROM playlist WHERE path = ?2 AND device = ?1)
+                    ORDER BY RANDOM() LIMIT ?3",
+                )?
+                .query_map((device, last_older, excess as i64), |row| {
+                    row.get::<_, String>(0)
+                })?
+                .collect::<SqlResult<Vec<_>>>()?
+                .iter()
+                .filter_map(|path| rows.iter().position(|row| row.0 == *path))
+                .collect();
+            (victims, (older..rows.len()).collect())
+        }
     };
 
-    Ok(final_name)
+    let transaction = conn.unchecked_transaction()?;
+    for &i in &removed {
+        conn.execute(
+            "DELETE FROM playlist WHERE path = ?1 AND device = ?2",
+            (&rows[i].0, device),
+        )?;
+    }
+    for (&i, &replaced) in incoming.iter().zip(&removed) {
+        conn.execute(
+            "UPDATE playlist SET position = ?1 WHERE path = ?2 AND device = ?3",
+            (rows[replaced].2, &rows[i].0, device),
+        )?;
+    }
+    transaction.commit()?;
+
+    for &i in &removed {
+        let quilt = &rows[i].1;
+        println!("Rotated out of the playlist: {quilt}");
+        if limit.delete {
+            if let Err(e) = std::fs::remove_file(quilt) {
+                eprintln!("Error deleting {quilt}: {e}");
+            }
+        }
+    }
+    Ok(())
 }
 
-fn export_m3u_playlist(conn: &Connection, output_dir: &Path) -> Result<(), Box<dyn Error>> {
-    let playlist = get_playlist(conn)?;
+/// Writes the playlist of `device`'s quilts as `<output dir><suffix>.m3u`, named as `paths` says,
+/// after taking in any edits made to the file since the last run and applying `limit`. Lines
+/// that aren't playlist entries, such as comments or quilts added by hand, stay after the entry
+/// they followed, and new quilts go last.
+fn export_m3u_playlist(
+    conn: &Connection,
+    output_dir: &Path,
+    device: &str,
+    suffix: &str,
+    paths: PlaylistPaths,
+    limit: Option<&PlaylistLimit>,
+) -> Result<(), Box<dyn Error>> {
     // Create m3u file named for the directory name
     let dir_name = output_dir.file_name().unwrap_or_default().to_string_lossy();
     let out = output_dir.parent().unwrap_or(output_dir);
-    let m3u_path = out.join(format!("{dir_name}.m3u"));
+    let m3u_path = out.join(format!("{dir_name}{suffix}.m3u"));
+    let lines = import_m3u_playlist(conn, &m3u_path, device)?;
+    if let Some(limit) = limit {
+        rotate_playlist(conn, device, limit)?;
+    }
+    let playlist = get_playlist(conn, device)?;
+
+    // Quilts this run knows, so lines naming ones that left the playlist are dropped
+    let known: std::collections::HashSet<String> = conn
+        .prepare("SELECT quiltfilename FROM processed_files WHERE device = ?1")?
+        .query_map([device], |row| row.get::<_, Option<String>>(0))?
+        .collect::<SqlResult<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .filter_map(|name| entry_name(&name))
+        .collect();
+    let by_name: std::collections::HashMap<String, usize> = playlist
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (_, filename))| Some((entry_name(filename)?, i)))
+        .collect();
+    // The other lines, by the entry they follow, None for the top of the file
+    let mut following: std::collections::HashMap<Option<usize>, Vec<&str>> = Default::default();
+    let mut anchor = None;
+    for line in &lines {
+        let name = entry_name(line);
+        match name.as_ref().and_then(|name| by_name.get(name)) {
+            Some(&i) => anchor = Some(i),
+            None if name.is_some_and(|name| known.contains(&name)) => {}
+            None => following.entry(anchor).or_default().push(line),
+        }
+    }
+
     let mut file = std::fs::File::create(m3u_path)?;
 
     // Write m3u header. Nope. Lookingglass Go does notaccept it.
     // writeln!(file, "#EXTM3U")?;
 
-    // Write each entry - the path is already the simplified output filename
-    for (_, filename) in playlist {
-        writeln!(file, "{filename}")?;
+    for line in following.remove(&None).unwrap_or_default() {
+        writeln!(file, "{line}")?;
+    }
+    for (i, (_, filename)) in playlist.iter().enumerate() {
+        writeln!(file, "{}", paths.entry(filename, output_dir))?;
+        for line in following.remove(&Some(i)).unwrap_or_default() {
+            writeln!(file, "{line}")?;
+        }
     }
 
     Ok(())
 }
 
-fn add_to_playlist(conn: &Connection, path: &str) -> Result<(), Box<dyn Error>> {
+fn add_to_playlist(conn: &Connection, path: &str, device: &str) -> Result<(), Box<dyn Error>> {
+    // A reprocessed image keeps its place
+    let listed: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM playlist WHERE path = ?1 AND device = ?2)",
+        [path, device],
+        |row| row.get(0),
+    )?;
+    if listed {
+        return Ok(());
+    }
+
     // Get the next available position
     let next_pos: i64 = conn.query_row(
         "SELECT COALESCE(MAX(position) + 1, 0) FROM playlist",
@@ -186,17 +1399,17 @@ fn add_to_playlist(conn: &Connection, path: &str) -> Result<(), Box<dyn Error>>
     )?;
 
     conn.execute(
-        "INSERT INTO playlist (path, position) VALUES (?1, ?2)",
-        (path, next_pos),
+        "INSERT INTO playlist (path, device, position) VALUES (?1, ?2, ?3)",
+        (path, device, next_pos),
     )?;
 
     Ok(())
 }
 
-fn get_processing_status(conn: &Connection, path: &str) -> ProcessingStatus {
+fn get_processing_status(conn: &Connection, path: &str, device: &str) -> ProcessingStatus {
     match conn.query_row(
-        "SELECT status FROM processed_files WHERE path = ?1",
-        [path],
+        "SELECT status FROM processed_files WHERE path = ?1 AND device = ?2",
+        [path, device],
         |row| row.get::<_, String>(0),
     ) {
         Ok(status) => {
@@ -220,166 +1433,1000 @@ enum ProcessingStatus {
 fn mark_processed(
     conn: &Connection,
     path: &str,
+    device: &str,
     basename: &str,
     quiltfilename: &str,
     status: &str,
+    error: Option<&str>,
+) -> SqlResult<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO processed_files (path, device, basename, quiltfilename, status, error) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (path, device, basename, quiltfilename, status, error),
+    )?;
+    Ok(())
+}
+
+/// Records that processing `path` for `device` failed at `stage` with `error`, so a run with
+/// `--retry-failed` can try it again.
+fn mark_failed(
+    conn: &Connection,
+    path: &str,
+    device: &str,
+    basename: &str,
+    stage: Option<Stage>,
+    error: &str,
 ) -> SqlResult<()> {
     conn.execute(
-        "INSERT OR REPLACE INTO processed_files (path, basename, quiltfilename, status) VALUES (?1, ?2, ?3, ?4)",
-        (path, basename, quiltfilename, status),
+        "INSERT OR REPLACE INTO processed_files (path, device, basename, quiltfilename, status, error, stage) VALUES (?1, ?2, ?3, '', 'error', ?4, ?5)",
+        (path, device, basename, error, stage.map(Stage::as_str)),
+    )?;
+    Ok(())
+}
+
+/// Removes the failures recorded for `path` on any device.
+fn clear_failures(conn: &Connection, path: &str) -> SqlResult<()> {
+    conn.execute(
+        "DELETE FROM processed_files WHERE path = ?1 AND status = 'error'",
+        [path],
     )?;
     Ok(())
 }
 
+/// How an image failed on an earlier run, as `--retry-failed` reports it.
+struct PreviousFailure {
+    device: String,
+    stage: Option<String>,
+    error: Option<String>,
+}
+
+/// The images that failed for one of `devices`, by path.
+fn failed_inputs(
+    conn: &Connection,
+    devices: &[Option<String>],
+) -> SqlResult<std::collections::HashMap<String, Vec<PreviousFailure>>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, device, stage, error FROM processed_files WHERE status = 'error' ORDER BY path",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            PreviousFailure {
+                device: row.get(1)?,
+                stage: row.get(2)?,
+                error: row.get(3)?,
+            },
+        ))
+    })?;
+    let mut failed: std::collections::HashMap<String, Vec<PreviousFailure>> = Default::default();
+    for row in rows {
+        let (path, failure) = row?;
+        if devices
+            .iter()
+            .any(|device| device_key(device) == failure.device)
+        {
+            failed.entry(path).or_default().push(failure);
+        }
+    }
+    Ok(failed)
+}
+
+/// The files under `input_dir` in name order, leaving out the cache.
+fn input_files(input_dir: &Path) -> impl Iterator<Item = PathBuf> {
+    WalkDir::new(input_dir)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".rgbd_cache")
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+}
+
+/// The still images to process for the input at `path`, extracted under `frames_dir` where
+/// needed: the path itself; for GIFs and animated PNGs, the first frame or with `animate` every
+/// frame; for MPO stereo photos, the left view.
+fn input_frames(
+    path: &Path,
+    frames_dir: &Path,
+    animate: bool,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let file_name = path
+        .file_name()
+        .ok_or("input path does not contain a file name")?;
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let dir = frames_dir.join(file_name);
+    if extension == "mpo" {
+        // Depth comes from a single view, so the right one isn't used
+        let left = dir.join(Path::new(file_name).with_extension("jpg"));
+        if !left.exists() {
+            let data = std::fs::read(path)?;
+            let images = mpo::split_mpo(&data)?;
+            let first = images.first().ok_or("MPO has no images")?;
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(&left, first)?;
+        }
+        return Ok(vec![left]);
+    }
+    if extension != "gif" && !animation::is_animated(path)? {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    animation::extract_frames(path, &dir, animate)
+}
+
+/// Name of a target device in the database: the device name, or empty for a custom layout.
+fn device_key(device: &Option<String>) -> &str {
+    device.as_deref().unwrap_or_default()
+}
+
+/// Suffix telling apart the quilts of one image in a multi-device run, e.g. `_portrait`: the
+/// device's first alias, which is shorter than its full name.
+fn device_suffix(device: &str) -> String {
+    let short = find_device(device)
+        .and_then(|preset| preset.aliases.first().copied())
+        .unwrap_or(device);
+    let slug: String = short
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect();
+    format!("_{slug}")
+}
+
+/// The part of processing an image that failed.
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+    /// Reading the input image or splitting it into frames.
+    Decode,
+    /// Generating, editing or inpainting the depth map, usually on the ComfyUI server.
+    Depth,
+    /// Rendering the views into a quilt.
+    Render,
+    /// Encoding and writing the quilt file.
+    Encode,
+}
+
+impl Stage {
+    fn as_str(self) -> &'static str {
+        match self {
+            Stage::Decode => "decode",
+            Stage::Depth => "depth",
+            Stage::Render => "render",
+            Stage::Encode => "encode",
+        }
+    }
+
+    /// Tags an error with this stage, for `map_err`.
+    fn error<E: Into<Box<dyn Error>>>(self) -> impl FnOnce(E) -> Box<dyn Error> {
+        move |error| {
+            Box::new(StageError {
+                stage: self,
+                error: error.into(),
+            })
+        }
+    }
+}
+
+/// An error from `process_image` with the stage it happened in. It reads as the error alone.
+#[derive(Debug)]
+struct StageError {
+    stage: Stage,
+    error: Box<dyn Error>,
+}
+
+impl std::fmt::Display for StageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl Error for StageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+/// What every image of a run is processed with.
+struct BatchContext<'a> {
+    output_dir: &'a Path,
+    config: &'a DepthConfig,
+    depth_outputs: &'a DepthOutputs,
+    depth_edit: &'a DepthEdit,
+    inpaint: Option<&'a InpaintConfig>,
+    quilt_config: &'a QuiltConfig,
+    render_args: &'a RenderDefaults,
+    devices: &'a [Option<String>],
+    conn: &'a Connection,
+    caption_config: &'a CaptionConfig,
+    script: Option<&'a ParameterScript>,
+    saves: &'a SaveQueue<QueuedQuilt>,
+    quality: &'a QualityGate,
+}
+
 fn process_image(
+    ctx: &BatchContext,
     input_path: &Path,
-    output_dir: &Path,
-    config: &DepthConfig,
-    quilt_config: &QuiltConfig,
-    conn: &Connection,
-    caption_config: &CaptionConfig,
+    temporal: Option<&mut TemporalDepthFilter>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Get both the original filename and a simple name for the database
-    let input_name = input_path.file_name().unwrap().to_string_lossy();
-    let simple_name = generate_simple_name(conn, &input_name)?;
+    let BatchContext { conn, devices, .. } = *ctx;
+    // A simple name for the database
+    let simple_name = generate_simple_name(conn, input_path)?;
 
-    match get_processing_status(conn, &input_path.to_string_lossy()) {
-        ProcessingStatus::Processed => {
-            println!("Skipping already processed file: {simple_name}");
-            return Ok(());
+    // Only the devices this image hasn't been rendered for yet
+    let statuses: Vec<_> = devices
+        .iter()
+        .map(|device| {
+            let status =
+                get_processing_status(conn, &input_path.to_string_lossy(), device_key(device));
+            (device, status)
+        })
+        .filter(|(_, status)| *status != ProcessingStatus::Processed)
+        .collect();
+    if statuses.is_empty() {
+        println!("Skipping already processed file: {simple_name}");
+        // The sequence has a gap here, so don't blend across it
+        if let Some(temporal) = temporal {
+            temporal.reset();
         }
-        ProcessingStatus::NeedsReprocessing => {
-            println!("Reprocessing: {simple_name}");
+        return Ok(());
+    }
+    let pending: Vec<String> = statuses
+        .iter()
+        .map(|(device, _)| device_key(device).to_string())
+        .collect();
+    let mut handed_off = 0;
+    let result = process_pending(
+        ctx,
+        input_path,
+        temporal,
+        &simple_name,
+        statuses,
+        &mut handed_off,
+    );
+    // Left unrecorded when interrupted, so the next run processes it again
+    if let (Err(e), false) = (&result, interrupt::interrupted()) {
+        let stage = e.downcast_ref::<StageError>().map(|e| e.stage);
+        // Devices handed off before the failure are recorded when their saves finish
+        for device in &pending[handed_off..] {
+            mark_failed(
+                conn,
+                &input_path.to_string_lossy(),
+                device,
+                &simple_name,
+                stage,
+                &e.to_string(),
+            )?;
+        }
+    }
+    result
+}
+
+/// Processes `input_path` for the devices in `statuses`, those it hasn't been rendered for yet,
+/// counting in `handed_off` the devices whose quilts were saved or queued for saving.
+fn process_pending(
+    ctx: &BatchContext,
+    input_path: &Path,
+    temporal: Option<&mut TemporalDepthFilter>,
+    simple_name: &str,
+    statuses: Vec<(&Option<String>, ProcessingStatus)>,
+    handed_off: &mut usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let BatchContext {
+        output_dir,
+        config,
+        depth_outputs,
+        depth_edit,
+        inpaint,
+        quilt_config,
+        render_args,
+        devices,
+        conn,
+        caption_config,
+        script,
+        saves,
+        quality,
+    } = *ctx;
+    let input_name = input_path.file_name().unwrap().to_string_lossy();
+    // Checked again on every run, so relaxed limits let earlier skips through
+    if let Some(reason) = quality.check(input_path).map_err(Stage::Decode.error())? {
+        println!("Skipping {simple_name}: {reason}");
+        METRICS.images_skipped.inc();
+        for (device, _) in &statuses {
+            mark_processed(
+                conn,
+                &input_path.to_string_lossy(),
+                device_key(device),
+                simple_name,
+                "",
+                "skipped",
+                Some(&reason.to_string()),
+            )?;
         }
-        ProcessingStatus::NotProcessed => {
-            println!("Processing new file: {input_name} -> {simple_name}");
+        if let Some(temporal) = temporal {
+            temporal.reset();
         }
+        return Ok(());
     }
+    if statuses
+        .iter()
+        .any(|(_, status)| *status == ProcessingStatus::NeedsReprocessing)
+    {
+        println!("Reprocessing: {simple_name}");
+    } else {
+        println!("Processing new file: {input_name} -> {simple_name}");
+    }
+    let pending_devices = statuses.into_iter().map(|(device, _)| device);
 
     println!("Processing: {simple_name}");
 
-    let (texture, depth) = generate_depth(input_path.to_path_buf(), config)?;
+    let (texture, depth) =
+        generate_depth(input_path.to_path_buf(), config).map_err(Stage::Depth.error())?;
+    let depth = match temporal {
+        Some(temporal) => temporal.filter(&texture, depth),
+        None => depth,
+    };
+    let depth = depth_edit
+        .apply(input_path, depth)
+        .map_err(Stage::Depth.error())?;
+    depth_outputs.save(input_path, &texture, &depth)?;
+    let back_layer = inpaint
+        .map(|inpaint| inpaint_back_layer(input_path, &texture, &depth, inpaint, config))
+        .transpose()
+        .map_err(Stage::Depth.error())?
+        .map(Arc::new);
 
     let ext = input_path
         .extension()
         .unwrap_or_else(|| std::ffi::OsStr::new("jpg"));
-    let output_path = output_dir.join(&simple_name).with_extension(ext);
-
-    // Replace {} in caption with filename if present
-    #[cfg(feature = "captions")]
-    let mut caption = caption_config.clone();
-    #[cfg(not(feature = "captions"))]
-    let caption = caption_config;
-    #[cfg(feature = "captions")]
+
+    // Take the caption from metadata if requested, then replace {} with filename if present
+    let mut caption = caption_config.clone().with_metadata_from(input_path);
     if let Some(text) = caption.text.as_ref() {
         let base_name = input_path.file_stem().unwrap_or_default().to_string_lossy();
         caption.text = Some(text.replace("{}", &base_name));
     }
 
-    let quiltfilename = generate_quilt(
-        texture,
-        depth,
-        output_path.to_string_lossy().to_string(),
-        &QuiltConfig {
-            device: quilt_config.device.clone(),
-            columns: quilt_config.columns,
-            rows: quilt_config.rows,
-            width: quilt_config.width,
-            height: quilt_config.height,
-            debug_mode: quilt_config.debug_mode.clone(),
-            bg: quilt_config.bg.clone(),
-            fov: quilt_config.fov,
-            zoom: quilt_config.zoom,
-            scale: quilt_config.scale,
-            resize: quilt_config.resize,
-            symlink_output: quilt_config.symlink_output,
+    // Each device starts from its own render defaults, so the script sees those
+    let facts = script.map(|_| ImageFacts::gather(input_path, &texture, &depth));
+    let mut device_configs = Vec::new();
+    for device in pending_devices {
+        let render = render_args.or(render_defaults(device.as_deref()));
+        let mut device_config = QuiltConfig {
+            device: device.clone(),
+            zoom: render.zoom.unwrap_or(quilt_config.zoom),
+            scale: render.scale.unwrap_or(quilt_config.scale),
+            resize: render.resize.unwrap_or(quilt_config.resize),
             caption: caption.clone(),
-        },
-    )?;
+            back_layer: back_layer.clone(),
+            ..quilt_config.clone()
+        };
+        if let (Some(script), Some(facts)) = (script, &facts) {
+            let parameters = script
+                .evaluate(
+                    facts,
+                    &ScriptParameters {
+                        fov: device_config.fov,
+                        zoom: device_config.zoom,
+                        scale: device_config.scale,
+                        caption: caption.text.clone(),
+                    },
+                )
+                .map_err(Stage::Render.error())?;
+            tracing::debug!("Script parameters for {simple_name}: {parameters:?}");
+            device_config.fov = parameters.fov;
+            device_config.zoom = parameters.zoom;
+            device_config.scale = parameters.scale;
+            device_config.caption.text = parameters.caption;
+        }
+        device_configs.push(device_config);
+    }
 
-    mark_processed(conn, &input_name, &simple_name, &quiltfilename, "success")?;
-    add_to_playlist(conn, &input_name)?;
-    println!("Successfully processed: {simple_name}");
+    // Shrink full-size photos once to fit the largest tile, so each device only resizes from that
+    let mut images = Some((texture, depth));
+    if device_configs.len() > 1 {
+        let (width, height) = device_configs
+            .iter()
+            .map(|config| config.target_size(&config.quilt_settings()))
+            .fold((0, 0), |(w, h), (tw, th)| (w.max(tw), h.max(th)));
+        images = images.map(|(texture, depth)| fit_within(texture, depth, width, height));
+    }
+
+    for (i, device_config) in device_configs.iter().enumerate() {
+        let device = device_key(&device_config.device).to_string();
+        let output_name = if devices.len() > 1 {
+            format!("{simple_name}{}", device_suffix(&device))
+        } else {
+            simple_name.to_string()
+        };
+        let output_path = output_dir.join(output_name).with_extension(ext);
+
+        // The last device takes the images instead of a copy
+        let (texture, depth) = if i + 1 < device_configs.len() {
+            images.clone()
+        } else {
+            images.take()
+        }
+        .expect("images are taken by the last device only");
+        let (quiltfilename, pending) = generate_quilt_deferred(
+            texture,
+            depth,
+            output_path.to_string_lossy().to_string(),
+            device_config,
+        )
+        .map_err(Stage::Render.error())?;
+
+        let quilt = QueuedQuilt {
+            input_path: input_path.to_string_lossy().into_owned(),
+            device,
+            primary: device_config.device == devices[0],
+            simple_name: simple_name.to_string(),
+            quiltfilename,
+        };
+        match pending {
+            Some(pending) => {
+                // Claim the name until the save finishes; a run interrupted before then redoes it
+                mark_processed(
+                    conn,
+                    &quilt.input_path,
+                    &quilt.device,
+                    &quilt.simple_name,
+                    &quilt.quiltfilename,
+                    "saving",
+                    None,
+                )?;
+                saves.push(quilt, pending)?;
+            }
+            None => record_saved(conn, &quilt)?,
+        }
+        *handed_off += 1;
+    }
+
+    Ok(())
+}
+
+/// An image whose quilt is waiting in the save queue.
+struct QueuedQuilt {
+    input_path: String,
+    device: String,
+    /// Whether this is the quilt for the first device, so the image is counted once.
+    primary: bool,
+    simple_name: String,
+    quiltfilename: String,
+}
+
+fn record_saved(conn: &Connection, quilt: &QueuedQuilt) -> Result<(), Box<dyn Error>> {
+    // Together, so a success always has its playlist entry
+    let transaction = conn.unchecked_transaction()?;
+    mark_processed(
+        conn,
+        &quilt.input_path,
+        &quilt.device,
+        &quilt.simple_name,
+        &quilt.quiltfilename,
+        "success",
+        None,
+    )?;
+    add_to_playlist(conn, &quilt.input_path, &quilt.device)?;
+    transaction.commit()?;
+    if quilt.primary {
+        METRICS.images_processed.inc();
+    }
+    println!("Successfully processed: {}", quilt.quiltfilename);
+    Ok(())
+}
 
+/// Records finished saves in the database: the playlist entry on success, an error otherwise.
+fn record_saves(
+    conn: &Connection,
+    results: Vec<SaveResult<QueuedQuilt>>,
+) -> Result<(), Box<dyn Error>> {
+    for (quilt, result) in results {
+        match result {
+            Ok(()) => record_saved(conn, &quilt)?,
+            Err(e) => {
+                eprintln!("Error saving {}: {e}", quilt.quiltfilename);
+                METRICS.images_failed.inc();
+                mark_failed(
+                    conn,
+                    &quilt.input_path,
+                    &quilt.device,
+                    &quilt.simple_name,
+                    Some(Stage::Encode),
+                    &e,
+                )?;
+            }
+        }
+    }
     Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-    let args = Args::parse();
+    let args: Args = cli::parse();
+    logging::init(args.log_format);
+    match &args.command {
+        Some(Command::Sync(sync_args)) => return sync(sync_args),
+        Some(Command::Status(status_args)) => return status(status_args),
+        None => {}
+    }
+    let input_dir = args.input_dir.clone().ok_or("missing INPUT_DIR")?;
+    let output_dir = args.output_dir.clone().ok_or("missing OUTPUT_DIR")?;
+    interrupt::install();
+    #[cfg(feature = "hid")]
+    let args = {
+        let mut args = args;
+        if args.devices.is_empty() && args.columns.is_none() {
+            let mut device = None;
+            quilt_painter::hid::use_detected_display(&mut device, &mut args.fov);
+            args.devices.extend(device);
+        }
+        args
+    };
+
+    if let Some(addr) = &args.metrics_listen {
+        metrics::serve(addr.as_str())?;
+    }
 
     // Create output directory if it doesn't exist
-    std::fs::create_dir_all(&args.output_dir)?;
+    std::fs::create_dir_all(&output_dir)?;
 
     // Initialize database
-    let db_path = args.input_dir.join("index.db");
+    let db_path = input_dir.join("index.db");
     let conn = Connection::open(db_path)?;
-    init_db(&conn)?;
+    // A custom layout renders once per image, recorded with an empty device
+    let devices: Vec<Option<String>> = if args.devices.is_empty() {
+        vec![None]
+    } else {
+        args.devices.into_iter().map(Some).collect()
+    };
+    init_db(&conn, device_key(&devices[0]))?;
 
     // Create cache directory in input dir
-    let cache_dir = args.input_dir.join(".rgbd_cache");
+    let cache_dir = input_dir.join(".rgbd_cache");
     let depth_config = DepthConfig {
-        comfy_url: args.comfy_url.clone(),
-        cache_dir: Some(cache_dir),
+        cache_dir: Some(cache_dir.clone()),
+        depth_suffix: args.depth_suffix,
+        depthd: args.depthd,
+        ..args.depth.into()
+    };
+
+    let depth_outputs = DepthOutputs {
+        rgbd_dir: args.save_rgbd,
+        depth_dir: args.save_depth,
     };
+    let depth_edit = DepthEdit {
+        command: args.depth_edit_cmd,
+        pause: args.pause_for_edit,
+    };
+    let inpaint = args.inpaint_behind.then(|| InpaintConfig {
+        prompt: args.inpaint_prompt,
+        checkpoint: args.inpaint_checkpoint,
+        workflow: args.inpaint_workflow,
+        subject_depth: args.subject_depth,
+        ..InpaintConfig::default()
+    });
 
-    #[cfg(feature = "captions")]
-    let caption = CaptionConfig::new(args.caption, args.caption_size, args.caption_position);
-    #[cfg(not(feature = "captions"))]
-    let caption = CaptionConfig::default();
+    let caption = CaptionConfig {
+        margin: args.caption_margin,
+        max_width: args.caption_max_width,
+        line_spacing: args.caption_line_spacing,
+        align: args.caption_align,
+        background: args.caption_bg,
+        outline: args.caption_outline,
+        shadow: args.caption_shadow,
+        fonts: args.caption_fonts,
+        target: args.caption_target,
+        from: args.caption_from,
+        ..CaptionConfig::new(None, args.caption_size, args.caption_position)
+    }
+    .with_specs(args.caption);
 
+    // Given on the command line, so they win over each device's defaults
+    let render_args = RenderDefaults {
+        zoom: args.zoom,
+        scale: args.scale,
+        resize: args.resize,
+    };
     let quilt_config = QuiltConfig {
-        device: args.device,
+        device: None,
         columns: args.columns,
         rows: args.rows,
         width: args.width,
         height: args.height,
         debug_mode: args.debug_mode,
         bg: args.bg,
+        matte: args.matte,
         fov: args.fov,
-        zoom: args.zoom,
-        scale: args.scale,
-        resize: args.resize,
+        view_spacing: args.view_spacing.clone(),
+        flip_h: args.flip_h,
+        flip_v: args.flip_v,
+        mirror_views: args.mirror_views,
+        tile_padding: args.tile_padding,
+        border_color: args.border_color,
+        zoom: args.zoom.unwrap_or(1.05),
+        scale: args.scale.unwrap_or(1.0),
+        resize: args.resize.unwrap_or(2.5),
         symlink_output: false,
+        link_mode: Default::default(),
         caption: CaptionConfig::default(),
+        export_normals: args.export_normals,
+        export_leia: args.export_leia,
+        watermark: args.watermark.map(|watermark| WatermarkConfig {
+            once: args.watermark_once,
+            ..watermark
+        }),
+        write_descriptor: args.descriptor,
+        preview: args.preview,
+        auto_tune: args.auto_tune,
+        auto_tune_parallax: args.auto_tune_parallax,
+        max_parallax: args.max_parallax,
+        sampling: args.sampling,
+        splat_radius: args.splat_radius,
+        pixel_aspect: args.pixel_aspect,
+        band_height: args.band_height,
+        render_cache_dir: Some(cache_dir.join("renders")),
+        zoom_center: args.zoom_center,
+        focus_depth: args.focus_depth,
+        dither: args.dither,
+        easing: args.easing,
+        hole_fill: args.hole_fill,
+        stabilize_views: args.stabilize_views,
+        postfx: PostFx {
+            color: None,
+            sharpen: args.sharpen,
+            grain: args.grain.map(|amount| Grain {
+                amount,
+                per_view: args.grain_per_view,
+            }),
+            vignette: args.vignette,
+            edge_fade: args.edge_fade,
+        }
+        .or(args.look.unwrap_or_default()),
+        back_layer: None,
+        panorama: args
+            .projection
+            .panorama(args.pano_yaw, args.pano_pitch, args.pano_fov),
+        undistort: args.undistort,
+    };
+
+    let quality = QualityGate {
+        min_resolution: args.min_resolution,
+        blur_threshold: args.skip_blurry,
+    };
+
+    let mut temporal = args.temporal_smoothing.map(TemporalDepthFilter::new);
+    let script = args
+        .script
+        .as_deref()
+        .map(ParameterScript::load)
+        .transpose()?;
+
+    // Encode and write each quilt while the next image's depth is generated
+    let saves = SaveQueue::new(SAVE_QUEUE_CAPACITY);
+
+    // Ctrl-C lets the current image finish, or cancels its depth prompt, and stops after
+    // recording the saves and writing the playlist, so the next run resumes there
+    let _deferred = interrupt::defer();
+
+    // With --retry-failed, only the images that failed before
+    let failed = if args.retry_failed {
+        let failed = failed_inputs(&conn, &devices)?;
+        if failed.is_empty() {
+            println!("No failed images to retry");
+        }
+        Some(failed)
+    } else {
+        None
     };
 
     // Process all images in input directory
-    for entry in WalkDir::new(&args.input_dir)
-        .follow_links(true)
-        .into_iter()
-        .filter(|e| {
-            e.as_ref().is_ok_and(|v| {
-                !v.path()
-                    .components()
-                    .any(|c| c.as_os_str() == ".rgbd_cache")
-            })
-        })
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        if path.is_file() {
+    let ctx = BatchContext {
+        output_dir: &output_dir,
+        config: &depth_config,
+        depth_outputs: &depth_outputs,
+        depth_edit: &depth_edit,
+        inpaint: inpaint.as_ref(),
+        quilt_config: &quilt_config,
+        render_args: &render_args,
+        devices: &devices,
+        conn: &conn,
+        caption_config: &caption,
+        script: script.as_ref(),
+        saves: &saves,
+        quality: &quality,
+    };
+    let inputs: Vec<PathBuf> = input_files(&input_dir).collect();
+    migrate_legacy_paths(&conn, &inputs)?;
+    'images: for path in &inputs {
+        let path = path.as_path();
+        if !depth_config.is_depth_sidecar(path) {
             if let Some(ext) = path.extension() {
                 let ext_str = ext.to_string_lossy().to_ascii_lowercase();
-                if ext_str == "jpg" || ext_str == "jpeg" || ext_str == "png" {
-                    if let Err(e) = process_image(
-                        path,
-                        &args.output_dir,
-                        &depth_config,
-                        &quilt_config,
-                        &conn,
-                        &caption,
-                    ) {
-                        let simple_name = generate_nonunique_simple_name(&path.to_string_lossy());
-                        eprintln!("Error processing {}: {e}", path.display());
-                        mark_processed(&conn, &path.to_string_lossy(), &simple_name, "", "error")?;
+                if ["jpg", "jpeg", "png", "gif", "mpo"].contains(&ext_str.as_str()) {
+                    let frames = match input_frames(path, &cache_dir.join("frames"), args.animate) {
+                        Ok(frames) => frames,
+                        Err(e) => {
+                            eprintln!("Error reading frames of {}: {e}", path.display());
+                            METRICS.images_failed.inc();
+                            let simple_name = generate_simple_name(&conn, path)?;
+                            let key = path.to_string_lossy();
+                            for device in &devices {
+                                let device = device_key(device);
+                                if get_processing_status(&conn, &key, device)
+                                    != ProcessingStatus::Processed
+                                {
+                                    mark_failed(
+                                        &conn,
+                                        &key,
+                                        device,
+                                        &simple_name,
+                                        Some(Stage::Decode),
+                                        &e.to_string(),
+                                    )?;
+                                }
+                            }
+                            continue;
+                        }
+                    };
+                    for frame in &frames {
+                        if interrupt::interrupted() {
+                            break 'images;
+                        }
+                        if let Some(failed) = &failed {
+                            // A file that couldn't be split into frames failed as a whole
+                            let Some(failures) = failed
+                                .get(frame.to_string_lossy().as_ref())
+                                .or_else(|| failed.get(path.to_string_lossy().as_ref()))
+                            else {
+                                // The sequence has a gap here, so don't blend across it
+                                if let Some(temporal) = temporal.as_mut() {
+                                    temporal.reset();
+                                }
+                                continue;
+                            };
+                            for failure in failures {
+                                let device = if failure.device.is_empty() {
+                                    "custom layout"
+                                } else {
+                                    &failure.device
+                                };
+                                println!(
+                                    "Retrying {} for {device}, which failed at {}: {}",
+                                    frame.display(),
+                                    failure.stage.as_deref().unwrap_or("an unknown stage"),
+                                    failure.error.as_deref().unwrap_or("no message recorded")
+                                );
+                            }
+                        }
+                        if let Err(e) = process_image(&ctx, frame, temporal.as_mut()) {
+                            if interrupt::interrupted() {
+                                // Left unrecorded, so the next run processes it again
+                                println!("Interrupted: {}", frame.display());
+                                break 'images;
+                            }
+                            if let Some(temporal) = temporal.as_mut() {
+                                temporal.reset();
+                            }
+                            eprintln!("Error processing {}: {e}", frame.display());
+                            METRICS.images_failed.inc();
+                        }
+                        record_saves(&conn, saves.completed())?;
+                    }
+                    // Each frame has its own rows now, so an earlier failure to split the file
+                    // no longer applies
+                    if frames.iter().any(|frame| frame != path) {
+                        clear_failures(&conn, &path.to_string_lossy())?;
                     }
                 }
             }
         }
     }
 
+    record_saves(&conn, saves.finish())?;
+
+    let playlist_limit = args.playlist_max.map(|max| PlaylistLimit {
+        max,
+        rotation: args.playlist_rotation,
+        delete: args.delete_rotated,
+    });
+
     // Export updated playlist
-    export_m3u_playlist(&conn, &args.output_dir)?;
+    for device in &devices {
+        let suffix = if devices.len() > 1 {
+            device_suffix(device_key(device))
+        } else {
+            String::new()
+        };
+        export_m3u_playlist(
+            &conn,
+            &output_dir,
+            device_key(device),
+            &suffix,
+            args.playlist_paths,
+            playlist_limit.as_ref(),
+        )?;
+    }
+    record_run(&conn)?;
+    if interrupt::interrupted() {
+        println!("Stopped early; run again to continue");
+        interrupt::exit();
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn, "").unwrap();
+        conn
+    }
+
+    #[test]
+    fn same_named_files_get_different_names() {
+        let conn = database();
+        // Recorded by a version that keyed rows by file name alone
+        mark_processed(
+            &conn,
+            "IMG_0001.jpg",
+            "",
+            "IMG0001",
+            "IMG0001.png",
+            "success",
+            None,
+        )
+        .unwrap();
+        let inputs = [
+            PathBuf::from("in/a/IMG_0001.jpg"),
+            PathBuf::from("in/b/IMG_0001.jpg"),
+        ];
+        migrate_legacy_paths(&conn, &inputs).unwrap();
+
+        let mut names = vec!["IMG0001".to_string()];
+        for input in &inputs {
+            let key = input.to_string_lossy();
+            assert!(get_processing_status(&conn, &key, "") == ProcessingStatus::NotProcessed);
+            let name = generate_simple_name(&conn, input).unwrap();
+            assert!(!names.contains(&name), "{name} reused");
+            mark_processed(&conn, &key, "", &name, "", "success", None).unwrap();
+            names.push(name);
+        }
+        // and keep them on the next run
+        for (input, name) in inputs.iter().zip(&names[1..]) {
+            assert_eq!(&generate_simple_name(&conn, input).unwrap(), name);
+        }
+    }
+
+    #[test]
+    fn legacy_row_moves_to_the_only_file_with_its_name() {
+        let conn = database();
+        mark_processed(
+            &conn,
+            "IMG_0001.jpg",
+            "",
+            "IMG0001",
+            "IMG0001.png",
+            "success",
+            None,
+        )
+        .unwrap();
+        let input = PathBuf::from("in/a/IMG_0001.jpg");
+        migrate_legacy_paths(&conn, &[input.clone(), PathBuf::from("in/IMG_0002.jpg")]).unwrap();
+
+        assert!(
+            get_processing_status(&conn, &input.to_string_lossy(), "")
+                == ProcessingStatus::Processed
+        );
+        assert_eq!(generate_simple_name(&conn, &input).unwrap(), "IMG0001");
+    }
+
+    #[test]
+    fn clearing_a_container_keeps_its_frames() {
+        let conn = database();
+        let devices = [None];
+        mark_failed(
+            &conn,
+            "in/anim.gif",
+            "",
+            "anim",
+            Some(Stage::Decode),
+            "bad GIF",
+        )
+        .unwrap();
+        mark_failed(&conn, "frames/anim_001.png", "", "anim001", None, "timeout").unwrap();
+        clear_failures(&conn, "in/anim.gif").unwrap();
+        let failed = failed_inputs(&conn, &devices).unwrap();
+        assert!(!failed.contains_key("in/anim.gif"));
+        assert!(failed.contains_key("frames/anim_001.png"));
+    }
+
+    #[test]
+    fn suffixes_use_the_short_name() {
+        assert_eq!(device_suffix("Looking Glass Portrait"), "_portrait");
+        assert_eq!(device_suffix("My Display"), "_mydisplay");
+    }
+
+    #[test]
+    fn alias_rows_move_to_the_full_name() {
+        let conn = database();
+        mark_processed(&conn, "in/a.jpg", "portrait", "a", "a.png", "success", None).unwrap();
+        migrate_device_aliases(&conn).unwrap();
+        assert!(
+            get_processing_status(&conn, "in/a.jpg", "portrait") == ProcessingStatus::NotProcessed
+        );
+        assert!(
+            get_processing_status(&conn, "in/a.jpg", "Looking Glass Portrait")
+                == ProcessingStatus::Processed
+        );
+    }
+
+    /// Records quilts `names` for inputs of the same names under `in/`, in playlist order.
+    fn playlist_of(conn: &Connection, names: &[&str]) {
+        for name in names {
+            let path = format!("in/{name}.jpg");
+            let quilt = format!("out/{name}.png");
+            mark_processed(conn, &path, "", name, &quilt, "success", None).unwrap();
+            add_to_playlist(conn, &path, "").unwrap();
+        }
+    }
+
+    fn playlist(conn: &Connection) -> Vec<String> {
+        conn.prepare("SELECT path FROM playlist WHERE device = '' ORDER BY position")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<SqlResult<_>>()
+            .unwrap()
+    }
+
+    fn m3u(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}_{}.m3u", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn m3u_order_is_taken_back() {
+        let conn = database();
+        playlist_of(&conn, &["a", "b", "c"]);
+        // Entries are matched by file name wherever the device keeps them
+        let contents = "#EXTM3U\n/media/quilts/c.png\n# a comment\nunknown.png\na.png\nb.png\n";
+        let path = m3u("reordered", contents);
+        let lines = import_m3u_playlist(&conn, &path, "").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(lines, contents.lines().collect::<Vec<_>>());
+        assert_eq!(playlist(&conn), ["in/c.jpg", "in/a.jpg", "in/b.jpg"]);
+    }
+
+    #[test]
+    fn m3u_removals_are_taken_back_but_not_newer_quilts() {
+        let conn = database();
+        playlist_of(&conn, &["a", "b", "c"]);
+        conn.execute(
+            "UPDATE processed_files SET timestamp = CASE path WHEN 'in/c.jpg'
+                THEN '2100-01-01 00:00:00' ELSE '2000-01-01 00:00:00' END",
+            [],
+        )
+        .unwrap();
+        // Written after a and b were recorded but before c, and with b removed by hand
+        let path = m3u("removed", "#EXTM3U\nout/a.png\n");
+        let lines = import_m3u_playlist(&conn, &path, "").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(lines, ["#EXTM3U", "out/a.png"]);
+        assert_eq!(playlist(&conn), ["in/a.jpg", "in/c.jpg"]);
+    }
+
+    #[test]
+    fn missing_m3u_changes_nothing() {
+        let conn = database();
+        playlist_of(&conn, &["a", "b"]);
+        let path = std::env::temp_dir().join("missing_playlist.m3u");
+        assert!(import_m3u_playlist(&conn, &path, "").unwrap().is_empty());
+        assert_eq!(playlist(&conn), ["in/a.jpg", "in/b.jpg"]);
+    }
+}
@@ -0,0 +1,49 @@
+use clap::Parser;
+use clap_complete::Shell;
+use quilt_painter::cli;
+use quilt_painter::depth_gen::DepthArgs;
+use quilt_painter::depthd::{serve, DepthDaemon, DEFAULT_DEPTHD_LISTEN};
+use quilt_painter::logging::{self, LogFormat};
+use std::net::TcpListener;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Serve depth maps over HTTP from a warm ComfyUI connection", long_about = None)]
+struct Args {
+    #[arg(long, default_value = DEFAULT_DEPTHD_LISTEN, help = "Address to listen on")]
+    listen: String,
+
+    #[command(flatten)]
+    depth: DepthArgs,
+
+    #[arg(
+        long,
+        default_value = "text",
+        value_enum,
+        help = "Log output: text, or json for one object per line with stage timings (level set by RUST_LOG)"
+    )]
+    log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "SHELL",
+        exclusive = true,
+        help = "Print a completion script for bash, elvish, fish, powershell or zsh and exit"
+    )]
+    completions: Option<Shell>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Args = cli::parse();
+    logging::init(args.log_format);
+
+    let daemon = DepthDaemon::new(args.depth.into())?;
+    daemon.connect()?;
+
+    let listener = TcpListener::bind(&args.listen)?;
+    println!(
+        "Serving depth maps at http://{}/depth",
+        listener.local_addr()?
+    );
+    serve(listener, daemon)?;
+    Ok(())
+}
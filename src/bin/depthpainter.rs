@@ -1,8 +1,26 @@
 use clap::Parser;
+use clap_complete::Shell;
+use quilt_painter::camera::ViewSpacing;
 use quilt_painter::captions::CaptionConfig;
-use quilt_painter::depth_gen::{generate_depth, DepthConfig};
-use quilt_painter::quilt_gen::{generate_quilt, QuiltConfig};
+use quilt_painter::cli;
+use quilt_painter::depth_gen::{generate_depth, DepthArgs, DepthConfig, DepthEdit, DepthOutputs};
+use quilt_painter::dither::Dither;
+use quilt_painter::inpaint::{inpaint_back_layer, InpaintConfig, DEFAULT_INPAINT_PROMPT};
+use quilt_painter::interrupt;
+use quilt_painter::logging::{self, LogFormat};
+use quilt_painter::output::{self, QuiltResult, Timings};
+use quilt_painter::panorama::Projection;
+use quilt_painter::postfx::{self, Grain, PostFx, Sharpen};
+use quilt_painter::preview::Preview;
+use quilt_painter::quilt::{
+    render_defaults, Easing, HoleFill, QuiltName, RenderDefaults, Sampling,
+};
+use quilt_painter::quilt_gen::{generate_quilt, FocusDepth, LinkMode, QuiltConfig, ZoomCenter};
+use quilt_painter::undistort::LensDistortion;
+use quilt_painter::watermark::WatermarkConfig;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -13,10 +31,76 @@ struct Args {
     #[arg(index = 2)]
     output: String,
 
-    #[arg(long, default_value = "http://127.0.0.1:8188")]
-    comfy_url: String,
+    #[command(flatten)]
+    depth: DepthArgs,
 
-    #[arg(short, long, conflicts_with_all=["columns", "rows", "width", "height"])]
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Get depth maps from a depthd daemon, e.g. http://127.0.0.1:8190, instead of running the workflow on ComfyUI directly"
+    )]
+    depthd: Option<String>,
+
+    #[arg(
+        long,
+        help = "Also save the combined RGBD image as <stem>_rgbd.png in this directory"
+    )]
+    save_rgbd: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Also save the depth map as <stem>_depth.png in this directory"
+    )]
+    save_depth: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Write each depth map to a temporary PNG and wait for Enter before rendering, so it can be fixed by hand"
+    )]
+    pause_for_edit: bool,
+
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        help = "Run this command on each depth map before rendering and read it back when it exits; {} is replaced by the PNG's path, which is otherwise appended"
+    )]
+    depth_edit_cmd: Option<String>,
+
+    #[arg(
+        long,
+        help = "Inpaint what is behind the nearest subject with a second ComfyUI workflow and render it as a back layer, so wide viewcones reveal background instead of stretched edges"
+    )]
+    inpaint_behind: bool,
+
+    #[arg(long, default_value = DEFAULT_INPAINT_PROMPT, help = "Prompt for --inpaint-behind")]
+    inpaint_prompt: String,
+
+    #[arg(
+        long,
+        help = "Inpainting checkpoint for --inpaint-behind, e.g. 512-inpainting-ema.safetensors"
+    )]
+    inpaint_checkpoint: Option<String>,
+
+    #[arg(
+        long,
+        help = "ComfyUI API-format workflow for --inpaint-behind; its LoadImage gets the image with the subject transparent"
+    )]
+    inpaint_workflow: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DEPTH",
+        help = "Depth 0-255 above which pixels are the subject for --inpaint-behind [default: picked from the depth histogram]"
+    )]
+    subject_depth: Option<u8>,
+
+    #[arg(
+        long,
+        help = "Use <stem><suffix>.png next to the input as its depth map instead of ComfyUI [default: tries _depth and .depth]"
+    )]
+    depth_suffix: Option<String>,
+
+    #[arg(short, long, value_parser = cli::parse_device, conflicts_with_all=["columns", "rows", "width", "height"])]
     device: Option<String>,
 
     #[arg(long, help = "The number of columns of tiles in the output quilt.")]
@@ -41,64 +125,466 @@ struct Args {
     #[arg(
         long,
         default_value = "black",
-        help = "black, sky, debug or an rgb triplet"
+        value_parser = cli::parse_background,
+        help = "black, sky, debug, an rgb triplet, or gradient:TOP,BOTTOM or radial:CENTER,EDGE with two of those drawn behind the scene in each view"
     )]
     bg: String,
 
-    #[arg(long, default_value = "60", help = "field of view in degrees")]
+    #[arg(
+        long,
+        value_name = "COLOR",
+        value_parser = cli::parse_color,
+        help = "Color of the letterbox bars where the texture doesn't cover the view, if not --bg: black, sky, debug or an rgb triplet"
+    )]
+    matte: Option<String>,
+
+    #[arg(long, default_value = "60", value_parser = cli::parse_fov, help = "field of view in degrees (0-120)")]
     fov: f32,
 
-    #[arg(long, default_value = "1.05", help = "zoom towards center of image")]
-    zoom: f32,
+    #[arg(
+        long,
+        default_value = "flat",
+        value_enum,
+        help = "How the input maps onto the scene: flat, or equirectangular for a 360x180 panorama seen from inside"
+    )]
+    projection: Projection,
 
-    #[arg(long, default_value = "1.0", help = "enhance height")]
-    scale: f32,
+    #[arg(
+        long,
+        default_value = "0",
+        allow_hyphen_values = true,
+        help = "Equirectangular only: heading of the view in degrees, positive to the right"
+    )]
+    pano_yaw: f32,
 
     #[arg(
         long,
-        default_value = "2.5",
-        help = "resize multiplier relative to tile size. Currently affects rendered height."
+        default_value = "0",
+        allow_hyphen_values = true,
+        help = "Equirectangular only: elevation of the view in degrees above the horizon"
     )]
-    resize: f32,
+    pano_pitch: f32,
+
+    #[arg(
+        long,
+        default_value = "90",
+        help = "Equirectangular only: horizontal field of view of each view in degrees"
+    )]
+    pano_fov: f32,
+
+    #[arg(
+        long,
+        default_value = "linear",
+        value_name = "SPACING",
+        help = "Spread of the views across the field of view: linear, cosine (closer together towards the edges), or curve:P0,P1,... with positions rising from 0 to 1"
+    )]
+    view_spacing: ViewSpacing,
+
+    #[arg(
+        long,
+        help = "Mirror the input (texture and depth together) left to right"
+    )]
+    flip_h: bool,
+
+    #[arg(
+        long,
+        help = "Mirror the input (texture and depth together) top to bottom"
+    )]
+    flip_v: bool,
+
+    #[arg(
+        long,
+        value_name = "PROFILE|K1[,K2[,K3[,P1,P2]]]",
+        allow_hyphen_values = true,
+        help = "Take lens distortion out of the texture and depth before rendering: a profile (wide, action, fisheye) or Brown-Conrady coefficients over half the shorter side"
+    )]
+    undistort: Option<LensDistortion>,
+
+    #[arg(
+        long,
+        help = "Reverse the order of the views in the quilt, for displays or sources with mirrored geometry"
+    )]
+    mirror_views: bool,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Pixels of border around each view; views are rendered that much smaller to fit their tiles"
+    )]
+    tile_padding: u32,
+
+    #[arg(
+        long,
+        default_value = "black",
+        value_parser = cli::parse_color,
+        help = "Color of the border around each view: black, sky, debug or an rgb triplet"
+    )]
+    border_color: String,
+
+    #[arg(
+        long,
+        value_parser = cli::parse_zoom,
+        help = "zoom towards center of image [default: the device's, or 1.05]"
+    )]
+    zoom: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "X,Y|auto",
+        help = "Zoom towards this point, as fractions of the image width and height, or auto to find the subject [default: 0.5,0.5]"
+    )]
+    zoom_center: Option<ZoomCenter>,
+
+    #[arg(long, help = "enhance height [default: the device's, or 1.0]")]
+    scale: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "DEPTH|auto",
+        help = "Depth (0-255) that stays on the display plane, or auto for the subject's median depth [default: 0, the farthest]"
+    )]
+    focus_depth: Option<FocusDepth>,
+
+    #[arg(
+        long,
+        help = "resize multiplier relative to tile size [default: the device's, or 2.5]. Currently affects rendered height."
+    )]
+    resize: Option<f32>,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "1.0",
+        help = "Also save a tangent-space normal map of the depth, with optional slope strength"
+    )]
+    export_normals: Option<f32>,
+
+    #[arg(
+        long,
+        help = "Also save the four views as a Leia (Lume Pad) 2x2 lightfield image, <name>_leia.jpg, with a JSON descriptor"
+    )]
+    export_leia: bool,
+
+    #[arg(
+        long,
+        value_name = "WHERE",
+        help = "Also make a small flat preview from the center view: file writes <name>-preview.jpg, exif embeds it as the thumbnail of a JPEG quilt"
+    )]
+    preview: Option<Preview>,
+
+    #[arg(
+        long,
+        value_name = "PNG[:POSITION[:OPACITY[:SCALE]]]",
+        help = "Composite a logo onto each view; SCALE is its width as a fraction of the view [default: bottom-right:1.0:0.15]"
+    )]
+    watermark: Option<WatermarkConfig>,
+
+    #[arg(
+        long,
+        help = "Draw the watermark once on the finished quilt instead of on every view"
+    )]
+    watermark_once: bool,
+
+    #[arg(
+        long,
+        help = "Also write a JSON layout descriptor (tile size, order, view count, viewcone) next to the quilt"
+    )]
+    descriptor: bool,
+
+    #[arg(
+        long,
+        help = "Pick --scale and --zoom from the depth map's range instead of using their values"
+    )]
+    auto_tune: bool,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Auto-tune parallax target: how far near content moves against far content in the outermost views [default: 3% of the view width]"
+    )]
+    auto_tune_parallax: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Flatten depth beyond what moves a pixel this far in the outermost views, to avoid stretching"
+    )]
+    max_parallax: Option<f32>,
+
+    #[arg(
+        long,
+        default_value = "nearest",
+        value_enum,
+        help = "Sample placement: nearest (fast) or subpixel (smoother edges, less crawling between views)"
+    )]
+    sampling: Sampling,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..=3),
+        help = "Pixels each texture sample covers (1-3); larger values fill pinholes when zoomed out or strongly displaced"
+    )]
+    splat_radius: u32,
+
+    #[arg(
+        long,
+        default_value = "none",
+        value_enum,
+        help = "Dither colors blended across gaps and between samples to hide banding: none, ordered or blue-noise"
+    )]
+    dither: Dither,
+
+    #[arg(
+        long,
+        default_value = "luminance:0.3333",
+        value_name = "CURVE",
+        help = "Color blend across gaps: linear, smoothstep, hold (no blend), or luminance[:SHARPNESS] to linger on the darker side"
+    )]
+    easing: Easing,
+
+    #[arg(
+        long,
+        default_value = "blend",
+        value_enum,
+        help = "How gaps opened by depth are filled: blend across them, or repeat the background side"
+    )]
+    hole_fill: HoleFill,
+
+    #[arg(
+        long,
+        help = "Histogram-match every view to the middle one after rendering, so color shifts from hole filling don't flicker between views"
+    )]
+    stabilize_views: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        value_parser = postfx::parse_look,
+        help = "Named bundle of color, sharpening, grain and vignette settings: natural, punchy, bw, vintage, or one from the user's looks.json; the effect options below override its parts"
+    )]
+    look: Option<PostFx>,
+
+    #[arg(
+        long,
+        value_name = "AMOUNT[:RADIUS]",
+        help = "Unsharp-mask every view after rendering, e.g. 0.5; a RADIUS of 20 or more boosts local contrast (clarity) instead [default radius: 1]"
+    )]
+    sharpen: Option<Sharpen>,
+
+    #[arg(
+        long,
+        value_name = "LEVELS",
+        help = "Add film grain of this standard deviation in 8-bit levels, e.g. 3, to hide banding in smooth gradients"
+    )]
+    grain: Option<f32>,
+
+    #[arg(
+        long,
+        requires = "grain",
+        help = "Use a different grain pattern in each view, so it fills the scene's depth instead of sitting on the display surface"
+    )]
+    grain_per_view: bool,
+
+    #[arg(
+        long,
+        value_name = "STRENGTH",
+        help = "Darken the corners of each view, from 0 (none) to 1 (black corners)"
+    )]
+    vignette: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Fade the outer PX pixels of each view to the background, hiding stretched edges in the outermost views"
+    )]
+    edge_fade: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "W/H",
+        help = "Display pixel width over height, for displays with non-square pixels [default: the device's, 1.0]"
+    )]
+    pixel_aspect: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "ROWS",
+        help = "Render and write the quilt in bands of this many view rows to bound memory on very large outputs (PNG and JPEG only)"
+    )]
+    band_height: Option<u32>,
 
     #[arg(short = 'L', long = "link-output", alias = "link_output")]
     symlink_output: bool,
+
+    #[arg(
+        long,
+        default_value = "symlink",
+        value_enum,
+        help = "How --link-output refers to the generated file: symlink, hardlink, or copy for filesystems without links"
+    )]
+    link_mode: LinkMode,
+
+    #[arg(
+        long,
+        help = "Print the result (output path, dimensions, layout, timings, warnings) as one JSON object on stdout, with progress messages on stderr"
+    )]
+    json: bool,
+
+    #[arg(
+        long,
+        default_value = "text",
+        value_enum,
+        help = "Log output: text, or json for one object per line with stage timings (level set by RUST_LOG)"
+    )]
+    log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "SHELL",
+        exclusive = true,
+        help = "Print a completion script for bash, elvish, fish, powershell or zsh and exit"
+    )]
+    completions: Option<Shell>,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-    let args = Args::parse();
+    let args: Args = cli::parse();
+    logging::init(args.log_format);
+    output::set_json(args.json);
+    let started = Instant::now();
+    interrupt::install();
+    #[cfg(feature = "hid")]
+    let args = {
+        let mut args = args;
+        if args.device.is_none() && args.columns.is_none() {
+            quilt_painter::hid::use_detected_display(&mut args.device, &mut args.fov);
+        }
+        args
+    };
+
+    let depth_config = DepthConfig {
+        depth_suffix: args.depth_suffix,
+        depthd: args.depthd,
+        ..args.depth.into()
+    };
 
     // Generate depth map first
-    let (texture, depth) = generate_depth(
-        args.input.clone(),
-        &DepthConfig {
-            comfy_url: args.comfy_url,
-            cache_dir: None,
-        },
-    )?;
+    let (texture, depth) = generate_depth(args.input.clone(), &depth_config)?;
+    let depth_seconds = started.elapsed().as_secs_f64();
+
+    let depth = DepthEdit {
+        command: args.depth_edit_cmd,
+        pause: args.pause_for_edit,
+    }
+    .apply(&args.input, depth)?;
+
+    DepthOutputs {
+        rgbd_dir: args.save_rgbd,
+        depth_dir: args.save_depth,
+    }
+    .save(&args.input, &texture, &depth)?;
+
+    let inpaint = args.inpaint_behind.then(|| InpaintConfig {
+        prompt: args.inpaint_prompt,
+        checkpoint: args.inpaint_checkpoint,
+        workflow: args.inpaint_workflow,
+        subject_depth: args.subject_depth,
+        ..InpaintConfig::default()
+    });
+    let back_layer = inpaint
+        .map(|inpaint| inpaint_back_layer(&args.input, &texture, &depth, &inpaint, &depth_config))
+        .transpose()?
+        .map(Arc::new);
 
     // Then generate quilt
-    generate_quilt(
-        texture,
-        depth,
-        args.output,
-        &QuiltConfig {
-            device: args.device,
-            columns: args.columns,
-            rows: args.rows,
-            width: args.width,
-            height: args.height,
-            debug_mode: args.debug_mode,
-            bg: args.bg,
-            fov: args.fov,
-            zoom: args.zoom,
-            scale: args.scale,
-            resize: args.resize,
-            symlink_output: args.symlink_output,
-            caption: CaptionConfig::default(),
-        },
-    )?;
+    let aspect = texture.width() as f32 / texture.height() as f32;
+    let render_started = Instant::now();
+    let render = RenderDefaults {
+        zoom: args.zoom,
+        scale: args.scale,
+        resize: args.resize,
+    }
+    .or(render_defaults(args.device.as_deref()));
+    let quilt_config = QuiltConfig {
+        device: args.device,
+        columns: args.columns,
+        rows: args.rows,
+        width: args.width,
+        height: args.height,
+        debug_mode: args.debug_mode,
+        bg: args.bg,
+        matte: args.matte,
+        fov: args.fov,
+        view_spacing: args.view_spacing.clone(),
+        flip_h: args.flip_h,
+        flip_v: args.flip_v,
+        mirror_views: args.mirror_views,
+        tile_padding: args.tile_padding,
+        border_color: args.border_color,
+        zoom: render.zoom.unwrap_or(1.05),
+        scale: render.scale.unwrap_or(1.0),
+        resize: render.resize.unwrap_or(2.5),
+        symlink_output: args.symlink_output,
+        link_mode: args.link_mode,
+        caption: CaptionConfig::default(),
+        export_normals: args.export_normals,
+        export_leia: args.export_leia,
+        watermark: args.watermark.map(|watermark| WatermarkConfig {
+            once: args.watermark_once,
+            ..watermark
+        }),
+        write_descriptor: args.descriptor,
+        preview: args.preview,
+        auto_tune: args.auto_tune,
+        auto_tune_parallax: args.auto_tune_parallax,
+        max_parallax: args.max_parallax,
+        sampling: args.sampling,
+        splat_radius: args.splat_radius,
+        pixel_aspect: args.pixel_aspect,
+        band_height: args.band_height,
+        render_cache_dir: None,
+        zoom_center: args.zoom_center,
+        focus_depth: args.focus_depth,
+        dither: args.dither,
+        easing: args.easing,
+        hole_fill: args.hole_fill,
+        stabilize_views: args.stabilize_views,
+        postfx: PostFx {
+            color: None,
+            sharpen: args.sharpen,
+            grain: args.grain.map(|amount| Grain {
+                amount,
+                per_view: args.grain_per_view,
+            }),
+            vignette: args.vignette,
+            edge_fade: args.edge_fade,
+        }
+        .or(args.look.unwrap_or_default()),
+        back_layer,
+        panorama: args
+            .projection
+            .panorama(args.pano_yaw, args.pano_pitch, args.pano_fov),
+        undistort: args.undistort,
+    };
+    let filename = generate_quilt(texture, depth, args.output, &quilt_config)?;
 
+    if args.json {
+        // The aspect in the file name, measured after resizing
+        let aspect = QuiltName::parse(&filename)
+            .and_then(|name| name.aspect)
+            .unwrap_or(aspect);
+        QuiltResult::new(
+            filename,
+            &quilt_config.quilt_settings(),
+            aspect,
+            Timings {
+                depth: Some(depth_seconds),
+                render: render_started.elapsed().as_secs_f64(),
+                total: started.elapsed().as_secs_f64(),
+            },
+        )
+        .print()?;
+    }
     Ok(())
 }
@@ -1,11 +1,40 @@
 use clap::Parser;
+use clap_complete::Shell;
 use image::{ImageBuffer, Rgb};
-use quilt_painter::captions::CaptionConfig;
-#[cfg(feature = "captions")]
-use quilt_painter::captions::Position;
+use quilt_painter::autotune::auto_tune;
+use quilt_painter::background::{parse_color, Background};
+use quilt_painter::camera::{ViewSpacing, IMAGE_CENTER};
+use quilt_painter::captions::{
+    parse_rgba, CaptionConfig, CaptionSpec, CaptionTarget, Length, Position, TextAlign,
+};
+use quilt_painter::cli;
 use quilt_painter::debug::{CliDebugFlags, DebugFlags, NullDebugFlags};
+use quilt_painter::descriptor::QuiltDescriptor;
+use quilt_painter::diff::{check_exposure, ExposureStep};
+use quilt_painter::dither::Dither;
+use quilt_painter::encode::save_image;
 use quilt_painter::image_types::{DepthImage, RgbdImage, TextureImage};
-use quilt_painter::quilt::{get_quilt_settings, make_quilt, QuiltSettings};
+use quilt_painter::ktx::save_ktx2;
+use quilt_painter::leia::save_leia;
+use quilt_painter::logging::{self, LogFormat};
+use quilt_painter::mesh::{export_scene, import_scene};
+use quilt_painter::metadata::MetadataField;
+use quilt_painter::output::{self, QuiltResult, Timings};
+use quilt_painter::panorama::Projection;
+use quilt_painter::postfx::{self, Grain, PostFx, Sharpen};
+use quilt_painter::preview::{embed_exif_thumbnail, prepare_preview, Preview};
+use quilt_painter::quilt::{
+    get_quilt_settings, render_defaults, render_quilt, Easing, HoleFill, QuiltName, QuiltSettings,
+    RenderDefaults, RenderOptions, Sampling,
+};
+use quilt_painter::quilt_gen::{
+    export_normals, flip_input, link_output, FocusDepth, LinkMode, ZoomCenter,
+};
+use quilt_painter::status;
+use quilt_painter::tiled::{save_quilt_tiled, TiledOptions};
+use quilt_painter::undistort::LensDistortion;
+use quilt_painter::watermark::WatermarkConfig;
+use std::time::Instant;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -19,7 +48,15 @@ struct Args {
     #[arg(short = 'L', long = "link-output", alias = "link_output")]
     symlink_output_base_name_to_generated_name: bool,
 
-    #[arg(short, long, conflicts_with_all=["columns", "rows", "width", "height"])]
+    #[arg(
+        long,
+        default_value = "symlink",
+        value_enum,
+        help = "How --link-output refers to the generated file: symlink, hardlink, or copy for filesystems without links"
+    )]
+    link_mode: LinkMode,
+
+    #[arg(short, long, value_parser = cli::parse_device, conflicts_with_all=["columns", "rows", "width", "height"])]
     device: Option<String>,
 
     #[arg(long, help = "The number of columns of tiles in the output quilt.")]
@@ -49,89 +86,477 @@ struct Args {
     #[arg(
         long,
         default_value = "black",
-        help = "black, sky, debug or an rgb triplet"
+        value_parser = cli::parse_background,
+        help = "black, sky, debug, an rgb triplet, or gradient:TOP,BOTTOM or radial:CENTER,EDGE with two of those drawn behind the scene in each view"
     )]
     bg: String,
 
-    #[arg(long, default_value = "60", help = "field of view in degrees")]
+    #[arg(
+        long,
+        value_name = "COLOR",
+        value_parser = cli::parse_color,
+        help = "Color of the letterbox bars where the texture doesn't cover the view, if not --bg: black, sky, debug or an rgb triplet"
+    )]
+    matte: Option<String>,
+
+    #[arg(long, default_value = "60", value_parser = cli::parse_fov, help = "field of view in degrees (0-120)")]
     fov: f32,
 
-    #[arg(long, default_value = "1.0", help = "zoom towards center of image")]
-    zoom: f32,
+    #[arg(
+        long,
+        default_value = "flat",
+        value_enum,
+        help = "How the input maps onto the scene: flat, or equirectangular for a 360x180 panorama seen from inside"
+    )]
+    projection: Projection,
+
+    #[arg(
+        long,
+        default_value = "0",
+        allow_hyphen_values = true,
+        help = "Equirectangular only: heading of the view in degrees, positive to the right"
+    )]
+    pano_yaw: f32,
+
+    #[arg(
+        long,
+        default_value = "0",
+        allow_hyphen_values = true,
+        help = "Equirectangular only: elevation of the view in degrees above the horizon"
+    )]
+    pano_pitch: f32,
+
+    #[arg(
+        long,
+        default_value = "90",
+        help = "Equirectangular only: horizontal field of view of each view in degrees"
+    )]
+    pano_fov: f32,
+
+    #[arg(
+        long,
+        default_value = "linear",
+        value_name = "SPACING",
+        help = "Spread of the views across the field of view: linear, cosine (closer together towards the edges), or curve:P0,P1,... with positions rising from 0 to 1"
+    )]
+    view_spacing: ViewSpacing,
+
+    #[arg(
+        long,
+        help = "Mirror the input (texture and depth together) left to right"
+    )]
+    flip_h: bool,
+
+    #[arg(
+        long,
+        help = "Mirror the input (texture and depth together) top to bottom"
+    )]
+    flip_v: bool,
+
+    #[arg(
+        long,
+        value_name = "PROFILE|K1[,K2[,K3[,P1,P2]]]",
+        allow_hyphen_values = true,
+        help = "Take lens distortion out of the texture and depth before rendering: a profile (wide, action, fisheye) or Brown-Conrady coefficients over half the shorter side"
+    )]
+    undistort: Option<LensDistortion>,
+
+    #[arg(
+        long,
+        help = "Reverse the order of the views in the quilt, for displays or sources with mirrored geometry"
+    )]
+    mirror_views: bool,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Pixels of border around each view; views are rendered that much smaller to fit their tiles"
+    )]
+    tile_padding: u32,
+
+    #[arg(
+        long,
+        default_value = "black",
+        value_parser = cli::parse_color,
+        help = "Color of the border around each view: black, sky, debug or an rgb triplet"
+    )]
+    border_color: String,
+
+    #[arg(
+        long,
+        value_parser = cli::parse_zoom,
+        help = "zoom towards center of image [default: the device's, or 1.0]"
+    )]
+    zoom: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "X,Y|auto",
+        help = "Zoom towards this point, as fractions of the image width and height, or auto to find the subject [default: 0.5,0.5]"
+    )]
+    zoom_center: Option<ZoomCenter>,
+
+    #[arg(long, help = "enhance height [default: the device's, or 1.0]")]
+    scale: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "DEPTH|auto",
+        help = "Depth (0-255) that stays on the display plane, or auto for the subject's median depth [default: 0, the farthest]"
+    )]
+    focus_depth: Option<FocusDepth>,
+
+    #[arg(
+        long,
+        help = "resize multiplier relative to tile size [default: the device's, or 2.0]"
+    )]
+    resize: Option<f32>,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "1.0",
+        help = "Also save a tangent-space normal map of the depth, with optional slope strength"
+    )]
+    export_normals: Option<f32>,
+
+    #[arg(
+        long,
+        help = "Also save the four views as a Leia (Lume Pad) 2x2 lightfield image, <name>_leia.jpg, with a JSON descriptor"
+    )]
+    export_leia: bool,
+
+    #[arg(
+        long,
+        value_name = "WHERE",
+        help = "Also make a small flat preview from the center view: file writes <name>-preview.jpg, exif embeds it as the thumbnail of a JPEG quilt"
+    )]
+    preview: Option<Preview>,
+
+    #[arg(
+        long,
+        help = "Also export the scene as a colored point cloud (.ply) or textured mesh (.obj)"
+    )]
+    export_mesh: Option<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PNG[:POSITION[:OPACITY[:SCALE]]]",
+        help = "Composite a logo onto each view; SCALE is its width as a fraction of the view [default: bottom-right:1.0:0.15]"
+    )]
+    watermark: Option<WatermarkConfig>,
+
+    #[arg(
+        long,
+        help = "Draw the watermark once on the finished quilt instead of on every view"
+    )]
+    watermark_once: bool,
+
+    #[arg(
+        long,
+        help = "Also write a JSON layout descriptor (tile size, order, view count, viewcone) next to the quilt"
+    )]
+    descriptor: bool,
+
+    #[arg(
+        long,
+        help = "Compare the luminance of adjacent views after rendering and warn where they diverge, a sign of renderer or hole-fill bugs"
+    )]
+    check_exposure: bool,
+
+    #[arg(
+        long,
+        help = "Pick --scale and --zoom from the depth map's range instead of using their values"
+    )]
+    auto_tune: bool,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Auto-tune parallax target: how far near content moves against far content in the outermost views [default: 3% of the view width]"
+    )]
+    auto_tune_parallax: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Flatten depth beyond what moves a pixel this far in the outermost views, to avoid stretching"
+    )]
+    max_parallax: Option<f32>,
+
+    #[arg(
+        long,
+        default_value = "nearest",
+        value_enum,
+        help = "Sample placement: nearest (fast) or subpixel (smoother edges, less crawling between views)"
+    )]
+    sampling: Sampling,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..=3),
+        help = "Pixels each texture sample covers (1-3); larger values fill pinholes when zoomed out or strongly displaced"
+    )]
+    splat_radius: u32,
+
+    #[arg(
+        long,
+        default_value = "none",
+        value_enum,
+        help = "Dither colors blended across gaps and between samples to hide banding: none, ordered or blue-noise"
+    )]
+    dither: Dither,
 
-    #[arg(long, default_value = "1.0", help = "enhance height")]
-    scale: f32,
+    #[arg(
+        long,
+        default_value = "luminance:0.3333",
+        value_name = "CURVE",
+        help = "Color blend across gaps: linear, smoothstep, hold (no blend), or luminance[:SHARPNESS] to linger on the darker side"
+    )]
+    easing: Easing,
+
+    #[arg(
+        long,
+        default_value = "blend",
+        value_enum,
+        help = "How gaps opened by depth are filled: blend across them, or repeat the background side"
+    )]
+    hole_fill: HoleFill,
 
     #[arg(
         long,
-        default_value = "2.0",
-        help = "resize multiplier relative to tile size"
+        help = "Histogram-match every view to the middle one after rendering, so color shifts from hole filling don't flicker between views"
     )]
-    resize: f32,
+    stabilize_views: bool,
 
-    #[cfg(feature = "captions")]
-    #[arg(long, help = "Optional caption text to render on the image")]
-    caption: Option<String>,
+    #[arg(
+        long,
+        value_name = "NAME",
+        value_parser = postfx::parse_look,
+        help = "Named bundle of color, sharpening, grain and vignette settings: natural, punchy, bw, vintage, or one from the user's looks.json; the effect options below override its parts"
+    )]
+    look: Option<PostFx>,
 
-    #[cfg(feature = "captions")]
-    #[arg(long, default_value = "16", help = "Font size for caption in pixels")]
-    caption_size: u32,
+    #[arg(
+        long,
+        value_name = "AMOUNT[:RADIUS]",
+        help = "Unsharp-mask every view after rendering, e.g. 0.5; a RADIUS of 20 or more boosts local contrast (clarity) instead [default radius: 1]"
+    )]
+    sharpen: Option<Sharpen>,
+
+    #[arg(
+        long,
+        value_name = "LEVELS",
+        help = "Add film grain of this standard deviation in 8-bit levels, e.g. 3, to hide banding in smooth gradients"
+    )]
+    grain: Option<f32>,
+
+    #[arg(
+        long,
+        requires = "grain",
+        help = "Use a different grain pattern in each view, so it fills the scene's depth instead of sitting on the display surface"
+    )]
+    grain_per_view: bool,
+
+    #[arg(
+        long,
+        value_name = "STRENGTH",
+        help = "Darken the corners of each view, from 0 (none) to 1 (black corners)"
+    )]
+    vignette: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Fade the outer PX pixels of each view to the background, hiding stretched edges in the outermost views"
+    )]
+    edge_fade: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "W/H",
+        help = "Display pixel width over height, for displays with non-square pixels [default: the device's, 1.0]"
+    )]
+    pixel_aspect: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "ROWS",
+        help = "Render and write the quilt in bands of this many view rows to bound memory on very large outputs (PNG and JPEG only)"
+    )]
+    band_height: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "TEXT[@POSITION[:SIZE[:COLOR[:DEPTH]]]]",
+        help = "Caption text to render on the image. Repeatable; each can set its own position, size, color and depth (pixels of shift between the outermost views, negative to float in front), e.g. --caption 'Lisbon@top-center' --caption '2024-06-01@bottom-right:3%:#ffd700'"
+    )]
+    caption: Vec<CaptionSpec>,
+
+    #[arg(
+        long,
+        default_value = "16",
+        help = "Caption font size in pixels, or a percentage of the view height such as 4%"
+    )]
+    caption_size: Length,
+
+    #[arg(
+        long,
+        default_value = "10",
+        help = "Space between the caption and the view edges, in pixels or a percentage of the view height"
+    )]
+    caption_margin: Length,
 
-    #[cfg(feature = "captions")]
     #[arg(
         long,
         default_value = "bottom-center",
         value_enum,
-        help = "Caption position (top-left, top-center, top-right, bottom-left, bottom-center)"
+        help = "Caption position (top-left, top-center, top-right, bottom-left, bottom-center, bottom-right, center)"
     )]
     caption_position: Position,
 
-    #[cfg(not(feature = "captions"))]
-    caption: (),
-    #[cfg(not(feature = "captions"))]
-    caption_size: (),
-    #[cfg(not(feature = "captions"))]
-    caption_position: (),
+    #[arg(
+        long,
+        help = "Wrap captions to this width in pixels [default: view width minus margins]"
+    )]
+    caption_max_width: Option<u32>,
+
+    #[arg(
+        long,
+        default_value = "1.0",
+        help = "Caption line spacing as a multiple of the font's line height"
+    )]
+    caption_line_spacing: f32,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Alignment of wrapped caption lines (left, center, right) [default: follows position]"
+    )]
+    caption_align: Option<TextAlign>,
+
+    #[arg(
+        long,
+        value_name = "COLOR",
+        value_parser = parse_rgba,
+        help = "Box behind the caption as R,G,B[,A] or #RRGGBB[AA], e.g. 0,0,0,128"
+    )]
+    caption_bg: Option<[u8; 4]>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Black outline width around caption glyphs in pixels"
+    )]
+    caption_outline: u32,
+
+    #[arg(long, help = "Draw a drop shadow under the caption")]
+    caption_shadow: bool,
+
+    #[arg(
+        long = "caption-font",
+        value_name = "PATH",
+        help = "Fallback font for caption characters the bundled font lacks (e.g. Arabic, emoji). Repeatable."
+    )]
+    caption_fonts: Vec<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "view",
+        value_enum,
+        help = "Draw the caption on every view (view) or once on the stitched quilt (quilt)"
+    )]
+    caption_target: CaptionTarget,
+
+    #[arg(
+        long = "caption-from",
+        value_name = "KIND:NAME",
+        help = "Take the caption from image metadata, e.g. exif:DateTimeOriginal, iptc:Caption-Abstract or xmp:dc:title; --caption is the fallback. Repeatable, first found wins."
+    )]
+    caption_from: Vec<MetadataField>,
+
+    #[arg(
+        long,
+        help = "Print the result (output path, dimensions, layout, timings, warnings) as one JSON object on stdout, with progress messages on stderr"
+    )]
+    json: bool,
+
+    #[arg(
+        long,
+        default_value = "text",
+        value_enum,
+        help = "Log output: text, or json for one object per line with stage timings (level set by RUST_LOG)"
+    )]
+    log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "SHELL",
+        exclusive = true,
+        help = "Print a completion script for bash, elvish, fish, powershell or zsh and exit"
+    )]
+    completions: Option<Shell>,
 }
 
-fn parse_color(arg: &str) -> Option<Rgb<u8>> {
-    match arg {
-        "black" => Some(Rgb([0, 0, 0])),
-        "sky" => Some(Rgb([128, (0.7 * 255.0) as u8, 255])),
-        "debug" => Some(Rgb([255, 0, 255])),
-        rgb => {
-            if rgb.contains(',') {
-                // parse 0,0,0
-                let parts: Vec<u8> = rgb
-                    .split(',')
-                    .map(|s| s.trim().parse::<u8>().unwrap_or(0))
-                    .collect();
-                if parts.len() == 3 {
-                    Some(Rgb([parts[0], parts[1], parts[2]]))
-                } else {
-                    Some(Rgb([0, 0, 0]))
-                }
-            } else {
-                // parse hex #rrggbb or rrggbb
-                let s = rgb.trim_start_matches('#');
-
-                // Parse 6-digit hex code
-                if s.len() == 6 {
-                    let r = u8::from_str_radix(&s[0..2], 16).ok()?;
-                    let g = u8::from_str_radix(&s[2..4], 16).ok()?;
-                    let b = u8::from_str_radix(&s[4..6], 16).ok()?;
-                    Some(Rgb([r, g, b]))
-                } else {
-                    None
-                }
-            }
-        }
+/// Prints the `--check-exposure` report: the largest steps, then every pair of views that
+/// diverges.
+fn report_exposure(
+    quilt: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    settings: &QuiltSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let report = check_exposure(quilt, settings.columns, settings.rows)?;
+    let mean_step = |step: &ExposureStep| (step.mean_luminance[1] - step.mean_luminance[0]).abs();
+    if let Some(step) = report
+        .steps
+        .iter()
+        .max_by(|a, b| mean_step(a).total_cmp(&mean_step(b)))
+    {
+        status!(
+            "Largest mean luminance step: {:.1} (views {} and {})",
+            mean_step(step),
+            step.view,
+            step.view + 1
+        );
     }
+    if let Some(step) = report
+        .steps
+        .iter()
+        .max_by(|a, b| a.histogram_distance.total_cmp(&b.histogram_distance))
+    {
+        status!(
+            "Largest histogram distance: {:.3} (views {} and {})",
+            step.histogram_distance,
+            step.view,
+            step.view + 1
+        );
+    }
+    for step in report.divergent() {
+        output::warn(format!(
+            "views {} and {} diverge: mean luminance {:.1} vs {:.1}, histogram distance {:.3}",
+            step.view,
+            step.view + 1,
+            step.mean_luminance[0],
+            step.mean_luminance[1],
+            step.histogram_distance
+        ));
+    }
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-    let args = Args::parse();
+    let args: Args = cli::parse();
+    logging::init(args.log_format);
+    output::set_json(args.json);
+    let started = Instant::now();
+    #[cfg(feature = "hid")]
+    let args = {
+        let mut args = args;
+        if args.device.is_none() && args.columns.is_none() {
+            quilt_painter::hid::use_detected_display(&mut args.device, &mut args.fov);
+        }
+        args
+    };
     let custom_device: QuiltSettings;
 
     let quilt_settings = if let Some(device) = &args.device {
@@ -150,18 +575,51 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 args.height
                     .expect("Height must be specified for custom settings"),
             ),
+            pixel_aspect: 1.0,
         };
         &custom_device
     };
+    let quilt_settings = &QuiltSettings {
+        pixel_aspect: args.pixel_aspect.unwrap_or(quilt_settings.pixel_aspect),
+        ..*quilt_settings
+    };
+    let render = RenderDefaults {
+        zoom: args.zoom,
+        scale: args.scale,
+        resize: args.resize,
+    }
+    .or(render_defaults(args.device.as_deref()));
+    let resize = render.resize.unwrap_or(2.0);
 
-    let input_img = image::open(&args.input)?;
-    let (mut texture, mut heightmap) = RgbdImage(input_img.to_rgb8()).split();
+    let input_path = std::path::Path::new(&args.input);
+    let is_mesh = input_path.extension().is_some_and(|ext| {
+        let ext = ext.to_string_lossy().to_ascii_lowercase();
+        ext == "ply" || ext == "obj"
+    });
+    let (mut texture, mut heightmap) = if is_mesh {
+        let tile_width = quilt_settings.resolution.0 / quilt_settings.columns;
+        import_scene(input_path, (tile_width as f32 * resize) as u32)?
+    } else {
+        let input_img = image::open(&args.input)?;
+        RgbdImage(input_img.to_rgb8()).split()
+    };
+    if let Some(lens) = &args.undistort {
+        (texture, heightmap) = lens.undistort(texture, heightmap);
+    }
 
     // Calculate target dimensions based on tile size and resize multiplier
     let tile_width = quilt_settings.resolution.0 / quilt_settings.columns;
     let tile_height = quilt_settings.resolution.1 / quilt_settings.rows;
-    let target_width = (tile_width as f32 * args.resize) as u32;
-    let target_height = (tile_height as f32 * args.resize) as u32;
+    let panorama = args
+        .projection
+        .panorama(args.pano_yaw, args.pano_pitch, args.pano_fov);
+    let (target_width, target_height) = match &panorama {
+        Some(panorama) => panorama.target_size(tile_width, resize),
+        None => (
+            (tile_width as f32 * resize) as u32,
+            (tile_height as f32 * resize) as u32,
+        ),
+    };
 
     // Resize if input is larger than target, preserving aspect ratio
     if texture.width() > target_width || texture.height() > target_height {
@@ -191,32 +649,45 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             image::imageops::FilterType::Lanczos3,
         ));
     }
+    (texture, heightmap) = flip_input(texture, heightmap, args.flip_h, args.flip_v);
 
     // Report dimensions
-    println!(
+    status!(
         "Input image dimensions: {}x{}",
         texture.width() * 2,
         texture.height()
     );
-    println!(
+    status!(
         "Texture dimensions: {}x{}",
         texture.width(),
         texture.height()
     );
-    println!(
+    status!(
         "Heightmap dimensions: {}x{}",
         heightmap.width(),
         heightmap.height()
     );
-    println!("Target tile dimensions: {}x{}", tile_width, tile_height);
-    println!(
+    status!("Target tile dimensions: {}x{}", tile_width, tile_height);
+    status!(
         "Target resize dimensions: {}x{}",
-        target_width, target_height
+        target_width,
+        target_height
     );
 
-    let input_aspect_ratio = texture.width() as f32 / texture.height() as f32;
+    // A panorama fills each view, so it shows in the shape of the tiles on the display
+    let input_aspect_ratio = match panorama {
+        Some(_) => tile_width as f32 * quilt_settings.pixel_aspect / tile_height as f32,
+        None => texture.width() as f32 / texture.height() as f32,
+    };
 
-    let bg_color = parse_color(args.bg.as_str()).expect("valid --bg value");
+    let background: Background = args.bg.parse().expect("valid --bg value");
+    let matte = args
+        .matte
+        .as_deref()
+        .map(|matte| parse_color(matte).ok_or_else(|| format!("invalid matte color '{matte}'")))
+        .transpose()?;
+    let border_color = parse_color(&args.border_color)
+        .ok_or_else(|| format!("invalid border color '{}'", args.border_color))?;
 
     let debug_flags = if let Some(debug_str) = args.debug_mode.as_ref() {
         let mut flags = CliDebugFlags::default();
@@ -227,7 +698,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "texture" => flags.texture_mode = Some(value.to_string()),
                     "startpt" => flags.start_point_color = parse_color(value),
                     "endpt" => flags.end_point_color = parse_color(value),
-                    _ => eprintln!("Unknown debug flag: {}", flag),
+                    _ => output::warn(format!("Unknown debug flag: {}", flag)),
                 }
             }
         }
@@ -252,36 +723,89 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         _ => texture,
     };
 
-    let quilt_image = if args.debug_mode.is_some() {
-        make_quilt(
-            quilt_settings,
-            &texture_to_use,
+    let caption = CaptionConfig {
+        margin: args.caption_margin,
+        max_width: args.caption_max_width,
+        line_spacing: args.caption_line_spacing,
+        align: args.caption_align,
+        background: args.caption_bg,
+        outline: args.caption_outline,
+        shadow: args.caption_shadow,
+        fonts: args.caption_fonts,
+        target: args.caption_target,
+        from: args.caption_from,
+        ..CaptionConfig::new(None, args.caption_size, args.caption_position)
+    }
+    .with_specs(args.caption)
+    .with_metadata_from(std::path::Path::new(&args.input));
+
+    let watermark = args
+        .watermark
+        .map(|watermark| WatermarkConfig {
+            once: args.watermark_once,
+            ..watermark
+        })
+        .map(|watermark| watermark.load())
+        .transpose()?;
+
+    let focus_depth = args
+        .focus_depth
+        .map_or(0.0, |focus| focus.resolve(&texture_to_use, &heightmap));
+
+    let (zoom, scale) = if args.auto_tune {
+        let tuned = auto_tune(
             &heightmap,
             args.fov,
-            args.zoom,
-            args.scale,
-            bg_color,
-            #[cfg(feature = "captions")]
-            CaptionConfig::new(args.caption, args.caption_size, args.caption_position),
-            #[cfg(not(feature = "captions"))]
-            CaptionConfig::default(),
-            &debug_flags,
-        )
+            tile_width,
+            args.auto_tune_parallax,
+            focus_depth,
+        );
+        status!(
+            "Auto-tuned scale {:.3}, zoom {:.3}",
+            tuned.scale,
+            tuned.zoom
+        );
+        (tuned.zoom, tuned.scale)
     } else {
-        make_quilt(
-            quilt_settings,
-            &texture_to_use,
-            &heightmap,
-            args.fov,
-            args.zoom,
-            args.scale,
-            bg_color,
-            #[cfg(feature = "captions")]
-            CaptionConfig::new(args.caption, args.caption_size, args.caption_position),
-            #[cfg(not(feature = "captions"))]
-            CaptionConfig::default(),
-            &NullDebugFlags {},
-        )
+        (render.zoom.unwrap_or(1.0), render.scale.unwrap_or(1.0))
+    };
+
+    let zoom_center = args.zoom_center.map_or(IMAGE_CENTER, |center| {
+        center.resolve(&texture_to_use, &heightmap)
+    });
+
+    let options = RenderOptions {
+        fov_deg: args.fov,
+        view_spacing: args.view_spacing.clone(),
+        mirror_views: args.mirror_views,
+        zoom,
+        zoom_center,
+        scale,
+        focus_depth,
+        background,
+        matte,
+        max_parallax: args.max_parallax,
+        sampling: args.sampling,
+        splat_radius: args.splat_radius,
+        dither: args.dither,
+        easing: args.easing,
+        hole_fill: args.hole_fill,
+        stabilize_views: args.stabilize_views,
+        postfx: PostFx {
+            color: None,
+            sharpen: args.sharpen,
+            grain: args.grain.map(|amount| Grain {
+                amount,
+                per_view: args.grain_per_view,
+            }),
+            vignette: args.vignette,
+            edge_fade: args.edge_fade,
+        }
+        .or(args.look.unwrap_or_default()),
+        tile_padding: args.tile_padding,
+        border_color,
+        back_layer: None,
+        panorama,
     };
 
     // Extract extension from output_base_name or default to png
@@ -290,60 +814,175 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .and_then(|ext| ext.to_str())
         .unwrap_or("png");
 
-    let filename = format!(
-        "{}_qs{}x{}a{:.2}.{}",
+    let filename = QuiltName::new(quilt_settings, input_aspect_ratio).filename(
         args.output_base_name
             .trim_end_matches(&format!(".{}", extension)),
-        quilt_settings.columns,
-        quilt_settings.rows,
-        input_aspect_ratio,
-        extension
+        extension,
     );
 
-    if filename.ends_with(".jpg") || filename.ends_with(".jpeg") {
-        let mut comp = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
-        comp.set_size(quilt_image.width() as usize, quilt_image.height() as usize);
-        comp.set_quality(100.0);
-        let mut jpeg_data = Vec::new();
-        let mut comp = comp.start_compress(&mut jpeg_data)?;
-        comp.write_scanlines(quilt_image.as_raw())?;
-        drop(comp);
-        std::fs::write(&filename, jpeg_data)?;
+    let render_started = Instant::now();
+    if let Some(band_height) = args.band_height {
+        let path = std::path::Path::new(&filename);
+        let tiled = TiledOptions {
+            band_height,
+            caption,
+            watermark: watermark.as_ref(),
+        };
+        if args.debug_mode.is_some() {
+            save_quilt_tiled(
+                path,
+                quilt_settings,
+                &texture_to_use,
+                &heightmap,
+                &options,
+                &tiled,
+                &debug_flags,
+            )?;
+        } else {
+            save_quilt_tiled(
+                path,
+                quilt_settings,
+                &texture_to_use,
+                &heightmap,
+                &options,
+                &tiled,
+                &NullDebugFlags {},
+            )?;
+        }
+        if args.check_exposure {
+            report_exposure(&image::open(path)?.to_rgb8(), quilt_settings)?;
+        }
     } else {
-        quilt_image.save(&filename)?;
+        let quilt_image = if args.debug_mode.is_some() {
+            render_quilt(
+                quilt_settings,
+                &texture_to_use,
+                &heightmap,
+                &options,
+                caption,
+                watermark.as_ref(),
+                &debug_flags,
+            )
+        } else {
+            render_quilt(
+                quilt_settings,
+                &texture_to_use,
+                &heightmap,
+                &options,
+                caption,
+                watermark.as_ref(),
+                &NullDebugFlags {},
+            )
+        };
+
+        if filename.ends_with(".jpg") || filename.ends_with(".jpeg") {
+            let mut comp = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+            comp.set_size(quilt_image.width() as usize, quilt_image.height() as usize);
+            comp.set_quality(100.0);
+            let mut jpeg_data = Vec::new();
+            let mut comp = comp.start_compress(&mut jpeg_data)?;
+            comp.write_scanlines(quilt_image.as_raw())?;
+            comp.finish()?;
+            std::fs::write(&filename, jpeg_data)?;
+        } else if extension.eq_ignore_ascii_case("ktx2") {
+            save_ktx2(
+                std::path::Path::new(&filename),
+                &quilt_image,
+                quilt_settings.columns,
+                quilt_settings.rows,
+                input_aspect_ratio,
+            )?;
+        } else {
+            save_image(std::path::Path::new(&filename), &quilt_image)?;
+        }
+        if args.check_exposure {
+            report_exposure(&quilt_image, quilt_settings)?;
+        }
     }
-    println!("Saved quilt image as: {}", filename);
+    let render_seconds = render_started.elapsed().as_secs_f64();
+    status!("Saved quilt image as: {}", filename);
 
-    // Create symlink if requested
-    if args.symlink_output_base_name_to_generated_name {
-        let link_name = args.output_base_name;
-        // Remove existing symlink if it exists
-        if std::path::Path::new(&link_name).exists() {
-            std::fs::remove_file(&link_name).unwrap_or_else(|e| {
-                eprintln!("Warning: Failed to remove existing symlink: {}", e);
-            });
+    if let Some(preview) = args.preview {
+        let path = std::path::Path::new(&filename);
+        let thumbnail = prepare_preview(
+            preview,
+            path,
+            args.output_base_name
+                .trim_end_matches(&format!(".{}", extension)),
+            quilt_settings,
+            &texture_to_use,
+            &heightmap,
+            &options,
+        )?;
+        if let Some(thumbnail) = thumbnail {
+            embed_exif_thumbnail(path, &thumbnail)?;
         }
+    }
 
-        #[cfg(unix)]
-        std::os::unix::fs::symlink(&filename, &link_name).unwrap_or_else(|e| {
-            eprintln!("Warning: Failed to create symlink: {}", e);
-        });
+    if args.descriptor {
+        let descriptor_path = QuiltDescriptor::new(quilt_settings, input_aspect_ratio, args.fov)
+            .save_beside(std::path::Path::new(&filename))?;
+        status!("Saved quilt descriptor as: {}", descriptor_path.display());
+    }
 
-        #[cfg(windows)]
-        std::os::windows::fs::symlink_file(&filename, &link_name).unwrap_or_else(|e| {
-            eprintln!("Warning: Failed to create symlink: {}", e);
-        });
+    if let Some(strength) = args.export_normals {
+        export_normals(
+            &heightmap,
+            strength,
+            args.output_base_name
+                .trim_end_matches(&format!(".{}", extension)),
+        )?;
+    }
 
-        println!("Created symlink: {} -> {}", link_name, filename);
+    if args.export_leia {
+        let leia_filename = format!(
+            "{}_leia.jpg",
+            args.output_base_name
+                .trim_end_matches(&format!(".{}", extension))
+        );
+        save_leia(
+            std::path::Path::new(&leia_filename),
+            &texture_to_use,
+            &heightmap,
+            &options,
+            &NullDebugFlags {},
+        )?;
+        status!("Saved Leia 2x2 image as: {}", leia_filename);
     }
 
+    if let Some(mesh_path) = &args.export_mesh {
+        export_scene(mesh_path, &texture_to_use, &heightmap, scale)?;
+        status!("Exported scene as: {}", mesh_path.display());
+    }
+
+    if args.symlink_output_base_name_to_generated_name {
+        link_output(
+            std::path::Path::new(&filename),
+            std::path::Path::new(&args.output_base_name),
+            args.link_mode,
+        );
+    }
+
+    if args.json {
+        QuiltResult::new(
+            filename,
+            quilt_settings,
+            input_aspect_ratio,
+            Timings {
+                depth: None,
+                render: render_seconds,
+                total: started.elapsed().as_secs_f64(),
+            },
+        )
+        .print()?;
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
     use image::{ImageBuffer, Rgb};
+    use quilt_painter::quilt_gen::load_rgbd_image;
 
     #[test]
     fn test_load_rgbd_image() {
@@ -376,10 +1015,10 @@ mod tests {
         assert_eq!(heightmap.dimensions(), (2, 2));
 
         // Check texture is red
-        assert_eq!(*texture.get_pixel(0, 0), Rgb([255, 0, 0]));
+        assert_eq!(*texture.0.get_pixel(0, 0), Rgb([255, 0, 0]));
 
         // Check heightmap is gray
-        assert_eq!(*heightmap.get_pixel(0, 0), Rgb([128, 128, 128]));
+        assert_eq!(*heightmap.0.get_pixel(0, 0), Rgb([128, 128, 128]));
 
         // Clean up
         std::fs::remove_file(temp_path).unwrap();
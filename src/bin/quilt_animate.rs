@@ -0,0 +1,392 @@
+use clap::Parser;
+use clap_complete::Shell;
+use quilt_painter::captions::{parse_rgba, CaptionConfig, CaptionTarget, Length, Position};
+use quilt_painter::cli;
+use quilt_painter::image_types::RgbdImage;
+use quilt_painter::interrupt;
+use quilt_painter::keyframe::{Keyframe, Keyframes};
+use quilt_painter::logging::{self, LogFormat};
+use quilt_painter::pan::{crop_window, pan_offsets, window_width, PanStep};
+use quilt_painter::quilt::{render_defaults, RenderDefaults};
+use quilt_painter::quilt_gen::{generate_quilt_deferred, FocusDepth, QuiltConfig, ZoomCenter};
+use quilt_painter::slideshow::{slideshow_quilts, Slideshow, Transition};
+use quilt_painter::status;
+use quilt_painter::subtitles::Subtitles;
+use quilt_painter::video::{FrameSink, VideoPreset};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Render a sequence of quilts from one RGB+D image, panning or keyframed, or a slideshow of finished quilts", long_about = None)]
+struct Args {
+    #[arg(
+        index = 1,
+        help = "Path to input RGB+D image, or a playlist (.m3u) or directory of quilts for a slideshow"
+    )]
+    input: String,
+
+    #[arg(
+        index = 2,
+        help = "Output video (.mp4, .mkv, .mov or .webm, needs ffmpeg), or an image name for numbered quilts"
+    )]
+    output: String,
+
+    #[arg(short, long, value_parser = cli::parse_device, conflicts_with_all=["columns", "rows", "width", "height"])]
+    device: Option<String>,
+
+    #[arg(long, help = "The number of columns of tiles in the output quilt.")]
+    columns: Option<u32>,
+
+    #[arg(long, help = "The number of rows of tiles in the output quilt.")]
+    rows: Option<u32>,
+
+    #[arg(long, help = "The width of the output quilt in pixels.")]
+    width: Option<u32>,
+
+    #[arg(long, help = "The height of the output quilt in pixels.")]
+    height: Option<u32>,
+
+    #[arg(
+        long,
+        default_value = "black",
+        value_parser = cli::parse_background,
+        help = "black, sky, debug, an rgb triplet, or gradient:TOP,BOTTOM or radial:CENTER,EDGE with two of those drawn behind the scene in each view"
+    )]
+    bg: String,
+
+    #[arg(long, default_value = "60", value_parser = cli::parse_fov, help = "field of view in degrees (0-120)")]
+    fov: f32,
+
+    #[arg(
+        long,
+        value_parser = cli::parse_zoom,
+        help = "zoom towards center of image [default: the device's, or 1.0]"
+    )]
+    zoom: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "X,Y|auto",
+        help = "Zoom towards this point, as fractions of the image width and height, or auto to find the subject [default: 0.5,0.5]"
+    )]
+    zoom_center: Option<ZoomCenter>,
+
+    #[arg(long, help = "enhance height [default: the device's, or 1.0]")]
+    scale: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "DEPTH|auto",
+        help = "Depth (0-255) that stays on the display plane, or auto for the subject's median depth [default: 0, the farthest]"
+    )]
+    focus_depth: Option<FocusDepth>,
+
+    #[arg(
+        long,
+        help = "resize multiplier relative to tile size [default: the device's, or 2.0]"
+    )]
+    resize: Option<f32>,
+
+    #[arg(long, default_value = "30", help = "Frames per second of the video")]
+    fps: f32,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Encode for smooth playback on a Go or Portrait (H.264, capped bitrate and size, a keyframe a second), or archive for full-size HEVC [default: H.264 at constant quality]"
+    )]
+    video_preset: Option<VideoPreset>,
+
+    #[arg(
+        long,
+        help = "Slide a crop window the shape of a view across the image, left to right, for wide panoramas"
+    )]
+    pan: bool,
+
+    #[arg(
+        long,
+        default_value = "0.1",
+        value_name = "WIDTHS",
+        help = "Pan speed in window widths per second"
+    )]
+    pan_speed: f32,
+
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        conflicts_with = "pan_speed",
+        help = "Step the pan so consecutive frames share this fraction of the window (0-1), instead of --pan-speed; suits numbered quilts shown one at a time"
+    )]
+    pan_overlap: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "t=SECONDS:NAME=VALUE,...",
+        help = "Zoom, scale or focus (0-255) at a time, eased between keyframes; repeat for each keyframe, e.g. --keyframe t=0:zoom=1.0,scale=1.0 --keyframe t=5:zoom=1.3,scale=1.5"
+    )]
+    keyframe: Vec<Keyframe>,
+
+    #[arg(
+        long,
+        default_value = "crossfade",
+        value_enum,
+        help = "Slideshow transition between quilts: crossfade, or depth-push to sink each quilt back as the next comes forward"
+    )]
+    transition: Transition,
+
+    #[arg(
+        long,
+        default_value = "5",
+        value_name = "SECONDS",
+        help = "How long the slideshow shows each quilt between transitions"
+    )]
+    hold: f32,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_name = "SECONDS",
+        help = "How long each slideshow transition takes"
+    )]
+    transition_time: f32,
+
+    #[arg(
+        long,
+        value_name = "SRT",
+        help = "Draw the timed captions of a SubRip (.srt) file onto the frames they cover"
+    )]
+    subtitles: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "5%",
+        help = "Subtitle font size in pixels, or a percentage of the view height"
+    )]
+    caption_size: Length,
+
+    #[arg(
+        long,
+        default_value = "3%",
+        help = "Space between subtitles and the view edges, in pixels or a percentage of the view height"
+    )]
+    caption_margin: Length,
+
+    #[arg(
+        long,
+        default_value = "bottom-center",
+        value_enum,
+        help = "Subtitle position (top-left, top-center, top-right, bottom-left, bottom-center, bottom-right, center)"
+    )]
+    caption_position: Position,
+
+    #[arg(
+        long,
+        value_name = "COLOR",
+        value_parser = parse_rgba,
+        help = "Box behind subtitles as R,G,B[,A] or #RRGGBB[AA], e.g. 0,0,0,128"
+    )]
+    caption_bg: Option<[u8; 4]>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Black outline width around subtitle glyphs in pixels"
+    )]
+    caption_outline: u32,
+
+    #[arg(long, help = "Draw a drop shadow under subtitles")]
+    caption_shadow: bool,
+
+    #[arg(
+        long = "caption-font",
+        value_name = "PATH",
+        help = "Fallback font for subtitle characters the bundled font lacks (e.g. Arabic, emoji). Repeatable."
+    )]
+    caption_fonts: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "view",
+        value_enum,
+        help = "Draw subtitles on every view (view), at screen depth, or once on the stitched quilt (quilt)"
+    )]
+    caption_target: CaptionTarget,
+
+    #[arg(
+        long,
+        default_value = "text",
+        value_enum,
+        help = "Log output: text, or json for one object per line with stage timings (level set by RUST_LOG)"
+    )]
+    log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "SHELL",
+        exclusive = true,
+        help = "Print a completion script for bash, elvish, fish, powershell or zsh and exit"
+    )]
+    completions: Option<Shell>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Args = cli::parse();
+    logging::init(args.log_format);
+    interrupt::install();
+    let subtitles = match &args.subtitles {
+        Some(path) => Subtitles::load(path)?,
+        None => Subtitles::default(),
+    };
+    let caption = CaptionConfig {
+        margin: args.caption_margin,
+        background: args.caption_bg,
+        outline: args.caption_outline,
+        shadow: args.caption_shadow,
+        fonts: args.caption_fonts,
+        target: args.caption_target,
+        ..CaptionConfig::new(None, args.caption_size, args.caption_position)
+    };
+    let input = Path::new(&args.input);
+    if input.is_dir()
+        || input
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("m3u"))
+    {
+        let quilts = slideshow_quilts(input)?;
+        status!("Showing {} quilts", quilts.len());
+        let slideshow = Slideshow {
+            transition: args.transition,
+            hold: args.hold,
+            transition_time: args.transition_time,
+            fps: args.fps,
+            video_preset: args.video_preset,
+            subtitles,
+            caption,
+        };
+        let path = slideshow.render(&quilts, &args.output)?;
+        status!("Saved slideshow as: {}", path);
+        return Ok(());
+    }
+    if !args.pan && args.keyframe.is_empty() {
+        return Err("nothing to animate; give --pan or --keyframe".into());
+    }
+    let keyframes = Keyframes::new(args.keyframe)?;
+    if let Some(overlap) = args.pan_overlap {
+        if !(0.0..1.0).contains(&overlap) {
+            return Err("--pan-overlap must be from 0 to below 1".into());
+        }
+    }
+
+    let render = RenderDefaults {
+        zoom: args.zoom,
+        scale: args.scale,
+        resize: args.resize,
+    }
+    .or(render_defaults(args.device.as_deref()));
+    let config = QuiltConfig {
+        device: args.device,
+        columns: args.columns,
+        rows: args.rows,
+        width: args.width,
+        height: args.height,
+        debug_mode: None,
+        bg: args.bg,
+        matte: None,
+        fov: args.fov,
+        view_spacing: Default::default(),
+        flip_h: false,
+        flip_v: false,
+        mirror_views: false,
+        tile_padding: 0,
+        border_color: "black".to_string(),
+        zoom: render.zoom.unwrap_or(1.0),
+        scale: render.scale.unwrap_or(1.0),
+        resize: render.resize.unwrap_or(2.0),
+        symlink_output: false,
+        link_mode: Default::default(),
+        caption: caption.clone(),
+        export_normals: None,
+        export_leia: false,
+        watermark: None,
+        write_descriptor: false,
+        preview: None,
+        auto_tune: false,
+        auto_tune_parallax: None,
+        max_parallax: None,
+        sampling: Default::default(),
+        splat_radius: 1,
+        pixel_aspect: None,
+        band_height: None,
+        render_cache_dir: None,
+        zoom_center: args.zoom_center,
+        focus_depth: args.focus_depth,
+        dither: Default::default(),
+        easing: Default::default(),
+        hole_fill: Default::default(),
+        back_layer: None,
+        panorama: None,
+        undistort: None,
+        stabilize_views: false,
+        postfx: Default::default(),
+    };
+    let settings = config.quilt_settings();
+
+    let (texture, heightmap) = RgbdImage(image::open(&args.input)?.to_rgb8()).split();
+    let (width, height) = texture.dimensions();
+    let (window, offsets) = if args.pan {
+        let window = window_width(width, height, &settings);
+        let step = match args.pan_overlap {
+            Some(overlap) => PanStep::Overlap(overlap),
+            None => PanStep::Speed {
+                speed: args.pan_speed,
+                fps: args.fps,
+            },
+        };
+        let offsets = pan_offsets(width, window, step);
+        status!(
+            "Panning a {}x{} window across {}x{} in {} frames",
+            window,
+            height,
+            width,
+            height,
+            offsets.len()
+        );
+        (window, offsets)
+    } else {
+        // The whole image, for as long as the keyframes last
+        let frames = (keyframes.duration() * args.fps).floor() as usize + 1;
+        status!("Rendering {} frames", frames);
+        (width, vec![0; frames])
+    };
+
+    let mut sink = FrameSink::new(
+        &args.output,
+        &settings,
+        window as f32 / height as f32,
+        args.fps,
+        args.video_preset,
+    )?;
+    for (frame, x) in offsets.into_iter().enumerate() {
+        let time = frame as f32 / args.fps;
+        let config = QuiltConfig {
+            zoom: keyframes.zoom(time).unwrap_or(config.zoom),
+            scale: keyframes.scale(time).unwrap_or(config.scale),
+            caption: CaptionConfig {
+                text: subtitles.at(time),
+                ..caption.clone()
+            },
+            focus_depth: keyframes
+                .focus(time)
+                .map(FocusDepth::Depth)
+                .or(config.focus_depth),
+            ..config.clone()
+        };
+        let (texture, heightmap) = crop_window(&texture, &heightmap, x, window);
+        let (_, pending) =
+            generate_quilt_deferred(texture, heightmap, args.output.clone(), &config)?;
+        let pending = pending.ok_or("quilt was written instead of returned")?;
+        sink.push(&pending.image)?;
+    }
+    let path = sink.finish()?;
+    status!("Saved quilt sequence as: {}", path);
+    Ok(())
+}
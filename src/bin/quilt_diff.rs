@@ -0,0 +1,116 @@
+use clap::Parser;
+use clap_complete::Shell;
+use quilt_painter::cli;
+use quilt_painter::descriptor::QuiltDescriptor;
+use quilt_painter::diff::{compare_quilts, difference_image};
+use quilt_painter::logging::{self, LogFormat};
+use quilt_painter::quilt::get_quilt_settings;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Compare two quilts view by view", long_about = None)]
+struct Args {
+    #[arg(index = 1, help = "Reference (golden) quilt")]
+    expected: PathBuf,
+
+    #[arg(index = 2, help = "Quilt to check")]
+    actual: PathBuf,
+
+    #[arg(short, long, value_parser = cli::parse_device, help = "Quilt layout of a target device")]
+    device: Option<String>,
+
+    #[arg(long, help = "Number of columns (if no device or descriptor)")]
+    columns: Option<u32>,
+
+    #[arg(long, help = "Number of rows (if no device or descriptor)")]
+    rows: Option<u32>,
+
+    #[arg(long, help = "Read the quilt layout from a JSON descriptor")]
+    descriptor: Option<PathBuf>,
+
+    #[arg(long, help = "Write a difference visualization to this path")]
+    diff_out: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Exit with status 1 if any view's SSIM is below this, e.g. 0.99"
+    )]
+    threshold: Option<f32>,
+
+    #[arg(long, help = "Print the scores as JSON")]
+    json: bool,
+
+    #[arg(
+        long,
+        default_value = "text",
+        value_enum,
+        help = "Log output: text, or json for one object per line with stage timings (level set by RUST_LOG)"
+    )]
+    log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "SHELL",
+        exclusive = true,
+        help = "Print a completion script for bash, elvish, fish, powershell or zsh and exit"
+    )]
+    completions: Option<Shell>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Args = cli::parse();
+    logging::init(args.log_format);
+
+    let (columns, rows) = if let Some(path) = &args.descriptor {
+        let settings = QuiltDescriptor::load(path)?.to_quilt_settings()?;
+        (settings.columns, settings.rows)
+    } else if let Some(device) = &args.device {
+        let settings = get_quilt_settings(device).ok_or("Unknown device")?;
+        (settings.columns, settings.rows)
+    } else {
+        (
+            args.columns
+                .ok_or("--columns is required without --device or --descriptor")?,
+            args.rows
+                .ok_or("--rows is required without --device or --descriptor")?,
+        )
+    };
+
+    let expected = image::open(&args.expected)?.to_rgb8();
+    let actual = image::open(&args.actual)?.to_rgb8();
+    let diff = compare_quilts(&expected, &actual, columns, rows)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        for view in &diff.views {
+            println!(
+                "view {:3}: ssim {:.5}  max diff {:3}",
+                view.view, view.ssim, view.max_difference
+            );
+        }
+        println!("min ssim {:.5}", diff.min_ssim);
+    }
+
+    if let Some(path) = &args.diff_out {
+        difference_image(&expected, &actual).save(path)?;
+        if !args.json {
+            println!("Saved difference image as: {}", path.display());
+        }
+    }
+
+    if let Some(threshold) = args.threshold {
+        let failing: Vec<u32> = diff
+            .views
+            .iter()
+            .filter(|view| view.ssim < threshold)
+            .map(|view| view.view)
+            .collect();
+        if !failing.is_empty() {
+            eprintln!("Views below ssim {}: {:?}", threshold, failing);
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
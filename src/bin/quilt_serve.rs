@@ -0,0 +1,53 @@
+use clap::Parser;
+use clap_complete::Shell;
+use quilt_painter::cli;
+use quilt_painter::logging::{self, LogFormat};
+use quilt_painter::serve::{serve, QuiltDirectory};
+use std::net::TcpListener;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Serve a directory of quilts and a playlist over HTTP", long_about = None)]
+struct Args {
+    #[arg(
+        index = 1,
+        help = "Directory of quilts, e.g. a batch_depth output directory"
+    )]
+    dir: PathBuf,
+
+    #[arg(long, default_value = "0.0.0.0:8080", help = "Address to listen on")]
+    listen: String,
+
+    #[arg(
+        long,
+        default_value = "text",
+        value_enum,
+        help = "Log output: text, or json for one object per line with stage timings (level set by RUST_LOG)"
+    )]
+    log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "SHELL",
+        exclusive = true,
+        help = "Print a completion script for bash, elvish, fish, powershell or zsh and exit"
+    )]
+    completions: Option<Shell>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Args = cli::parse();
+    logging::init(args.log_format);
+
+    if !args.dir.is_dir() {
+        return Err(format!("{} is not a directory", args.dir.display()).into());
+    }
+    let listener = TcpListener::bind(&args.listen)?;
+    println!(
+        "Serving {} with its playlist at http://{}/playlist.m3u",
+        args.dir.display(),
+        listener.local_addr()?
+    );
+    serve(listener, QuiltDirectory::new(args.dir))?;
+    Ok(())
+}
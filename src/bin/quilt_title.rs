@@ -0,0 +1,227 @@
+use clap::Parser;
+use clap_complete::Shell;
+use quilt_painter::background::Background;
+use quilt_painter::captions::{parse_rgba, CaptionConfig, CaptionSpec, Length, Position};
+use quilt_painter::cli;
+use quilt_painter::logging::{self, LogFormat};
+use quilt_painter::quilt::{render_defaults, RenderDefaults};
+use quilt_painter::quilt_gen::{generate_quilt, FocusDepth, QuiltConfig};
+use quilt_painter::title::title_card;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Render a text-only title card quilt, e.g. to open a playlist", long_about = None)]
+struct Args {
+    #[arg(index = 1, help = "Title text; use \\n for a line break")]
+    text: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Output base name (e.g. title.png) [default: the title in lowercase, words joined by -]"
+    )]
+    output: Option<String>,
+
+    #[arg(short, long, value_parser = cli::parse_device, conflicts_with_all=["columns", "rows", "width", "height"])]
+    device: Option<String>,
+
+    #[arg(long, help = "The number of columns of tiles in the output quilt.")]
+    columns: Option<u32>,
+
+    #[arg(long, help = "The number of rows of tiles in the output quilt.")]
+    rows: Option<u32>,
+
+    #[arg(long, help = "The width of the output quilt in pixels.")]
+    width: Option<u32>,
+
+    #[arg(long, help = "The height of the output quilt in pixels.")]
+    height: Option<u32>,
+
+    #[arg(
+        long,
+        default_value = "black",
+        value_parser = cli::parse_background,
+        help = "Backdrop: black, sky, an rgb triplet, or gradient:TOP,BOTTOM or radial:CENTER,EDGE with two of those"
+    )]
+    bg: String,
+
+    #[arg(
+        long,
+        default_value = "96",
+        help = "Depth of the lettering (0-255) in front of the backdrop, which sits at 0"
+    )]
+    depth: u8,
+
+    #[arg(
+        long,
+        default_value = "12%",
+        help = "Title font size in pixels, or a percentage of the view height"
+    )]
+    size: Length,
+
+    #[arg(
+        long,
+        default_value = "center",
+        value_enum,
+        help = "Title position (top-left, top-center, top-right, bottom-left, bottom-center, bottom-right, center)"
+    )]
+    position: Position,
+
+    #[arg(
+        long,
+        value_name = "COLOR",
+        value_parser = parse_rgba,
+        help = "Title color as R,G,B[,A] or #RRGGBB[AA] [default: white]"
+    )]
+    color: Option<[u8; 4]>,
+
+    #[arg(
+        long,
+        value_name = "TEXT[@POSITION[:SIZE[:COLOR]]]",
+        help = "Further text on the card, such as a date or place, styled like the title unless set. Repeatable."
+    )]
+    caption: Vec<CaptionSpec>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Black outline width around glyphs in pixels"
+    )]
+    outline: u32,
+
+    #[arg(long, help = "Draw a drop shadow under the text")]
+    shadow: bool,
+
+    #[arg(
+        long = "font",
+        value_name = "PATH",
+        help = "Fallback font for characters the bundled font lacks (e.g. Arabic, emoji). Repeatable."
+    )]
+    fonts: Vec<PathBuf>,
+
+    #[arg(long, default_value = "60", value_parser = cli::parse_fov, help = "field of view in degrees (0-120)")]
+    fov: f32,
+
+    #[arg(long, help = "enhance height [default: the device's, or 1.0]")]
+    scale: Option<f32>,
+
+    #[arg(
+        long,
+        help = "resize multiplier relative to tile size [default: the device's, or 2.0]"
+    )]
+    resize: Option<f32>,
+
+    #[arg(
+        long,
+        default_value = "text",
+        value_enum,
+        help = "Log output: text, or json for one object per line with stage timings (level set by RUST_LOG)"
+    )]
+    log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "SHELL",
+        exclusive = true,
+        help = "Print a completion script for bash, elvish, fish, powershell or zsh and exit"
+    )]
+    completions: Option<Shell>,
+}
+
+/// `text` in lowercase with its words joined by `-`, for a file name.
+fn slug(text: &str) -> String {
+    let words: Vec<String> = text
+        .replace("\\n", " ")
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    if words.is_empty() {
+        "title".to_string()
+    } else {
+        words.join("-")
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Args = cli::parse();
+    logging::init(args.log_format);
+    if !cfg!(feature = "captions") {
+        return Err("quilt-title draws text, so it needs a build with --features captions".into());
+    }
+
+    let render = RenderDefaults {
+        zoom: None,
+        scale: args.scale,
+        resize: args.resize,
+    }
+    .or(render_defaults(args.device.as_deref()));
+    let caption = CaptionConfig {
+        color: args.color,
+        outline: args.outline,
+        shadow: args.shadow,
+        fonts: args.fonts,
+        captions: args.caption,
+        ..CaptionConfig::new(Some(args.text.clone()), args.size, args.position)
+    };
+    let config = QuiltConfig {
+        device: args.device,
+        columns: args.columns,
+        rows: args.rows,
+        width: args.width,
+        height: args.height,
+        debug_mode: None,
+        bg: args.bg,
+        matte: None,
+        fov: args.fov,
+        view_spacing: Default::default(),
+        flip_h: false,
+        flip_v: false,
+        mirror_views: false,
+        tile_padding: 0,
+        border_color: "black".to_string(),
+        // The card is made to fill the view, so zooming would only crop it
+        zoom: 1.0,
+        scale: render.scale.unwrap_or(1.0),
+        resize: render.resize.unwrap_or(2.0),
+        symlink_output: false,
+        link_mode: Default::default(),
+        caption: CaptionConfig::default(),
+        export_normals: None,
+        export_leia: false,
+        watermark: None,
+        write_descriptor: false,
+        preview: None,
+        auto_tune: false,
+        auto_tune_parallax: None,
+        max_parallax: None,
+        sampling: Default::default(),
+        splat_radius: 1,
+        pixel_aspect: None,
+        band_height: None,
+        render_cache_dir: None,
+        zoom_center: None,
+        focus_depth: Some(FocusDepth::Depth(0.0)),
+        dither: Default::default(),
+        easing: Default::default(),
+        hole_fill: Default::default(),
+        back_layer: None,
+        panorama: None,
+        undistort: None,
+        stabilize_views: false,
+        postfx: Default::default(),
+    };
+
+    // A card the shape of a view as the display shows it
+    let settings = config.quilt_settings();
+    let (width, height) = config.target_size(&settings);
+    let width = (width as f32 * settings.pixel_aspect).round() as u32;
+    let background: Background = config.bg.parse()?;
+    let (texture, heightmap) = title_card(&caption, &background, args.depth, width, height);
+
+    let output = args
+        .output
+        .unwrap_or_else(|| format!("{}.png", slug(&args.text)));
+    generate_quilt(texture, heightmap, output, &config)?;
+    Ok(())
+}
@@ -0,0 +1,466 @@
+use clap::Parser;
+use clap_complete::Shell;
+use quilt_painter::camera::ViewSpacing;
+use quilt_painter::captions::CaptionConfig;
+use quilt_painter::cli;
+use quilt_painter::depth_gen::{generate_depth, DepthArgs, DepthConfig};
+use quilt_painter::dither::Dither;
+use quilt_painter::interrupt;
+use quilt_painter::logging::{self, LogFormat};
+use quilt_painter::postfx::{self, Grain, PostFx, Sharpen};
+use quilt_painter::preview::Preview;
+use quilt_painter::quilt::{render_defaults, Easing, HoleFill, RenderDefaults, Sampling};
+use quilt_painter::quilt_gen::{generate_quilt, FocusDepth, LinkMode, QuiltConfig, ZoomCenter};
+use quilt_painter::txt2img::{generate_image, Txt2ImgConfig};
+use quilt_painter::watermark::WatermarkConfig;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(index = 1)]
+    output: String,
+
+    #[arg(long, help = "Text prompt describing the image to generate")]
+    prompt: String,
+
+    #[arg(long, help = "Negative prompt for the txt2img workflow")]
+    negative_prompt: Option<String>,
+
+    #[arg(long, help = "Sampler seed. Random if not given.")]
+    seed: Option<u64>,
+
+    #[arg(long, help = "Checkpoint to load in the txt2img workflow")]
+    checkpoint: Option<String>,
+
+    #[arg(
+        long,
+        help = "ComfyUI API-format txt2img workflow to use instead of the bundled one"
+    )]
+    workflow: Option<PathBuf>,
+
+    #[command(flatten)]
+    depth: DepthArgs,
+
+    #[arg(
+        long,
+        help = "Use <stem><suffix>.png next to the input as its depth map instead of ComfyUI [default: tries _depth and .depth]"
+    )]
+    depth_suffix: Option<String>,
+
+    #[arg(short, long, value_parser = cli::parse_device, conflicts_with_all=["columns", "rows", "width", "height"])]
+    device: Option<String>,
+
+    #[arg(long, help = "The number of columns of tiles in the output quilt.")]
+    columns: Option<u32>,
+
+    #[arg(long, help = "The number of rows of tiles in the output quilt.")]
+    rows: Option<u32>,
+
+    #[arg(long, help = "The width of the output quilt in pixels.")]
+    width: Option<u32>,
+
+    #[arg(long, help = "The height of the output quilt in pixels.")]
+    height: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Comma separated key=value pairs for debug options",
+        alias = "debug_mode"
+    )]
+    debug_mode: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "black",
+        value_parser = cli::parse_background,
+        help = "black, sky, debug, an rgb triplet, or gradient:TOP,BOTTOM or radial:CENTER,EDGE with two of those drawn behind the scene in each view"
+    )]
+    bg: String,
+
+    #[arg(
+        long,
+        value_name = "COLOR",
+        value_parser = cli::parse_color,
+        help = "Color of the letterbox bars where the texture doesn't cover the view, if not --bg: black, sky, debug or an rgb triplet"
+    )]
+    matte: Option<String>,
+
+    #[arg(long, default_value = "60", value_parser = cli::parse_fov, help = "field of view in degrees (0-120)")]
+    fov: f32,
+
+    #[arg(
+        long,
+        default_value = "linear",
+        value_name = "SPACING",
+        help = "Spread of the views across the field of view: linear, cosine (closer together towards the edges), or curve:P0,P1,... with positions rising from 0 to 1"
+    )]
+    view_spacing: ViewSpacing,
+
+    #[arg(
+        long,
+        help = "Mirror the input (texture and depth together) left to right"
+    )]
+    flip_h: bool,
+
+    #[arg(
+        long,
+        help = "Mirror the input (texture and depth together) top to bottom"
+    )]
+    flip_v: bool,
+
+    #[arg(
+        long,
+        help = "Reverse the order of the views in the quilt, for displays or sources with mirrored geometry"
+    )]
+    mirror_views: bool,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Pixels of border around each view; views are rendered that much smaller to fit their tiles"
+    )]
+    tile_padding: u32,
+
+    #[arg(
+        long,
+        default_value = "black",
+        value_parser = cli::parse_color,
+        help = "Color of the border around each view: black, sky, debug or an rgb triplet"
+    )]
+    border_color: String,
+
+    #[arg(
+        long,
+        value_parser = cli::parse_zoom,
+        help = "zoom towards center of image [default: the device's, or 1.05]"
+    )]
+    zoom: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "X,Y|auto",
+        help = "Zoom towards this point, as fractions of the image width and height, or auto to find the subject [default: 0.5,0.5]"
+    )]
+    zoom_center: Option<ZoomCenter>,
+
+    #[arg(long, help = "enhance height [default: the device's, or 1.0]")]
+    scale: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "DEPTH|auto",
+        help = "Depth (0-255) that stays on the display plane, or auto for the subject's median depth [default: 0, the farthest]"
+    )]
+    focus_depth: Option<FocusDepth>,
+
+    #[arg(
+        long,
+        help = "resize multiplier relative to tile size [default: the device's, or 2.5]. Currently affects rendered height."
+    )]
+    resize: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "PNG[:POSITION[:OPACITY[:SCALE]]]",
+        help = "Composite a logo onto each view; SCALE is its width as a fraction of the view [default: bottom-right:1.0:0.15]"
+    )]
+    watermark: Option<WatermarkConfig>,
+
+    #[arg(
+        long,
+        help = "Draw the watermark once on the finished quilt instead of on every view"
+    )]
+    watermark_once: bool,
+
+    #[arg(
+        long,
+        help = "Also write a JSON layout descriptor (tile size, order, view count, viewcone) next to the quilt"
+    )]
+    descriptor: bool,
+
+    #[arg(
+        long,
+        value_name = "WHERE",
+        help = "Also make a small flat preview from the center view: file writes <name>-preview.jpg, exif embeds it as the thumbnail of a JPEG quilt"
+    )]
+    preview: Option<Preview>,
+
+    #[arg(
+        long,
+        help = "Pick --scale and --zoom from the depth map's range instead of using their values"
+    )]
+    auto_tune: bool,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Auto-tune parallax target: how far near content moves against far content in the outermost views [default: 3% of the view width]"
+    )]
+    auto_tune_parallax: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Flatten depth beyond what moves a pixel this far in the outermost views, to avoid stretching"
+    )]
+    max_parallax: Option<f32>,
+
+    #[arg(
+        long,
+        default_value = "nearest",
+        value_enum,
+        help = "Sample placement: nearest (fast) or subpixel (smoother edges, less crawling between views)"
+    )]
+    sampling: Sampling,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..=3),
+        help = "Pixels each texture sample covers (1-3); larger values fill pinholes when zoomed out or strongly displaced"
+    )]
+    splat_radius: u32,
+
+    #[arg(
+        long,
+        default_value = "none",
+        value_enum,
+        help = "Dither colors blended across gaps and between samples to hide banding: none, ordered or blue-noise"
+    )]
+    dither: Dither,
+
+    #[arg(
+        long,
+        default_value = "luminance:0.3333",
+        value_name = "CURVE",
+        help = "Color blend across gaps: linear, smoothstep, hold (no blend), or luminance[:SHARPNESS] to linger on the darker side"
+    )]
+    easing: Easing,
+
+    #[arg(
+        long,
+        default_value = "blend",
+        value_enum,
+        help = "How gaps opened by depth are filled: blend across them, or repeat the background side"
+    )]
+    hole_fill: HoleFill,
+
+    #[arg(
+        long,
+        help = "Histogram-match every view to the middle one after rendering, so color shifts from hole filling don't flicker between views"
+    )]
+    stabilize_views: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        value_parser = postfx::parse_look,
+        help = "Named bundle of color, sharpening, grain and vignette settings: natural, punchy, bw, vintage, or one from the user's looks.json; the effect options below override its parts"
+    )]
+    look: Option<PostFx>,
+
+    #[arg(
+        long,
+        value_name = "AMOUNT[:RADIUS]",
+        help = "Unsharp-mask every view after rendering, e.g. 0.5; a RADIUS of 20 or more boosts local contrast (clarity) instead [default radius: 1]"
+    )]
+    sharpen: Option<Sharpen>,
+
+    #[arg(
+        long,
+        value_name = "LEVELS",
+        help = "Add film grain of this standard deviation in 8-bit levels, e.g. 3, to hide banding in smooth gradients"
+    )]
+    grain: Option<f32>,
+
+    #[arg(
+        long,
+        requires = "grain",
+        help = "Use a different grain pattern in each view, so it fills the scene's depth instead of sitting on the display surface"
+    )]
+    grain_per_view: bool,
+
+    #[arg(
+        long,
+        value_name = "STRENGTH",
+        help = "Darken the corners of each view, from 0 (none) to 1 (black corners)"
+    )]
+    vignette: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "Fade the outer PX pixels of each view to the background, hiding stretched edges in the outermost views"
+    )]
+    edge_fade: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "W/H",
+        help = "Display pixel width over height, for displays with non-square pixels [default: the device's, 1.0]"
+    )]
+    pixel_aspect: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "ROWS",
+        help = "Render and write the quilt in bands of this many view rows to bound memory on very large outputs (PNG and JPEG only)"
+    )]
+    band_height: Option<u32>,
+
+    #[arg(short = 'L', long = "link-output", alias = "link_output")]
+    symlink_output: bool,
+
+    #[arg(
+        long,
+        default_value = "symlink",
+        value_enum,
+        help = "How --link-output refers to the generated file: symlink, hardlink, or copy for filesystems without links"
+    )]
+    link_mode: LinkMode,
+
+    #[arg(
+        long,
+        default_value = "text",
+        value_enum,
+        help = "Log output: text, or json for one object per line with stage timings (level set by RUST_LOG)"
+    )]
+    log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "SHELL",
+        exclusive = true,
+        help = "Print a completion script for bash, elvish, fish, powershell or zsh and exit"
+    )]
+    completions: Option<Shell>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Args = cli::parse();
+    logging::init(args.log_format);
+    interrupt::install();
+    #[cfg(feature = "hid")]
+    let args = {
+        let mut args = args;
+        if args.device.is_none() && args.columns.is_none() {
+            quilt_painter::hid::use_detected_display(&mut args.device, &mut args.fov);
+        }
+        args
+    };
+
+    let comfy = DepthConfig {
+        depth_suffix: args.depth_suffix,
+        ..args.depth.into()
+    };
+
+    let seed = args.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+    });
+    println!("Using seed: {}", seed);
+
+    // Generate the source image from the prompt
+    let image_bytes = generate_image(
+        &Txt2ImgConfig {
+            prompt: args.prompt,
+            negative_prompt: args.negative_prompt,
+            seed,
+            checkpoint: args.checkpoint,
+            workflow: args.workflow,
+        },
+        &comfy,
+    )?;
+
+    // Keep the generated image next to the quilt; it is also the input to the depth workflow.
+    let output_path = Path::new(&args.output);
+    let source_path = output_path.with_file_name(format!(
+        "{}_source.png",
+        output_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+    ));
+    image::load_from_memory(&image_bytes)?.save(&source_path)?;
+    println!("Saved generated image as: {}", source_path.display());
+
+    // Then generate the depth map
+    let (texture, depth) = generate_depth(source_path, &comfy)?;
+
+    let render = RenderDefaults {
+        zoom: args.zoom,
+        scale: args.scale,
+        resize: args.resize,
+    }
+    .or(render_defaults(args.device.as_deref()));
+    // Then generate quilt
+    generate_quilt(
+        texture,
+        depth,
+        args.output,
+        &QuiltConfig {
+            device: args.device,
+            columns: args.columns,
+            rows: args.rows,
+            width: args.width,
+            height: args.height,
+            debug_mode: args.debug_mode,
+            bg: args.bg,
+            matte: args.matte,
+            fov: args.fov,
+            view_spacing: args.view_spacing.clone(),
+            flip_h: args.flip_h,
+            flip_v: args.flip_v,
+            mirror_views: args.mirror_views,
+            tile_padding: args.tile_padding,
+            border_color: args.border_color,
+            zoom: render.zoom.unwrap_or(1.05),
+            scale: render.scale.unwrap_or(1.0),
+            resize: render.resize.unwrap_or(2.5),
+            symlink_output: args.symlink_output,
+            link_mode: args.link_mode,
+            caption: CaptionConfig::default(),
+            export_normals: None,
+            export_leia: false,
+            back_layer: None,
+            panorama: None,
+            undistort: None,
+            watermark: args.watermark.map(|watermark| WatermarkConfig {
+                once: args.watermark_once,
+                ..watermark
+            }),
+            write_descriptor: args.descriptor,
+            preview: args.preview,
+            auto_tune: args.auto_tune,
+            auto_tune_parallax: args.auto_tune_parallax,
+            max_parallax: args.max_parallax,
+            sampling: args.sampling,
+            splat_radius: args.splat_radius,
+            pixel_aspect: args.pixel_aspect,
+            band_height: args.band_height,
+            render_cache_dir: None,
+            zoom_center: args.zoom_center,
+            focus_depth: args.focus_depth,
+            dither: args.dither,
+            easing: args.easing,
+            hole_fill: args.hole_fill,
+            stabilize_views: args.stabilize_views,
+            postfx: PostFx {
+                color: None,
+                sharpen: args.sharpen,
+                grain: args.grain.map(|amount| Grain {
+                    amount,
+                    per_view: args.grain_per_view,
+                }),
+                vignette: args.vignette,
+                edge_fade: args.edge_fade,
+            }
+            .or(args.look.unwrap_or_default()),
+        },
+    )?;
+
+    Ok(())
+}
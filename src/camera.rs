@@ -1,8 +1,209 @@
-#[derive(Debug, Copy, Clone)]
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct Camera {
     pub zoom: f32,
     pub view_width: u32,
     pub view_height: u32,
     pub view_theta: f32,
     pub z_scale: f32,
+    /// Angle of the outermost view in radians, for `max_parallax`.
+    #[serde(default)]
+    pub max_theta: f32,
+    /// Largest horizontal displacement, in view pixels, that depth may cause at the outermost
+    /// views. Deeper points are flattened to that depth.
+    #[serde(default)]
+    pub max_parallax: Option<f32>,
+    /// Width of a display pixel over its height. Above 1 the image is narrowed by that factor so
+    /// it looks right on wide pixels.
+    #[serde(default = "default_pixel_aspect")]
+    pub pixel_aspect: f32,
+    /// Point of the image, as fractions of its width and height, that zooming in moves towards
+    /// the middle of the view. It only moves as far as the image still covers the view.
+    #[serde(default = "default_zoom_center")]
+    pub zoom_center: [f32; 2],
+    /// Depth value that stays on the display plane. Nearer content comes out of the display,
+    /// farther content sits behind it.
+    #[serde(default)]
+    pub focus_depth: f32,
+}
+
+/// `zoom_center` for zooming on the middle of the image.
+pub const IMAGE_CENTER: [f32; 2] = [0.5, 0.5];
+
+fn default_pixel_aspect() -> f32 {
+    1.0
+}
+
+fn default_zoom_center() -> [f32; 2] {
+    IMAGE_CENTER
+}
+
+/// The fraction of the image shown in the middle of the view along one axis, for `zoom_center`
+/// `center`. Zoomed out, not at all, or by a NaN zoom, the image stays centered.
+fn zoom_focus(center: f32, zoom: f32) -> f32 {
+    // Clamping to the NaN bounds a NaN zoom gives would panic
+    if zoom.is_nan() || zoom <= 1.0 {
+        return 0.5;
+    }
+    let half_view = 0.5 / zoom;
+    center.clamp(half_view, 1.0 - half_view)
+}
+
+impl Camera {
+    /// The camera for view `index` of `view_count`, as the quilt renderer places them.
+    pub fn for_view(
+        index: u32,
+        view_count: u32,
+        fov_deg: f32,
+        zoom: f32,
+        z_scale: f32,
+        view_width: u32,
+        view_height: u32,
+    ) -> Self {
+        Self {
+            zoom,
+            view_width,
+            view_height,
+            view_theta: view_theta(index, view_count, fov_deg),
+            z_scale,
+            max_theta: view_theta(view_count.saturating_sub(1), view_count, fov_deg).abs(),
+            max_parallax: None,
+            pixel_aspect: 1.0,
+            zoom_center: IMAGE_CENTER,
+            focus_depth: 0.0,
+        }
+    }
+
+    /// Horizontal zoom, after correcting for the pixel aspect ratio.
+    pub fn zoom_x(&self) -> f32 {
+        self.zoom / self.pixel_aspect
+    }
+
+    /// Vertical zoom.
+    pub fn zoom_y(&self) -> f32 {
+        self.zoom
+    }
+
+    /// The point of the image, as fractions of its width and height, drawn in the middle of the
+    /// view: `zoom_center`, as far as the zoom allows.
+    pub fn view_center(&self) -> [f32; 2] {
+        [
+            zoom_focus(self.zoom_center[0], self.zoom_x()),
+            zoom_focus(self.zoom_center[1], self.zoom_y()),
+        ]
+    }
+
+    /// Largest scaled distance from the focus plane allowed by `max_parallax`, for a texture
+    /// `tex_width` pixels wide.
+    pub fn depth_limit(&self, tex_width: u32) -> Option<f32> {
+        let max_parallax = self.max_parallax?;
+        let pixels_per_depth =
+            self.max_theta.sin() * self.zoom_x() * self.view_width as f32 / tex_width as f32;
+        (pixels_per_depth > 0.0).then(|| max_parallax / pixels_per_depth)
+    }
+
+    /// Horizontal screen position, relative to the middle of the view, of the point in the
+    /// middle of the texture if it had depth value `height`. Differences between cameras are the
+    /// disparity depth causes.
+    pub fn screen_offset(&self, height: f32, tex_width: u32) -> f32 {
+        let depth = (height - self.focus_depth) * self.z_scale;
+        let depth = match self.depth_limit(tex_width) {
+            Some(limit) => depth.clamp(-limit, limit),
+            None => depth,
+        };
+        let pt = self.rotation() * na::point!(depth, 0.0);
+        pt[1] * self.zoom_x() * (self.view_width as f32 / tex_width as f32)
+    }
+
+    /// Rotation applied to the scene for this camera's viewpoint.
+    pub fn rotation(&self) -> na::UnitComplex<f32> {
+        na::UnitComplex::from_angle(self.view_theta)
+    }
+}
+
+/// Scene rotation angle in radians for view `index` of `view_count`. Views are spread evenly over
+/// half of `fov_deg`, centered on the straight-on view; a single view looks straight on.
+pub fn view_theta(index: u32, view_count: u32, fov_deg: f32) -> f32 {
+    ViewSpacing::Linear.view_theta(index, view_count, fov_deg)
+}
+
+/// How views are spread across the viewcone. The first and last views stay at its edges.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ViewSpacing {
+    /// Evenly spaced in angle.
+    #[default]
+    Linear,
+    /// Closer together towards the edges of the cone, for devices that blend the edge views
+    /// more heavily: the spacing falls off with the cosine of the distance from the middle.
+    Cosine,
+    /// Positions in the cone, from 0 at the first view to 1 at the last, at evenly spaced points
+    /// along the views; views between points are interpolated linearly.
+    Curve(Vec<f32>),
+}
+
+impl ViewSpacing {
+    /// Where view `index` of `view_count` sits in the cone, from 0 for the first to 1 for the
+    /// last. Needs at least two views.
+    fn position(&self, index: u32, view_count: u32) -> f32 {
+        let t = index as f32 / (view_count - 1) as f32;
+        match self {
+            ViewSpacing::Linear => t,
+            ViewSpacing::Cosine => 0.5 + 0.5 * ((t - 0.5) * std::f32::consts::PI).sin(),
+            ViewSpacing::Curve(points) => {
+                let x = t * (points.len() - 1) as f32;
+                let i = (x as usize).min(points.len() - 2);
+                points[i] + (points[i + 1] - points[i]) * (x - i as f32)
+            }
+        }
+    }
+
+    /// Scene rotation angle in radians for view `index` of `view_count`, placed over half of
+    /// `fov_deg` by this spacing.
+    pub fn view_theta(&self, index: u32, view_count: u32, fov_deg: f32) -> f32 {
+        if view_count < 2 {
+            return 0.0;
+        }
+        let fov_size = fov_deg / 360.0 * std::f32::consts::PI;
+        fov_size * self.position(index, view_count) - fov_size / 2.0
+    }
+}
+
+impl FromStr for ViewSpacing {
+    type Err = String;
+
+    /// Parses `linear`, `cosine` or `curve:P0,P1,...`, where the points rise from 0 to 1.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => return Ok(ViewSpacing::Linear),
+            "cosine" => return Ok(ViewSpacing::Cosine),
+            _ => {}
+        }
+        let points = s
+            .strip_prefix("curve:")
+            .ok_or_else(|| {
+                format!(
+                    "invalid view spacing '{}', expected linear, cosine or curve:P0,P1,...",
+                    s
+                )
+            })?
+            .split(',')
+            .map(|p| p.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid curve point in '{}': {}", s, e))?;
+        if points.len() < 2
+            || points[0] != 0.0
+            || points[points.len() - 1] != 1.0
+            || points.windows(2).any(|pair| pair[1] < pair[0])
+        {
+            return Err(format!(
+                "invalid curve '{}': give at least two points rising from 0 to 1",
+                s
+            ));
+        }
+        Ok(ViewSpacing::Curve(points))
+    }
 }
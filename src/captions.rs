@@ -1,110 +1,739 @@
+use crate::metadata::MetadataField;
 use image::{ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "captions")]
+use std::collections::HashMap;
+use std::path::PathBuf;
+#[cfg(feature = "captions")]
+use std::sync::{Arc, LazyLock, Mutex};
 
-#[cfg(not(feature = "captions"))]
-#[derive(Default, Clone, Debug)]
-pub struct CaptionConfig();
-
-#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Position {
     TopLeft,
     TopCenter,
     TopRight,
     BottomLeft,
+    #[default]
     BottomCenter,
+    BottomRight,
+    /// The middle of the view, e.g. for title cards.
+    Center,
+}
+
+impl Position {
+    /// Line alignment matching the side of the view the caption is anchored to.
+    pub fn default_align(self) -> TextAlign {
+        match self {
+            Position::TopLeft | Position::BottomLeft => TextAlign::Left,
+            Position::TopCenter | Position::BottomCenter | Position::Center => TextAlign::Center,
+            Position::TopRight | Position::BottomRight => TextAlign::Right,
+        }
+    }
+}
+
+/// A caption measurement, either in pixels or as a percentage of the view height.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Length {
+    Px(u32),
+    Percent(f32),
 }
 
-impl Default for Position {
+impl Default for Length {
     fn default() -> Self {
-        Position::BottomCenter
+        Length::Px(0)
     }
 }
 
-#[cfg(feature = "captions")]
-#[derive(Default, Clone, Debug)]
+impl Length {
+    /// Resolves to pixels for a view `view_height` pixels tall.
+    pub fn to_pixels(self, view_height: u32) -> u32 {
+        match self {
+            Length::Px(px) => px,
+            Length::Percent(percent) => (view_height as f32 * percent / 100.0).round() as u32,
+        }
+    }
+}
+
+impl std::str::FromStr for Length {
+    type Err = String;
+
+    /// Parses `16`, `16px` or `4%`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(percent) = s.strip_suffix('%') {
+            percent
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|p| p.is_finite() && *p >= 0.0)
+                .map(Length::Percent)
+                .ok_or_else(|| format!("invalid percentage '{}'", s))
+        } else {
+            s.trim_end_matches("px")
+                .trim()
+                .parse::<u32>()
+                .map(Length::Px)
+                .map_err(|_| format!("invalid length '{}', expected pixels or a percentage", s))
+        }
+    }
+}
+
+/// Which image a caption is drawn on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaptionTarget {
+    /// Every view, so the caption sits at screen depth on the device.
+    #[default]
+    View,
+    /// Once on the stitched quilt, e.g. for archive or contact-sheet use.
+    Quilt,
+}
+
+/// Alignment of the lines of a wrapped caption within its block.
+#[derive(Debug, Clone, Copy, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+/// Caption text and layout. The type is the same with or without the `captions` feature; without
+/// it, `draw_caption` leaves views untouched. Fields missing from a serialized config take their
+/// values from `CaptionConfig::default()`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CaptionConfig {
     pub text: Option<String>,
-    pub size: u32,
+    pub size: Length,
     pub position: Position,
+    /// Distance kept between the caption and the view edges.
+    pub margin: Length,
+    /// Wrap width in pixels. Defaults to the view width minus margins.
+    pub max_width: Option<u32>,
+    /// Distance between baselines as a multiple of the font's line height.
+    pub line_spacing: f32,
+    /// Alignment of wrapped lines. Defaults to following `position`.
+    pub align: Option<TextAlign>,
+    /// RGBA fill for a box behind the caption block.
+    pub background: Option<[u8; 4]>,
+    /// Width in pixels of a black outline around each glyph. 0 disables it.
+    pub outline: u32,
+    /// Draw a soft black drop shadow under the text.
+    pub shadow: bool,
+    /// Fonts tried in order for characters the bundled font lacks, e.g. an Arabic or Devanagari
+    /// font and a color emoji font.
+    pub fonts: Vec<PathBuf>,
+    pub target: CaptionTarget,
+    /// Metadata fields to take the caption text from, first present wins. `text` is the
+    /// fallback for images that carry none of them.
+    pub from: Vec<MetadataField>,
+    /// RGBA text color. Defaults to white.
+    pub color: Option<[u8; 4]>,
+    /// Total shift in pixels between the outermost views, which moves the caption behind the
+    /// display when positive and in front of it when negative. 0 keeps it at screen depth.
+    pub depth: f32,
+    /// More captions drawn in the same style, e.g. a date in a corner under a title.
+    pub captions: Vec<CaptionSpec>,
+}
+
+/// No caption, in the style `CaptionConfig::new` starts from.
+impl Default for CaptionConfig {
+    fn default() -> Self {
+        CaptionConfig::new(None, Length::default(), Position::default())
+    }
 }
 
-#[cfg(feature = "captions")]
 impl CaptionConfig {
-    pub fn new(text: Option<String>, size: u32, position: Position) -> Self {
+    pub fn new(text: Option<String>, size: Length, position: Position) -> Self {
         Self {
             text,
             size,
             position,
+            margin: Length::Px(10),
+            max_width: None,
+            line_spacing: 1.0,
+            align: None,
+            background: None,
+            outline: 0,
+            shadow: false,
+            fonts: Vec::new(),
+            target: CaptionTarget::View,
+            from: Vec::new(),
+            color: None,
+            depth: 0.0,
+            captions: Vec::new(),
         }
     }
+
+    /// This style drawing `specs`: the first as the main caption, which `from` can replace, and
+    /// the rest as further `captions`.
+    pub fn with_specs(self, specs: Vec<CaptionSpec>) -> Self {
+        let mut specs = specs.into_iter();
+        let main = match specs.next() {
+            Some(spec) => spec.apply(&self),
+            None => self,
+        };
+        CaptionConfig {
+            captions: specs.collect(),
+            ..main
+        }
+    }
+
+    /// Whether there is any caption text to draw.
+    pub fn is_empty(&self) -> bool {
+        self.text.is_none() && self.captions.is_empty()
+    }
+
+    /// The main caption and then each of `captions`, as configs drawing a single caption.
+    #[cfg(feature = "captions")]
+    fn layers(&self) -> Vec<CaptionConfig> {
+        let single = CaptionConfig {
+            captions: Vec::new(),
+            ..self.clone()
+        };
+        let extra: Vec<_> = self
+            .captions
+            .iter()
+            .map(|spec| spec.apply(&single))
+            .collect();
+        std::iter::once(single)
+            .filter(|caption| caption.text.is_some())
+            .chain(extra)
+            .collect()
+    }
+
+    /// Resolves `from` against the image at `path`, replacing `text` when a field is found.
+    pub fn with_metadata_from(mut self, path: &std::path::Path) -> Self {
+        if let Some(text) = crate::metadata::read_first(path, &self.from) {
+            self.text = Some(text);
+        }
+        self
+    }
+}
+
+/// One caption among several: its text and, where set, its own position, size, color and depth.
+/// The rest of the style comes from the `CaptionConfig` it is listed in.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CaptionSpec {
+    pub text: String,
+    pub position: Option<Position>,
+    pub size: Option<Length>,
+    pub color: Option<[u8; 4]>,
+    pub depth: Option<f32>,
+}
+
+impl CaptionSpec {
+    /// `base` drawing this caption instead of its own.
+    pub fn apply(&self, base: &CaptionConfig) -> CaptionConfig {
+        CaptionConfig {
+            text: Some(self.text.clone()),
+            size: self.size.unwrap_or(base.size),
+            position: self.position.unwrap_or(base.position),
+            color: self.color.or(base.color),
+            depth: self.depth.unwrap_or(base.depth),
+            ..base.clone()
+        }
+    }
+}
+
+impl std::str::FromStr for CaptionSpec {
+    type Err = String;
+
+    /// Parses `TEXT[@POSITION[:SIZE[:COLOR[:DEPTH]]]]`, e.g. `2024-06-01@bottom-right:3%:#ffd700`.
+    /// Empty fields keep the defaults. Text is only split at its last `@` when a position or an
+    /// empty field follows, so `me@example.com` stays whole.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut spec = CaptionSpec {
+            text: s.to_string(),
+            position: None,
+            size: None,
+            color: None,
+            depth: None,
+        };
+        let Some((text, layout)) = s.rsplit_once('@') else {
+            return Ok(spec);
+        };
+        let mut fields = layout.split(':').map(str::trim);
+        let position = fields.next().unwrap_or_default();
+        if !position.is_empty() {
+            match <Position as clap::ValueEnum>::from_str(position, true) {
+                Ok(position) => spec.position = Some(position),
+                Err(_) => return Ok(spec),
+            }
+        }
+        let mut field = || fields.next().filter(|field| !field.is_empty());
+        spec.size = field().map(str::parse).transpose()?;
+        spec.color = field().map(parse_rgba).transpose()?;
+        spec.depth = field()
+            .map(|depth| {
+                depth
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|depth| depth.is_finite())
+                    .ok_or_else(|| format!("invalid caption depth '{}'", depth))
+            })
+            .transpose()?;
+        if let Some(extra) = fields.next() {
+            return Err(format!(
+                "unexpected '{}' in caption '{}', expected TEXT[@POSITION[:SIZE[:COLOR[:DEPTH]]]]",
+                extra, s
+            ));
+        }
+        spec.text = text.to_string();
+        Ok(spec)
+    }
 }
+
+/// Where view `index` in render order sits across the view cone, from -0.5 for the leftmost to
+/// 0.5 for the rightmost, for placing captions in depth.
+pub fn view_position(index: u32, views: u32) -> f32 {
+    // Render order runs from the rightmost viewpoint to the leftmost
+    0.5 - index as f32 / (views - 1).max(1) as f32
+}
+
+/// Parses an `R,G,B[,A]` or `#RRGGBB[AA]` color. Alpha defaults to opaque.
+pub fn parse_rgba(arg: &str) -> Result<[u8; 4], String> {
+    let err = || format!("invalid color '{}', expected R,G,B[,A] or #RRGGBB[AA]", arg);
+    let parts: Vec<u8> = if arg.contains(',') {
+        arg.split(',')
+            .map(|s| s.trim().parse::<u8>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| err())?
+    } else {
+        let hex = arg.trim_start_matches('#');
+        if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+            return Err(err());
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<_, _>>()
+            .map_err(|_| err())?
+    };
+    match parts[..] {
+        [r, g, b] => Ok([r, g, b, 255]),
+        [r, g, b, a] => Ok([r, g, b, a]),
+        _ => Err(err()),
+    }
+}
+
 #[cfg(not(feature = "captions"))]
 pub fn draw_caption(
     view: ImageBuffer<Rgb<u8>, Vec<u8>>,
-    _caption: CaptionConfig,
+    caption: CaptionConfig,
+    _view_position: f32,
 ) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    static WARN_ONCE: std::sync::Once = std::sync::Once::new();
+    if !caption.is_empty() {
+        WARN_ONCE.call_once(|| {
+            crate::output::warn("Built without the captions feature; caption not drawn");
+        });
+    }
     view
 }
 
+#[cfg(feature = "captions")]
+static BUNDLED_FONT: &[u8] = include_bytes!("../assets/font.ttf");
+
+#[cfg(feature = "captions")]
+type FontFiles = HashMap<PathBuf, Option<Arc<Vec<u8>>>>;
+
+// Fallback font files, read once per process. `None` marks a file that failed to load.
+#[cfg(feature = "captions")]
+static FONT_FILES: LazyLock<Mutex<FontFiles>> = LazyLock::new(Default::default);
+
+/// One font of the fallback chain: ab_glyph rasterizes, rustybuzz shapes.
+#[cfg(feature = "captions")]
+struct CaptionFont<'a> {
+    glyphs: ab_glyph::FontRef<'a>,
+    face: rustybuzz::Face<'a>,
+}
+
+#[cfg(feature = "captions")]
+impl<'a> CaptionFont<'a> {
+    fn from_slice(data: &'a [u8]) -> Option<Self> {
+        Some(Self {
+            glyphs: ab_glyph::FontRef::try_from_slice(data).ok()?,
+            face: rustybuzz::Face::from_slice(data, 0)?,
+        })
+    }
+
+    fn has_glyph(&self, c: char) -> bool {
+        self.face.glyph_index(c).is_some()
+    }
+}
+
+#[cfg(feature = "captions")]
+fn load_font_files(paths: &[PathBuf]) -> Vec<Arc<Vec<u8>>> {
+    let mut files = FONT_FILES.lock().unwrap();
+    paths
+        .iter()
+        .filter_map(|path| {
+            files
+                .entry(path.clone())
+                .or_insert_with(|| match std::fs::read(path) {
+                    Ok(data) => Some(Arc::new(data)),
+                    Err(e) => {
+                        tracing::warn!("Skipping caption font {}: {}", path.display(), e);
+                        None
+                    }
+                })
+                .clone()
+        })
+        .collect()
+}
+
+/// Characters that belong to the cluster before them and so stay in that run's font: joiners,
+/// variation selectors, emoji skin tone modifiers and tag characters.
+#[cfg(feature = "captions")]
+fn continues_cluster(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200C}'
+            | '\u{200D}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{1F3FB}'..='\u{1F3FF}'
+            | '\u{E0020}'..='\u{E007F}'
+    )
+}
+
+/// A shaped glyph, positioned relative to the start of its line's baseline.
+#[cfg(feature = "captions")]
+struct ShapedGlyph {
+    font: usize,
+    id: ab_glyph::GlyphId,
+    x: f32,
+    y: f32,
+}
+
+/// Shapes a line of text, splitting it into runs by the first font in the chain that covers each
+/// character. Returns the glyphs and the advance width of the line. Each run gets its own
+/// direction, so mixed left-to-right and right-to-left text is laid out run by run in logical
+/// order.
+#[cfg(feature = "captions")]
+fn shape_line(
+    fonts: &[CaptionFont],
+    scale: ab_glyph::PxScale,
+    text: &str,
+) -> (Vec<ShapedGlyph>, f32) {
+    use ab_glyph::{Font, ScaleFont};
+
+    let mut runs: Vec<(usize, std::ops::Range<usize>)> = Vec::new();
+    for (i, c) in text.char_indices() {
+        let font = match runs.last() {
+            Some((current, _)) if c.is_whitespace() || continues_cluster(c) => *current,
+            _ => fonts.iter().position(|f| f.has_glyph(c)).unwrap_or(0),
+        };
+        match runs.last_mut() {
+            Some((current, range)) if *current == font => range.end = i + c.len_utf8(),
+            _ => runs.push((font, i..i + c.len_utf8())),
+        }
+    }
+
+    let mut glyphs = Vec::new();
+    let mut pen = 0.0;
+    for (font_index, range) in runs {
+        let font = &fonts[font_index];
+        let units_to_px = font.glyphs.as_scaled(scale).h_scale_factor();
+
+        let mut buffer = rustybuzz::UnicodeBuffer::new();
+        buffer.push_str(&text[range]);
+        buffer.guess_segment_properties();
+        let output = rustybuzz::shape(&font.face, &[], buffer);
+        for (info, position) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+            glyphs.push(ShapedGlyph {
+                font: font_index,
+                id: ab_glyph::GlyphId(info.glyph_id as u16),
+                x: pen + position.x_offset as f32 * units_to_px,
+                y: -position.y_offset as f32 * units_to_px,
+            });
+            pen += position.x_advance as f32 * units_to_px;
+        }
+    }
+    (glyphs, pen)
+}
+
+/// Splits `text` into lines at explicit newlines (real ones or a literal `\n` as typed on the
+/// command line), then greedily wraps each paragraph to `max_width` pixels. A single word wider
+/// than `max_width` gets a line of its own.
+#[cfg(feature = "captions")]
+fn wrap_lines(
+    fonts: &[CaptionFont],
+    scale: ab_glyph::PxScale,
+    text: &str,
+    max_width: f32,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.replace("\\n", "\n").split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if !line.is_empty() && shape_line(fonts, scale, &candidate).1 > max_width {
+                lines.push(std::mem::replace(&mut line, word.to_string()));
+            } else {
+                line = candidate;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// A glyph ready to stamp onto a view: a vector outline, or a bitmap from a color emoji font.
+#[cfg(feature = "captions")]
+enum GlyphSprite {
+    Outline(ab_glyph::OutlinedGlyph),
+    Image {
+        left: i32,
+        top: i32,
+        image: image::RgbaImage,
+    },
+}
+
+#[cfg(feature = "captions")]
+fn rasterize_glyph(
+    font: &CaptionFont,
+    scale: ab_glyph::PxScale,
+    id: ab_glyph::GlyphId,
+    (x, y): (f32, f32),
+) -> Option<GlyphSprite> {
+    use ab_glyph::{Font, GlyphImageFormat, ScaleFont};
+
+    if let Some(outline) = font
+        .glyphs
+        .outline_glyph(id.with_scale_and_position(scale, ab_glyph::point(x, y)))
+    {
+        return Some(GlyphSprite::Outline(outline));
+    }
+
+    // No outline: look for a PNG strike (CBDT/sbix color emoji) and scale it to the em size
+    let em_px = font.glyphs.units_per_em()? * font.glyphs.as_scaled(scale).h_scale_factor();
+    let raster = font.glyphs.glyph_raster_image2(id, em_px.round() as u16)?;
+    if !matches!(raster.format, GlyphImageFormat::Png) {
+        return None;
+    }
+    let decoded = image::load_from_memory(raster.data).ok()?.to_rgba8();
+    let k = em_px / raster.pixels_per_em as f32;
+    let image = image::imageops::resize(
+        &decoded,
+        ((decoded.width() as f32 * k).round() as u32).max(1),
+        ((decoded.height() as f32 * k).round() as u32).max(1),
+        image::imageops::FilterType::Triangle,
+    );
+    Some(GlyphSprite::Image {
+        left: (x + raster.origin.x * k).round() as i32,
+        top: (y - (raster.origin.y + raster.height as f32) * k).round() as i32,
+        image,
+    })
+}
+
+/// Draws the caption and any further `captions` onto a view. `view_position` places the view
+/// across the view cone, as `view_position()` gives it, and sets how far captions with a depth
+/// shift; 0 for a whole quilt.
 #[cfg(feature = "captions")]
 pub fn draw_caption(
     mut view: ImageBuffer<Rgb<u8>, Vec<u8>>,
     caption: CaptionConfig,
+    view_position: f32,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    for layer in caption.layers() {
+        let shift = (layer.depth * view_position).round() as i32;
+        view = draw_single_caption(view, layer, shift);
+    }
+    view
+}
+
+/// Draws `caption.text` shifted `shift` pixels to the right.
+#[cfg(feature = "captions")]
+fn draw_single_caption(
+    mut view: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    caption: CaptionConfig,
+    shift: i32,
 ) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
     if let Some(text) = caption.text {
-        use rusttype::{Font, Scale};
+        use ab_glyph::{Font, PxScale, ScaleFont};
 
-        // Load font
-        let font_data = include_bytes!("../assets/font.ttf");
-        let font = Font::try_from_bytes(font_data as &[u8]).unwrap();
+        // Load the font chain: bundled font first, then the configured fallbacks
+        let font_files = load_font_files(&caption.fonts);
+        let fonts: Vec<CaptionFont> = std::iter::once(BUNDLED_FONT)
+            .chain(font_files.iter().map(|data| data.as_slice()))
+            .filter_map(CaptionFont::from_slice)
+            .collect();
+        if fonts.is_empty() {
+            tracing::warn!("No usable caption font, skipping caption");
+            return view;
+        }
 
         // Prepare scale and color
-        let scale = Scale::uniform(caption.size as f32);
-        let color = Rgb([255, 255, 255]); // White text
+        let size = caption.size.to_pixels(view.height()).max(1);
+        let margin = caption.margin.to_pixels(view.height()) as i32;
+        let scale = PxScale::from(size as f32);
+        let [r, g, b, a] = caption.color.unwrap_or([255, 255, 255, 255]);
+        let (color, opacity) = (Rgb([r, g, b]), a as f32 / 255.0);
 
-        // Calculate text size
-        let v_metrics = font.v_metrics(scale);
-        let glyphs: Vec<_> = font
-            .layout(&text, scale, rusttype::Point { x: 0.0, y: 0.0 })
+        // Lay out the wrapped lines as one block
+        let max_width = caption
+            .max_width
+            .map(|w| w as f32)
+            .unwrap_or((view.width() as i32 - 2 * margin).max(1) as f32);
+        let lines: Vec<_> = wrap_lines(&fonts, scale, &text, max_width)
+            .iter()
+            .map(|line| shape_line(&fonts, scale, line))
             .collect();
-        let text_width = glyphs
+
+        let metrics = fonts[0].glyphs.as_scaled(scale);
+        let text_height = metrics.ascent() - metrics.descent();
+        let line_height = (text_height + metrics.line_gap()) * caption.line_spacing;
+        let block_width = lines
             .iter()
-            .next_back()
-            .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
-            .unwrap_or(0.0) as i32;
-        let text_height = (v_metrics.ascent - v_metrics.descent).ceil() as i32;
-
-        let (x, y) = match caption.position {
-            Position::TopLeft => (10, 10),
-            Position::TopCenter => ((view.width() as i32 - text_width) / 2, 10),
-            Position::TopRight => (view.width() as i32 - text_width - 10, 10),
-            Position::BottomLeft => (10, view.height() as i32 - text_height - 10),
+            .map(|(_, width)| width.ceil() as i32)
+            .max()
+            .unwrap_or(0);
+        let block_height = (line_height * (lines.len() - 1) as f32 + text_height).ceil() as i32;
+
+        let (view_width, view_height) = (view.width() as i32, view.height() as i32);
+        let (mut x, y) = match caption.position {
+            Position::TopLeft => (margin, margin),
+            Position::TopCenter => ((view_width - block_width) / 2, margin),
+            Position::TopRight => (view_width - block_width - margin, margin),
+            Position::BottomLeft => (margin, view_height - block_height - margin),
             Position::BottomCenter => (
-                (view.width() as i32 - text_width) / 2,
-                view.height() as i32 - text_height - 10,
+                (view_width - block_width) / 2,
+                view_height - block_height - margin,
+            ),
+            Position::BottomRight => (
+                view_width - block_width - margin,
+                view_height - block_height - margin,
+            ),
+            Position::Center => (
+                (view_width - block_width) / 2,
+                (view_height - block_height) / 2,
             ),
         };
+        x += shift;
+        let align = caption
+            .align
+            .unwrap_or_else(|| caption.position.default_align());
 
-        // Draw text
-        for glyph in glyphs {
-            if let Some(bounding_box) = glyph.pixel_bounding_box() {
-                glyph.draw(|gx, gy, intensity| {
-                    let gx = gx as i32 + bounding_box.min.x + x;
-                    let gy = gy as i32 + bounding_box.min.y + y;
-
-                    if gx >= 0 && gx < view.width() as i32 && gy >= 0 && gy < view.height() as i32 {
-                        let pixel = view.get_pixel_mut(gx as u32, gy as u32);
-                        *pixel = Rgb([
-                            ((1.0 - intensity) * pixel[0] as f32 + intensity * color[0] as f32)
-                                as u8,
-                            ((1.0 - intensity) * pixel[1] as f32 + intensity * color[1] as f32)
-                                as u8,
-                            ((1.0 - intensity) * pixel[2] as f32 + intensity * color[2] as f32)
-                                as u8,
-                        ]);
-                    }
-                });
+        // Background box
+        if let Some([r, g, b, a]) = caption.background {
+            let padding = margin / 2;
+            let opacity = a as f32 / 255.0;
+            for py in (y - padding).max(0)..(y + block_height + padding).min(view_height) {
+                for px in (x - padding).max(0)..(x + block_width + padding).min(view_width) {
+                    blend_pixel(&mut view, px, py, Rgb([r, g, b]), opacity);
+                }
+            }
+        }
+
+        // Rasterize every glyph of every line once, then stamp the set for each layer
+        let mut sprites = Vec::new();
+        for (i, (glyphs, line_width)) in lines.iter().enumerate() {
+            let line_width = line_width.ceil() as i32;
+            let line_x = x + match align {
+                TextAlign::Left => 0,
+                TextAlign::Center => (block_width - line_width) / 2,
+                TextAlign::Right => block_width - line_width,
+            };
+            let baseline = y as f32 + i as f32 * line_height + metrics.ascent();
+            sprites.extend(glyphs.iter().filter_map(|glyph| {
+                rasterize_glyph(
+                    &fonts[glyph.font],
+                    scale,
+                    glyph.id,
+                    (line_x as f32 + glyph.x, baseline + glyph.y),
+                )
+            }));
+        }
+
+        let black = Rgb([0, 0, 0]);
+        if caption.shadow {
+            let offset = (size as i32 / 16).max(1);
+            draw_glyphs(
+                &mut view,
+                &sprites,
+                (offset, offset),
+                black,
+                0.6 * opacity,
+                false,
+            );
+        }
+        let outline = caption.outline as i32;
+        for dy in -outline..=outline {
+            for dx in -outline..=outline {
+                if (dx, dy) != (0, 0) && dx * dx + dy * dy <= outline * outline {
+                    draw_glyphs(&mut view, &sprites, (dx, dy), black, opacity, false);
+                }
             }
         }
+        draw_glyphs(&mut view, &sprites, (0, 0), color, opacity, true);
     }
     view
 }
+
+/// Stamps glyphs onto the view. Outlines are filled with `color`; emoji bitmaps keep their own
+/// colors when `image_colors` is set, and otherwise contribute only their alpha (for shadow and
+/// outline layers).
+#[cfg(feature = "captions")]
+fn draw_glyphs(
+    view: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+    sprites: &[GlyphSprite],
+    (dx, dy): (i32, i32),
+    color: Rgb<u8>,
+    opacity: f32,
+    image_colors: bool,
+) {
+    for sprite in sprites {
+        match sprite {
+            GlyphSprite::Outline(glyph) => {
+                let bounds = glyph.px_bounds();
+                glyph.draw(|gx, gy, coverage| {
+                    let gx = gx as i32 + bounds.min.x as i32 + dx;
+                    let gy = gy as i32 + bounds.min.y as i32 + dy;
+                    blend_pixel(view, gx, gy, color, coverage * opacity);
+                });
+            }
+            GlyphSprite::Image { left, top, image } => {
+                for (ix, iy, pixel) in image.enumerate_pixels() {
+                    let fill = if image_colors {
+                        Rgb([pixel[0], pixel[1], pixel[2]])
+                    } else {
+                        color
+                    };
+                    blend_pixel(
+                        view,
+                        left + ix as i32 + dx,
+                        top + iy as i32 + dy,
+                        fill,
+                        pixel[3] as f32 / 255.0 * opacity,
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "captions")]
+fn blend_pixel(
+    view: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+    x: i32,
+    y: i32,
+    color: Rgb<u8>,
+    intensity: f32,
+) {
+    if x >= 0 && x < view.width() as i32 && y >= 0 && y < view.height() as i32 {
+        let pixel = view.get_pixel_mut(x as u32, y as u32);
+        *pixel = Rgb([
+            ((1.0 - intensity) * pixel[0] as f32 + intensity * color[0] as f32) as u8,
+            ((1.0 - intensity) * pixel[1] as f32 + intensity * color[1] as f32) as u8,
+            ((1.0 - intensity) * pixel[2] as f32 + intensity * color[2] as f32) as u8,
+        ]);
+    }
+}
@@ -0,0 +1,84 @@
+//! Command line helpers shared by the binaries: shell completions, and value parsers that reject
+//! bad arguments with a usage error before any work starts.
+
+use crate::background::Background;
+use crate::quilt::devices;
+use clap::Parser;
+use clap_complete::Shell;
+
+/// Largest accepted `--fov`, in degrees.
+pub const MAX_FOV: f32 = 120.0;
+
+/// Parses the command line like [`Parser::parse`], except that with `--completions <SHELL>` the
+/// completion script for `A` is printed and the process exits. `A` declares the flag as an
+/// exclusive `completions: Option<Shell>`, so required arguments may be left out with it.
+pub fn parse<A: Parser>() -> A {
+    let mut command = A::command();
+    let mut matches = command.get_matches_mut();
+    if let Some(shell) = matches.get_one::<Shell>("completions").copied() {
+        let name = command
+            .get_bin_name()
+            .unwrap_or(command.get_name())
+            .to_string();
+        clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        std::process::exit(0);
+    }
+    A::from_arg_matches_mut(&mut matches).unwrap_or_else(|e| e.format(&mut A::command()).exit())
+}
+
+/// Checks a `--bg` value, which is kept as written.
+pub fn parse_background(s: &str) -> Result<String, String> {
+    s.parse::<Background>()?;
+    Ok(s.to_string())
+}
+
+/// Checks a color such as `--border-color` or `--matte`, which is kept as written: black, sky, debug, an rgb
+/// triplet or a hex code.
+pub fn parse_color(s: &str) -> Result<String, String> {
+    crate::background::parse_color(s).ok_or_else(|| {
+        format!("invalid color '{s}', expected black, sky, debug, R,G,B or #RRGGBB")
+    })?;
+    Ok(s.to_string())
+}
+
+/// Field of view in degrees, from 0 to [`MAX_FOV`].
+pub fn parse_fov(s: &str) -> Result<f32, String> {
+    let fov: f32 = s
+        .parse()
+        .map_err(|_| format!("expected degrees, got '{}'", s))?;
+    if !(0.0..=MAX_FOV).contains(&fov) {
+        return Err(format!("must be from 0 to {} degrees", MAX_FOV));
+    }
+    Ok(fov)
+}
+
+/// A zoom factor, any finite number above 0.
+pub fn parse_zoom(s: &str) -> Result<f32, String> {
+    let zoom: f32 = s
+        .parse()
+        .map_err(|_| format!("expected a zoom factor, got '{}'", s))?;
+    if !(zoom.is_finite() && zoom > 0.0) {
+        return Err("must be a number above 0".to_string());
+    }
+    Ok(zoom)
+}
+
+/// A device name or alias from [`devices`], in any case. Returned as the device's full name, so
+/// `GO`, `go` and `Looking Glass Go` name the same device in batch_depth's database.
+pub fn parse_device(s: &str) -> Result<String, String> {
+    if let Some(preset) = devices().find(|preset| preset.matches(s)) {
+        return Ok(preset.name.to_string());
+    }
+    let mut known: Vec<&str> = Vec::new();
+    for preset in devices() {
+        let name = preset.aliases.first().unwrap_or(&preset.name);
+        if !known.contains(name) {
+            known.push(name);
+        }
+    }
+    Err(format!(
+        "unknown device '{}'; known devices: {}",
+        s,
+        known.join(", ")
+    ))
+}
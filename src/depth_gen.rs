@@ -1,22 +1,487 @@
-use crate::image_types::{DepthImage, TextureImage};
-use image::ImageBuffer;
+use crate::image_types::{DepthImage, RgbdImage, TextureImage};
+use crate::metrics::METRICS;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Ordering;
 use std::error::Error;
+use std::io::ErrorKind;
+use std::net::TcpStream;
 use std::path::PathBuf;
-use std::rc::Rc;
-use tungstenite::{connect, Message};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::handshake::client::Request;
+use tungstenite::http::{HeaderName, HeaderValue};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{connect, Message, WebSocket};
 use ureq_multipart::MultipartBuilder;
 use url::Url;
 
+use base64::prelude::{Engine, BASE64_STANDARD};
 use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::Path;
 
+/// The address of a ComfyUI server started with its default settings.
+pub const DEFAULT_COMFY_URL: &str = "http://127.0.0.1:8188";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DepthConfig {
     pub comfy_url: String,
     pub cache_dir: Option<PathBuf>,
+    /// `user:password` for HTTP basic auth in front of the ComfyUI server.
+    #[serde(default)]
+    pub comfy_auth: Option<String>,
+    /// Extra headers sent with every HTTP and websocket request to ComfyUI.
+    #[serde(default)]
+    pub comfy_headers: Vec<(String, String)>,
+    /// Websocket client id. Defaults to an id unique to this process so concurrent runs
+    /// against one server don't receive each other's results.
+    #[serde(default)]
+    pub client_id: Option<String>,
+    /// Suffix for existing depth maps next to the input, e.g. `_depth` for `photo_depth.png`.
+    /// When unset, both `photo_depth.png` and `photo.depth.png` are tried.
+    #[serde(default)]
+    pub depth_suffix: Option<String>,
+    #[serde(default)]
+    pub depth_model: Option<String>,
+    #[serde(default)]
+    pub workflow_overrides: Vec<WorkflowOverride>,
+    /// URL of a `depthd` daemon to ask for depth maps instead of running the workflow here. The
+    /// daemon's own model and workflow settings apply.
+    #[serde(default)]
+    pub depthd: Option<String>,
+    /// Where prompts join the ComfyUI queue.
+    #[serde(default)]
+    pub priority: QueuePriority,
+}
+
+// The ComfyUI connection and depth workflow options the depth generating commands share. A
+// plain comment, as clap would take a doc comment for the description of every command.
+#[derive(clap::Args, Debug, Clone)]
+pub struct DepthArgs {
+    #[arg(long, default_value = DEFAULT_COMFY_URL)]
+    pub comfy_url: String,
+
+    #[arg(long, help = "user:password for HTTP basic auth to the ComfyUI server")]
+    pub comfy_auth: Option<String>,
+
+    #[arg(
+        long = "comfy-header",
+        value_name = "NAME=VALUE",
+        value_parser = parse_header,
+        help = "Extra header for ComfyUI HTTP and websocket requests. Repeatable."
+    )]
+    pub comfy_headers: Vec<(String, String)>,
+
+    #[arg(
+        long,
+        help = "ComfyUI websocket client id [default: unique per process]"
+    )]
+    pub client_id: Option<String>,
+
+    #[arg(
+        long,
+        help = "Depth model checkpoint for the depth workflow, e.g. depth_anything_v2_vits_fp16.safetensors"
+    )]
+    pub depth_model: Option<String>,
+
+    #[arg(
+        long = "workflow-set",
+        value_name = "NODE:INPUT=VALUE",
+        help = "Override a depth workflow input; NODE is a node id or class_type. Repeatable."
+    )]
+    pub workflow_set: Vec<WorkflowOverride>,
+
+    #[arg(
+        long,
+        default_value = "back",
+        value_enum,
+        help = "Where prompts join the ComfyUI queue: back, or front to jump ahead of other clients' waiting prompts"
+    )]
+    pub priority: QueuePriority,
+}
+
+/// Runs the bundled workflow on a local ComfyUI server, without a cache, sidecars or a depthd
+/// daemon.
+impl Default for DepthConfig {
+    fn default() -> Self {
+        DepthConfig {
+            comfy_url: DEFAULT_COMFY_URL.to_string(),
+            cache_dir: None,
+            comfy_auth: None,
+            comfy_headers: Vec::new(),
+            client_id: None,
+            depth_suffix: None,
+            depth_model: None,
+            workflow_overrides: Vec::new(),
+            depthd: None,
+            priority: QueuePriority::default(),
+        }
+    }
+}
+
+/// A config that runs the workflow on ComfyUI, without a cache, sidecars or a depthd daemon.
+impl From<DepthArgs> for DepthConfig {
+    fn from(args: DepthArgs) -> Self {
+        DepthConfig {
+            comfy_url: args.comfy_url,
+            comfy_auth: args.comfy_auth,
+            comfy_headers: args.comfy_headers,
+            client_id: args.client_id,
+            depth_model: args.depth_model,
+            workflow_overrides: args.workflow_set,
+            priority: args.priority,
+            ..DepthConfig::default()
+        }
+    }
+}
+
+/// Where a prompt joins the ComfyUI queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueuePriority {
+    /// Behind the prompts already waiting.
+    #[default]
+    Back,
+    /// Ahead of the prompts already waiting, to jump the queue of a shared server. The running
+    /// prompt still finishes first.
+    Front,
+}
+
+/// The prompts in the ComfyUI queue, by prompt id.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueueStatus {
+    pub running: Vec<String>,
+    pub pending: Vec<String>,
+}
+
+static PROCESS_CLIENT_ID: LazyLock<String> = LazyLock::new(|| {
+    format!(
+        "quilt_painter-{}-{:x}",
+        std::process::id(),
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default()
+    )
+});
+
+impl DepthConfig {
+    pub fn client_id(&self) -> String {
+        self.client_id
+            .clone()
+            .unwrap_or_else(|| PROCESS_CLIENT_ID.clone())
+    }
+
+    pub(crate) fn request_headers(&self) -> Vec<(String, String)> {
+        let mut headers = self.comfy_headers.clone();
+        if let Some(auth) = &self.comfy_auth {
+            headers.push((
+                "Authorization".into(),
+                format!("Basic {}", BASE64_STANDARD.encode(auth)),
+            ));
+        }
+        headers
+    }
+
+    /// Full URL of `path` on the ComfyUI server.
+    pub(crate) fn endpoint(&self, path: &str) -> String {
+        format!("{}{}", self.comfy_url.trim_end_matches('/'), path)
+    }
+
+    /// Starts a POST request to `path` on the ComfyUI server with the configured headers.
+    pub fn post(&self, path: &str) -> ureq::Request {
+        let url = self.endpoint(path);
+        self.request_headers()
+            .iter()
+            .fold(ureq::post(&url), |request, (name, value)| {
+                request.set(name, value)
+            })
+    }
+
+    /// Starts a GET request to `path` on the ComfyUI server with the configured headers.
+    pub fn get(&self, path: &str) -> ureq::Request {
+        let url = self.endpoint(path);
+        self.request_headers()
+            .iter()
+            .fold(ureq::get(&url), |request, (name, value)| {
+                request.set(name, value)
+            })
+    }
+
+    /// Body of a POST to `/prompt` queueing `workflow`.
+    pub fn prompt_request(&self, workflow: &Value) -> Value {
+        let mut request = serde_json::json!({
+            "prompt": workflow,
+            "client_id": self.client_id()
+        });
+        if self.priority == QueuePriority::Front {
+            request["front"] = Value::Bool(true);
+        }
+        request
+    }
+
+    /// Websocket URL for `path` on the ComfyUI server: ws for http and wss for https.
+    pub fn websocket_url(&self, path: &str) -> Result<Url, Box<dyn Error>> {
+        let mut url = Url::parse(&self.endpoint(path))?;
+        let scheme = match url.scheme() {
+            "http" | "ws" => "ws",
+            "https" | "wss" => "wss",
+            other => return Err(format!("unsupported ComfyUI URL scheme: {}", other).into()),
+        };
+        url.set_scheme(scheme)
+            .map_err(|_| format!("cannot use {} as a websocket URL", url))?;
+        Ok(url)
+    }
+
+    /// Websocket handshake request for `path` carrying the configured headers.
+    pub(crate) fn websocket_request(&self, path: &str) -> Result<Request, Box<dyn Error>> {
+        let mut request = self.websocket_url(path)?.as_str().into_client_request()?;
+        for (name, value) in self.request_headers() {
+            request.headers_mut().insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(&value)?,
+            );
+        }
+        Ok(request)
+    }
+
+    /// Opens the ComfyUI websocket at `path` with the configured headers.
+    pub fn connect_websocket(
+        &self,
+        path: &str,
+    ) -> Result<WebSocket<MaybeTlsStream<TcpStream>>, Box<dyn Error>> {
+        let (socket, _) = connect(self.websocket_request(path)?)?;
+        Ok(socket)
+    }
+}
+
+/// Parses a `name=value` command line header.
+pub fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected name=value, got {:?}", s))?;
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
+
+/// Where to keep copies of generated depth results outside the hash-named cache, so they can be
+/// inspected, edited, and reused. The depth map is written as `<stem>_depth.png`, which is also
+/// the default sidecar name picked up by [`DepthConfig::find_depth_sidecar`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DepthOutputs {
+    pub rgbd_dir: Option<PathBuf>,
+    pub depth_dir: Option<PathBuf>,
+}
+
+impl DepthOutputs {
+    pub fn save(
+        &self,
+        input_path: &Path,
+        texture: &TextureImage,
+        depth: &DepthImage,
+    ) -> Result<(), Box<dyn Error>> {
+        let stem = input_path
+            .file_stem()
+            .ok_or("input path does not contain a file name")?
+            .to_string_lossy();
+
+        if let Some(depth_dir) = &self.depth_dir {
+            fs::create_dir_all(depth_dir)?;
+            let depth_path = depth_dir.join(format!("{}_depth.png", stem));
+            depth.0.save(&depth_path)?;
+            crate::status!("Saved depth map to: {}", depth_path.display());
+        }
+
+        if let Some(rgbd_dir) = &self.rgbd_dir {
+            fs::create_dir_all(rgbd_dir)?;
+            let rgbd_path = rgbd_dir.join(format!("{}_rgbd.png", stem));
+            RgbdImage::from((texture.clone(), depth.clone()))
+                .0
+                .save(&rgbd_path)?;
+            crate::status!("Saved combined RGBD image to: {}", rgbd_path.display());
+        }
+        Ok(())
+    }
+}
+
+/// A manual touch-up of each generated depth map before it is rendered: the map is written to a
+/// temporary PNG, handed to `command` and/or left for the user until they press Enter, and read
+/// back. Runs before [`DepthOutputs::save`], so `--save-depth` keeps the edited map, which later
+/// runs pick up as a sidecar.
+#[derive(Clone, Debug, Default)]
+pub struct DepthEdit {
+    /// Shell command to run on the map; `{}` is replaced by its path, which is otherwise
+    /// appended. The edit continues when the command exits, so GUI editors must be told to
+    /// wait, e.g. `gimp` rather than a launcher that returns at once.
+    pub command: Option<String>,
+    /// Wait for Enter on the terminal after writing the map (and running `command`).
+    pub pause: bool,
+}
+
+impl DepthEdit {
+    pub fn is_enabled(&self) -> bool {
+        self.command.is_some() || self.pause
+    }
+
+    /// Returns `depth` as edited, resized back to its original size if the editor changed it.
+    pub fn apply(
+        &self,
+        input_path: &Path,
+        depth: DepthImage,
+    ) -> Result<DepthImage, Box<dyn Error>> {
+        if !self.is_enabled() {
+            return Ok(depth);
+        }
+        let stem = input_path
+            .file_stem()
+            .ok_or("input path does not contain a file name")?
+            .to_string_lossy();
+        let edit_path =
+            std::env::temp_dir().join(format!("{}_depth_edit_{}.png", stem, std::process::id()));
+        depth.0.save(&edit_path)?;
+        let edited = self.edit(&edit_path);
+        let _ = fs::remove_file(&edit_path);
+        let edited = edited?;
+
+        let (width, height) = depth.0.dimensions();
+        if edited.dimensions() != (width, height) {
+            tracing::warn!(
+                "Edited depth map is {}x{}; scaling back to {}x{}",
+                edited.width(),
+                edited.height(),
+                width,
+                height
+            );
+            return Ok(DepthImage(image::imageops::resize(
+                &edited,
+                width,
+                height,
+                image::imageops::FilterType::Triangle,
+            )));
+        }
+        Ok(DepthImage(edited))
+    }
+
+    fn edit(&self, edit_path: &Path) -> Result<image::RgbImage, Box<dyn Error>> {
+        let path = edit_path.to_string_lossy();
+        if let Some(command) = &self.command {
+            let command = if command.contains("{}") {
+                command.replace("{}", &shell_quote(&path))
+            } else {
+                format!("{} {}", command, shell_quote(&path))
+            };
+            crate::status!("Editing depth map: {}", command);
+            let status = shell(&command).status()?;
+            if !status.success() {
+                return Err(format!("depth edit command failed ({}): {}", status, command).into());
+            }
+        }
+        if self.pause {
+            crate::status!(
+                "Depth map written to {}; edit it, then press Enter to continue",
+                path
+            );
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+        }
+        Ok(image::open(edit_path)?.to_rgb8())
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> std::process::Command {
+    let mut shell = std::process::Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> std::process::Command {
+    let mut shell = std::process::Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(unix)]
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+#[cfg(not(unix))]
+fn shell_quote(arg: &str) -> String {
+    format!("\"{}\"", arg)
+}
+
+/// A single `node:input=value` patch applied to a loaded ComfyUI workflow. `node` matches
+/// either a node id or a node class_type; `value` is parsed as JSON, falling back to a string.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowOverride {
+    pub node: String,
+    pub input: String,
+    pub value: Value,
+}
+
+impl std::str::FromStr for WorkflowOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected node:input=value, got {:?}", s))?;
+        let (node, input) = target
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected node:input=value, got {:?}", s))?;
+        if node.is_empty() || input.is_empty() {
+            return Err(format!("expected node:input=value, got {:?}", s));
+        }
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into()));
+        Ok(WorkflowOverride {
+            node: node.into(),
+            input: input.into(),
+            value,
+        })
+    }
+}
+
+/// Applies the depth model selection and input overrides from `config` to `workflow`.
+pub fn apply_workflow_overrides(
+    workflow: &mut Value,
+    config: &DepthConfig,
+) -> Result<(), Box<dyn Error>> {
+    let nodes = workflow
+        .as_object_mut()
+        .ok_or("workflow is not a JSON object")?;
+
+    if let Some(model) = &config.depth_model {
+        let mut found = false;
+        for node in nodes.values_mut() {
+            if node["class_type"] == "DownloadAndLoadDepthAnythingV2Model" {
+                node["inputs"]["model"] = Value::String(model.clone());
+                found = true;
+            }
+        }
+        if !found {
+            return Err("Could not find a depth model loader node in workflow".into());
+        }
+    }
+
+    for patch in &config.workflow_overrides {
+        let mut found = false;
+        for (id, node) in nodes.iter_mut() {
+            if *id == patch.node || node["class_type"] == patch.node.as_str() {
+                node["inputs"][&patch.input] = patch.value.clone();
+                found = true;
+            }
+        }
+        if !found {
+            return Err(
+                format!("No workflow node matches override target {:?}", patch.node).into(),
+            );
+        }
+    }
+    Ok(())
 }
 
 fn create_cache_key(input_path: &Path, config: &DepthConfig) -> Result<String, Box<dyn Error>> {
@@ -28,53 +493,79 @@ fn create_cache_key(input_path: &Path, config: &DepthConfig) -> Result<String, B
 
     // Hash relevant config settings that affect the output
     hasher.update(config.comfy_url.as_bytes());
+    if let Some(model) = &config.depth_model {
+        hasher.update(model.as_bytes());
+    }
+    for patch in &config.workflow_overrides {
+        hasher.update(format!("{}:{}={}", patch.node, patch.input, patch.value).as_bytes());
+    }
 
     let result = format!("{:x}", hasher.finalize());
     Ok(result)
 }
 
-type TextDispatchFn<'a> = Box<dyn Fn(&str) + 'a>;
-type BinaryDispatchFn<'a> = Box<dyn Fn(&[u8]) -> Result<(), Box<dyn Error>> + 'a>;
-
-struct WsMessageHandler<'a> {
+/// Follows the websocket messages for one prompt, keeping the image its output node sends.
+/// Messages about other clients' prompts on the same server are ignored.
+pub struct PromptMessages {
+    prompt_id: String,
+    output_node: String,
     current_node: String,
-    node_dispatch_text: HashMap<String, TextDispatchFn<'a>>,
-    node_dispatch_binary: HashMap<String, BinaryDispatchFn<'a>>,
+    image: Option<Vec<u8>>,
 }
 
-impl<'a> WsMessageHandler<'a> {
-    fn handle_ws_message(&mut self, msg: Message) -> Result<bool, Box<dyn Error>> {
+impl PromptMessages {
+    pub fn new(prompt_id: &str, output_node: &str) -> Self {
+        Self {
+            prompt_id: prompt_id.to_string(),
+            output_node: output_node.to_string(),
+            current_node: String::new(),
+            image: None,
+        }
+    }
+
+    /// Takes in the next message. Returns whether the prompt has finished.
+    pub fn handle(&mut self, msg: Message) -> Result<bool, Box<dyn Error>> {
         match msg {
             Message::Text(text) => {
                 let data: Value = serde_json::from_str(&text)?;
+                if data["data"]["prompt_id"]
+                    .as_str()
+                    .is_some_and(|id| id != self.prompt_id)
+                {
+                    // Progress for someone else's prompt; drop any binary output that follows.
+                    self.current_node.clear();
+                    return Ok(false);
+                }
                 if data["type"] == "executing" {
-                    if let Some(node) = data["data"]["node"].as_str() {
-                        self.current_node = node.into();
-
-                        if let Some(handler) = self.node_dispatch_text.get(&self.current_node) {
-                            handler(&text);
-                        }
-                    } else {
-                        return Ok(true); // Execution complete
+                    match data["data"]["node"].as_str() {
+                        Some(node) => self.current_node = node.into(),
+                        None => return Ok(true), // Execution complete
                     }
                 }
                 Ok(false)
             }
             Message::Binary(bytes) => {
-                if let Some(handler) = self.node_dispatch_binary.get(&self.current_node) {
-                    handler(&bytes)?;
+                if self.current_node == self.output_node {
+                    // first 8 bytes are some id (1, 2) in 4 byte ints.
+                    let image = bytes.get(8..).ok_or("short image message from ComfyUI")?;
+                    self.image = Some(image.to_vec());
                 }
                 Ok(false)
             }
-            Message::Ping(_) => Ok(false),
-            Message::Pong(_) => Ok(false),
             Message::Close(_) => Ok(true),
-            Message::Frame(_) => Ok(false),
+            Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => Ok(false),
         }
     }
+
+    /// The image the output node sent.
+    pub fn into_image(self) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.image
+            .ok_or_else(|| "workflow finished without producing an image".into())
+    }
 }
 
-fn find_node_id(workflow: &Value, class_type: &str) -> Option<String> {
+/// The id of the first node of `class_type` in `workflow`.
+pub fn find_node_id(workflow: &Value, class_type: &str) -> Option<String> {
     workflow
         .as_object()?
         .iter()
@@ -82,63 +573,275 @@ fn find_node_id(workflow: &Value, class_type: &str) -> Option<String> {
         .map(|(id, _)| id.to_string())
 }
 
-pub fn generate_depth(
-    input_path: PathBuf,
+/// Queues `workflow` on the ComfyUI server and waits for the encoded image emitted by its
+/// SaveImageWebsocket node.
+pub fn run_workflow(workflow: &Value, config: &DepthConfig) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Connect to the websocket before queueing so no messages for the prompt are missed
+    let mut socket = config.connect_websocket(&format!("/ws?clientId={}", config.client_id()))?;
+    run_workflow_on(&mut socket, workflow, config)
+}
+
+/// As `run_workflow`, over a websocket already open for `config.client_id()`, which stays open
+/// for the next workflow.
+pub fn run_workflow_on(
+    socket: &mut WebSocket<MaybeTlsStream<TcpStream>>,
+    workflow: &Value,
     config: &DepthConfig,
-) -> Result<(TextureImage, DepthImage), Box<dyn Error>> {
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Find the SaveImageWebsocket node ID
+    let save_image_node_id = find_node_id(workflow, "SaveImageWebsocket")
+        .ok_or("Could not find SaveImageWebSocket node in workflow")?;
+
+    // Queue the prompt
+    let queue_span = tracing::info_span!("queue_prompt").entered();
+    let prompt_response: Value = config
+        .post("/prompt")
+        .send_json(config.prompt_request(workflow))?
+        .into_json()?;
+    queue_span.exit();
+
+    let prompt_id = prompt_response["prompt_id"]
+        .as_str()
+        .ok_or("ComfyUI did not return a prompt_id")?
+        .to_string();
+    tracing::debug!("Workflow queued with prompt_id: {}", prompt_id);
+    if let Ok(queue) = queue_status(config) {
+        if let Some(position) = queue.pending.iter().position(|id| *id == prompt_id) {
+            tracing::info!(
+                "Waiting for {} prompts ahead in the ComfyUI queue",
+                queue.running.len() + position
+            );
+        }
+    }
+
+    // Ctrl-C cancels the prompt instead of leaving the server working for nobody
+    let _deferred = crate::interrupt::defer();
+    set_read_timeout(socket, Some(INTERRUPT_POLL))?;
+
+    // Wait for completion and image data
+    let mut messages = PromptMessages::new(&prompt_id, &save_image_node_id);
+    {
+        let _span = tracing::info_span!("websocket_wait", prompt_id = %prompt_id).entered();
+        loop {
+            let message = match socket.read() {
+                Ok(message) => message,
+                Err(tungstenite::Error::Io(e))
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    if crate::interrupt::interrupted() {
+                        eprintln!("Interrupted; cancelling ComfyUI prompt {}", prompt_id);
+                        cancel_prompt(config, &prompt_id)?;
+                        return Err("interrupted".into());
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if messages.handle(message)? {
+                break;
+            }
+        }
+    }
+    set_read_timeout(socket, None)?;
+
+    messages.into_image()
+}
+
+/// How often a wait for ComfyUI checks for Ctrl-C.
+const INTERRUPT_POLL: Duration = Duration::from_millis(250);
+
+fn set_read_timeout(
+    socket: &WebSocket<MaybeTlsStream<TcpStream>>,
+    timeout: Option<Duration>,
+) -> std::io::Result<()> {
+    match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream.set_read_timeout(timeout),
+        MaybeTlsStream::NativeTls(stream) => stream.get_ref().set_read_timeout(timeout),
+        _ => Ok(()),
+    }
+}
+
+/// The running and pending prompts of the ComfyUI server.
+pub fn queue_status(config: &DepthConfig) -> Result<QueueStatus, Box<dyn Error>> {
+    let queue: Value = config.get("/queue").call()?.into_json()?;
+    // Each entry is [number, prompt_id, prompt, extra_data, outputs_to_execute]
+    let ids = |key: &str| -> Vec<String> {
+        queue[key]
+            .as_array()
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| entry[1].as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let mut pending = ids("queue_pending");
+    // Kept in a heap on the server, so listed in no particular order; this is the run order
+    let number = |id: &String| {
+        queue["queue_pending"].as_array().and_then(|entries| {
+            entries
+                .iter()
+                .find(|entry| entry[1].as_str() == Some(id))
+                .and_then(|entry| entry[0].as_f64())
+        })
+    };
+    pending.sort_by(|a, b| number(a).partial_cmp(&number(b)).unwrap_or(Ordering::Equal));
+    Ok(QueueStatus {
+        running: ids("queue_running"),
+        pending,
+    })
+}
+
+/// Stops the prompt `prompt_id`: deletes it from the queue if it hasn't started, or interrupts
+/// it if it is running. Does nothing if it already finished.
+pub fn cancel_prompt(config: &DepthConfig, prompt_id: &str) -> Result<(), Box<dyn Error>> {
+    let queue = queue_status(config)?;
+    if queue.pending.iter().any(|id| id == prompt_id) {
+        config
+            .post("/queue")
+            .send_json(serde_json::json!({ "delete": [prompt_id] }))?;
+    } else if queue.running.iter().any(|id| id == prompt_id) {
+        // Servers that predate the prompt_id field interrupt whatever is running, which is
+        // checked to be this prompt
+        config
+            .post("/interrupt")
+            .send_json(serde_json::json!({ "prompt_id": prompt_id }))?;
+    }
+    Ok(())
+}
+
+/// Metadata written next to each cache entry. The depth map is kept as 16-bit grayscale, rather
+/// than squeezed into the old combined 8-bit RGBD cache, and only reduced to 8 bits where the
+/// `DepthImage` for rendering is built.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheManifest {
+    pub source_sha256: String,
+    pub workflow_sha256: String,
+    pub created_unix: u64,
+    pub texture: String,
+    pub depth: String,
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn cache_entry(
+    input_path: &Path,
+    config: &DepthConfig,
+) -> Result<Option<(PathBuf, String)>, Box<dyn Error>> {
+    match &config.cache_dir {
+        Some(cache_dir) => Ok(Some((
+            cache_dir.clone(),
+            create_cache_key(input_path, config)?,
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// Returns the cached texture and depth for `input_path`, if caching is enabled and present.
+pub(crate) fn load_cached_rgbd(
+    input_path: &Path,
+    config: &DepthConfig,
+) -> Result<Option<(TextureImage, DepthImage)>, Box<dyn Error>> {
     // Create cache directory if it doesn't exist
     if let Some(cache_dir) = &config.cache_dir {
         fs::create_dir_all(cache_dir)?;
+    }
 
-        // Generate cache key
-        let cache_key = create_cache_key(&input_path, config)?;
-        let cache_path = cache_dir.join(format!("{}_rgbd.png", cache_key));
-        // Check if cached version exists
-        if cache_path.exists() {
-            log::debug!("Loading cached RGBD image from: {}", cache_path.display());
-            log::debug!("Loading cached RGBD image from: {}", cache_path.display());
-            let cached_image = image::open(&cache_path)?.to_rgb8();
-            let width = cached_image.width();
-            let half_width = width / 2;
-            let height = cached_image.height();
-
-            // Split the cached image into texture and depth components
-            let mut texture = ImageBuffer::new(half_width, height);
-            let mut depth = ImageBuffer::new(half_width, height);
-
-            for y in 0..height {
-                for x in 0..half_width {
-                    texture.put_pixel(x, y, *cached_image.get_pixel(x, y));
-                    depth.put_pixel(x, y, *cached_image.get_pixel(x + half_width, y));
-                }
-            }
+    let Some((cache_dir, cache_key)) = cache_entry(input_path, config)? else {
+        return Ok(None);
+    };
 
-            log::debug!("Successfully loaded cached RGBD image");
-            return Ok((TextureImage(texture), DepthImage(depth)));
-        }
+    let manifest_path = cache_dir.join(format!("{}.json", cache_key));
+    if manifest_path.exists() {
+        tracing::debug!("Loading cached depth from: {}", manifest_path.display());
+        let manifest: CacheManifest = serde_json::from_str(&fs::read_to_string(&manifest_path)?)?;
+        let texture = image::open(cache_dir.join(&manifest.texture))?.to_rgb8();
+        let depth = image::open(cache_dir.join(&manifest.depth))?.to_luma16();
+        tracing::debug!("Successfully loaded cached depth");
+        return Ok(Some((
+            TextureImage(texture),
+            depth_image(&DynamicImage::ImageLuma16(depth)),
+        )));
     }
 
-    // If not cached, generate new depth map
-    log::debug!("No cached version found, generating new depth map");
+    // Entries written before the manifest existed are a single side-by-side RGBD image.
+    let legacy_path = cache_dir.join(format!("{}_rgbd.png", cache_key));
+    if legacy_path.exists() {
+        tracing::debug!("Loading cached RGBD image from: {}", legacy_path.display());
+        let cached_image = image::open(&legacy_path)?.to_rgb8();
+
+        // Split the cached image into texture and depth components
+        let (texture, depth) = RgbdImage(cached_image).split();
+
+        tracing::debug!("Successfully loaded cached RGBD image");
+        return Ok(Some((texture, depth)));
+    }
+
+    Ok(None)
+}
+
+/// Writes the texture, the full-precision depth and a manifest to the cache, if enabled.
+pub(crate) fn save_cached_rgbd(
+    input_path: &Path,
+    config: &DepthConfig,
+    texture: &TextureImage,
+    depth: &DynamicImage,
+) -> Result<(), Box<dyn Error>> {
+    let Some((cache_dir, cache_key)) = cache_entry(input_path, config)? else {
+        return Ok(());
+    };
+
+    let manifest = CacheManifest {
+        source_sha256: sha256_hex(&fs::read(input_path)?),
+        workflow_sha256: sha256_hex(depth_workflow(config)?.to_string().as_bytes()),
+        created_unix: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        texture: format!("{}_texture.png", cache_key),
+        depth: format!("{}_depth16.png", cache_key),
+    };
+
+    texture.0.save(cache_dir.join(&manifest.texture))?;
+    depth.to_luma16().save(cache_dir.join(&manifest.depth))?;
+    let manifest_path = cache_dir.join(format!("{}.json", cache_key));
+    fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    tracing::debug!("Saved depth to cache: {}", manifest_path.display());
+    Ok(())
+}
+
+/// The 8-bit depth map the renderer reads, from a depth image of any precision.
+pub(crate) fn depth_image(depth: &DynamicImage) -> DepthImage {
+    DepthImage(depth.to_rgb8())
+}
 
-    // Load the workflow template
+/// Loads the bundled depth workflow with the overrides from `config` applied.
+pub(crate) fn depth_workflow(config: &DepthConfig) -> Result<Value, Box<dyn Error>> {
     let workflow_str = include_str!("../data/DepthWorkflow.json");
     let mut workflow: Value = serde_json::from_str(workflow_str)?;
+    apply_workflow_overrides(&mut workflow, config)?;
+    Ok(workflow)
+}
 
+/// Loads the input image, applying its EXIF orientation.
+pub(crate) fn load_oriented_image(input_path: &Path) -> Result<DynamicImage, Box<dyn Error>> {
     use image::io::Reader as ImageReader;
     use std::fs::File;
     use std::io::BufReader;
 
     // Load input image with EXIF orientation
-    let reader = ImageReader::open(&input_path)?;
+    let reader = ImageReader::open(input_path)?;
 
     // Read and decode the image
     let img = reader.decode()?.to_rgb8();
 
     // Create EXIF reader and try to read EXIF data from file directly
-    let file = File::open(&input_path)?;
+    let file = File::open(input_path)?;
     let exif_reader = exif::Reader::new();
-    let mut rotated = image::DynamicImage::ImageRgb8(img.clone());
+    let mut rotated = DynamicImage::ImageRgb8(img);
     rotated = match exif_reader.read_from_container(&mut BufReader::new(file)) {
         Ok(exif_data) => {
             match exif_data.get_field(exif::Tag::Orientation, exif::In::PRIMARY) {
@@ -153,153 +856,266 @@ pub fn generate_depth(
                         Some(7) => rotated.fliph().rotate90(), // Mirrored horizontally and rotated 90 degrees
                         Some(8) => rotated.rotate270(),        // Rotated 270 degrees
                         _ => {
-                            log::warn!("Unknown EXIF orientation value, defaulting to 0");
+                            tracing::warn!("Unknown EXIF orientation value, defaulting to 0");
                             rotated
                         }
                     }
                 }
                 None => {
-                    log::debug!("No EXIF orientation tag found");
+                    tracing::debug!("No EXIF orientation tag found");
                     rotated
                 }
             }
         }
         Err(e) => {
-            log::debug!("Failed to read EXIF data: {}", e);
+            tracing::debug!("Failed to read EXIF data: {}", e);
             rotated
         }
     };
 
-    // Use the rotated image instead of raw input
-    let input_image = rotated.clone();
+    Ok(rotated)
+}
+
+/// The path ComfyUI stored an uploaded image under, including its subfolder.
+pub(crate) fn uploaded_image_path(response: &Value, filename: &str) -> String {
+    if let Some(subfolder) = response["subfolder"].as_str() {
+        format!(
+            "{}/{}",
+            subfolder,
+            response["name"].as_str().unwrap_or(filename)
+        )
+    } else {
+        response["name"].as_str().unwrap_or(filename).to_string()
+    }
+}
 
-    let filename = input_path
+/// Uploads the image at `path` to ComfyUI's temp folder and returns the name to give a LoadImage
+/// node.
+pub fn upload_image(path: &Path, config: &DepthConfig) -> Result<String, Box<dyn Error>> {
+    let filename = path
         .file_name()
         .ok_or("input path does not contain a file name")?
         .to_string_lossy();
 
     // Upload image as multipart form with temp subfolder
     let (content_type, data) = MultipartBuilder::new()
-        .add_file("image", &input_path)
+        .add_file("image", path)
         .unwrap()
         .add_text("subfolder", "temp")
         .unwrap()
         .finish()
         .unwrap();
 
-    log::debug!(
+    let upload_span = tracing::info_span!("upload", file = %filename).entered();
+    tracing::debug!(
         "Uploading image {} to {}/upload/image",
         filename,
         config.comfy_url
     );
-    let response: Value = ureq::post(&format!("{}/upload/image", config.comfy_url))
+    let response: Value = config
+        .post("/upload/image")
         .set("Content-Type", &content_type)
         .send_bytes(&data)?
         .into_json()?;
-    log::debug!("Upload complete");
+    tracing::debug!("Upload complete");
+    upload_span.exit();
 
     // Get the full path including subfolder from response
-    let uploaded_path = if let Some(subfolder) = response["subfolder"].as_str() {
-        format!(
-            "{}/{}",
-            subfolder,
-            response["name"].as_str().unwrap_or(&filename)
-        )
-    } else {
-        response["name"].as_str().unwrap_or(&filename).to_string()
-    };
-    log::debug!("Uploaded image path: {}", uploaded_path);
+    let uploaded_path = uploaded_image_path(&response, &filename);
+    tracing::debug!("Uploaded image path: {}", uploaded_path);
+
+    Ok(uploaded_path)
+}
 
-    // Update workflow with uploaded image path
-    let mut load_image = workflow
+/// Points the workflow's LoadImage node at an uploaded image.
+pub fn set_workflow_input_image(
+    workflow: &mut Value,
+    uploaded_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let load_image = workflow
         .as_object_mut()
-        .unwrap()
-        .into_iter()
-        .filter_map(|(_, v)| {
-            if v["class_type"] == "LoadImage" {
-                Some(v)
-            } else {
-                None
-            }
-        })
-        .collect::<Vec<_>>();
-    load_image[0]["inputs"]["image"] = Value::String(uploaded_path.clone());
+        .ok_or("workflow is not a JSON object")?
+        .values_mut()
+        .find(|v| v["class_type"] == "LoadImage")
+        .ok_or("Could not find LoadImage node in workflow")?;
+    load_image["inputs"]["image"] = Value::String(uploaded_path.to_string());
+    Ok(())
+}
 
-    // Find the SaveImageWebsocket node ID
-    let save_image_node_id = find_node_id(&workflow, "SaveImageWebsocket")
-        .ok_or("Could not find SaveImageWebSocket node in workflow")?;
+impl DepthConfig {
+    fn depth_suffixes(&self) -> Vec<&str> {
+        match &self.depth_suffix {
+            Some(suffix) => vec![suffix.as_str()],
+            None => vec!["_depth", ".depth"],
+        }
+    }
 
-    // Queue the prompt
-    let prompt_response: Value = ureq::post(&format!("{}/prompt", config.comfy_url))
-        .send_json(serde_json::json!({
-            "prompt": workflow,
-            "client_id": "depth_charge"
-        }))?
-        .into_json()?;
+    /// Whether `path` is the depth sidecar of another image next to it, as
+    /// [`find_depth_sidecar`](Self::find_depth_sidecar) would find it. A photo that only has a
+    /// sidecar-like name, such as `ocean_depth.jpg`, is not.
+    pub fn is_depth_sidecar(&self, path: &Path) -> bool {
+        if !path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+        {
+            return false;
+        }
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let Ok(siblings) = path.parent().unwrap_or(Path::new(".")).read_dir() else {
+            return false;
+        };
+        let sibling_stems: Vec<String> = siblings
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|sibling| sibling.as_path() != path && sibling.is_file())
+            .filter_map(|sibling| Some(sibling.file_stem()?.to_string_lossy().into_owned()))
+            .collect();
+        self.depth_suffixes().iter().any(|suffix| {
+            stem.strip_suffix(suffix)
+                .is_some_and(|image_stem| sibling_stems.iter().any(|s| s == image_stem))
+        })
+    }
 
-    let prompt_id = prompt_response["prompt_id"].as_str().unwrap();
-    log::debug!("Workflow queued with prompt_id: {}", prompt_id);
+    /// Finds an existing depth map next to `input_path`, such as `photo_depth.png`.
+    pub fn find_depth_sidecar(&self, input_path: &Path) -> Option<PathBuf> {
+        let stem = input_path.file_stem()?.to_string_lossy();
+        self.depth_suffixes()
+            .iter()
+            .map(|suffix| input_path.with_file_name(format!("{}{}.png", stem, suffix)))
+            .find(|path| path.is_file())
+    }
+}
 
-    // Connect to WebSocket
-    let ws_url = Url::parse(&format!(
-        "{}/ws?clientId=depth_charge",
-        config.comfy_url.replace("http", "ws")
-    ))?;
-    let (mut socket, _) = connect(ws_url)?;
+/// Loads the input image together with its depth sidecar, if there is one. The depth map is
+/// resized to match the texture when their sizes differ.
+pub(crate) fn load_depth_sidecar(
+    input_path: &Path,
+    config: &DepthConfig,
+) -> Result<Option<(TextureImage, DepthImage)>, Box<dyn Error>> {
+    let Some(sidecar) = config.find_depth_sidecar(input_path) else {
+        return Ok(None);
+    };
+    tracing::debug!("Using depth sidecar: {}", sidecar.display());
 
-    // Wait for completion and image data
-    let image_bytes = Rc::new(RefCell::new(None));
-    {
-        let save_image: Box<dyn for<'a> Fn(&'a [u8]) -> Result<(), Box<dyn Error>>> =
-            Box::new(|bytes: &[u8]| -> Result<(), Box<dyn Error>> {
-                // first 8 bytes are some id (1, 2) in 4 byte ints.
-                *image_bytes.borrow_mut() = Some(Vec::from(&bytes[8..]));
-                Ok(())
-            });
-
-        let dispatch: HashMap<String, _> = (vec![(save_image_node_id.clone(), save_image)])
-            .into_iter()
-            .collect();
-        let mut handler = WsMessageHandler {
-            current_node: "".into(),
-            node_dispatch_text: HashMap::new(),
-            node_dispatch_binary: dispatch,
-        };
+    let texture = TextureImage(load_oriented_image(input_path)?.to_rgb8());
+    let mut depth = image::open(&sidecar)?.to_rgb8();
+    if depth.dimensions() != texture.dimensions() {
+        depth = image::imageops::resize(
+            &depth,
+            texture.width(),
+            texture.height(),
+            image::imageops::FilterType::Lanczos3,
+        );
+    }
+    Ok(Some((texture, DepthImage(depth))))
+}
 
-        while !handler.handle_ws_message(socket.read()?)? {}
+#[tracing::instrument(skip_all, fields(input = %input_path.display()))]
+pub fn generate_depth(
+    input_path: PathBuf,
+    config: &DepthConfig,
+) -> Result<(TextureImage, DepthImage), Box<dyn Error>> {
+    if let Some(sidecar) = load_depth_sidecar(&input_path, config)? {
+        METRICS.depth_cache_hits.inc();
+        return Ok(sidecar);
+    }
+    if let Some(cached) = load_cached_rgbd(&input_path, config)? {
+        METRICS.depth_cache_hits.inc();
+        return Ok(cached);
     }
 
-    // let input_img = image::load_from_memory(&input_image).unwrap().to_rgb8();
-    let depth_img = image::load_from_memory(&image_bytes.take().expect("expected an image"))
-        .unwrap()
-        .to_rgb8();
+    // If not cached, generate new depth map
+    tracing::debug!("No cached version found, generating new depth map");
+    METRICS.depth_cache_misses.inc();
+    let started = Instant::now();
+
+    // Use the rotated image instead of raw input
+    let input_image = load_oriented_image(&input_path)?;
+
+    let depth_bytes = match &config.depthd {
+        Some(depthd) => crate::depthd::request_depth(depthd, &input_path)?,
+        None => {
+            // Load the workflow template
+            let mut workflow = depth_workflow(config)?;
+
+            let uploaded_path = upload_image(&input_path, config)?;
+
+            // Update workflow with uploaded image path
+            set_workflow_input_image(&mut workflow, &uploaded_path)?;
+
+            run_workflow(&workflow, config)?
+        }
+    };
+    let depth_img = image::load_from_memory(&depth_bytes)?;
+    METRICS.depth_seconds.observe(started.elapsed());
 
     let texture = TextureImage(input_image.to_rgb8());
-    let depth = DepthImage(depth_img);
+    let depth = depth_image(&depth_img);
 
     // Save to cache
-    let mut cached_image = ImageBuffer::new(texture.width() * 2, texture.height());
+    save_cached_rgbd(&input_path, config, &texture, &depth_img)?;
 
-    // Copy texture to left half
-    for y in 0..texture.height() {
-        for x in 0..texture.width() {
-            cached_image.put_pixel(x, y, *texture.0.get_pixel(x, y));
-        }
+    Ok((texture, depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn executing(prompt_id: &str, node: Option<&str>) -> Message {
+        let message = serde_json::json!({
+            "type": "executing",
+            "data": { "prompt_id": prompt_id, "node": node },
+        });
+        Message::Text(message.to_string())
     }
 
-    // Copy depth to right half
-    for y in 0..depth.height() {
-        for x in 0..depth.width() {
-            cached_image.put_pixel(x + texture.width(), y, *depth.0.get_pixel(x, y));
-        }
+    fn image(bytes: &[u8]) -> Message {
+        Message::Binary([&[0, 0, 0, 1, 0, 0, 0, 2], bytes].concat())
     }
 
-    if let Some(cache_dir) = &config.cache_dir {
-        let cache_key = create_cache_key(&input_path, config)?;
-        let cache_path = cache_dir.join(format!("{}_rgbd.png", cache_key));
-        cached_image.save(&cache_path)?;
-        log::debug!("Saved RGBD image to cache: {}", cache_path.display());
+    #[test]
+    fn keeps_only_its_own_prompts_image() {
+        let mut messages = PromptMessages::new("mine", "9");
+        let mut feed = |message| messages.handle(message).unwrap();
+        assert!(!feed(executing("mine", Some("9"))));
+        assert!(!feed(image(b"ours")));
+        // Another client's prompt running the same workflow
+        assert!(!feed(executing("theirs", Some("9"))));
+        assert!(!feed(image(b"theirs")));
+        assert!(!feed(executing("theirs", None)));
+        assert!(feed(executing("mine", None)));
+        assert_eq!(messages.into_image().unwrap(), b"ours");
     }
 
-    Ok((texture, depth))
+    #[test]
+    fn ignores_other_nodes_output() {
+        let mut messages = PromptMessages::new("mine", "9");
+        assert!(!messages.handle(executing("mine", Some("3"))).unwrap());
+        assert!(!messages.handle(image(b"preview")).unwrap());
+        assert!(messages.handle(executing("mine", None)).unwrap());
+        assert!(messages.into_image().is_err());
+    }
+
+    #[test]
+    fn workflow_overrides() {
+        let set = |s: &str| s.parse::<WorkflowOverride>();
+        let over = set("KSampler:steps=20").unwrap();
+        assert_eq!(over.node, "KSampler");
+        assert_eq!(over.input, "steps");
+        assert_eq!(over.value, serde_json::json!(20));
+        // Values that aren't JSON are strings
+        assert_eq!(set("7:text=a cat").unwrap().value, "a cat");
+        assert_eq!(set("7:text=\"20\"").unwrap().value, "20");
+        assert_eq!(set("7:flag=true").unwrap().value, true);
+        // The node may itself hold a colon; the value may hold '=' and ':'
+        let over = set("a:b:input=x=1:2").unwrap();
+        assert_eq!((over.node.as_str(), over.input.as_str()), ("a:b", "input"));
+        assert_eq!(over.value, "x=1:2");
+        assert_eq!(set("7:text=").unwrap().value, "");
+
+        for bad in ["7:steps", "steps=20", ":steps=20", "7:=20", ""] {
+            assert!(set(bad).is_err(), "{bad:?} parsed");
+        }
+    }
 }
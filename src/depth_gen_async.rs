@@ -0,0 +1,203 @@
+use crate::depth_gen::{
+    depth_image, depth_workflow, find_node_id, load_cached_rgbd, load_depth_sidecar,
+    load_oriented_image, save_cached_rgbd, set_workflow_input_image, uploaded_image_path,
+    DepthConfig, PromptMessages,
+};
+use crate::depthd::request_depth;
+use crate::image_types::{DepthImage, TextureImage};
+use crate::metrics::METRICS;
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+/// How long a workflow may go without any message from ComfyUI before it is given up on. The
+/// server broadcasts queue changes, so a prompt waiting behind others still hears from it.
+pub const WORKFLOW_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Numbers the workflows this process runs, to give each its own websocket client id.
+static NEXT_WORKFLOW: AtomicU64 = AtomicU64::new(0);
+
+/// Errors from the async API are `Send` so the futures can be spawned onto a runtime.
+pub type AsyncError = Box<dyn Error + Send + Sync>;
+
+fn send_error(e: Box<dyn Error>) -> AsyncError {
+    e.to_string().into()
+}
+
+fn with_headers(request: reqwest::RequestBuilder, config: &DepthConfig) -> reqwest::RequestBuilder {
+    config
+        .request_headers()
+        .into_iter()
+        .fold(request, |request, (name, value)| {
+            request.header(name, value)
+        })
+}
+
+/// Async variant of [`crate::depth_gen::run_workflow`].
+pub async fn run_workflow_async(
+    client: &reqwest::Client,
+    workflow: &Value,
+    config: &DepthConfig,
+) -> Result<Vec<u8>, AsyncError> {
+    let save_image_node_id = find_node_id(workflow, "SaveImageWebsocket")
+        .ok_or("Could not find SaveImageWebSocket node in workflow")?;
+
+    // ComfyUI keeps one websocket per client id, so concurrent workflows each need their own
+    let client_id = format!(
+        "{}-{}",
+        config.client_id(),
+        NEXT_WORKFLOW.fetch_add(1, Ordering::Relaxed)
+    );
+    let config = &DepthConfig {
+        client_id: Some(client_id.clone()),
+        ..config.clone()
+    };
+
+    // Connect to the websocket before queueing so no messages for the prompt are missed
+    let request = config
+        .websocket_request(&format!("/ws?clientId={}", client_id))
+        .map_err(send_error)?;
+    let (mut socket, _) = tokio_tungstenite::connect_async(request).await?;
+
+    // Queue the prompt
+    let prompt_response = async {
+        with_headers(client.post(config.endpoint("/prompt")), config)
+            .json(&config.prompt_request(workflow))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await
+    }
+    .instrument(tracing::info_span!("queue_prompt"))
+    .await?;
+    let prompt_id = prompt_response["prompt_id"]
+        .as_str()
+        .ok_or("ComfyUI did not return a prompt_id")?
+        .to_string();
+    tracing::debug!("Workflow queued with prompt_id: {}", prompt_id);
+
+    // Wait for completion and image data
+    async {
+        let mut messages = PromptMessages::new(&prompt_id, &save_image_node_id);
+        loop {
+            let msg = tokio::time::timeout(WORKFLOW_IDLE_TIMEOUT, socket.next())
+                .await
+                .map_err(|_| {
+                    format!(
+                        "no word from ComfyUI for {} seconds on prompt {prompt_id}",
+                        WORKFLOW_IDLE_TIMEOUT.as_secs()
+                    )
+                })?;
+            let Some(msg) = msg else { break };
+            if messages.handle(msg?).map_err(send_error)? {
+                break;
+            }
+        }
+        messages.into_image().map_err(send_error)
+    }
+    .instrument(tracing::info_span!("websocket_wait", prompt_id = %prompt_id))
+    .await
+}
+
+/// Async variant of [`crate::depth_gen::generate_depth`]. Network I/O runs on the async runtime
+/// and image decoding on its blocking pool, so many requests can be in flight at once.
+#[tracing::instrument(skip_all, fields(input = %input_path.display()))]
+pub async fn generate_depth_async(
+    input_path: PathBuf,
+    config: &DepthConfig,
+) -> Result<(TextureImage, DepthImage), AsyncError> {
+    let cached = {
+        let (input_path, config) = (input_path.clone(), config.clone());
+        tokio::task::spawn_blocking(move || match load_depth_sidecar(&input_path, &config) {
+            Ok(None) => load_cached_rgbd(&input_path, &config).map_err(send_error),
+            other => other.map_err(send_error),
+        })
+        .await??
+    };
+    if let Some(cached) = cached {
+        METRICS.depth_cache_hits.inc();
+        return Ok(cached);
+    }
+
+    tracing::debug!("No cached version found, generating new depth map");
+    METRICS.depth_cache_misses.inc();
+    let started = Instant::now();
+
+    let input_image = {
+        let input_path = input_path.clone();
+        tokio::task::spawn_blocking(move || load_oriented_image(&input_path).map_err(send_error))
+            .await??
+    };
+    let depth_bytes = match &config.depthd {
+        Some(depthd) => {
+            let (depthd, input_path) = (depthd.clone(), input_path.clone());
+            tokio::task::spawn_blocking(move || {
+                request_depth(&depthd, &input_path).map_err(send_error)
+            })
+            .await??
+        }
+        None => run_depth_workflow(&input_path, config).await?,
+    };
+    METRICS.depth_seconds.observe(started.elapsed());
+
+    let config = config.clone();
+    tokio::task::spawn_blocking(move || {
+        let depth_img = image::load_from_memory(&depth_bytes)?;
+        let texture = TextureImage(input_image.to_rgb8());
+        let depth = depth_image(&depth_img);
+        save_cached_rgbd(&input_path, &config, &texture, &depth_img).map_err(send_error)?;
+        Ok((texture, depth))
+    })
+    .await?
+}
+
+/// Uploads the image at `input_path` and runs the depth workflow on it.
+async fn run_depth_workflow(
+    input_path: &Path,
+    config: &DepthConfig,
+) -> Result<Vec<u8>, AsyncError> {
+    let mut workflow = depth_workflow(config).map_err(send_error)?;
+
+    let filename = input_path
+        .file_name()
+        .ok_or("input path does not contain a file name")?
+        .to_string_lossy()
+        .to_string();
+
+    // Upload image as multipart form with temp subfolder
+    let client = reqwest::Client::new();
+    let form = reqwest::multipart::Form::new()
+        .part(
+            "image",
+            reqwest::multipart::Part::bytes(tokio::fs::read(&input_path).await?)
+                .file_name(filename.clone()),
+        )
+        .text("subfolder", "temp");
+    tracing::debug!(
+        "Uploading image {} to {}/upload/image",
+        filename,
+        config.comfy_url
+    );
+    let response = async {
+        with_headers(client.post(config.endpoint("/upload/image")), config)
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await
+    }
+    .instrument(tracing::info_span!("upload", file = %filename))
+    .await?;
+
+    let uploaded_path = uploaded_image_path(&response, &filename);
+    tracing::debug!("Uploaded image path: {}", uploaded_path);
+    set_workflow_input_image(&mut workflow, &uploaded_path).map_err(send_error)?;
+
+    run_workflow_async(&client, &workflow, config).await
+}
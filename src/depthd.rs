@@ -0,0 +1,181 @@
+//! Depth generation as a local service, for `depthd`.
+//!
+//! Every depthpainter or batch_depth run otherwise opens its own ComfyUI websocket and loads the
+//! workflow before its first image. The daemon keeps one websocket open to ComfyUI with the
+//! workflow ready, and clients with `--depthd` send it their images over HTTP instead:
+//! `POST /depth?name=<file name>` with the encoded image as the body answers with the depth map
+//! as ComfyUI produced it, and `GET /health` answers `ok`.
+
+use crate::depth_gen::{
+    depth_workflow, run_workflow_on, set_workflow_input_image, upload_image, DepthConfig,
+};
+use crate::http::{read_request, serve_connections, write_head};
+use serde_json::Value;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::WebSocket;
+
+/// Where `depthd` listens unless told otherwise.
+pub const DEFAULT_DEPTHD_LISTEN: &str = "127.0.0.1:8190";
+
+/// Largest image accepted in a request.
+const MAX_REQUEST_BYTES: usize = 256 << 20;
+
+/// Uploads made so far, numbering the temporary files they are written to.
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+type ComfySocket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+pub struct DepthDaemon {
+    config: DepthConfig,
+    workflow: Value,
+    /// The warm connection, opened on the first request and again after an error. Held for the
+    /// whole workflow, so requests run one at a time, as ComfyUI would run them anyway.
+    socket: Mutex<Option<ComfySocket>>,
+}
+
+impl DepthDaemon {
+    pub fn new(config: DepthConfig) -> Result<Self, Box<dyn Error>> {
+        let workflow = depth_workflow(&config)?;
+        Ok(Self {
+            config,
+            workflow,
+            socket: Mutex::new(None),
+        })
+    }
+
+    /// Opens the ComfyUI websocket now rather than on the first request, so a wrong URL shows
+    /// at startup.
+    pub fn connect(&self) -> Result<(), Box<dyn Error>> {
+        *self.socket.lock().unwrap() = Some(self.open_socket()?);
+        Ok(())
+    }
+
+    fn open_socket(&self) -> Result<ComfySocket, Box<dyn Error>> {
+        self.config
+            .connect_websocket(&format!("/ws?clientId={}", self.config.client_id()))
+    }
+
+    /// The depth map ComfyUI makes of the encoded image `data`, uploaded as `name`.
+    pub fn depth(&self, name: &str, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        // Keep only the file name, so a request can't write elsewhere
+        let name = Path::new(name)
+            .file_name()
+            .ok_or("request has no image name")?
+            .to_string_lossy();
+        // Numbered, as requests for images of the same name may run at once
+        let upload = UPLOADS.fetch_add(1, Ordering::Relaxed);
+        let upload_path =
+            std::env::temp_dir().join(format!("depthd_{}_{}_{}", std::process::id(), upload, name));
+        std::fs::write(&upload_path, data)?;
+        let uploaded = upload_image(&upload_path, &self.config);
+        let _ = std::fs::remove_file(&upload_path);
+
+        let mut workflow = self.workflow.clone();
+        set_workflow_input_image(&mut workflow, &uploaded?)?;
+
+        let mut socket = self.socket.lock().unwrap();
+        let reused = socket.is_some();
+        if !reused {
+            *socket = Some(self.open_socket()?);
+        }
+        let result = run_workflow_on(socket.as_mut().unwrap(), &workflow, &self.config);
+        match result {
+            Ok(depth) => Ok(depth),
+            // The connection may have gone stale, e.g. when ComfyUI restarted; retry on a new one
+            Err(e) if reused => {
+                tracing::debug!("Reconnecting to ComfyUI after: {}", e);
+                *socket = None;
+                let mut fresh = self.open_socket()?;
+                let depth = run_workflow_on(&mut fresh, &workflow, &self.config)?;
+                *socket = Some(fresh);
+                Ok(depth)
+            }
+            Err(e) => {
+                *socket = None;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Asks the daemon at `url` for the depth map of the image at `input_path`.
+pub fn request_depth(url: &str, input_path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let name = input_path
+        .file_name()
+        .ok_or("input path does not contain a file name")?
+        .to_string_lossy();
+    let data = std::fs::read(input_path)?;
+    tracing::debug!("Requesting depth for {} from {}", name, url);
+    let response = match ureq::post(&format!("{}/depth", url.trim_end_matches('/')))
+        .query("name", &name)
+        .send_bytes(&data)
+    {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, response)) => {
+            let message = response.into_string().unwrap_or_default();
+            return Err(format!("depthd returned {}: {}", status, message.trim()).into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mut depth = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_REQUEST_BYTES as u64)
+        .read_to_end(&mut depth)?;
+    Ok(depth)
+}
+
+fn respond(stream: &mut TcpStream, daemon: &DepthDaemon) -> Result<(), Box<dyn Error>> {
+    let write_response = |stream: &mut TcpStream, status, content_type, body: &[u8]| {
+        write_head(stream, status, content_type, body.len() as u64, "")?;
+        stream.write_all(body)
+    };
+    let request = match read_request(stream, MAX_REQUEST_BYTES) {
+        Ok(request) => request,
+        Err(e) => {
+            let message = e.to_string();
+            write_response(stream, "400 Bad Request", "text/plain", message.as_bytes())?;
+            return Ok(());
+        }
+    };
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/health") => write_response(stream, "200 OK", "text/plain", b"ok")?,
+        ("POST", "/depth") => {
+            let name = request
+                .query_param("name")
+                .unwrap_or_else(|| "image.png".to_string());
+            let started = std::time::Instant::now();
+            match daemon.depth(&name, &request.body) {
+                Ok(depth) => {
+                    tracing::info!(
+                        "Depth for {} in {:.2}s",
+                        name,
+                        started.elapsed().as_secs_f32()
+                    );
+                    write_response(stream, "200 OK", "image/png", &depth)?
+                }
+                Err(e) => {
+                    tracing::warn!("Depth for {} failed: {}", name, e);
+                    let message = e.to_string();
+                    write_response(stream, "502 Bad Gateway", "text/plain", message.as_bytes())?
+                }
+            }
+        }
+        ("GET" | "POST", _) => write_response(stream, "404 Not Found", "text/plain", b"")?,
+        _ => write_response(stream, "405 Method Not Allowed", "text/plain", b"")?,
+    }
+    Ok(())
+}
+
+/// Answers requests on `listener` until the process exits, one thread per connection.
+pub fn serve(listener: TcpListener, daemon: DepthDaemon) -> io::Result<()> {
+    let daemon = Arc::new(daemon);
+    serve_connections(listener, move |stream| respond(stream, &daemon));
+    Ok(())
+}
@@ -0,0 +1,123 @@
+//! JSON descriptors that tell external quilt players (WebGL viewers and the like) how a quilt
+//! image is laid out.
+
+use crate::quilt::QuiltSettings;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+
+/// Tile order of a quilt image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TileOrder {
+    /// The Looking Glass convention: first view at the bottom left, left to right, then upwards.
+    /// This is what the renderer writes.
+    #[default]
+    BottomLeft,
+    /// First view at the top left, left to right, then downwards.
+    TopLeft,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuiltDescriptor {
+    /// Quilt image file name, relative to the descriptor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(alias = "cols")]
+    pub columns: u32,
+    pub rows: u32,
+    #[serde(default, alias = "viewCount", alias = "numViews")]
+    pub views: u32,
+    #[serde(default)]
+    pub tile_width: u32,
+    #[serde(default)]
+    pub tile_height: u32,
+    #[serde(default)]
+    pub quilt_width: u32,
+    #[serde(default)]
+    pub quilt_height: u32,
+    /// Aspect ratio (width / height) of the source image the views show.
+    #[serde(default, alias = "quiltAspect")]
+    pub aspect: f32,
+    /// Total angle in degrees the views sweep across.
+    #[serde(default, alias = "viewcone")]
+    pub view_cone: f32,
+    /// The `--fov` value the quilt was rendered with.
+    #[serde(default)]
+    pub fov: f32,
+    #[serde(default)]
+    pub order: TileOrder,
+    /// Width of a display pixel over its height that the views were rendered for.
+    #[serde(default = "default_pixel_aspect")]
+    pub pixel_aspect: f32,
+}
+
+fn default_pixel_aspect() -> f32 {
+    1.0
+}
+
+impl QuiltDescriptor {
+    pub fn new(settings: &QuiltSettings, aspect: f32, fov_deg: f32) -> Self {
+        let (quilt_width, quilt_height) = settings.resolution;
+        Self {
+            image: None,
+            columns: settings.columns,
+            rows: settings.rows,
+            views: settings.columns * settings.rows,
+            tile_width: quilt_width / settings.columns,
+            tile_height: quilt_height / settings.rows,
+            quilt_width,
+            quilt_height,
+            aspect,
+            // The renderer spreads views over half of the --fov angle; see render_quilt_views.
+            view_cone: fov_deg / 2.0,
+            fov: fov_deg,
+            order: TileOrder::BottomLeft,
+            pixel_aspect: settings.pixel_aspect,
+        }
+    }
+
+    /// The quilt layout, taking the quilt size from the tile size when a descriptor only gives
+    /// one of them.
+    pub fn to_quilt_settings(&self) -> Result<QuiltSettings, Box<dyn Error>> {
+        if self.columns == 0 || self.rows == 0 {
+            return Err("quilt descriptor needs non-zero columns and rows".into());
+        }
+        let resolution = match (self.quilt_width, self.quilt_height) {
+            (0, _) | (_, 0) => (self.tile_width * self.columns, self.tile_height * self.rows),
+            resolution => resolution,
+        };
+        if resolution.0 == 0 || resolution.1 == 0 {
+            return Err("quilt descriptor needs a quilt or tile size".into());
+        }
+        Ok(QuiltSettings {
+            columns: self.columns,
+            rows: self.rows,
+            resolution,
+            pixel_aspect: self.pixel_aspect,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Writes the descriptor as `<quilt file stem>.json` next to the quilt image.
+    pub fn save_beside(&self, quilt_path: &Path) -> Result<std::path::PathBuf, Box<dyn Error>> {
+        let descriptor = Self {
+            image: quilt_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            ..self.clone()
+        };
+        let path = quilt_path.with_extension("json");
+        std::fs::write(&path, serde_json::to_string_pretty(&descriptor)?)?;
+        Ok(path)
+    }
+}
+
+/// Parses a quilt descriptor, ours or another tool's, into `QuiltSettings`.
+pub fn parse_quilt_descriptor(json: &str) -> Result<QuiltSettings, Box<dyn Error>> {
+    serde_json::from_str::<QuiltDescriptor>(json)?.to_quilt_settings()
+}
@@ -0,0 +1,155 @@
+//! Copying a batch output directory and its playlists onto a Looking Glass Go's storage, as
+//! `batch_depth sync` does after a run. The device is reached through a mounted path: USB mass
+//! storage, or its MTP storage as gvfs mounts it. Files already on the device are compared by
+//! hash, so only new and changed quilts are copied.
+
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What a sync did.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SyncReport {
+    pub copied: usize,
+    pub unchanged: usize,
+    pub deleted: usize,
+}
+
+/// The storage of the first MTP device gvfs has mounted, such as a Go plugged in over USB.
+pub fn find_mtp_storage() -> Result<PathBuf, Box<dyn Error>> {
+    let gvfs = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime) => PathBuf::from(runtime).join("gvfs"),
+        None => return Err("XDG_RUNTIME_DIR is not set, so the gvfs mounts can't be found".into()),
+    };
+    let mut devices: Vec<PathBuf> = std::fs::read_dir(&gvfs)
+        .map_err(|e| format!("no gvfs mounts in {}: {e}", gvfs.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("mtp"))
+        .map(|entry| entry.path())
+        .collect();
+    devices.sort();
+    let device = devices
+        .into_iter()
+        .next()
+        .ok_or("no MTP device mounted; plug in the device and open it in the file manager")?;
+    // The storage is a folder of the device, "Card Storage" on the Go
+    let storage = std::fs::read_dir(&device)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| path.is_dir())
+        .ok_or_else(|| format!("no storage found on {}", device.display()))?;
+    Ok(storage)
+}
+
+fn sha256_file(path: &Path) -> io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+/// Whether `target` already holds what `source` does. Sizes are compared first, so only files
+/// that might match are read back from the device.
+fn same_contents(source: &Path, target: &Path) -> io::Result<bool> {
+    let Ok(target_metadata) = std::fs::metadata(target) else {
+        return Ok(false);
+    };
+    if std::fs::metadata(source)?.len() != target_metadata.len() {
+        return Ok(false);
+    }
+    Ok(sha256_file(source)? == sha256_file(target)?)
+}
+
+/// Copies `source` to `target` unless it is already there. Copies the bytes alone, as MTP
+/// mounts refuse the permission changes `std::fs::copy` makes.
+fn copy_if_changed(
+    source: &Path,
+    target: &Path,
+    report: &mut SyncReport,
+) -> Result<(), Box<dyn Error>> {
+    if same_contents(source, target)? {
+        report.unchanged += 1;
+        return Ok(());
+    }
+    crate::status!("Copying {}", source.display());
+    io::copy(&mut File::open(source)?, &mut File::create(target)?)
+        .map_err(|e| format!("copying {} to {}: {e}", source.display(), target.display()))?;
+    report.copied += 1;
+    Ok(())
+}
+
+/// The playlists batch_depth writes for `output_dir`: `<dir>.m3u` beside it, and with several
+/// devices `<dir>_<device>.m3u`.
+fn playlists(output_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let name = output_dir.file_name().unwrap_or_default().to_string_lossy();
+    let parent = match output_dir.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut playlists: Vec<PathBuf> = std::fs::read_dir(parent)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            file_name
+                .strip_suffix(".m3u")
+                .and_then(|stem| stem.strip_prefix(name.as_ref()))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('_'))
+        })
+        .collect();
+    playlists.sort();
+    Ok(playlists)
+}
+
+/// Copies the files of `output_dir` into a folder of the same name on the device storage at
+/// `target`, then its playlists beside that folder, so the device never lists a quilt it doesn't
+/// have yet. With `delete`, files in the device's folder that are gone from `output_dir`, e.g.
+/// rotated out of the playlist, are deleted too.
+pub fn sync_output(
+    output_dir: &Path,
+    target: &Path,
+    delete: bool,
+) -> Result<SyncReport, Box<dyn Error>> {
+    if !target.is_dir() {
+        return Err(format!("{} is not a mounted directory", target.display()).into());
+    }
+    let name = output_dir
+        .file_name()
+        .ok_or_else(|| format!("{} has no directory name", output_dir.display()))?;
+    let target_dir = target.join(name);
+    std::fs::create_dir_all(&target_dir)?;
+
+    let mut report = SyncReport::default();
+    let mut names = Vec::new();
+    let mut files: Vec<PathBuf> = std::fs::read_dir(output_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    for file in files {
+        let file_name = file.file_name().unwrap_or_default();
+        if file_name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        copy_if_changed(&file, &target_dir.join(file_name), &mut report)?;
+        names.push(file_name.to_os_string());
+    }
+    for playlist in playlists(output_dir)? {
+        let file_name = playlist.file_name().unwrap_or_default();
+        copy_if_changed(&playlist, &target.join(file_name), &mut report)?;
+    }
+
+    if delete {
+        for entry in std::fs::read_dir(&target_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() && !names.contains(&entry.file_name()) {
+                crate::status!("Deleting {}", entry.path().display());
+                std::fs::remove_file(entry.path())?;
+                report.deleted += 1;
+            }
+        }
+    }
+    Ok(report)
+}
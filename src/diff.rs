@@ -0,0 +1,231 @@
+//! Tile-by-tile comparison of two quilts, for checking renderer changes against golden outputs.
+
+use crate::image_types::RgbImage;
+use crate::quilt::tile_origin;
+use image::{ImageBuffer, Luma, Rgb};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::error::Error;
+
+/// Side of the square SSIM window, and the step between windows.
+const WINDOW: u32 = 8;
+const STEP: u32 = 4;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ViewScore {
+    /// View index, in render order.
+    pub view: u32,
+    /// Mean structural similarity of the luminance, 1.0 for identical views.
+    pub ssim: f32,
+    /// Largest per-channel difference of any pixel, 0-255.
+    pub max_difference: u8,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QuiltDiff {
+    pub views: Vec<ViewScore>,
+    /// Lowest per-view SSIM.
+    pub min_ssim: f32,
+}
+
+/// Compares two quilts with the same size and layout, view by view.
+pub fn compare_quilts(
+    a: &RgbImage,
+    b: &RgbImage,
+    columns: u32,
+    rows: u32,
+) -> Result<QuiltDiff, Box<dyn Error>> {
+    if a.dimensions() != b.dimensions() {
+        return Err(format!(
+            "quilt sizes differ: {}x{} vs {}x{}",
+            a.width(),
+            a.height(),
+            b.width(),
+            b.height()
+        )
+        .into());
+    }
+    let (view_width, view_height) = view_size(a, columns, rows)?;
+
+    let views: Vec<ViewScore> = (0..columns * rows)
+        .into_par_iter()
+        .map(|view| {
+            let (x, y) = tile_origin(view, columns, view_width, view_height);
+            let tile_a = image::imageops::crop_imm(a, x, y, view_width, view_height).to_image();
+            let tile_b = image::imageops::crop_imm(b, x, y, view_width, view_height).to_image();
+            let max_difference = tile_a
+                .pixels()
+                .zip(tile_b.pixels())
+                .flat_map(|(pa, pb)| (0..3).map(move |c| pa[c].abs_diff(pb[c])))
+                .max()
+                .unwrap_or(0);
+            ViewScore {
+                view,
+                ssim: ssim(&luminance(&tile_a), &luminance(&tile_b)),
+                max_difference,
+            }
+        })
+        .collect();
+
+    let min_ssim = views.iter().map(|v| v.ssim).fold(1.0, f32::min);
+    Ok(QuiltDiff { views, min_ssim })
+}
+
+/// Largest step in mean luminance between adjacent views, 0-255, before the exposure check
+/// reports them. Neighboring views see nearly the same scene, so their means barely move.
+pub const MAX_MEAN_LUMINANCE_STEP: f32 = 4.0;
+
+/// Largest histogram distance between adjacent views before the exposure check reports them.
+pub const MAX_HISTOGRAM_STEP: f32 = 0.1;
+
+/// Luminance histogram bins for the exposure check.
+const HISTOGRAM_BINS: usize = 32;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ExposureStep {
+    /// The earlier of the two views, in render order; the other is the next one.
+    pub view: u32,
+    /// Mean luminance of each view, 0-255.
+    pub mean_luminance: [f32; 2],
+    /// Fraction of pixels that would have to change luminance bin to turn one view's histogram
+    /// into the other's, 0-1.
+    pub histogram_distance: f32,
+}
+
+impl ExposureStep {
+    /// Whether the views differ more than the scene moving between them would explain.
+    pub fn diverges(&self) -> bool {
+        (self.mean_luminance[1] - self.mean_luminance[0]).abs() > MAX_MEAN_LUMINANCE_STEP
+            || self.histogram_distance > MAX_HISTOGRAM_STEP
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ExposureReport {
+    pub steps: Vec<ExposureStep>,
+}
+
+impl ExposureReport {
+    pub fn divergent(&self) -> impl Iterator<Item = &ExposureStep> {
+        self.steps.iter().filter(|step| step.diverges())
+    }
+}
+
+/// Compares the luminance of each view of a quilt with the next. A renderer bug or a hole-fill
+/// artifact tends to show up as one view brighter or darker than its neighbors.
+pub fn check_exposure(
+    quilt: &RgbImage,
+    columns: u32,
+    rows: u32,
+) -> Result<ExposureReport, Box<dyn Error>> {
+    let (view_width, view_height) = view_size(quilt, columns, rows)?;
+    let histograms: Vec<(f32, [f32; HISTOGRAM_BINS])> = (0..columns * rows)
+        .into_par_iter()
+        .map(|view| {
+            let (x, y) = tile_origin(view, columns, view_width, view_height);
+            let tile = image::imageops::crop_imm(quilt, x, y, view_width, view_height).to_image();
+            let mut histogram = [0.0; HISTOGRAM_BINS];
+            let mut sum = 0.0;
+            for pixel in tile.pixels() {
+                let luma = rgb_to_luma(pixel);
+                sum += luma;
+                histogram[(luma as usize * HISTOGRAM_BINS / 256).min(HISTOGRAM_BINS - 1)] += 1.0;
+            }
+            let n = (view_width * view_height) as f32;
+            histogram.iter_mut().for_each(|count| *count /= n);
+            (sum / n, histogram)
+        })
+        .collect();
+
+    let steps = histograms
+        .windows(2)
+        .zip(0..)
+        .map(|(pair, view)| {
+            let ((mean_a, histogram_a), (mean_b, histogram_b)) = (&pair[0], &pair[1]);
+            ExposureStep {
+                view,
+                mean_luminance: [*mean_a, *mean_b],
+                histogram_distance: histogram_a
+                    .iter()
+                    .zip(histogram_b)
+                    .map(|(a, b)| (a - b).abs())
+                    .sum::<f32>()
+                    / 2.0,
+            }
+        })
+        .collect();
+    Ok(ExposureReport { steps })
+}
+
+fn view_size(quilt: &RgbImage, columns: u32, rows: u32) -> Result<(u32, u32), Box<dyn Error>> {
+    if columns == 0 || rows == 0 || quilt.width() < columns || quilt.height() < rows {
+        return Err("quilt layout doesn't fit the image".into());
+    }
+    Ok((quilt.width() / columns, quilt.height() / rows))
+}
+
+/// Visualizes where two images differ: the first image dimmed to gray, with differing pixels
+/// tinted from yellow to red by the size of the difference.
+pub fn difference_image(a: &RgbImage, b: &RgbImage) -> RgbImage {
+    ImageBuffer::from_fn(a.width(), a.height(), |x, y| {
+        let (pa, pb) = (a.get_pixel(x, y), b.get_pixel(x, y));
+        let difference = (0..3).map(|c| pa[c].abs_diff(pb[c])).max().unwrap_or(0);
+        if difference == 0 {
+            let gray = (rgb_to_luma(pa) * 0.3) as u8;
+            Rgb([gray, gray, gray])
+        } else {
+            // Small differences are still clearly visible
+            let t = (difference as f32 / 64.0).min(1.0);
+            Rgb([255, (255.0 * (1.0 - t)) as u8, 0])
+        }
+    })
+}
+
+fn rgb_to_luma(p: &Rgb<u8>) -> f32 {
+    0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32
+}
+
+fn luminance(image: &RgbImage) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        Luma([rgb_to_luma(image.get_pixel(x, y))])
+    })
+}
+
+/// Mean SSIM over overlapping square windows (Wang et al. 2004 constants, 8-bit range).
+fn ssim(a: &ImageBuffer<Luma<f32>, Vec<f32>>, b: &ImageBuffer<Luma<f32>, Vec<f32>>) -> f32 {
+    const C1: f32 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f32 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    // Views smaller than a window are compared as a single window
+    let window_width = WINDOW.min(a.width());
+    let window_height = WINDOW.min(a.height());
+    let starts = |size: u32, window: u32| (0..=size - window).step_by(STEP as usize);
+
+    let mut total = 0.0;
+    let mut count = 0;
+    for wy in starts(a.height(), window_height) {
+        for wx in starts(a.width(), window_width) {
+            let n = (window_width * window_height) as f32;
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in wy..wy + window_height {
+                for x in wx..wx + window_width {
+                    let (va, vb) = (a.get_pixel(x, y)[0], b.get_pixel(x, y)[0]);
+                    sum_a += va;
+                    sum_b += vb;
+                    sum_aa += va * va;
+                    sum_bb += vb * vb;
+                    sum_ab += va * vb;
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            count += 1;
+        }
+    }
+    total / count as f32
+}
@@ -0,0 +1,175 @@
+//! Dithering for colors the renderer computes at more than 8-bit precision: the gradients drawn
+//! across disocclusions and the averages of subpixel samples. Rounding those to 8 bits shows up
+//! as bands on the display; adding a threshold pattern before quantizing trades the bands for
+//! fine noise.
+
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dither {
+    /// Round to the nearest value.
+    #[default]
+    None,
+    /// An 8x8 Bayer matrix. Cheap and regular; the pattern can be visible in flat areas.
+    Ordered,
+    /// A 64x64 blue noise tile, without the Bayer cross-hatching.
+    BlueNoise,
+}
+
+const BAYER_SIZE: usize = 8;
+
+/// Side of the generated blue noise tile.
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Spread of the energy each point of the pattern radiates, in pixels.
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+/// Thresholds in [0, 1), row-major.
+static BAYER: LazyLock<[f32; BAYER_SIZE * BAYER_SIZE]> = LazyLock::new(|| {
+    let mut matrix = [0.0; BAYER_SIZE * BAYER_SIZE];
+    for y in 0..BAYER_SIZE {
+        for x in 0..BAYER_SIZE {
+            // Interleave the bits of x ^ y and y, least significant first
+            let (a, b) = (x ^ y, y);
+            let mut rank = 0;
+            for bit in 0..3 {
+                rank = (rank << 2) | (((a >> bit) & 1) << 1) | ((b >> bit) & 1);
+            }
+            matrix[y * BAYER_SIZE + x] = rank as f32 / (BAYER_SIZE * BAYER_SIZE) as f32;
+        }
+    }
+    matrix
+});
+
+/// Thresholds in [0, 1), row-major.
+static BLUE_NOISE: LazyLock<Vec<f32>> =
+    LazyLock::new(|| void_and_cluster(BLUE_NOISE_SIZE, BLUE_NOISE_SIGMA));
+
+/// Quantizes colors to 8 bits at view pixels, with the dither pattern anchored to the whole view
+/// so banded renders match full ones.
+#[derive(Clone, Copy, Debug)]
+pub struct Ditherer {
+    pub dither: Dither,
+    /// View row of the first row of the image being rendered.
+    pub row_offset: u32,
+}
+
+impl Ditherer {
+    /// The threshold added before flooring at pixel (x, y), or `None` to round.
+    fn threshold(&self, x: u32, y: u32) -> Option<f32> {
+        let y = y + self.row_offset;
+        match self.dither {
+            Dither::None => None,
+            Dither::Ordered => {
+                Some(BAYER[(y as usize % BAYER_SIZE) * BAYER_SIZE + x as usize % BAYER_SIZE])
+            }
+            Dither::BlueNoise => Some(
+                BLUE_NOISE[(y as usize % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE
+                    + x as usize % BLUE_NOISE_SIZE],
+            ),
+        }
+    }
+
+    /// `value` quantized at pixel (x, y), rounding without dithering.
+    pub fn quantize(&self, value: f32, x: u32, y: u32) -> u8 {
+        match self.threshold(x, y) {
+            Some(threshold) => (value + threshold).floor().clamp(0.0, 255.0) as u8,
+            None => value.round().clamp(0.0, 255.0) as u8,
+        }
+    }
+
+    /// `value` quantized at pixel (x, y), truncating without dithering.
+    pub fn quantize_floor(&self, value: f32, x: u32, y: u32) -> u8 {
+        match self.threshold(x, y) {
+            Some(threshold) => (value + threshold).floor().clamp(0.0, 255.0) as u8,
+            None => value.clamp(0.0, 255.0) as u8,
+        }
+    }
+}
+
+/// Ulichney's void-and-cluster method: ranks the pixels of a `size` x `size` tile so that every
+/// prefix of the ranking is spread as evenly as possible. Returns each pixel's rank scaled to
+/// [0, 1).
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<f32> {
+    let n = size * size;
+    // Toroidal Gaussian falloff by offset, so the tile repeats seamlessly
+    let falloff: Vec<f32> = (0..n)
+        .map(|i| {
+            let (dx, dy) = (i % size, i / size);
+            let dx = dx.min(size - dx) as f32;
+            let dy = dy.min(size - dy) as f32;
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let offset = |a: usize, b: usize| {
+        let dx = (a % size + size - b % size) % size;
+        let dy = (a / size + size - b / size) % size;
+        dy * size + dx
+    };
+
+    let mut points = vec![false; n];
+    let mut energy = vec![0.0f32; n];
+    let toggle = |points: &mut Vec<bool>, energy: &mut Vec<f32>, i: usize| {
+        points[i] = !points[i];
+        let sign = if points[i] { 1.0 } else { -1.0 };
+        for (j, e) in energy.iter_mut().enumerate() {
+            *e += sign * falloff[offset(j, i)];
+        }
+    };
+    // Tightest cluster: the point with the most energy. Largest void: the empty pixel with the
+    // least.
+    let tightest = |points: &[bool], energy: &[f32]| {
+        (0..n)
+            .filter(|&i| points[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+    let largest_void = |points: &[bool], energy: &[f32]| {
+        (0..n)
+            .filter(|&i| !points[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+
+    // Initial pattern: a tenth of the pixels from a fixed pseudo-random sequence, then evened
+    // out by moving the tightest cluster into the largest void until that changes nothing.
+    let mut state: u32 = 0x9e37_79b9;
+    let initial = n / 10;
+    let mut placed = 0;
+    while placed < initial {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let i = state as usize % n;
+        if !points[i] {
+            toggle(&mut points, &mut energy, i);
+            placed += 1;
+        }
+    }
+    loop {
+        let cluster = tightest(&points, &energy).expect("pattern has points");
+        toggle(&mut points, &mut energy, cluster);
+        let void = largest_void(&points, &energy).expect("pattern has gaps");
+        if void == cluster {
+            toggle(&mut points, &mut energy, cluster);
+            break;
+        }
+        toggle(&mut points, &mut energy, void);
+    }
+
+    let mut rank = vec![0usize; n];
+    // Ranks below the initial pattern: remove its tightest clusters one by one
+    let (mut phase1_points, mut phase1_energy) = (points.clone(), energy.clone());
+    for r in (0..initial).rev() {
+        let cluster = tightest(&phase1_points, &phase1_energy).expect("pattern has points");
+        toggle(&mut phase1_points, &mut phase1_energy, cluster);
+        rank[cluster] = r;
+    }
+    // Ranks above it: fill the largest voids
+    for r in initial..n {
+        let void = largest_void(&points, &energy).expect("pattern has gaps");
+        toggle(&mut points, &mut energy, void);
+        rank[void] = r;
+    }
+    rank.into_iter().map(|r| r as f32 / n as f32).collect()
+}
@@ -0,0 +1,57 @@
+//! Multithreaded PNG encoding.
+//!
+//! The `image` crate compresses PNGs on one thread, which for an 8K quilt can take longer than
+//! rendering it. mtpng splits the image into chunks and deflates them on the rayon pool.
+
+use image::{ImageBuffer, Rgb};
+use mtpng::encoder::{Encoder, Options};
+use mtpng::{ColorType, Header};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Starts a PNG of RGB8 rows on `writer`; feed it with `write_image_rows` and call `finish`.
+///
+/// With `streaming`, compressed chunks are written out as they complete instead of being held
+/// until the end, which bounds memory for very large images at the cost of a slightly larger
+/// file.
+pub fn png_encoder<W: Write>(
+    writer: W,
+    width: u32,
+    height: u32,
+    streaming: bool,
+) -> Result<Encoder<'static, W>, Box<dyn Error>> {
+    let mut header = Header::new();
+    header.set_size(width, height)?;
+    header.set_color(ColorType::Truecolor, 8)?;
+    let mut options = Options::new();
+    options.set_streaming(streaming)?;
+    let mut encoder = Encoder::new(writer, &options);
+    encoder.write_header(&header)?;
+    Ok(encoder)
+}
+
+/// Saves an RGB8 image as a PNG, compressing on all cores.
+pub fn save_png(path: &Path, image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<(), Box<dyn Error>> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png_encoder(file, image.width(), image.height(), false)?;
+    encoder.write_image_rows(image.as_raw())?;
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+/// Saves an image in the format named by the extension of `path`, using `save_png` for PNGs.
+pub fn save_image(
+    path: &Path,
+    image: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+) -> Result<(), Box<dyn Error>> {
+    let is_png = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    if is_png {
+        save_png(path, image)
+    } else {
+        Ok(image.save(path)?)
+    }
+}
@@ -0,0 +1,296 @@
+//! C ABI for embedding the renderer in other applications, for the `ffi` feature.
+//!
+//! The C libraries are only built on request, with
+//! `cargo rustc --lib --release --features ffi --crate-type staticlib,cdylib`.
+//! `include/quilt_painter.h` is generated from this file with
+//! `cbindgen --config cbindgen.toml --output include/quilt_painter.h`. Images cross the boundary
+//! as tightly packed 8-bit RGB. Functions that can fail return null or false and leave a message
+//! for `quilt_last_error`.
+
+use crate::debug::NullDebugFlags;
+use crate::depth_gen::{generate_depth, DepthConfig, DEFAULT_COMFY_URL};
+use crate::image_types::{DepthImage, TextureImage};
+use crate::quilt::{get_quilt_settings, render_quilt, QuiltSettings, RenderOptions, Sampling};
+use image::{ImageBuffer, Rgb};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl ToString) {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, turning errors and panics into a `quilt_last_error` message.
+fn guarded<T>(f: impl FnOnce() -> Result<T, Box<dyn std::error::Error>>) -> Option<T> {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(e)) => {
+            set_last_error(e);
+            None
+        }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panic in quilt_painter".to_string());
+            set_last_error(message);
+            None
+        }
+    }
+}
+
+/// Quilt layout: tile grid, quilt size in pixels and display pixel aspect.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct QuiltLayout {
+    pub columns: u32,
+    pub rows: u32,
+    pub width: u32,
+    pub height: u32,
+    pub pixel_aspect: f32,
+}
+
+impl From<&QuiltSettings> for QuiltLayout {
+    fn from(settings: &QuiltSettings) -> Self {
+        Self {
+            columns: settings.columns,
+            rows: settings.rows,
+            width: settings.resolution.0,
+            height: settings.resolution.1,
+            pixel_aspect: settings.pixel_aspect,
+        }
+    }
+}
+
+impl From<&QuiltLayout> for QuiltSettings {
+    fn from(layout: &QuiltLayout) -> Self {
+        Self {
+            columns: layout.columns,
+            rows: layout.rows,
+            resolution: (layout.width, layout.height),
+            pixel_aspect: layout.pixel_aspect,
+        }
+    }
+}
+
+/// Render settings for `quilt_make`, matching the painter options of the same names.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct QuiltRenderOptions {
+    pub fov: f32,
+    pub zoom: f32,
+    pub scale: f32,
+    pub bg: [u8; 3],
+    /// Limit in view pixels on how far depth moves a point; 0 for none.
+    pub max_parallax: f32,
+    /// Split samples between neighbouring pixels instead of using the nearest.
+    pub subpixel: bool,
+    /// Pixels each texture sample covers, 1-3.
+    pub splat_radius: u32,
+}
+
+/// An 8-bit RGB image owned by the library. Release it with `quilt_free`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct QuiltImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl QuiltImage {
+    fn boxed(image: ImageBuffer<Rgb<u8>, Vec<u8>>) -> *mut QuiltImage {
+        let (width, height) = image.dimensions();
+        let mut data = image.into_raw().into_boxed_slice();
+        let len = data.len();
+        let data_ptr = data.as_mut_ptr();
+        std::mem::forget(data);
+        Box::into_raw(Box::new(QuiltImage {
+            width,
+            height,
+            data: data_ptr,
+            len,
+        }))
+    }
+}
+
+/// Copies a caller's RGB buffer into an image.
+///
+/// # Safety
+/// `data` must point to `width * height * 3` readable bytes.
+unsafe fn rgb_image(
+    data: *const u8,
+    width: u32,
+    height: u32,
+) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, Box<dyn std::error::Error>> {
+    if data.is_null() || width == 0 || height == 0 {
+        return Err("image data is null or empty".into());
+    }
+    let len = width as usize * height as usize * 3;
+    let pixels = std::slice::from_raw_parts(data, len).to_vec();
+    Ok(ImageBuffer::from_raw(width, height, pixels).ok_or("image buffer is too small")?)
+}
+
+/// # Safety
+/// `s` must be null or a NUL-terminated string.
+unsafe fn optional_str<'a>(
+    s: *const c_char,
+) -> Result<Option<&'a str>, Box<dyn std::error::Error>> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(CStr::from_ptr(s).to_str()?))
+}
+
+/// The message for the last failed call on this thread, or null. Valid until the next failing
+/// call on the same thread.
+#[no_mangle]
+pub extern "C" fn quilt_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Looks up the layout of a named device, e.g. "portrait" or "Looking Glass Go".
+///
+/// # Safety
+/// `device` must be a NUL-terminated string and `out` writable.
+#[no_mangle]
+pub unsafe extern "C" fn quilt_settings_for_device(
+    device: *const c_char,
+    out: *mut QuiltLayout,
+) -> bool {
+    guarded(|| {
+        let device = optional_str(device)?.ok_or("device is null")?;
+        let settings = get_quilt_settings(device).ok_or("Unknown device")?;
+        if out.is_null() {
+            return Err("out is null".into());
+        }
+        *out = settings.into();
+        Ok(())
+    })
+    .is_some()
+}
+
+/// The painter defaults.
+#[no_mangle]
+pub extern "C" fn quilt_render_options_default() -> QuiltRenderOptions {
+    QuiltRenderOptions {
+        fov: 60.0,
+        zoom: 1.0,
+        scale: 1.0,
+        bg: [0, 0, 0],
+        max_parallax: 0.0,
+        subpixel: false,
+        splat_radius: 1,
+    }
+}
+
+/// Renders a quilt from an RGB texture and a depth map, also RGB with depth in the red channel.
+/// Returns null on failure.
+///
+/// # Safety
+/// `layout` and `options` must be valid, and each image pointer must point to
+/// `width * height * 3` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn quilt_make(
+    layout: *const QuiltLayout,
+    texture: *const u8,
+    texture_width: u32,
+    texture_height: u32,
+    depth: *const u8,
+    depth_width: u32,
+    depth_height: u32,
+    options: *const QuiltRenderOptions,
+) -> *mut QuiltImage {
+    guarded(|| {
+        let settings = QuiltSettings::from(layout.as_ref().ok_or("layout is null")?);
+        if settings.columns == 0 || settings.rows == 0 {
+            return Err("layout needs non-zero columns and rows".into());
+        }
+        let options = options.as_ref().ok_or("options is null")?;
+        let texture = TextureImage(rgb_image(texture, texture_width, texture_height)?);
+        let depth = DepthImage(rgb_image(depth, depth_width, depth_height)?);
+        let render_options = RenderOptions {
+            fov_deg: options.fov,
+            zoom: options.zoom,
+            scale: options.scale,
+            background: Rgb(options.bg).into(),
+            max_parallax: (options.max_parallax > 0.0).then_some(options.max_parallax),
+            sampling: if options.subpixel {
+                Sampling::Subpixel
+            } else {
+                Sampling::Nearest
+            },
+            splat_radius: options.splat_radius.clamp(1, 3),
+            ..Default::default()
+        };
+        let quilt = render_quilt(
+            &settings,
+            &texture,
+            &depth,
+            &render_options,
+            Default::default(),
+            None,
+            &NullDebugFlags,
+        );
+        Ok(QuiltImage::boxed(quilt))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Generates a depth map for an image file with ComfyUI, using the depth cache in `cache_dir`
+/// if it isn't null. On success, `texture_out` and `depth_out` receive images to release with
+/// `quilt_free`.
+///
+/// # Safety
+/// The strings must be NUL-terminated (`cache_dir` may be null) and the out pointers writable.
+#[no_mangle]
+pub unsafe extern "C" fn depth_generate(
+    input_path: *const c_char,
+    comfy_url: *const c_char,
+    cache_dir: *const c_char,
+    texture_out: *mut *mut QuiltImage,
+    depth_out: *mut *mut QuiltImage,
+) -> bool {
+    guarded(|| {
+        if texture_out.is_null() || depth_out.is_null() {
+            return Err("out pointers must not be null".into());
+        }
+        let input_path = optional_str(input_path)?.ok_or("input_path is null")?;
+        let config = DepthConfig {
+            comfy_url: optional_str(comfy_url)?
+                .unwrap_or(DEFAULT_COMFY_URL)
+                .to_string(),
+            cache_dir: optional_str(cache_dir)?.map(PathBuf::from),
+            ..DepthConfig::default()
+        };
+        let (texture, depth) = generate_depth(PathBuf::from(input_path), &config)?;
+        *texture_out = QuiltImage::boxed(texture.0);
+        *depth_out = QuiltImage::boxed(depth.0);
+        Ok(())
+    })
+    .is_some()
+}
+
+/// Releases an image returned by this library. Null is ignored.
+///
+/// # Safety
+/// `image` must be null or an image from this library that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn quilt_free(image: *mut QuiltImage) {
+    if image.is_null() {
+        return;
+    }
+    let image = Box::from_raw(image);
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+        image.data, image.len,
+    )));
+}
@@ -0,0 +1,168 @@
+//! Synthetic texture/heightmap pairs and their reference quilts, behind the `fixtures` feature.
+//!
+//! Everything here is generated procedurally, so a golden-image check needs no sample photos or
+//! network access: render the fixtures before a change with `write_references`, then compare
+//! after it with `compare_to_references`. `cargo test --features fixtures` does both.
+
+use crate::captions::CaptionConfig;
+use crate::debug::NullDebugFlags;
+use crate::diff::{compare_quilts, QuiltDiff};
+use crate::image_types::{DepthImage, TextureImage};
+use crate::quilt::{render_quilt, QuiltSettings, RenderOptions};
+use image::{ImageBuffer, Rgb};
+use std::error::Error;
+use std::path::Path;
+
+/// Size of the generated textures and heightmaps.
+pub const FIXTURE_SIZE: u32 = 64;
+
+/// Small layout used for reference quilts, so a full fixture run takes well under a second.
+pub const FIXTURE_QUILT: QuiltSettings = QuiltSettings {
+    columns: 4,
+    rows: 2,
+    resolution: (256, 128),
+    pixel_aspect: 1.0,
+};
+
+pub const FIXTURE_FOV: f32 = 40.0;
+pub const FIXTURE_ZOOM: f32 = 1.0;
+pub const FIXTURE_SCALE: f32 = 1.0;
+pub const FIXTURE_BACKGROUND: Rgb<u8> = Rgb([0, 0, 0]);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    /// Texture and depth both ramp from left to right.
+    Gradient,
+    /// 8px checkerboard texture over a flat plane tilted towards the bottom.
+    Checkerboard,
+    /// Four vertical bands at increasing depth, with sharp edges between them.
+    Steps,
+}
+
+impl Pattern {
+    pub const ALL: [Pattern; 3] = [Pattern::Gradient, Pattern::Checkerboard, Pattern::Steps];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pattern::Gradient => "gradient",
+            Pattern::Checkerboard => "checkerboard",
+            Pattern::Steps => "steps",
+        }
+    }
+}
+
+pub struct Fixture {
+    pub pattern: Pattern,
+    pub texture: TextureImage,
+    pub heightmap: DepthImage,
+}
+
+impl Fixture {
+    pub fn new(pattern: Pattern, width: u32, height: u32) -> Self {
+        Self {
+            pattern,
+            texture: texture(pattern, width, height),
+            heightmap: heightmap(pattern, width, height),
+        }
+    }
+
+    /// Renders the fixture with the fixed `FIXTURE_*` settings.
+    pub fn reference_quilt(&self) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        render_quilt(
+            &FIXTURE_QUILT,
+            &self.texture,
+            &self.heightmap,
+            &RenderOptions {
+                fov_deg: FIXTURE_FOV,
+                zoom: FIXTURE_ZOOM,
+                scale: FIXTURE_SCALE,
+                background: FIXTURE_BACKGROUND.into(),
+                ..Default::default()
+            },
+            CaptionConfig::default(),
+            None,
+            &NullDebugFlags,
+        )
+    }
+}
+
+/// Every pattern at `FIXTURE_SIZE`.
+pub fn all() -> Vec<Fixture> {
+    Pattern::ALL
+        .iter()
+        .map(|&pattern| Fixture::new(pattern, FIXTURE_SIZE, FIXTURE_SIZE))
+        .collect()
+}
+
+pub fn texture(pattern: Pattern, width: u32, height: u32) -> TextureImage {
+    TextureImage(ImageBuffer::from_fn(width, height, |x, y| match pattern {
+        Pattern::Gradient => {
+            let t = ramp(x, width);
+            Rgb([t, 255 - t, ramp(y, height)])
+        }
+        Pattern::Checkerboard => {
+            if (x / 8 + y / 8) % 2 == 0 {
+                Rgb([230, 230, 230])
+            } else {
+                Rgb([30, 30, 120])
+            }
+        }
+        Pattern::Steps => {
+            const COLORS: [[u8; 3]; 4] =
+                [[200, 40, 40], [40, 200, 40], [40, 40, 200], [200, 200, 40]];
+            Rgb(COLORS[band(x, width)])
+        }
+    }))
+}
+
+pub fn heightmap(pattern: Pattern, width: u32, height: u32) -> DepthImage {
+    DepthImage(ImageBuffer::from_fn(width, height, |x, y| {
+        let depth = match pattern {
+            Pattern::Gradient => ramp(x, width),
+            Pattern::Checkerboard => ramp(y, height),
+            Pattern::Steps => (band(x, width) * 85) as u8,
+        };
+        Rgb([depth, depth, depth])
+    }))
+}
+
+/// 0 at the first pixel to 255 at the last.
+fn ramp(position: u32, size: u32) -> u8 {
+    (position * 255 / (size - 1).max(1)) as u8
+}
+
+/// Which of four equal vertical bands `x` falls in.
+fn band(x: u32, width: u32) -> usize {
+    (x * 4 / width.max(1)).min(3) as usize
+}
+
+/// Saves every fixture's reference quilt as `<dir>/<pattern>.png`.
+pub fn write_references(dir: &Path) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(dir)?;
+    for fixture in all() {
+        fixture
+            .reference_quilt()
+            .save(dir.join(format!("{}.png", fixture.pattern.name())))?;
+    }
+    Ok(())
+}
+
+/// Renders every fixture and compares it with the quilt saved by `write_references`.
+pub fn compare_to_references(dir: &Path) -> Result<Vec<(Pattern, QuiltDiff)>, Box<dyn Error>> {
+    all()
+        .iter()
+        .map(|fixture| {
+            let path = dir.join(format!("{}.png", fixture.pattern.name()));
+            let reference = image::open(&path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?
+                .to_rgb8();
+            let diff = compare_quilts(
+                &reference,
+                &fixture.reference_quilt(),
+                FIXTURE_QUILT.columns,
+                FIXTURE_QUILT.rows,
+            )?;
+            Ok((fixture.pattern, diff))
+        })
+        .collect()
+}
@@ -0,0 +1,141 @@
+//! Detection of a connected Looking Glass display over USB HID, for the `hid` feature.
+//!
+//! Each display stores its calibration as JSON in flash, readable through HID feature reports.
+//! The panel size and density identify the model, and the calibrated view cone replaces the
+//! `--fov` default, so on the machine driving the display `--device` can be left out.
+
+use crate::quilt::get_quilt_settings;
+use hidapi::{HidApi, HidDevice};
+use serde_json::Value;
+use std::error::Error;
+
+/// USB ids of the HID interface the displays expose for calibration.
+const VENDOR_ID: u16 = 0x04d8;
+const PRODUCT_ID: u16 = 0xef7e;
+
+/// Bytes of calibration data in each feature report, after the report id and page number.
+const PAGE_SIZE: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct DetectedDisplay {
+    /// Name of the matching `DEVICE_PRESETS` entry.
+    pub device: &'static str,
+    pub serial: String,
+    /// Calibrated view cone in degrees, if the calibration has one.
+    pub view_cone: Option<f32>,
+}
+
+/// Reads one calibration field. Older firmware stores fields as `{"value": x}`, newer as plain
+/// numbers.
+fn calibration_value(calibration: &Value, key: &str) -> Option<f64> {
+    let field = &calibration[key];
+    field["value"].as_f64().or_else(|| field.as_f64())
+}
+
+/// The `DEVICE_PRESETS` entry for a display with this calibration. The 32" and 65" displays
+/// share a panel resolution, so pixel density tells them apart.
+pub fn device_for_calibration(calibration: &Value) -> Option<&'static str> {
+    let width = calibration_value(calibration, "screenW")? as u32;
+    let height = calibration_value(calibration, "screenH")? as u32;
+    let dpi = calibration_value(calibration, "DPI").unwrap_or(0.0);
+    let device = match (width, height) {
+        (1440, 2560) => "Looking Glass Go",
+        (1536, 2048) => "Looking Glass Portrait",
+        (3840, 2160) => "Looking Glass 16\" Landscape",
+        (2160, 3840) => "Looking Glass 16\" Portrait",
+        (5120, 2880) => "Looking Glass 27\"",
+        (7680, 4320) if dpi >= 200.0 => "Looking Glass 32\" Landscape",
+        (7680, 4320) => "Looking Glass 65\"",
+        (4320, 7680) => "Looking Glass 32\" Portrait",
+        _ => return None,
+    };
+    get_quilt_settings(device).map(|_| device)
+}
+
+/// Reads the calibration JSON page by page. Page data starts with the JSON length as a
+/// big-endian u32.
+fn read_calibration(device: &HidDevice) -> Result<Value, Box<dyn Error>> {
+    let mut data = Vec::new();
+    let mut len = None;
+    for page in 0u16.. {
+        let [page_hi, page_lo] = page.to_be_bytes();
+        device.send_feature_report(&[0, 0, page_hi, page_lo])?;
+        let mut report = [0u8; 4 + PAGE_SIZE];
+        let read = device.get_feature_report(&mut report)?;
+        if read < 4 || report[2..4] != [page_hi, page_lo] {
+            return Err(format!("unexpected reply reading calibration page {page}").into());
+        }
+        data.extend_from_slice(&report[4..read]);
+
+        if len.is_none() && data.len() >= 4 {
+            len = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize);
+        }
+        if let Some(len) = len {
+            if data.len() >= 4 + len {
+                return Ok(serde_json::from_slice(&data[4..4 + len])?);
+            }
+        }
+        if read < report.len() {
+            break;
+        }
+    }
+    Err("calibration ended early".into())
+}
+
+/// Finds the first connected display whose calibration identifies a known model.
+pub fn detect_display() -> Result<Option<DetectedDisplay>, Box<dyn Error>> {
+    let api = HidApi::new()?;
+    for info in api.device_list() {
+        if info.vendor_id() != VENDOR_ID || info.product_id() != PRODUCT_ID {
+            continue;
+        }
+        let calibration = match info
+            .open_device(&api)
+            .map_err(Into::into)
+            .and_then(|device| read_calibration(&device))
+        {
+            Ok(calibration) => calibration,
+            Err(e) => {
+                tracing::warn!("Failed to read display calibration: {}", e);
+                continue;
+            }
+        };
+        let serial = calibration["serial"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        match device_for_calibration(&calibration) {
+            Some(device) => {
+                return Ok(Some(DetectedDisplay {
+                    device,
+                    serial,
+                    view_cone: calibration_value(&calibration, "viewCone").map(|v| v as f32),
+                }))
+            }
+            None => tracing::warn!("Display {} is not a known model", serial),
+        }
+    }
+    Ok(None)
+}
+
+/// Whether `flag` was given on the command line, to tell an explicit value from a default.
+fn flag_given(flag: &str) -> bool {
+    std::env::args().any(|arg| arg == flag || arg.starts_with(&format!("{flag}=")))
+}
+
+/// Fills in the device, and `fov` from the calibrated view cone unless `--fov` was given, from
+/// the connected display. Detection failures are logged; the caller's values stay as they were.
+pub fn use_detected_display(device: &mut Option<String>, fov: &mut f32) {
+    match detect_display() {
+        Ok(Some(display)) => {
+            crate::status!("Detected {} ({})", display.device, display.serial);
+            *device = Some(display.device.to_string());
+            if let Some(view_cone) = display.view_cone.filter(|_| !flag_given("--fov")) {
+                // The renderer spreads views over half of the --fov angle
+                *fov = view_cone * 2.0;
+            }
+        }
+        Ok(None) => tracing::debug!("No Looking Glass display detected"),
+        Err(e) => tracing::warn!("Display detection failed: {}", e),
+    }
+}
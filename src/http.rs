@@ -0,0 +1,140 @@
+//! The small HTTP/1.1 server `quilt-serve` and `depthd` share: one request per connection, one
+//! thread per connection, no keep-alive.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+
+pub(crate) struct Request {
+    pub method: String,
+    /// The request target without its query string, still percent-encoded.
+    pub path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The value of the header `name`, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The decoded value of the query parameter `name`.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+            .and_then(|value| percent_decode(&value.replace('+', " ")))
+    }
+}
+
+/// Decodes `%XX` escapes. Returns `None` for malformed escapes or text that isn't UTF-8.
+pub(crate) fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads one request, with a body of at most `max_body` bytes.
+pub(crate) fn read_request(stream: &TcpStream, max_body: usize) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method,
+        path: path.to_string(),
+        query: query.to_string(),
+        headers: Vec::new(),
+        body: Vec::new(),
+    };
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? <= 2 {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            request
+                .headers
+                .push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let length: usize = match request.header("content-length") {
+        Some(length) => length
+            .parse()
+            .map_err(|e| invalid(format!("bad Content-Length: {e}")))?,
+        None => 0,
+    };
+    if length > max_body {
+        return Err(invalid(format!("request of {length} bytes is too large")));
+    }
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+/// Writes a response head for a body of `len` bytes. `extra` holds further header lines, each
+/// ending in `\r\n`.
+pub(crate) fn write_head(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    len: u64,
+    extra: &str,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {len}\r\n{extra}Connection: close\r\n\r\n"
+    )
+}
+
+/// Answers connections on `listener` with `respond` until the process exits, one thread per
+/// connection.
+pub(crate) fn serve_connections<F>(listener: TcpListener, respond: F)
+where
+    F: Fn(&mut TcpStream) -> Result<(), Box<dyn std::error::Error>> + Send + Sync + 'static,
+{
+    let respond = Arc::new(respond);
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let respond = respond.clone();
+        std::thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_default();
+            if let Err(e) = respond(&mut stream) {
+                tracing::debug!("Request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
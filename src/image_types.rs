@@ -1,5 +1,8 @@
 use image::{ImageBuffer, Rgb};
 
+/// An 8-bit RGB image, as views and quilts are rendered.
+pub type RgbImage = ImageBuffer<Rgb<u8>, Vec<u8>>;
+
 #[derive(Clone)]
 pub struct TextureImage(pub ImageBuffer<Rgb<u8>, Vec<u8>>);
 
@@ -9,6 +12,99 @@ pub struct DepthImage(pub ImageBuffer<Rgb<u8>, Vec<u8>>);
 #[derive(Clone)]
 pub struct RgbdImage(pub ImageBuffer<Rgb<u8>, Vec<u8>>);
 
+/// Colors the renderer reads, one texel at a time and from many threads. `TextureImage` is the
+/// usual one; procedural scenes can compute texels on demand instead of holding an image.
+pub trait TextureSource: Sync {
+    fn dimensions(&self) -> (u32, u32);
+
+    /// Color at (x, y), both within `dimensions`.
+    fn texel(&self, x: u32, y: u32) -> Rgb<u8>;
+}
+
+/// Depths the renderer reads alongside a `TextureSource` of the same size.
+pub trait DepthSource: Sync {
+    fn dimensions(&self) -> (u32, u32);
+
+    /// Depth at (x, y), both within `dimensions`, from 0 for the farthest to 255 for the nearest.
+    fn depth(&self, x: u32, y: u32) -> u8;
+}
+
+impl TextureSource for TextureImage {
+    fn dimensions(&self) -> (u32, u32) {
+        self.0.dimensions()
+    }
+
+    fn texel(&self, x: u32, y: u32) -> Rgb<u8> {
+        *self.0.get_pixel(x, y)
+    }
+}
+
+impl DepthSource for DepthImage {
+    fn dimensions(&self) -> (u32, u32) {
+        self.0.dimensions()
+    }
+
+    fn depth(&self, x: u32, y: u32) -> u8 {
+        self.0.get_pixel(x, y)[0]
+    }
+}
+
+/// A texture computed by a function of the texel position, e.g. a fractal or a plot, rendered
+/// without materializing it.
+pub struct ProceduralTexture<F> {
+    width: u32,
+    height: u32,
+    texel: F,
+}
+
+impl<F: Fn(u32, u32) -> Rgb<u8> + Sync> ProceduralTexture<F> {
+    pub fn new(width: u32, height: u32, texel: F) -> Self {
+        Self {
+            width,
+            height,
+            texel,
+        }
+    }
+}
+
+impl<F: Fn(u32, u32) -> Rgb<u8> + Sync> TextureSource for ProceduralTexture<F> {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn texel(&self, x: u32, y: u32) -> Rgb<u8> {
+        (self.texel)(x, y)
+    }
+}
+
+/// A depth map computed by a function of the texel position, the counterpart of
+/// `ProceduralTexture`.
+pub struct ProceduralDepth<F> {
+    width: u32,
+    height: u32,
+    depth: F,
+}
+
+impl<F: Fn(u32, u32) -> u8 + Sync> ProceduralDepth<F> {
+    pub fn new(width: u32, height: u32, depth: F) -> Self {
+        Self {
+            width,
+            height,
+            depth,
+        }
+    }
+}
+
+impl<F: Fn(u32, u32) -> u8 + Sync> DepthSource for ProceduralDepth<F> {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn depth(&self, x: u32, y: u32) -> u8 {
+        (self.depth)(x, y)
+    }
+}
+
 impl TextureImage {
     pub fn width(&self) -> u32 {
         self.0.width()
@@ -35,6 +131,28 @@ impl DepthImage {
     pub fn dimensions(&self) -> (u32, u32) {
         self.0.dimensions()
     }
+
+    /// Builds a tangent-space normal map (OpenGL convention, green up) from the depth values
+    /// using central differences. `strength` scales the slopes before normalization.
+    pub fn to_normal_map(&self, strength: f32) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        let (width, height) = self.dimensions();
+        let depth_at = |x: i64, y: i64| -> f32 {
+            let x = x.clamp(0, width as i64 - 1) as u32;
+            let y = y.clamp(0, height as i64 - 1) as u32;
+            self.0.get_pixel(x, y)[0] as f32 / 255.0
+        };
+
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let (x, y) = (x as i64, y as i64);
+            let dx = (depth_at(x + 1, y) - depth_at(x - 1, y)) * 0.5 * strength;
+            // Image rows grow downwards, so flip the slope to get a y-up normal.
+            let dy = (depth_at(x, y - 1) - depth_at(x, y + 1)) * 0.5 * strength;
+            let (nx, ny, nz) = (-dx, -dy, 1.0);
+            let len = (nx * nx + ny * ny + nz * nz).sqrt();
+            let encode = |v: f32| ((v / len * 0.5 + 0.5) * 255.0).round() as u8;
+            Rgb([encode(nx), encode(ny), encode(nz)])
+        })
+    }
 }
 
 impl RgbdImage {
@@ -0,0 +1,233 @@
+//! Inpainting behind the main subject. The subject is masked out by depth and a ComfyUI inpaint
+//! workflow paints what would be behind it, which becomes the back layer of a two-layer scene:
+//! views from the sides of a wide viewcone then see real background where the subject moves
+//! aside, instead of its edge pixels stretched across the gap.
+
+use crate::depth_gen::{run_workflow, set_workflow_input_image, upload_image, DepthConfig};
+use crate::image_types::{DepthImage, TextureImage};
+use crate::quilt::BackLayer;
+use crate::txt2img::{apply_txt2img_config, Txt2ImgConfig};
+use image::{GrayImage, ImageBuffer, Luma, Rgb, Rgba};
+use serde_json::Value;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Prompt for what fills the hole, when none is given.
+pub const DEFAULT_INPAINT_PROMPT: &str = "empty background, scenery, nothing in the foreground";
+
+/// How far, as a fraction of the image's longer side, the subject mask is grown before
+/// inpainting, so the subject's soft edge and halo are painted over too.
+const MASK_GROWTH: f32 = 0.01;
+
+#[derive(Clone, Debug)]
+pub struct InpaintConfig {
+    pub prompt: String,
+    pub negative_prompt: Option<String>,
+    /// Inpainting checkpoint for the bundled workflow's CheckpointLoaderSimple.
+    pub checkpoint: Option<String>,
+    /// ComfyUI API-format workflow to use instead of the bundled one. Its LoadImage node is
+    /// given the image with the subject transparent, which LoadImage turns into the mask.
+    pub workflow: Option<PathBuf>,
+    /// Depth above which pixels are the subject; picked from the depth histogram when not set.
+    pub subject_depth: Option<u8>,
+}
+
+impl Default for InpaintConfig {
+    fn default() -> Self {
+        Self {
+            prompt: DEFAULT_INPAINT_PROMPT.to_string(),
+            negative_prompt: None,
+            checkpoint: None,
+            workflow: None,
+            subject_depth: None,
+        }
+    }
+}
+
+/// Depth that best splits the depth map into a near subject and a far background: Otsu's
+/// threshold, which maximizes the variance between the two classes.
+pub fn subject_threshold(depth: &DepthImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in depth.0.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let total: u64 = histogram.iter().sum();
+    let weighted_total: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, &count)| value as f64 * count as f64)
+        .sum();
+
+    let (mut best, mut best_variance) = (0, 0.0);
+    let (mut below, mut weighted_below) = (0u64, 0.0);
+    for (value, &count) in histogram.iter().enumerate() {
+        below += count;
+        weighted_below += value as f64 * count as f64;
+        let above = total - below;
+        if below == 0 || above == 0 {
+            continue;
+        }
+        let mean_below = weighted_below / below as f64;
+        let mean_above = (weighted_total - weighted_below) / above as f64;
+        let variance = below as f64 * above as f64 * (mean_below - mean_above).powi(2);
+        if variance > best_variance {
+            (best, best_variance) = (value as u8, variance);
+        }
+    }
+    best
+}
+
+/// 255 where the depth is above `threshold`, grown by `grow` pixels in every direction.
+pub fn subject_mask(depth: &DepthImage, threshold: u8, grow: u32) -> GrayImage {
+    let mask = GrayImage::from_fn(depth.width(), depth.height(), |x, y| {
+        Luma([if depth.0.get_pixel(x, y)[0] > threshold {
+            255
+        } else {
+            0
+        }])
+    });
+    // A square dilation, as a horizontal then a vertical pass
+    let grow = grow as i64;
+    let dilate = |mask: &GrayImage, dx: i64, dy: i64| {
+        let (width, height) = mask.dimensions();
+        GrayImage::from_fn(width, height, |x, y| {
+            let covered = (-grow..=grow).any(|offset| {
+                let (sx, sy) = (x as i64 + offset * dx, y as i64 + offset * dy);
+                sx >= 0
+                    && sy >= 0
+                    && sx < width as i64
+                    && sy < height as i64
+                    && mask.get_pixel(sx as u32, sy as u32)[0] != 0
+            });
+            Luma([if covered { 255 } else { 0 }])
+        })
+    };
+    dilate(&dilate(&mask, 1, 0), 0, 1)
+}
+
+/// The depth map with the masked pixels of each row interpolated between the unmasked ones on
+/// either side, so the background continues behind the subject. Rows masked all the way across
+/// take the mean depth of the unmasked pixels.
+pub fn fill_behind(depth: &DepthImage, mask: &GrayImage) -> DepthImage {
+    let (width, height) = depth.dimensions();
+    let unmasked: Vec<f32> = depth
+        .0
+        .pixels()
+        .zip(mask.pixels())
+        .filter(|(_, masked)| masked[0] == 0)
+        .map(|(pixel, _)| pixel[0] as f32)
+        .collect();
+    let fallback = if unmasked.is_empty() {
+        0.0
+    } else {
+        unmasked.iter().sum::<f32>() / unmasked.len() as f32
+    };
+
+    let mut filled = depth.0.clone();
+    for y in 0..height {
+        let mut x = 0;
+        while x < width {
+            if mask.get_pixel(x, y)[0] == 0 {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < width && mask.get_pixel(x, y)[0] != 0 {
+                x += 1;
+            }
+            let left = (start > 0).then(|| depth.0.get_pixel(start - 1, y)[0] as f32);
+            let right = (x < width).then(|| depth.0.get_pixel(x, y)[0] as f32);
+            let run = (x - start + 1) as f32;
+            for fill_x in start..x {
+                let value = match (left, right) {
+                    (Some(left), Some(right)) => {
+                        let t = (fill_x - start + 1) as f32 / run;
+                        left + (right - left) * t
+                    }
+                    (Some(edge), None) | (None, Some(edge)) => edge,
+                    (None, None) => fallback,
+                };
+                let value = value.round() as u8;
+                filled.put_pixel(fill_x, y, Rgb([value, value, value]));
+            }
+        }
+    }
+    DepthImage(filled)
+}
+
+/// Inpaints the subject out of `texture` with ComfyUI and returns the back layer for it. Only
+/// the masked pixels are taken from the inpainted image, so the rest of the back layer matches
+/// the main layer exactly.
+pub fn inpaint_back_layer(
+    input_path: &Path,
+    texture: &TextureImage,
+    depth: &DepthImage,
+    config: &InpaintConfig,
+    comfy: &DepthConfig,
+) -> Result<BackLayer, Box<dyn Error>> {
+    let (width, height) = texture.dimensions();
+    let subject_depth = config
+        .subject_depth
+        .unwrap_or_else(|| subject_threshold(depth));
+    let grow = (width.max(height) as f32 * MASK_GROWTH).ceil() as u32;
+    let mask = subject_mask(depth, subject_depth, grow);
+    tracing::debug!("Inpainting behind depths above {}", subject_depth);
+
+    // LoadImage masks the transparent pixels
+    let stem = input_path
+        .file_stem()
+        .ok_or("input path does not contain a file name")?
+        .to_string_lossy();
+    let masked_path =
+        std::env::temp_dir().join(format!("{}_inpaint_{}.png", stem, std::process::id()));
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let Rgb([r, g, b]) = *texture.0.get_pixel(x, y);
+        Rgba([r, g, b, 255 - mask.get_pixel(x, y)[0]])
+    })
+    .save(&masked_path)?;
+    let uploaded = upload_image(&masked_path, comfy);
+    let _ = std::fs::remove_file(&masked_path);
+    let uploaded = uploaded?;
+
+    let mut workflow: Value = match &config.workflow {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => serde_json::from_str(include_str!("../data/InpaintWorkflow.json"))?,
+    };
+    apply_txt2img_config(
+        &mut workflow,
+        &Txt2ImgConfig {
+            prompt: config.prompt.clone(),
+            negative_prompt: config.negative_prompt.clone(),
+            seed: 0,
+            checkpoint: config.checkpoint.clone(),
+            workflow: None,
+        },
+    )?;
+    set_workflow_input_image(&mut workflow, &uploaded)?;
+
+    let inpainted = image::load_from_memory(&run_workflow(&workflow, comfy)?)?.to_rgb8();
+    // Inpainting models work in multiples of 8 pixels and may crop the rest
+    let inpainted = if inpainted.dimensions() != (width, height) {
+        image::imageops::resize(
+            &inpainted,
+            width,
+            height,
+            image::imageops::FilterType::Lanczos3,
+        )
+    } else {
+        inpainted
+    };
+    let back_texture = ImageBuffer::from_fn(width, height, |x, y| {
+        if mask.get_pixel(x, y)[0] != 0 {
+            *inpainted.get_pixel(x, y)
+        } else {
+            *texture.0.get_pixel(x, y)
+        }
+    });
+
+    Ok(BackLayer {
+        texture: TextureImage(back_texture),
+        heightmap: fill_behind(depth, &mask),
+        subject_depth,
+    })
+}
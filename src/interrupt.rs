@@ -0,0 +1,60 @@
+//! Ctrl-C handling. Once [`install`]ed, Ctrl-C still exits at once, except while some code has
+//! said it will handle it with [`defer`]: then it only sets a flag, which that code checks with
+//! [`interrupted`] to stop cleanly, e.g. by cancelling its ComfyUI prompt. A second Ctrl-C always
+//! exits.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static DEFERRING: AtomicUsize = AtomicUsize::new(0);
+
+/// Exit status of a process killed by SIGINT, as shells report it.
+const INTERRUPTED_STATUS: i32 = 130;
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_: libc::c_int) {
+    // Only async-signal-safe calls here
+    if INTERRUPTED.swap(true, Ordering::SeqCst) || DEFERRING.load(Ordering::SeqCst) == 0 {
+        unsafe { libc::_exit(INTERRUPTED_STATUS) };
+    }
+    let message = b"\nStopping; press Ctrl-C again to exit at once\n";
+    unsafe { libc::write(libc::STDERR_FILENO, message.as_ptr().cast(), message.len()) };
+}
+
+/// Installs the Ctrl-C handler. Does nothing on platforms other than Unix, where Ctrl-C keeps
+/// exiting at once.
+pub fn install() {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// Whether Ctrl-C was pressed while deferred.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Defers Ctrl-C until the returned guard is dropped.
+pub fn defer() -> Deferred {
+    DEFERRING.fetch_add(1, Ordering::SeqCst);
+    Deferred { _private: () }
+}
+
+/// Exits as Ctrl-C would have, for code that stopped cleanly after [`interrupted`].
+pub fn exit() -> ! {
+    std::process::exit(INTERRUPTED_STATUS)
+}
+
+pub struct Deferred {
+    _private: (),
+}
+
+impl Drop for Deferred {
+    fn drop(&mut self) {
+        DEFERRING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
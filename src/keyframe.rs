@@ -0,0 +1,106 @@
+//! Keyframed render parameters for quilt sequences, as given by `--keyframe`: zoom, scale and
+//! focus depth at points in time, eased between so a still image drifts like a Ken Burns pan.
+
+use std::str::FromStr;
+
+/// Parameter values at one point in time. Values left out are eased between the keyframes on
+/// either side that give them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Keyframe {
+    /// Seconds from the start of the sequence.
+    pub time: f32,
+    pub zoom: Option<f32>,
+    pub scale: Option<f32>,
+    /// Depth map value kept on the display plane, 0 to 255.
+    pub focus: Option<f32>,
+}
+
+impl FromStr for Keyframe {
+    type Err = String;
+
+    /// Parses `t=SECONDS:NAME=VALUE,...` with names `zoom`, `scale` and `focus`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid keyframe '{}', expected t=SECONDS:NAME=VALUE,... with zoom, scale or focus",
+                s
+            )
+        };
+        let (time, values) = s.split_once(':').ok_or_else(invalid)?;
+        let time: f32 = time
+            .strip_prefix("t=")
+            .and_then(|time| time.parse().ok())
+            .filter(|time: &f32| *time >= 0.0)
+            .ok_or_else(invalid)?;
+        let mut keyframe = Keyframe {
+            time,
+            ..Default::default()
+        };
+        for value in values.split(',') {
+            let (name, value) = value.split_once('=').ok_or_else(invalid)?;
+            let value: f32 = value.parse().map_err(|_| invalid())?;
+            match name {
+                "zoom" if value > 0.0 => keyframe.zoom = Some(value),
+                "scale" => keyframe.scale = Some(value),
+                "focus" if (0.0..=255.0).contains(&value) => keyframe.focus = Some(value),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(keyframe)
+    }
+}
+
+/// Keyframes in time order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Keyframes(Vec<Keyframe>);
+
+impl Keyframes {
+    /// Sorts `keyframes` by time. Two keyframes at the same time are an error.
+    pub fn new(mut keyframes: Vec<Keyframe>) -> Result<Self, String> {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        if let Some(pair) = keyframes
+            .windows(2)
+            .find(|pair| pair[0].time == pair[1].time)
+        {
+            return Err(format!("two keyframes at t={}", pair[0].time));
+        }
+        Ok(Self(keyframes))
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.0.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// The value `field` picks out at `time`, eased with smoothstep between the keyframes that
+    /// give it and held before the first and after the last. `None` if no keyframe gives it.
+    fn value(&self, time: f32, field: impl Fn(&Keyframe) -> Option<f32>) -> Option<f32> {
+        let points: Vec<(f32, f32)> = self
+            .0
+            .iter()
+            .filter_map(|keyframe| Some((keyframe.time, field(keyframe)?)))
+            .collect();
+        let next = points.iter().position(|&(t, _)| t > time);
+        match next {
+            Some(0) => Some(points[0].1),
+            Some(next) => {
+                let ((t0, v0), (t1, v1)) = (points[next - 1], points[next]);
+                let t = (time - t0) / (t1 - t0);
+                Some(v0 + (v1 - v0) * t * t * (3.0 - 2.0 * t))
+            }
+            None => points.last().map(|&(_, value)| value),
+        }
+    }
+
+    pub fn zoom(&self, time: f32) -> Option<f32> {
+        self.value(time, |keyframe| keyframe.zoom)
+    }
+
+    pub fn scale(&self, time: f32) -> Option<f32> {
+        self.value(time, |keyframe| keyframe.scale)
+    }
+
+    pub fn focus(&self, time: f32) -> Option<f32> {
+        self.value(time, |keyframe| keyframe.focus)
+    }
+}
@@ -0,0 +1,150 @@
+//! KTX2 quilt export for game engine plugins, behind the `ktx` feature.
+//!
+//! Quilts are written as a single-level, uncompressed `VK_FORMAT_R8G8B8A8_SRGB` texture. The quilt
+//! layout is stored in the key/value data under `LKGquilt` as JSON, e.g.
+//! `{"columns":8,"rows":6,"views":48,"aspect":0.75}`.
+
+use image::{ImageBuffer, Rgb};
+use std::error::Error;
+use std::path::Path;
+
+#[cfg(feature = "ktx")]
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+#[cfg(feature = "ktx")]
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+
+/// Saves `quilt` as a KTX2 file with its layout in the key/value data.
+#[cfg(feature = "ktx")]
+pub fn save_ktx2(
+    path: &Path,
+    quilt: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    columns: u32,
+    rows: u32,
+    aspect: f32,
+) -> Result<(), Box<dyn Error>> {
+    let layout = serde_json::json!({
+        "columns": columns,
+        "rows": rows,
+        "views": columns * rows,
+        "aspect": aspect,
+    });
+    let data = encode_ktx2(
+        quilt,
+        &[
+            ("KTXorientation", "rd".to_string()),
+            (
+                "KTXwriter",
+                format!("quilt_painter {}", env!("CARGO_PKG_VERSION")),
+            ),
+            ("LKGquilt", layout.to_string()),
+        ],
+    );
+    std::fs::write(path, data)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "ktx"))]
+pub fn save_ktx2(
+    _path: &Path,
+    _quilt: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    _columns: u32,
+    _rows: u32,
+    _aspect: f32,
+) -> Result<(), Box<dyn Error>> {
+    Err("KTX2 output requires building with the ktx feature".into())
+}
+
+/// Encodes an RGB image as RGBA8 sRGB KTX2. `key_values` must be sorted by key, as the format
+/// requires.
+#[cfg(feature = "ktx")]
+fn encode_ktx2(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, key_values: &[(&str, String)]) -> Vec<u8> {
+    let pixels: Vec<u8> = image
+        .pixels()
+        .flat_map(|p| [p[0], p[1], p[2], 255])
+        .collect();
+
+    let dfd = data_format_descriptor();
+    let mut kvd = Vec::new();
+    for (key, value) in key_values {
+        let entry_length = key.len() + 1 + value.len() + 1;
+        kvd.extend((entry_length as u32).to_le_bytes());
+        kvd.extend(key.as_bytes());
+        kvd.push(0);
+        kvd.extend(value.as_bytes());
+        kvd.push(0);
+        kvd.resize(kvd.len().next_multiple_of(4), 0);
+    }
+
+    // Identifier, header (9 u32) and index (4 u32 + 2 u64), then one level index entry (3 u64)
+    let dfd_offset = IDENTIFIER.len() + 9 * 4 + 4 * 4 + 2 * 8 + 3 * 8;
+    let kvd_offset = dfd_offset + dfd.len();
+    // Level data is aligned to lcm(texel size 4, 4)
+    let level_offset = (kvd_offset + kvd.len()).next_multiple_of(4);
+
+    let mut out = Vec::with_capacity(level_offset + pixels.len());
+    out.extend(IDENTIFIER);
+    for value in [
+        VK_FORMAT_R8G8B8A8_SRGB,
+        1, // typeSize
+        image.width(),
+        image.height(),
+        0, // pixelDepth
+        0, // layerCount
+        1, // faceCount
+        1, // levelCount
+        0, // supercompressionScheme
+    ] {
+        out.extend(value.to_le_bytes());
+    }
+    for value in [
+        dfd_offset as u32,
+        dfd.len() as u32,
+        kvd_offset as u32,
+        kvd.len() as u32,
+    ] {
+        out.extend(value.to_le_bytes());
+    }
+    out.extend(0u64.to_le_bytes()); // sgdByteOffset
+    out.extend(0u64.to_le_bytes()); // sgdByteLength
+    for value in [level_offset, pixels.len(), pixels.len()] {
+        out.extend((value as u64).to_le_bytes());
+    }
+    out.extend(dfd);
+    out.extend(kvd);
+    out.resize(level_offset, 0);
+    out.extend(pixels);
+    out
+}
+
+/// Khronos basic data format descriptor for RGBA8 sRGB with straight alpha.
+#[cfg(feature = "ktx")]
+fn data_format_descriptor() -> Vec<u8> {
+    const SAMPLE_DATATYPE_LINEAR: u8 = 0x10;
+    let samples: [(u16, u8); 4] = [(0, 0), (8, 1), (16, 2), (24, 15 | SAMPLE_DATATYPE_LINEAR)];
+    let block_size = 24 + 16 * samples.len();
+
+    let mut dfd = Vec::new();
+    dfd.extend(((4 + block_size) as u32).to_le_bytes()); // dfdTotalSize
+    dfd.extend(0u32.to_le_bytes()); // vendorId 0 (Khronos), descriptorType 0 (basic)
+    dfd.extend(2u16.to_le_bytes()); // versionNumber
+    dfd.extend((block_size as u16).to_le_bytes());
+    dfd.extend([
+        1, // colorModel RGBSDA
+        1, // colorPrimaries BT709
+        2, // transferFunction sRGB
+        0, // flags: straight alpha
+    ]);
+    dfd.extend([0, 0, 0, 0]); // texelBlockDimension: 1x1x1x1
+    dfd.extend([4, 0, 0, 0, 0, 0, 0, 0]); // bytesPlane0..7
+    for (bit_offset, channel) in samples {
+        dfd.extend(bit_offset.to_le_bytes());
+        dfd.push(7); // bitLength - 1
+        dfd.push(channel);
+        dfd.extend([0, 0, 0, 0]); // samplePosition
+        dfd.extend(0u32.to_le_bytes()); // sampleLower
+        dfd.extend(255u32.to_le_bytes()); // sampleUpper
+    }
+    dfd
+}
@@ -0,0 +1,121 @@
+//! Export for Leia lightfield displays such as the Lume Pad: four views in a 2x2 grid, leftmost
+//! view at the top left and then in reading order, with a JSON sidecar giving the layout and the
+//! disparity between neighboring views, which Leia's viewers use to set the convergence range.
+
+use crate::debug::DebugFlags;
+use crate::encode::save_image;
+use crate::image_types::{DepthImage, TextureImage};
+use crate::quilt::{render_raw_quilt, QuiltSettings, RenderOptions};
+use image::{ImageBuffer, Rgb};
+use serde::Serialize;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Views in a Leia 2x2 image.
+pub const LEIA_VIEWS: u32 = 4;
+
+/// Longer side of each view.
+pub const LEIA_VIEW_SIZE: u32 = 1280;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeiaDescriptor {
+    /// Grid image file name, relative to the descriptor.
+    pub image: Option<String>,
+    pub layout: &'static str,
+    pub views: u32,
+    pub view_width: u32,
+    pub view_height: u32,
+    /// Horizontal disparity in view pixels between neighboring views of the farthest and the
+    /// nearest depth. Negative values are behind the display plane.
+    pub disparity_min: f32,
+    pub disparity_max: f32,
+}
+
+/// View size for a texture: `LEIA_VIEW_SIZE` on the longer side, in the texture's aspect.
+fn view_size(texture: &TextureImage) -> (u32, u32) {
+    let (width, height) = texture.dimensions();
+    let scale = LEIA_VIEW_SIZE as f32 / width.max(height) as f32;
+    (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    )
+}
+
+/// Renders the four views with the quilt renderer, without captions or watermarks, and arranges
+/// them in Leia's 2x2 grid.
+pub fn render_leia_grid<D: DebugFlags>(
+    texture: &TextureImage,
+    heightmap: &DepthImage,
+    options: &RenderOptions,
+    debug_flags: &D,
+) -> (ImageBuffer<Rgb<u8>, Vec<u8>>, LeiaDescriptor) {
+    let (view_width, view_height) = view_size(texture);
+    // One row of tiles has the views left to right, as the grid wants them
+    let settings = QuiltSettings {
+        columns: LEIA_VIEWS,
+        rows: 1,
+        resolution: (view_width * LEIA_VIEWS, view_height),
+        pixel_aspect: 1.0,
+    };
+    let strip = render_raw_quilt(&settings, texture, heightmap, options, debug_flags);
+
+    let mut grid = ImageBuffer::new(view_width * 2, view_height * 2);
+    for position in 0..LEIA_VIEWS {
+        let view =
+            image::imageops::crop_imm(&strip, position * view_width, 0, view_width, view_height);
+        image::imageops::replace(
+            &mut grid,
+            &*view,
+            (position % 2 * view_width) as i64,
+            (position / 2 * view_height) as i64,
+        );
+    }
+
+    // Tile `position` from the left is view LEIA_VIEWS - 1 - position; see tile_origin
+    let camera = |position: u32| options.camera(&settings, LEIA_VIEWS - 1 - position);
+    let disparities: Vec<f32> = (0..LEIA_VIEWS - 1)
+        .flat_map(|position| {
+            let (left, right) = (camera(position), camera(position + 1));
+            [0.0, 255.0].map(|height| {
+                right.screen_offset(height, texture.width())
+                    - left.screen_offset(height, texture.width())
+            })
+        })
+        .collect();
+    let descriptor = LeiaDescriptor {
+        image: None,
+        layout: "2x2",
+        views: LEIA_VIEWS,
+        view_width,
+        view_height,
+        disparity_min: disparities.iter().copied().fold(f32::INFINITY, f32::min),
+        disparity_max: disparities
+            .iter()
+            .copied()
+            .fold(f32::NEG_INFINITY, f32::max),
+    };
+    (grid, descriptor)
+}
+
+/// Renders the 2x2 grid to `path` (PNG or JPEG) and its descriptor to `<stem>.json` beside it,
+/// and returns the descriptor's path.
+pub fn save_leia<D: DebugFlags>(
+    path: &Path,
+    texture: &TextureImage,
+    heightmap: &DepthImage,
+    options: &RenderOptions,
+    debug_flags: &D,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let (grid, descriptor) = render_leia_grid(texture, heightmap, options, debug_flags);
+    save_image(path, &grid)?;
+    let descriptor = LeiaDescriptor {
+        image: path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+        ..descriptor
+    };
+    let descriptor_path = path.with_extension("json");
+    std::fs::write(&descriptor_path, serde_json::to_string_pretty(&descriptor)?)?;
+    Ok(descriptor_path)
+}
@@ -1,7 +1,57 @@
+pub mod animation;
+pub mod autotune;
+pub mod background;
 pub mod camera;
 pub mod captions;
+pub mod cli;
 pub mod debug;
 pub mod depth_gen;
+#[cfg(feature = "async")]
+pub mod depth_gen_async;
+pub mod depthd;
+pub mod descriptor;
+pub mod device_sync;
+pub mod diff;
+pub mod dither;
+pub mod encode;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
+#[cfg(feature = "hid")]
+pub mod hid;
+mod http;
 pub mod image_types;
+pub mod inpaint;
+pub mod interrupt;
+pub mod keyframe;
+pub mod ktx;
+pub mod leia;
+pub mod logging;
+pub mod mesh;
+pub mod metadata;
+pub mod metrics;
+pub mod mpo;
+pub mod output;
+pub mod pan;
+pub mod panorama;
+pub mod postfx;
+pub mod preview;
+pub mod quality;
 pub mod quilt;
 pub mod quilt_gen;
+pub mod render_cache;
+pub mod saliency;
+pub mod save_queue;
+pub mod script;
+pub mod serve;
+pub mod slideshow;
+pub mod subtitles;
+pub mod temporal;
+pub mod tiled;
+pub mod title;
+pub mod txt2img;
+pub mod undistort;
+pub mod video;
+pub mod viz;
+pub mod watermark;
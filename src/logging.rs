@@ -0,0 +1,40 @@
+//! Log and trace output for the command line tools.
+//!
+//! Diagnostics are `tracing` events inside spans for each stage (upload, prompt queue, websocket
+//! wait, per-view render, stitch and encode). `RUST_LOG` picks what is shown, as before; at debug
+//! level each stage reports its duration when it closes. `--log-format json` writes one JSON
+//! object per line for log collectors and profiling scripts.
+
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per event, with the enclosing spans
+    Json,
+}
+
+/// Installs the global subscriber, writing to stderr. Records from crates that use `log` are
+/// forwarded too.
+pub fn init(format: LogFormat) {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr);
+    let result = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .try_init(),
+    };
+    if let Err(e) = result {
+        eprintln!("Warning: Failed to set up logging: {}", e);
+    }
+}
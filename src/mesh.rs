@@ -0,0 +1,336 @@
+//! Export of scenes as PLY point clouds and OBJ grid meshes, for inspection in Blender or
+//! MeshLab. glTF is not supported; Blender and MeshLab both convert OBJ to it.
+//!
+//! `painter` also takes the same formats as input, resampling their vertices into a texture and
+//! heightmap. Only ASCII PLY is read, and OBJ faces are ignored, so a mesh is only as detailed as
+//! its vertices. The other commands take RGBD images only.
+
+use crate::image_types::{DepthImage, TextureImage};
+use image::{ImageBuffer, Rgb};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Position of a texture sample in scene space, matching the renderer: the texture is centered
+/// on the origin with x to the right, y up, and the height pushed towards the viewer along z.
+fn scene_point(
+    tex_x: u32,
+    tex_y: u32,
+    height: u8,
+    tex_width: u32,
+    tex_height: u32,
+    z_scale: f32,
+) -> (f32, f32, f32) {
+    let x = tex_x as f32 - tex_width as f32 / 2.0;
+    let y = tex_height as f32 / 2.0 - tex_y as f32;
+    let z = height as f32 * z_scale;
+    (x, y, z)
+}
+
+/// Writes one colored vertex per texture pixel as an ASCII PLY point cloud.
+pub fn write_point_cloud_ply<W: Write>(
+    out: &mut W,
+    texture: &TextureImage,
+    heightmap: &DepthImage,
+    z_scale: f32,
+) -> std::io::Result<()> {
+    let (width, height) = texture.dimensions();
+    writeln!(out, "ply")?;
+    writeln!(out, "format ascii 1.0")?;
+    writeln!(out, "element vertex {}", width * height)?;
+    writeln!(out, "property float x")?;
+    writeln!(out, "property float y")?;
+    writeln!(out, "property float z")?;
+    writeln!(out, "property uchar red")?;
+    writeln!(out, "property uchar green")?;
+    writeln!(out, "property uchar blue")?;
+    writeln!(out, "end_header")?;
+
+    for tex_y in 0..height {
+        for tex_x in 0..width {
+            let depth = heightmap.0.get_pixel(tex_x, tex_y)[0];
+            let (x, y, z) = scene_point(tex_x, tex_y, depth, width, height, z_scale);
+            let color = texture.0.get_pixel(tex_x, tex_y);
+            writeln!(
+                out,
+                "{} {} {} {} {} {}",
+                x, y, z, color[0], color[1], color[2]
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the heightmap as a displaced grid mesh in OBJ format. The material library and the
+/// texture image are written next to `obj_path` with `.mtl` and `_texture.png` suffixes.
+pub fn write_grid_mesh_obj(
+    obj_path: &Path,
+    texture: &TextureImage,
+    heightmap: &DepthImage,
+    z_scale: f32,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = texture.dimensions();
+    let stem = obj_path
+        .file_stem()
+        .ok_or("mesh path does not contain a file name")?
+        .to_string_lossy()
+        .to_string();
+    let mtl_path = obj_path.with_extension("mtl");
+    let texture_name = format!("{}_texture.png", stem);
+    texture.0.save(obj_path.with_file_name(&texture_name))?;
+
+    let mut mtl = BufWriter::new(File::create(&mtl_path)?);
+    writeln!(mtl, "newmtl {}", stem)?;
+    writeln!(mtl, "Ka 1.0 1.0 1.0")?;
+    writeln!(mtl, "Kd 1.0 1.0 1.0")?;
+    writeln!(mtl, "map_Kd {}", texture_name)?;
+    mtl.flush()?;
+
+    let mut obj = BufWriter::new(File::create(obj_path)?);
+    writeln!(
+        obj,
+        "mtllib {}",
+        mtl_path.file_name().unwrap_or_default().to_string_lossy()
+    )?;
+    writeln!(obj, "usemtl {}", stem)?;
+
+    let u_den = (width.max(2) - 1) as f32;
+    let v_den = (height.max(2) - 1) as f32;
+    for tex_y in 0..height {
+        for tex_x in 0..width {
+            let depth = heightmap.0.get_pixel(tex_x, tex_y)[0];
+            let (x, y, z) = scene_point(tex_x, tex_y, depth, width, height, z_scale);
+            writeln!(obj, "v {} {} {}", x, y, z)?;
+            writeln!(
+                obj,
+                "vt {} {}",
+                tex_x as f32 / u_den,
+                1.0 - tex_y as f32 / v_den
+            )?;
+        }
+    }
+
+    // OBJ indices are 1-based, and each vertex shares its index with its texture coordinate.
+    let index = |x: u32, y: u32| y * width + x + 1;
+    for tex_y in 0..height.saturating_sub(1) {
+        for tex_x in 0..width.saturating_sub(1) {
+            let a = index(tex_x, tex_y);
+            let b = index(tex_x + 1, tex_y);
+            let c = index(tex_x, tex_y + 1);
+            let d = index(tex_x + 1, tex_y + 1);
+            writeln!(obj, "f {a}/{a} {c}/{c} {b}/{b}")?;
+            writeln!(obj, "f {b}/{b} {c}/{c} {d}/{d}")?;
+        }
+    }
+    obj.flush()?;
+    Ok(())
+}
+
+/// Exports the scene as a PLY point cloud or OBJ mesh depending on the extension of `path`.
+pub fn export_scene(
+    path: &Path,
+    texture: &TextureImage,
+    heightmap: &DepthImage,
+    z_scale: f32,
+) -> Result<(), Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("ply") => {
+            let mut out = BufWriter::new(File::create(path)?);
+            write_point_cloud_ply(&mut out, texture, heightmap, z_scale)?;
+            out.flush()?;
+            Ok(())
+        }
+        Some("obj") => write_grid_mesh_obj(path, texture, heightmap, z_scale),
+        _ => Err(format!("unsupported mesh export format: {}", path.display()).into()),
+    }
+}
+
+/// A colored point in scene space, as read from an imported point cloud or mesh.
+#[derive(Debug, Clone, Copy)]
+pub struct ScenePoint {
+    pub position: (f32, f32, f32),
+    pub color: [u8; 3],
+}
+
+/// Reads the vertices of an ASCII PLY file. Vertex colors default to mid gray when absent; binary
+/// PLY files are refused.
+pub fn read_point_cloud_ply(path: &Path) -> Result<Vec<ScenePoint>, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut lines = contents.lines();
+
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(format!("{} is not a PLY file", path.display()).into());
+    }
+
+    let mut vertex_count = 0;
+    let mut vertex_properties = Vec::new();
+    let mut in_vertex_element = false;
+    for line in lines.by_ref() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["format", format, ..] if *format != "ascii" => {
+                return Err(format!("unsupported PLY format: {}", format).into());
+            }
+            ["element", "vertex", count] => {
+                vertex_count = count.parse::<usize>()?;
+                in_vertex_element = true;
+            }
+            ["element", ..] => in_vertex_element = false,
+            ["property", .., name] if in_vertex_element => vertex_properties.push(name.to_string()),
+            ["end_header"] => break,
+            _ => {}
+        }
+    }
+
+    let index_of = |name: &str| vertex_properties.iter().position(|p| p == name);
+    let (x, y, z) = match (index_of("x"), index_of("y"), index_of("z")) {
+        (Some(x), Some(y), Some(z)) => (x, y, z),
+        _ => return Err("PLY vertices are missing x, y or z".into()),
+    };
+    let rgb = match (index_of("red"), index_of("green"), index_of("blue")) {
+        (Some(r), Some(g), Some(b)) => Some((r, g, b)),
+        _ => None,
+    };
+
+    let mut points = Vec::with_capacity(vertex_count);
+    for line in lines.take(vertex_count) {
+        let values: Vec<f32> = line
+            .split_whitespace()
+            .map(|v| v.parse::<f32>())
+            .collect::<Result<_, _>>()?;
+        let value = |i: usize| values.get(i).copied().ok_or("truncated PLY vertex");
+        let color = match rgb {
+            Some((r, g, b)) => [value(r)? as u8, value(g)? as u8, value(b)? as u8],
+            None => [128, 128, 128],
+        };
+        points.push(ScenePoint {
+            position: (value(x)?, value(y)?, value(z)?),
+            color,
+        });
+    }
+    Ok(points)
+}
+
+/// Reads the vertices of an OBJ file, including the common `v x y z r g b` vertex color
+/// extension. Faces are ignored; the vertices are treated as samples of a heightfield.
+pub fn read_mesh_obj(path: &Path) -> Result<Vec<ScenePoint>, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut points = Vec::new();
+    for line in contents.lines() {
+        let mut parts = line.split_whitespace();
+        if parts.next() != Some("v") {
+            continue;
+        }
+        let values: Vec<f32> = parts.map(|v| v.parse::<f32>()).collect::<Result<_, _>>()?;
+        if values.len() < 3 {
+            return Err(format!("malformed OBJ vertex: {}", line).into());
+        }
+        let color = if values.len() >= 6 {
+            let channel = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+            [channel(values[3]), channel(values[4]), channel(values[5])]
+        } else {
+            [128, 128, 128]
+        };
+        points.push(ScenePoint {
+            position: (values[0], values[1], values[2]),
+            color,
+        });
+    }
+    Ok(points)
+}
+
+/// Resamples scene points into a texture and heightmap whose longer side is `long_side` pixels,
+/// so the regular renderer can draw them. Where several points land in one cell the one nearest
+/// the viewer wins; empty cells borrow from their filled neighbours.
+pub fn rasterize_heightfield(points: &[ScenePoint], long_side: u32) -> (TextureImage, DepthImage) {
+    let mut min = (f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    let mut max = (f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for point in points {
+        let (x, y, z) = point.position;
+        min = (min.0.min(x), min.1.min(y), min.2.min(z));
+        max = (max.0.max(x), max.1.max(y), max.2.max(z));
+    }
+
+    let extent_x = (max.0 - min.0).max(f32::EPSILON);
+    let extent_y = (max.1 - min.1).max(f32::EPSILON);
+    let extent_z = (max.2 - min.2).max(f32::EPSILON);
+    let long_side = long_side.max(1);
+    // Size the grid by sample spacing so that integer-spaced exports come back at their size.
+    let (width, height) = if extent_x >= extent_y {
+        let height = ((long_side - 1) as f32 * extent_y / extent_x).round() as u32 + 1;
+        (long_side, height)
+    } else {
+        let width = ((long_side - 1) as f32 * extent_x / extent_y).round() as u32 + 1;
+        (width, long_side)
+    };
+
+    let mut cells: Vec<Option<(f32, [u8; 3])>> = vec![None; (width * height) as usize];
+    for point in points {
+        let (x, y, z) = point.position;
+        let cell_x = ((x - min.0) / extent_x * (width - 1) as f32).round() as u32;
+        // Scene y points up, image rows grow down.
+        let cell_y = ((max.1 - y) / extent_y * (height - 1) as f32).round() as u32;
+        let cell = &mut cells[(cell_y * width + cell_x) as usize];
+        let depth = (z - min.2) / extent_z;
+        if cell.is_none_or(|(d, _)| depth > d) {
+            *cell = Some((depth, point.color));
+        }
+    }
+
+    // Grow filled cells into holes left by sparse sampling.
+    while cells.iter().any(Option::is_none) && cells.iter().any(Option::is_some) {
+        let previous = cells.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let i = (y * width + x) as usize;
+                if previous[i].is_some() {
+                    continue;
+                }
+                let neighbours = [(-1i64, 0i64), (1, 0), (0, -1), (0, 1)];
+                cells[i] = neighbours.iter().find_map(|(dx, dy)| {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                        None
+                    } else {
+                        previous[(ny as u32 * width + nx as u32) as usize]
+                    }
+                });
+            }
+        }
+    }
+
+    let mut texture = ImageBuffer::new(width, height);
+    let mut depth = ImageBuffer::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let (d, color) = cells[(y * width + x) as usize].unwrap_or((0.0, [0, 0, 0]));
+            let d = (d * 255.0).round() as u8;
+            texture.put_pixel(x, y, Rgb(color));
+            depth.put_pixel(x, y, Rgb([d, d, d]));
+        }
+    }
+    (TextureImage(texture), DepthImage(depth))
+}
+
+/// Loads a PLY point cloud or OBJ mesh and resamples it into a texture and heightmap.
+pub fn import_scene(
+    path: &Path,
+    long_side: u32,
+) -> Result<(TextureImage, DepthImage), Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    let points = match extension.as_deref() {
+        Some("ply") => read_point_cloud_ply(path)?,
+        Some("obj") => read_mesh_obj(path)?,
+        _ => return Err(format!("unsupported mesh import format: {}", path.display()).into()),
+    };
+    if points.is_empty() {
+        return Err(format!("{} contains no vertices", path.display()).into());
+    }
+    Ok(rasterize_heightfield(&points, long_side))
+}
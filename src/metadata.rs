@@ -0,0 +1,359 @@
+//! Reads single text fields out of image metadata, for captions such as `--caption-from
+//! exif:DateTimeOriginal`.
+
+use serde::{Deserialize, Serialize};
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A metadata field, written `exif:<Tag>`, `iptc:<Dataset>` or `xmp:<prefix:property>`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetadataField {
+    /// An EXIF tag by name, e.g. `DateTimeOriginal` or `ImageDescription`.
+    Exif(String),
+    /// An IPTC IIM application record dataset by name, e.g. `Caption-Abstract`.
+    Iptc(String),
+    /// An XMP property by qualified name, e.g. `dc:title`.
+    Xmp(String),
+}
+
+impl FromStr for MetadataField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, name) = s
+            .split_once(':')
+            .filter(|(_, name)| !name.is_empty())
+            .ok_or_else(|| format!("invalid metadata field '{}', expected KIND:NAME", s))?;
+        match kind.to_ascii_lowercase().as_str() {
+            "exif" => Ok(MetadataField::Exif(name.to_string())),
+            "iptc" => iptc_dataset(name)
+                .map(|_| MetadataField::Iptc(name.to_string()))
+                .ok_or_else(|| format!("unknown IPTC dataset '{}'", name)),
+            "xmp" => Ok(MetadataField::Xmp(name.to_string())),
+            _ => Err(format!(
+                "unknown metadata kind '{}', expected exif, iptc or xmp",
+                kind
+            )),
+        }
+    }
+}
+
+impl MetadataField {
+    /// Reads the field from the image at `path`. Returns `None` if the file can't be read or
+    /// doesn't carry the field.
+    pub fn read(&self, path: &Path) -> Option<String> {
+        let value = match self {
+            MetadataField::Exif(name) => read_exif(path, name),
+            MetadataField::Iptc(name) => read_iptc(&std::fs::read(path).ok()?, name),
+            MetadataField::Xmp(name) => read_xmp(&std::fs::read(path).ok()?, name),
+        }?;
+        let value = value.trim();
+        (!value.is_empty()).then(|| value.to_string())
+    }
+}
+
+/// The first field in `fields` present in the image at `path`.
+pub fn read_first(path: &Path, fields: &[MetadataField]) -> Option<String> {
+    fields.iter().find_map(|field| field.read(path))
+}
+
+fn read_exif(path: &Path, name: &str) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    let field = exif
+        .fields()
+        .filter(|field| field.tag.to_string().eq_ignore_ascii_case(name))
+        .min_by_key(|field| field.ifd_num != exif::In::PRIMARY)?;
+    Some(exif_text(field, &exif))
+}
+
+/// Every EXIF tag of the image at `path` as `(name, text)`, primary image tags first. Empty if
+/// the file has no EXIF data.
+pub fn read_exif_fields(path: &Path) -> Vec<(String, String)> {
+    let Some(exif) = std::fs::File::open(path).ok().and_then(|file| {
+        exif::Reader::new()
+            .read_from_container(&mut BufReader::new(file))
+            .ok()
+    }) else {
+        return Vec::new();
+    };
+    let mut fields: Vec<&exif::Field> = exif.fields().collect();
+    fields.sort_by_key(|field| field.ifd_num != exif::In::PRIMARY);
+    fields
+        .into_iter()
+        .map(|field| (field.tag.to_string(), exif_text(field, &exif)))
+        .collect()
+}
+
+fn exif_text(field: &exif::Field, exif: &exif::Exif) -> String {
+    match &field.value {
+        // display_value() would quote strings; captions want the bare text.
+        exif::Value::Ascii(strings) => strings
+            .iter()
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => field.display_value().with_unit(exif).to_string(),
+    }
+}
+
+/// Application record (record 2) dataset numbers for the IPTC names people actually use.
+fn iptc_dataset(name: &str) -> Option<u8> {
+    let datasets = [
+        ("ObjectName", 5),
+        ("Keywords", 25),
+        ("DateCreated", 55),
+        ("By-line", 80),
+        ("City", 90),
+        ("Sub-location", 92),
+        ("Province-State", 95),
+        ("Country-PrimaryLocationName", 101),
+        ("Headline", 105),
+        ("Credit", 110),
+        ("Source", 115),
+        ("CopyrightNotice", 116),
+        ("Caption-Abstract", 120),
+    ];
+    datasets
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, dataset)| *dataset)
+}
+
+/// Reads an IPTC dataset from a JPEG's Photoshop APP13 segment. Repeated datasets (keywords)
+/// are joined with ", ".
+fn read_iptc(data: &[u8], name: &str) -> Option<String> {
+    let dataset = iptc_dataset(name)?;
+    let iim = photoshop_resource(jpeg_segment(data, 0xED, b"Photoshop 3.0\0")?, 0x0404)?;
+
+    let mut values = Vec::new();
+    let mut pos = 0;
+    while pos + 5 <= iim.len() && iim[pos] == 0x1C {
+        let (record, number) = (iim[pos + 1], iim[pos + 2]);
+        let size = u16::from_be_bytes([iim[pos + 3], iim[pos + 4]]) as usize;
+        let value = iim.get(pos + 5..pos + 5 + size)?;
+        if record == 2 && number == dataset {
+            values.push(String::from_utf8_lossy(value).into_owned());
+        }
+        pos += 5 + size;
+    }
+    (!values.is_empty()).then(|| values.join(", "))
+}
+
+/// The payload of the first JPEG APPn segment (`marker` is the byte after 0xFF) whose payload
+/// starts with `signature`, with the signature stripped.
+fn jpeg_segment<'a>(data: &'a [u8], marker: u8, signature: &[u8]) -> Option<&'a [u8]> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let segment_marker = data[pos + 1];
+        if segment_marker == 0xDA || segment_marker == 0xD9 {
+            break; // Start of scan: no more metadata segments
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let payload = data.get(pos + 4..pos + 2 + length)?;
+        if segment_marker == marker && payload.starts_with(signature) {
+            return Some(&payload[signature.len()..]);
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+/// The data of Photoshop image resource `id` from a sequence of `8BIM` resource blocks.
+fn photoshop_resource(blocks: &[u8], id: u16) -> Option<&[u8]> {
+    let mut pos = 0;
+    while blocks.get(pos..pos + 4)? == b"8BIM" {
+        let block_id = u16::from_be_bytes([*blocks.get(pos + 4)?, *blocks.get(pos + 5)?]);
+        // Pascal string name, padded so length byte + name is even
+        let name_length = *blocks.get(pos + 6)? as usize;
+        pos += 6 + (name_length + 1).div_ceil(2) * 2;
+        let size = u32::from_be_bytes(blocks.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let data = blocks.get(pos + 4..pos + 4 + size)?;
+        if block_id == id {
+            return Some(data);
+        }
+        pos += 4 + size.div_ceil(2) * 2;
+    }
+    None
+}
+
+/// Reads an XMP property from the first XMP packet in the file. The packet is found by its
+/// `<x:xmpmeta` wrapper, so this works for JPEG, PNG, TIFF and WebP alike. Handles both the
+/// attribute form (`dc:title="..."`) and the element form, where language alternatives and
+/// lists yield their first entry.
+fn read_xmp(data: &[u8], name: &str) -> Option<String> {
+    let start = find(data, b"<x:xmpmeta")?;
+    let end = find(&data[start..], b"</x:xmpmeta>")? + start;
+    let packet = String::from_utf8_lossy(&data[start..end]);
+
+    // Names must match whole, so `dc:title` doesn't find `xdc:title` or `<dc:titleX`.
+    let attribute = format!("{}=\"", name);
+    if let Some((pos, _)) = packet
+        .match_indices(&attribute)
+        .find(|(pos, _)| packet[..*pos].ends_with(char::is_whitespace))
+    {
+        let value = &packet[pos + attribute.len()..];
+        return Some(xml_unescape(&value[..value.find('"')?]));
+    }
+
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let start = packet
+        .match_indices(&open)
+        .map(|(pos, _)| pos + open.len())
+        .find(|&pos| {
+            packet[pos..].starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace())
+        })?;
+    let element = &packet[start..];
+    let element = &element[..element.find(&close)?];
+    // First text between tags inside the element
+    let text = element
+        .split('<')
+        .filter_map(|part| part.split_once('>').map(|(_, text)| text.trim()))
+        .find(|text| !text.is_empty())?;
+    Some(xml_unescape(text))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A JPEG holding `segments` as `(marker, payload)` APPn segments, then a start of scan.
+    fn jpeg(segments: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        for (marker, payload) in segments {
+            data.extend([0xFF, *marker]);
+            data.extend((payload.len() as u16 + 2).to_be_bytes());
+            data.extend(*payload);
+        }
+        data.extend([0xFF, 0xDA, 0x00, 0x02]);
+        data
+    }
+
+    /// A Photoshop image resource block with an empty name.
+    fn resource(id: u16, data: &[u8]) -> Vec<u8> {
+        let mut block = b"8BIM".to_vec();
+        block.extend(id.to_be_bytes());
+        block.extend([0, 0]);
+        block.extend((data.len() as u32).to_be_bytes());
+        block.extend(data);
+        if data.len() % 2 == 1 {
+            block.push(0);
+        }
+        block
+    }
+
+    fn dataset(number: u8, value: &str) -> Vec<u8> {
+        let mut dataset = vec![0x1C, 2, number];
+        dataset.extend((value.len() as u16).to_be_bytes());
+        dataset.extend(value.as_bytes());
+        dataset
+    }
+
+    fn photoshop_jpeg(iim: &[u8]) -> Vec<u8> {
+        let mut app13 = b"Photoshop 3.0\0".to_vec();
+        app13.extend(resource(0x03ED, b"odd"));
+        app13.extend(resource(0x0404, iim));
+        jpeg(&[(0xE1, b"Exif\0\0"), (0xED, &app13)])
+    }
+
+    #[test]
+    fn jpeg_segments_are_found_by_marker_and_signature() {
+        let data = jpeg(&[
+            (0xE1, b"Exif\0\0tiff"),
+            (0xE1, b"http://ns/xmp"),
+            (0xED, b"8BIM"),
+        ]);
+        assert_eq!(jpeg_segment(&data, 0xE1, b"Exif\0\0"), Some(&b"tiff"[..]));
+        assert_eq!(jpeg_segment(&data, 0xE1, b"http://"), Some(&b"ns/xmp"[..]));
+        assert_eq!(jpeg_segment(&data, 0xED, b""), Some(&b"8BIM"[..]));
+        assert_eq!(jpeg_segment(&data, 0xE2, b""), None);
+        // Not a JPEG
+        assert_eq!(jpeg_segment(&data[2..], 0xE1, b""), None);
+        // A segment running past the end of the file
+        assert_eq!(jpeg_segment(&data[..12], 0xE1, b"Exif"), None);
+    }
+
+    #[test]
+    fn jpeg_segments_after_the_scan_are_not_metadata() {
+        let mut data = jpeg(&[]);
+        data.extend([0xFF, 0xED, 0x00, 0x03, 0x01]);
+        assert_eq!(jpeg_segment(&data, 0xED, b""), None);
+    }
+
+    #[test]
+    fn photoshop_resources_skip_padding() {
+        let mut blocks = resource(1, b"abc");
+        // A named block: length byte and name padded to an even length
+        blocks.extend(b"8BIM\x00\x02\x03abc\x00\x00\x00\x02hi");
+        blocks.extend(resource(3, b"last"));
+        assert_eq!(photoshop_resource(&blocks, 1), Some(&b"abc"[..]));
+        assert_eq!(photoshop_resource(&blocks, 2), Some(&b"hi"[..]));
+        assert_eq!(photoshop_resource(&blocks, 3), Some(&b"last"[..]));
+        assert_eq!(photoshop_resource(&blocks, 4), None);
+        assert_eq!(photoshop_resource(&blocks[..blocks.len() - 1], 3), None);
+    }
+
+    #[test]
+    fn iptc_datasets() {
+        let mut iim = dataset(120, "A caption");
+        iim.extend(dataset(25, "beach"));
+        iim.extend(dataset(25, "dog"));
+        let data = photoshop_jpeg(&iim);
+        assert_eq!(
+            read_iptc(&data, "Caption-Abstract").as_deref(),
+            Some("A caption")
+        );
+        assert_eq!(read_iptc(&data, "keywords").as_deref(), Some("beach, dog"));
+        assert_eq!(read_iptc(&data, "Headline"), None);
+        assert_eq!(read_iptc(&data, "Unknown"), None);
+        assert_eq!(read_iptc(&jpeg(&[]), "Caption-Abstract"), None);
+    }
+
+    #[test]
+    fn xmp_attributes() {
+        let data = br#"junk<x:xmpmeta><rdf:Description xdc:title="wrong" dc:title="Tom &amp; Jerry"/></x:xmpmeta>"#;
+        assert_eq!(read_xmp(data, "dc:title").as_deref(), Some("Tom & Jerry"));
+        assert_eq!(read_xmp(data, "dc:creator"), None);
+    }
+
+    #[test]
+    fn xmp_elements() {
+        let data = br#"<x:xmpmeta><rdf:Description>
+            <dc:titleX>wrong</dc:titleX>
+            <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Sunset</rdf:li><rdf:li xml:lang="de">Abend</rdf:li></rdf:Alt></dc:title>
+            <dc:subject><rdf:Bag><rdf:li>beach</rdf:li></rdf:Bag></dc:subject>
+            <xmp:Rating>5</xmp:Rating>
+        </rdf:Description></x:xmpmeta>"#;
+        assert_eq!(read_xmp(data, "dc:title").as_deref(), Some("Sunset"));
+        assert_eq!(read_xmp(data, "dc:subject").as_deref(), Some("beach"));
+        assert_eq!(read_xmp(data, "xmp:Rating").as_deref(), Some("5"));
+        assert_eq!(read_xmp(data, "dc:titleX").as_deref(), Some("wrong"));
+        assert_eq!(read_xmp(data, "dc:creator"), None);
+        assert_eq!(
+            read_xmp(b"<dc:title>No packet</dc:title>", "dc:title"),
+            None
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Process-wide counters and histograms in the Prometheus text format.
+//!
+//! Long runs start `serve` to expose them at `/metrics` for a Prometheus scrape. The counters are
+//! plain atomics and always on, so recording costs next to nothing when nobody is scraping.
+
+use crate::http::{read_request, serve_connections, write_head};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+pub struct Counter {
+    value: AtomicU64,
+}
+
+impl Counter {
+    const fn new() -> Self {
+        Self {
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Upper bounds in seconds, wide enough for both one view (milliseconds) and a depth request
+/// queued behind other ComfyUI jobs (minutes).
+const BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0, 30.0, 120.0,
+];
+
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, &bound) in self.buckets.iter().zip(BUCKETS.iter()) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Metrics {
+    pub images_processed: Counter,
+    pub images_failed: Counter,
+    pub images_skipped: Counter,
+    pub depth_seconds: Histogram,
+    pub depth_cache_hits: Counter,
+    pub depth_cache_misses: Counter,
+    pub view_render_seconds: Histogram,
+    pub render_cache_hits: Counter,
+    pub render_cache_misses: Counter,
+}
+
+pub static METRICS: Metrics = Metrics {
+    images_processed: Counter::new(),
+    images_failed: Counter::new(),
+    images_skipped: Counter::new(),
+    depth_seconds: Histogram::new(),
+    depth_cache_hits: Counter::new(),
+    depth_cache_misses: Counter::new(),
+    view_render_seconds: Histogram::new(),
+    render_cache_hits: Counter::new(),
+    render_cache_misses: Counter::new(),
+};
+
+fn write_counter(out: &mut String, name: &str, help: &str, counter: &Counter) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {}", counter.get());
+}
+
+fn write_histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    for (bucket, bound) in histogram.buckets.iter().zip(BUCKETS.iter()) {
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{le=\"{bound}\"}} {}",
+            bucket.load(Ordering::Relaxed)
+        );
+    }
+    let count = histogram.count();
+    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+    let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+    let _ = writeln!(out, "{name}_sum {sum}");
+    let _ = writeln!(out, "{name}_count {count}");
+}
+
+impl Metrics {
+    /// All metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        write_counter(
+            &mut out,
+            "quilt_images_processed_total",
+            "Images turned into quilts.",
+            &self.images_processed,
+        );
+        write_counter(
+            &mut out,
+            "quilt_images_failed_total",
+            "Images that failed to process.",
+            &self.images_failed,
+        );
+        write_counter(
+            &mut out,
+            "quilt_images_skipped_total",
+            "Images the quality checks turned away.",
+            &self.images_skipped,
+        );
+        write_histogram(
+            &mut out,
+            "quilt_depth_seconds",
+            "Time to generate a depth map with ComfyUI, including the upload.",
+            &self.depth_seconds,
+        );
+        write_counter(
+            &mut out,
+            "quilt_depth_cache_hits_total",
+            "Depth maps taken from a sidecar or the cache.",
+            &self.depth_cache_hits,
+        );
+        write_counter(
+            &mut out,
+            "quilt_depth_cache_misses_total",
+            "Depth maps that had to be generated.",
+            &self.depth_cache_misses,
+        );
+        write_histogram(
+            &mut out,
+            "quilt_view_render_seconds",
+            "Time to render one view.",
+            &self.view_render_seconds,
+        );
+        write_counter(
+            &mut out,
+            "quilt_render_cache_hits_total",
+            "Quilts whose views were reused from the render cache.",
+            &self.render_cache_hits,
+        );
+        write_counter(
+            &mut out,
+            "quilt_render_cache_misses_total",
+            "Quilts rendered with the render cache enabled but empty.",
+            &self.render_cache_misses,
+        );
+        out
+    }
+}
+
+/// Serves `METRICS` at `http://<addr>/metrics` from a background thread.
+pub fn serve(addr: impl ToSocketAddrs) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    tracing::info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    std::thread::spawn(move || {
+        serve_connections(listener, |stream| {
+            // A client that never finishes its request only holds up its own thread, and not
+            // for long
+            stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+            let request = read_request(stream, 0)?;
+            let (status, body) = if request.path == "/metrics" {
+                ("200 OK", METRICS.render())
+            } else {
+                ("404 Not Found", "Not found\n".to_string())
+            };
+            let content_type = "text/plain; version=0.0.4";
+            write_head(stream, status, content_type, body.len() as u64, "")?;
+            stream.write_all(body.as_bytes())?;
+            Ok(())
+        })
+    });
+    Ok(())
+}
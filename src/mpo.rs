@@ -0,0 +1,95 @@
+//! Multi-picture object (MPO) files, as written by stereo cameras such as the Fujifilm FinePix
+//! REAL 3D and the Nintendo 3DS: a JPEG for the left eye whose APP2 "MPF" segment indexes the
+//! JPEGs that follow it in the same file.
+
+use std::error::Error;
+
+const MPF_IDENTIFIER: &[u8] = b"MPF\0";
+const TAG_MP_ENTRY: u16 = 0xB002;
+/// Bytes per image in the MP Entry table.
+const MP_ENTRY_SIZE: usize = 16;
+
+/// Reads the integers of the MP index in the byte order its TIFF header declares.
+struct Endian {
+    little: bool,
+}
+
+impl Endian {
+    fn u16(&self, data: &[u8], at: usize) -> Option<u16> {
+        let bytes = data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.little {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, data: &[u8], at: usize) -> Option<u32> {
+        let bytes = data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.little {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+}
+
+/// Offset of the MP header (the TIFF header after "MPF\0") in the first image, found by walking
+/// its marker segments up to the start of the scan.
+fn find_mp_header(data: &[u8]) -> Option<usize> {
+    if data.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut at = 2;
+    while data.get(at)? == &0xFF {
+        let marker = *data.get(at + 1)?;
+        // Start of scan: the metadata segments are all before it
+        if marker == 0xDA {
+            return None;
+        }
+        let length = u16::from_be_bytes([*data.get(at + 2)?, *data.get(at + 3)?]) as usize;
+        let payload = at + 4;
+        if marker == 0xE2 && data.get(payload..payload + MPF_IDENTIFIER.len())? == MPF_IDENTIFIER {
+            return Some(payload + MPF_IDENTIFIER.len());
+        }
+        at += 2 + length;
+    }
+    None
+}
+
+/// The JPEGs in an MPO file, in the order its index lists them; for stereo cameras, left then
+/// right.
+pub fn split_mpo(data: &[u8]) -> Result<Vec<&[u8]>, Box<dyn Error>> {
+    let header = find_mp_header(data).ok_or("no MPF segment; not an MPO file")?;
+    let endian = match data.get(header..header + 2) {
+        Some(b"II") => Endian { little: true },
+        Some(b"MM") => Endian { little: false },
+        _ => return Err("bad MP header byte order".into()),
+    };
+    let malformed = || -> Box<dyn Error> { "malformed MP index".into() };
+
+    let ifd = header + endian.u32(data, header + 4).ok_or_else(malformed)? as usize;
+    let entry_count = endian.u16(data, ifd).ok_or_else(malformed)? as usize;
+    let (table, table_size) = (0..entry_count)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| endian.u16(data, entry) == Some(TAG_MP_ENTRY))
+        .and_then(|entry| {
+            let size = endian.u32(data, entry + 4)? as usize;
+            let offset = endian.u32(data, entry + 8)? as usize;
+            Some((header + offset, size))
+        })
+        .ok_or("MP index has no image table")?;
+
+    (0..table_size / MP_ENTRY_SIZE)
+        .map(|i| {
+            let entry = table + i * MP_ENTRY_SIZE;
+            let size = endian.u32(data, entry + 4).ok_or_else(malformed)? as usize;
+            let offset = endian.u32(data, entry + 8).ok_or_else(malformed)? as usize;
+            // The first image starts the file; the others are relative to the MP header
+            let start = if i == 0 { 0 } else { header + offset };
+            data.get(start..start + size)
+                .filter(|image| image.starts_with(&[0xFF, 0xD8]))
+                .ok_or_else(|| format!("MPO image {} is outside the file", i + 1).into())
+        })
+        .collect()
+}
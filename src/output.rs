@@ -0,0 +1,98 @@
+//! Where the commands' messages go. Progress messages normally go to stdout; with `--json`,
+//! stdout carries only the result object, so they move to stderr. Warnings always go to stderr
+//! and are also collected for the result object.
+
+use crate::quilt::QuiltSettings;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Prints a progress message like `println!`, to stderr in `--json` mode.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::output::json() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Switches to `--json` mode.
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Prints `Warning: <message>` to stderr and keeps it for [`QuiltResult::warnings`].
+pub fn warn(message: impl std::fmt::Display) {
+    let message = message.to_string();
+    eprintln!("Warning: {}", message);
+    WARNINGS.lock().unwrap().push(message);
+}
+
+/// The warnings given so far.
+pub fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut WARNINGS.lock().unwrap())
+}
+
+/// What `--json` prints when a quilt was made.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuiltResult {
+    pub output: String,
+    pub width: u32,
+    pub height: u32,
+    pub layout: Layout,
+    pub timings: Timings,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Layout {
+    pub columns: u32,
+    pub rows: u32,
+    pub views: u32,
+    /// Aspect ratio of each view, as in the quilt's file name.
+    pub aspect: f32,
+}
+
+/// Seconds spent in each stage.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Timings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<f64>,
+    pub render: f64,
+    pub total: f64,
+}
+
+impl QuiltResult {
+    /// The result for the quilt written to `output`, with the warnings given so far.
+    pub fn new(output: String, settings: &QuiltSettings, aspect: f32, timings: Timings) -> Self {
+        Self {
+            output,
+            width: settings.resolution.0,
+            height: settings.resolution.1,
+            layout: Layout {
+                columns: settings.columns,
+                rows: settings.rows,
+                views: settings.columns * settings.rows,
+                aspect,
+            },
+            timings,
+            warnings: take_warnings(),
+        }
+    }
+
+    /// Prints the result to stdout as one line of JSON.
+    pub fn print(&self) -> Result<(), serde_json::Error> {
+        println!("{}", serde_json::to_string(self)?);
+        Ok(())
+    }
+}
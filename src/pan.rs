@@ -0,0 +1,62 @@
+//! Pan-and-scan across panoramas: a crop window the shape of a view slides from the left edge of
+//! the image to the right, and each position becomes one quilt of a sequence.
+
+use crate::image_types::{DepthImage, TextureImage};
+use crate::quilt::QuiltSettings;
+use image::imageops::crop_imm;
+
+/// How far the crop window moves between frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PanStep {
+    /// Window widths per second, at `fps` frames per second.
+    Speed { speed: f32, fps: f32 },
+    /// Fraction of the window that consecutive frames share.
+    Overlap(f32),
+}
+
+impl PanStep {
+    /// The step in pixels for a window `window` pixels wide, at least one.
+    fn pixels(&self, window: u32) -> f32 {
+        let step = match *self {
+            PanStep::Speed { speed, fps } => speed * window as f32 / fps,
+            PanStep::Overlap(overlap) => (1.0 - overlap) * window as f32,
+        };
+        step.max(1.0)
+    }
+}
+
+/// Width of a window the full `height` of the image, in the shape the views of `settings` have
+/// on the display, and no wider than `width`.
+pub fn window_width(width: u32, height: u32, settings: &QuiltSettings) -> u32 {
+    let tile_width = settings.resolution.0 / settings.columns;
+    let tile_height = settings.resolution.1 / settings.rows;
+    let aspect = tile_width as f32 * settings.pixel_aspect / tile_height as f32;
+    ((height as f32 * aspect).round() as u32).clamp(1, width)
+}
+
+/// Left edges of the windows, `window` pixels wide, across an image `width` pixels wide. The
+/// last window ends at the right edge, so the pan always reaches it.
+pub fn pan_offsets(width: u32, window: u32, step: PanStep) -> Vec<u32> {
+    let travel = width.saturating_sub(window);
+    let step = step.pixels(window);
+    let mut offsets: Vec<u32> = (0..)
+        .map(|i| (i as f32 * step).round() as u32)
+        .take_while(|&x| x < travel)
+        .collect();
+    offsets.push(travel);
+    offsets
+}
+
+/// The texture and depth inside the window at `x`.
+pub fn crop_window(
+    texture: &TextureImage,
+    heightmap: &DepthImage,
+    x: u32,
+    window: u32,
+) -> (TextureImage, DepthImage) {
+    let height = texture.height();
+    (
+        TextureImage(crop_imm(&texture.0, x, 0, window, height).to_image()),
+        DepthImage(crop_imm(&heightmap.0, x, 0, window, height).to_image()),
+    )
+}
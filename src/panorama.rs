@@ -0,0 +1,157 @@
+//! Equirectangular panoramas with depth: the input wraps around the viewer as a sphere, each
+//! texel pushed in from the sphere by its depth, and every view is a virtual camera inside it
+//! looking out towards `Panorama::yaw` and `Panorama::pitch`. The views step sideways across the
+//! view cone, with the frustum sheared so the focus depth stays put, as the flat renderer's
+//! rotation does.
+
+use crate::camera::Camera;
+use crate::image_types::{DepthSource, TextureSource};
+use crate::quilt::ViewOptions;
+use image::{ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{FRAC_PI_2, PI};
+
+/// How the input image maps onto the scene.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Projection {
+    /// A flat picture displaced towards the viewer by depth.
+    #[default]
+    Flat,
+    /// A 360x180 degree equirectangular panorama around the viewer.
+    Equirectangular,
+}
+
+impl Projection {
+    /// The panorama view for this projection, looking towards `yaw` and `pitch` with a
+    /// horizontal field of view of `fov`, all in degrees. `None` for flat images.
+    pub fn panorama(self, yaw: f32, pitch: f32, fov: f32) -> Option<Panorama> {
+        match self {
+            Projection::Flat => None,
+            Projection::Equirectangular => Some(Panorama { yaw, pitch, fov }),
+        }
+    }
+}
+
+/// Where the cameras inside a panorama look, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Panorama {
+    /// Heading from the middle of the panorama, positive to the right.
+    pub yaw: f32,
+    /// Elevation above the horizon.
+    pub pitch: f32,
+    /// Horizontal field of view of each view.
+    pub fov: f32,
+}
+
+/// Nearness added by the nearest depth value at scale 1, as a fraction of the sphere radius:
+/// depth 255 sits at `1 / (1 + PANO_DEPTH_RANGE)` of the radius. Depth maps of the usual models
+/// are relative inverse depth, so nearness is linear in the depth value.
+const PANO_DEPTH_RANGE: f32 = 0.5;
+
+/// Largest splat side in pixels, so texels right in front of a camera don't blot out the view.
+const MAX_SPLAT: f32 = 64.0;
+
+impl Panorama {
+    /// Largest source size worth rendering into views `view_width` pixels wide: one texel per
+    /// view pixel around the whole sphere, times `resize`.
+    pub fn target_size(&self, view_width: u32, resize: f32) -> (u32, u32) {
+        let width = (view_width as f32 * resize * 360.0 / self.fov.clamp(1.0, 179.0)) as u32;
+        (width, width / 2)
+    }
+
+    /// The camera's forward, right and up directions, with y up and z forward at yaw 0.
+    fn basis(&self) -> [[f32; 3]; 3] {
+        let (yaw, pitch) = (self.yaw.to_radians(), self.pitch.to_radians());
+        let forward = [
+            pitch.cos() * yaw.sin(),
+            pitch.sin(),
+            pitch.cos() * yaw.cos(),
+        ];
+        let right = [yaw.cos(), 0.0, -yaw.sin()];
+        let up = [
+            forward[1] * right[2] - forward[2] * right[1],
+            forward[2] * right[0] - forward[0] * right[2],
+            forward[0] * right[1] - forward[1] * right[0],
+        ];
+        [forward, right, up]
+    }
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Renders screen rows `rows` of the view from `camera` inside the panorama, as an image
+/// `rows.len()` high. Pixels no texel lands on show the background.
+pub(crate) fn render_panorama_rows<T: TextureSource, H: DepthSource>(
+    texture: &T,
+    heightmap: &H,
+    camera: &Camera,
+    panorama: &Panorama,
+    opts: &ViewOptions,
+    rows: std::ops::Range<u32>,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let (tex_width, tex_height) = texture.dimensions();
+    let (view_width, view_height) = (camera.view_width, camera.view_height);
+    let band_height = rows.len() as u32;
+    let mut img = opts.background.fill(view_width, view_height, rows.clone());
+    let mut zbuffer = vec![f32::INFINITY; (view_width * band_height) as usize];
+
+    // Focal lengths in pixels; wide display pixels take fewer of them across
+    let fx = view_width as f32 / 2.0 / (panorama.fov.clamp(1.0, 179.0).to_radians() / 2.0).tan()
+        * camera.zoom;
+    let fy = fx * camera.pixel_aspect;
+    let nearness = |depth: f32| 1.0 + PANO_DEPTH_RANGE * camera.z_scale * depth / 255.0;
+    let focus_nearness = nearness(camera.focus_depth);
+    // Step opposite the scene rotation of the flat renderer, so near points move the same way
+    let offset = -camera.view_theta.tan() / focus_nearness;
+    let shear = offset * focus_nearness;
+
+    let [forward, right, up] = panorama.basis();
+    let (texel_width, texel_height) = (2.0 * PI / tex_width as f32, PI / tex_height as f32);
+    let longitudes: Vec<(f32, f32)> = (0..tex_width)
+        .map(|x| ((x as f32 + 0.5) * texel_width - PI).sin_cos())
+        .collect();
+
+    for tex_y in 0..tex_height {
+        let (lat_sin, lat_cos) = (FRAC_PI_2 - (tex_y as f32 + 0.5) * texel_height).sin_cos();
+        for (tex_x, &(lon_sin, lon_cos)) in longitudes.iter().enumerate() {
+            let direction = [lat_cos * lon_sin, lat_sin, lat_cos * lon_cos];
+            let z = dot(direction, forward);
+            if z <= 1e-3 {
+                continue;
+            }
+            let height = heightmap.depth(tex_x as u32, tex_y);
+            let radius = 1.0 / nearness(height as f32);
+            let z = z * radius;
+            let x = dot(direction, right) * radius - offset;
+            let y = dot(direction, up) * radius;
+            let screen_x = fx * (x / z + shear) + view_width as f32 / 2.0;
+            let screen_y = view_height as f32 / 2.0 - fy * y / z;
+
+            // The texel's footprint, narrower towards the poles where meridians converge
+            let half_width = (fx * texel_width * lat_cos * radius / z).min(MAX_SPLAT) / 2.0;
+            let half_height = (fy * texel_height * radius / z).min(MAX_SPLAT) / 2.0;
+            let columns = (screen_x - half_width).round().max(0.0) as i64
+                ..=((screen_x + half_width).round() as i64).min(view_width as i64 - 1);
+            let top = ((screen_y - half_height).round() as i64).max(rows.start as i64);
+            let bottom = ((screen_y + half_height).round() as i64).min(rows.end as i64 - 1);
+            if columns.is_empty() || top > bottom {
+                continue;
+            }
+            let color = texture.texel(tex_x as u32, tex_y);
+            for sy in top..=bottom {
+                let band_y = (sy - rows.start as i64) as u32;
+                for sx in columns.clone() {
+                    let i = (band_y * view_width) as usize + sx as usize;
+                    if z < zbuffer[i] {
+                        zbuffer[i] = z;
+                        img.put_pixel(sx as u32, band_y, color);
+                    }
+                }
+            }
+        }
+    }
+    img
+}
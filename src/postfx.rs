@@ -0,0 +1,354 @@
+//! Post-processing of rendered views, applied before they are framed and stitched into a quilt.
+
+use crate::background::Background;
+use image::{ImageBuffer, Rgb};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+/// Unsharp masking: each pixel is pushed away from a Gaussian blur of its surroundings, to win
+/// back the detail the Lanczos downscale and blending between samples soften.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sharpen {
+    /// How far pixels are pushed, as a multiple of their difference from the blur.
+    pub amount: f32,
+    /// Standard deviation of the blur in view pixels. Around 1 sharpens fine detail; 20 or more
+    /// raises local contrast instead, the "clarity" of photo editors.
+    #[serde(default = "default_sharpen_radius")]
+    pub radius: f32,
+}
+
+fn default_sharpen_radius() -> f32 {
+    1.0
+}
+
+impl FromStr for Sharpen {
+    type Err = String;
+
+    /// Parses `AMOUNT[:RADIUS]`, e.g. `0.5` or `0.3:25`, with a radius of 1 if none is given.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (amount, radius) = s.split_once(':').unwrap_or((s, "1"));
+        let sharpen = amount
+            .trim()
+            .parse()
+            .ok()
+            .zip(radius.trim().parse().ok())
+            .map(|(amount, radius)| Sharpen { amount, radius })
+            .filter(|sharpen| sharpen.amount >= 0.0 && sharpen.radius > 0.0);
+        sharpen.ok_or_else(|| {
+            format!(
+                "invalid sharpening '{}', expected AMOUNT[:RADIUS] with a positive radius",
+                s
+            )
+        })
+    }
+}
+
+/// Film grain: monochrome noise added to every pixel, which breaks up banding in smooth gradients.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Grain {
+    /// Standard deviation of the noise in 8-bit levels.
+    pub amount: f32,
+    /// A different pattern in each view, so the grain fills the scene's depth. Otherwise every
+    /// view has the same pattern and the grain sits on the display surface.
+    #[serde(default)]
+    pub per_view: bool,
+}
+
+impl Grain {
+    /// Noise with zero mean and unit variance at a pixel of `view`, the same on every run.
+    fn noise(&self, x: u32, y: u32, view: u32) -> f32 {
+        let view = if self.per_view { view + 1 } else { 0 };
+        let mut hash = (x as u64) | (y as u64) << 24 | (view as u64) << 48;
+        // The sum of four uniform variables is near enough to Gaussian for grain
+        let mut sum = 0.0;
+        for _ in 0..4 {
+            // splitmix64
+            hash = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = hash;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            sum += (z >> 40) as f32 / (1u64 << 24) as f32;
+        }
+        (sum - 2.0) * 3.0f32.sqrt()
+    }
+}
+
+/// Color adjustments, applied before the other effects.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColorGrade {
+    /// 0 for black and white, 1 unchanged, above 1 more vivid.
+    pub saturation: f32,
+    /// Spread of the levels around mid-gray; 1 unchanged.
+    pub contrast: f32,
+    /// -1 to 1: scales red up and blue down by up to 15% for positive values, the reverse for
+    /// negative ones.
+    pub warmth: f32,
+}
+
+impl Default for ColorGrade {
+    fn default() -> Self {
+        ColorGrade {
+            saturation: 1.0,
+            contrast: 1.0,
+            warmth: 0.0,
+        }
+    }
+}
+
+impl ColorGrade {
+    fn apply(&self, [r, g, b]: [f32; 3]) -> [f32; 3] {
+        let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let warmth = 0.15 * self.warmth.clamp(-1.0, 1.0);
+        [r * (1.0 + warmth), g, b * (1.0 - warmth)]
+            .map(|c| luma + (c - luma) * self.saturation)
+            .map(|c| (c - 127.5) * self.contrast + 127.5)
+    }
+}
+
+/// Effects applied the same way to every view after rendering.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostFx {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorGrade>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sharpen: Option<Sharpen>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grain: Option<Grain>,
+    /// How much the corners of each view are darkened, 0 to 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vignette: Option<f32>,
+    /// Width in pixels of the border of each view that fades out to the background, which hides
+    /// the stretched edges of the outermost views.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edge_fade: Option<u32>,
+}
+
+impl PostFx {
+    /// Whether there is nothing to apply.
+    pub fn is_none(&self) -> bool {
+        *self == PostFx::default()
+    }
+
+    /// These effects, with any left out taken from `defaults`.
+    pub fn or(self, defaults: PostFx) -> PostFx {
+        PostFx {
+            color: self.color.or(defaults.color),
+            sharpen: self.sharpen.or(defaults.sharpen),
+            grain: self.grain.or(defaults.grain),
+            vignette: self.vignette.or(defaults.vignette),
+            edge_fade: self.edge_fade.or(defaults.edge_fade),
+        }
+    }
+
+    /// Rows above and below a pixel the effects read, so a banded render can render that many
+    /// more around each band and get the same result as a whole view.
+    pub fn margin(&self) -> u32 {
+        self.sharpen
+            .map_or(0, |sharpen| (3.0 * sharpen.radius).ceil() as u32)
+    }
+
+    /// Applies the effects to view `index`, `height` rows high, or to a band of it starting at row
+    /// `top` with `margin` rows around the part that is kept. Edges fade out to `background`.
+    pub fn apply(
+        &self,
+        view: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+        index: u32,
+        top: u32,
+        height: u32,
+        background: &Background,
+    ) {
+        if let Some(color) = self.color.filter(|color| *color != ColorGrade::default()) {
+            view.par_chunks_mut(3).for_each(|pixel| {
+                let graded = color.apply([pixel[0] as f32, pixel[1] as f32, pixel[2] as f32]);
+                for (value, c) in pixel.iter_mut().zip(graded) {
+                    *value = c.round().clamp(0.0, 255.0) as u8;
+                }
+            });
+        }
+        if let Some(sharpen) = self.sharpen.filter(|sharpen| sharpen.amount > 0.0) {
+            let blurred = image::imageops::blur(view, sharpen.radius);
+            view.par_iter_mut()
+                .zip(blurred.as_raw().par_iter())
+                .for_each(|(value, &blurred)| {
+                    let detail = *value as f32 - blurred as f32;
+                    *value = (*value as f32 + sharpen.amount * detail)
+                        .round()
+                        .clamp(0.0, 255.0) as u8;
+                });
+        }
+
+        let vignette = self.vignette.filter(|strength| *strength > 0.0);
+        let grain = self.grain.filter(|grain| grain.amount > 0.0);
+        let edge_fade = self.edge_fade.filter(|pixels| *pixels > 0);
+        if vignette.is_none() && grain.is_none() && edge_fade.is_none() {
+            return;
+        }
+        let width = view.width();
+        let faded = edge_fade.map(|_| background.fill(width, height, top..top + view.height()));
+        view.par_chunks_mut(width as usize * 3)
+            .enumerate()
+            .for_each(|(band_y, row)| {
+                let y = top + band_y as u32;
+                for (x, pixel) in row.chunks_exact_mut(3).enumerate() {
+                    let x = x as u32;
+                    let mut color = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+                    if let Some(strength) = vignette {
+                        // Squared distance from the middle, 1 at the corners
+                        let dx = (x as f32 + 0.5) / width as f32 - 0.5;
+                        let dy = (y as f32 + 0.5) / height as f32 - 0.5;
+                        let falloff = 1.0 - strength.min(1.0) * 2.0 * (dx * dx + dy * dy);
+                        color = color.map(|c| c * falloff);
+                    }
+                    if let Some(grain) = grain {
+                        let offset = grain.amount * grain.noise(x, y, index);
+                        color = color.map(|c| c + offset);
+                    }
+                    if let (Some(pixels), Some(faded)) = (edge_fade, &faded) {
+                        let edge = x.min(y).min(width - 1 - x).min(height - 1 - y);
+                        let t = ((edge as f32 + 0.5) / pixels as f32).min(1.0);
+                        let t = t * t * (3.0 - 2.0 * t);
+                        let background = faded.get_pixel(x, band_y as u32).0;
+                        for (c, b) in color.iter_mut().zip(background) {
+                            *c = b as f32 + (*c - b as f32) * t;
+                        }
+                    }
+                    for (value, c) in pixel.iter_mut().zip(color) {
+                        *value = c.round().clamp(0.0, 255.0) as u8;
+                    }
+                }
+            });
+    }
+}
+
+/// A named bundle of effects for `--look`.
+#[derive(Deserialize)]
+struct Look {
+    name: String,
+    #[serde(flatten)]
+    postfx: PostFx,
+}
+
+/// Where the user's looks file is: `$QUILT_PAINTER_LOOKS`, or else `quilt-painter/looks.json`
+/// in the XDG config directory.
+pub fn user_looks_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("QUILT_PAINTER_LOOKS") {
+        return Some(path.into());
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join("quilt-painter").join("looks.json"))
+}
+
+fn parse_looks(json: &str) -> Result<Vec<(String, PostFx)>, Box<dyn Error>> {
+    let looks: Vec<Look> = serde_json::from_str(json)?;
+    Ok(looks
+        .into_iter()
+        .map(|look| (look.name, look.postfx))
+        .collect())
+}
+
+/// The built-in looks from `data/Looks.json`, then the user's, which change a built-in look
+/// of the same name field by field.
+static LOOKS: LazyLock<Vec<(String, PostFx)>> = LazyLock::new(|| {
+    let mut looks = parse_looks(include_str!("../data/Looks.json")).expect("valid data/Looks.json");
+    let user = match user_looks_path() {
+        Some(path) if path.exists() => std::fs::read_to_string(&path)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|json| parse_looks(&json))
+            .unwrap_or_else(|e| {
+                crate::output::warn(format!("Ignoring {}: {}", path.display(), e));
+                Vec::new()
+            }),
+        _ => Vec::new(),
+    };
+    for (name, postfx) in user {
+        match looks
+            .iter_mut()
+            .find(|(known, _)| known.eq_ignore_ascii_case(&name))
+        {
+            Some((_, look)) => *look = postfx.or(*look),
+            None => looks.push((name, postfx)),
+        }
+    }
+    looks
+});
+
+/// The effects of the look named `s`, in any case, for `--look`.
+pub fn parse_look(s: &str) -> Result<PostFx, String> {
+    match LOOKS.iter().find(|(name, _)| name.eq_ignore_ascii_case(s)) {
+        Some((_, postfx)) => Ok(*postfx),
+        None => {
+            let known: Vec<&str> = LOOKS.iter().map(|(name, _)| name.as_str()).collect();
+            Err(format!(
+                "unknown look '{}'; known looks: {}",
+                s,
+                known.join(", ")
+            ))
+        }
+    }
+}
+
+/// Cumulative histogram of each channel of `view`, leaving out pixels of the colors in `ignore`.
+fn channel_cdfs(view: &ImageBuffer<Rgb<u8>, Vec<u8>>, ignore: &[Rgb<u8>]) -> [[u64; 256]; 3] {
+    let mut histograms = [[0u64; 256]; 3];
+    for pixel in view.pixels().filter(|pixel| !ignore.contains(pixel)) {
+        for (histogram, &value) in histograms.iter_mut().zip(pixel.0.iter()) {
+            histogram[value as usize] += 1;
+        }
+    }
+    for histogram in histograms.iter_mut() {
+        for i in 1..256 {
+            histogram[i] += histogram[i - 1];
+        }
+    }
+    histograms
+}
+
+/// Histogram-matches every view to the middle one, channel by channel, so the color shifts hole
+/// filling and edge stretching leave in the outer views don't flicker as the viewer moves.
+///
+/// Pixels of the colors in `ignore`, such as a solid background or the letterbox matte, aren't
+/// part of the scene: they are left out of the histograms and keep their color, so bars that only
+/// some views have don't recolor the rest.
+pub fn stabilize_views(views: &mut [ImageBuffer<Rgb<u8>, Vec<u8>>], ignore: &[Rgb<u8>]) {
+    let Some(reference) = views.get(views.len() / 2) else {
+        return;
+    };
+    let reference_cdfs = channel_cdfs(reference, ignore);
+    let reference_total = reference_cdfs[0][255];
+    views.par_iter_mut().for_each(|view| {
+        let cdfs = channel_cdfs(view, ignore);
+        let total = cdfs[0][255];
+        if total == 0 || reference_total == 0 {
+            return;
+        }
+        // Each value goes to the reference value covering the middle of its run of ranks, so
+        // flat colors, whose share of the view changes with parallax, keep their value
+        let tables: Vec<[u8; 256]> = cdfs
+            .iter()
+            .zip(reference_cdfs.iter())
+            .map(|(cdf, reference_cdf)| {
+                std::array::from_fn(|value| {
+                    let below = if value == 0 { 0 } else { cdf[value - 1] };
+                    let rank = (below + cdf[value]) as u128 * reference_total as u128;
+                    reference_cdf
+                        .partition_point(|&count| count as u128 * 2 * (total as u128) <= rank)
+                        .min(255) as u8
+                })
+            })
+            .collect();
+        for pixel in view.pixels_mut().filter(|pixel| !ignore.contains(pixel)) {
+            for (value, table) in pixel.0.iter_mut().zip(tables.iter()) {
+                *value = table[*value as usize];
+            }
+        }
+    });
+}
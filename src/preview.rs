@@ -0,0 +1,188 @@
+//! A flat preview of a quilt, its center view at a small size, so file browsers show something
+//! recognizable instead of a grid of tiny tiles. It goes beside the quilt as `<name>-preview.jpg`
+//! or into a JPEG quilt as its EXIF thumbnail.
+
+use crate::debug::NullDebugFlags;
+use crate::image_types::{DepthImage, RgbImage, TextureImage};
+use crate::quilt::{render_raw_quilt, QuiltSettings, RenderOptions};
+use image::codecs::jpeg::JpegEncoder;
+use image::ColorType;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+
+/// Where `--preview` puts the preview.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preview {
+    /// A `<name>-preview.jpg` beside the quilt.
+    File,
+    /// The EXIF thumbnail of a JPEG quilt. Other formats get the file instead.
+    Exif,
+}
+
+/// Longer side of a preview file.
+pub const PREVIEW_SIZE: u32 = 512;
+
+/// Longer side of an EXIF thumbnail, which has to fit in a 64 KiB APP1 segment.
+pub const THUMBNAIL_SIZE: u32 = 160;
+
+const EXIF_IDENTIFIER: &[u8] = b"Exif\0\0";
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+
+/// Renders the middle view of a quilt of `settings` with `options`, without captions or
+/// watermarks, `size` pixels on its longer side and in the shape a tile has on the display.
+pub fn render_preview(
+    settings: &QuiltSettings,
+    texture: &TextureImage,
+    heightmap: &DepthImage,
+    options: &RenderOptions,
+    size: u32,
+) -> RgbImage {
+    let (tile_width, tile_height) = options.view_size(settings);
+    let width = tile_width as f32 * settings.pixel_aspect;
+    let scale = size as f32 / width.max(tile_height as f32);
+    // A single view sits in the middle of the field of view
+    let settings = QuiltSettings {
+        columns: 1,
+        rows: 1,
+        resolution: (
+            ((width * scale).round() as u32).max(1),
+            ((tile_height as f32 * scale).round() as u32).max(1),
+        ),
+        pixel_aspect: 1.0,
+    };
+    let options = RenderOptions {
+        tile_padding: 0,
+        ..options.clone()
+    };
+    render_raw_quilt(&settings, texture, heightmap, &options, &NullDebugFlags {})
+}
+
+fn encode_jpeg(image: &RgbImage, quality: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, quality).encode(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        ColorType::Rgb8,
+    )?;
+    Ok(jpeg)
+}
+
+/// Writes a preview from `render_preview` as a JPEG.
+pub fn save_preview(path: &Path, preview: &RgbImage) -> Result<(), Box<dyn Error>> {
+    std::fs::write(path, encode_jpeg(preview, 90)?)?;
+    Ok(())
+}
+
+fn ifd_entry(tag: u16, kind: u16, value: u32) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(12);
+    entry.extend_from_slice(&tag.to_be_bytes());
+    entry.extend_from_slice(&kind.to_be_bytes());
+    entry.extend_from_slice(&1u32.to_be_bytes());
+    // Values shorter than four bytes are left-aligned in the field
+    match kind {
+        TYPE_SHORT => entry.extend_from_slice(&[(value as u16).to_be_bytes(), [0, 0]].concat()),
+        _ => entry.extend_from_slice(&value.to_be_bytes()),
+    }
+    entry
+}
+
+/// The APP1 segment of an EXIF block holding only an orientation and `thumbnail`, a JPEG.
+fn exif_segment(thumbnail: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Big-endian TIFF header, then IFD0 with one entry and IFD1 with three, then the thumbnail
+    const IFD0: u32 = 8;
+    const IFD1: u32 = IFD0 + 2 + 12 + 4;
+    const THUMBNAIL: u32 = IFD1 + 2 + 3 * 12 + 4;
+    let mut tiff = b"MM\0\x2a".to_vec();
+    tiff.extend_from_slice(&IFD0.to_be_bytes());
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    tiff.extend(ifd_entry(TAG_ORIENTATION, TYPE_SHORT, 1));
+    tiff.extend_from_slice(&IFD1.to_be_bytes());
+    tiff.extend_from_slice(&3u16.to_be_bytes());
+    // Compression 6 is JPEG
+    tiff.extend(ifd_entry(TAG_COMPRESSION, TYPE_SHORT, 6));
+    tiff.extend(ifd_entry(TAG_JPEG_OFFSET, TYPE_LONG, THUMBNAIL));
+    tiff.extend(ifd_entry(
+        TAG_JPEG_LENGTH,
+        TYPE_LONG,
+        thumbnail.len() as u32,
+    ));
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+    tiff.extend_from_slice(thumbnail);
+
+    let length = u16::try_from(2 + EXIF_IDENTIFIER.len() + tiff.len())
+        .map_err(|_| "thumbnail too large for an EXIF segment")?;
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&length.to_be_bytes());
+    segment.extend_from_slice(EXIF_IDENTIFIER);
+    segment.extend(tiff);
+    Ok(segment)
+}
+
+/// Adds `thumbnail` as the EXIF thumbnail of the JPEG at `path`, after its JFIF header if it
+/// has one, as the EXIF standard expects.
+pub fn embed_exif_thumbnail(path: &Path, thumbnail: &RgbImage) -> Result<(), Box<dyn Error>> {
+    let data = std::fs::read(path)?;
+    if data.get(..2) != Some(&[0xFF, 0xD8]) {
+        return Err(format!("{} is not a JPEG", path.display()).into());
+    }
+    let mut at = 2;
+    while data.get(at..at + 2) == Some(&[0xFF, 0xE0]) {
+        let length = u16::from_be_bytes([data[at + 2], data[at + 3]]) as usize;
+        at += 2 + length;
+    }
+    let at = at.min(data.len());
+    let segment = exif_segment(&encode_jpeg(thumbnail, 80)?)?;
+    let mut jpeg = Vec::with_capacity(data.len() + segment.len());
+    jpeg.extend_from_slice(&data[..at]);
+    jpeg.extend(segment);
+    jpeg.extend_from_slice(&data[at..]);
+    std::fs::write(path, jpeg)?;
+    Ok(())
+}
+
+/// Carries out `--preview` for the quilt at `quilt_path`: writes `<base>-preview.jpg`, or for
+/// [`Preview::Exif`] returns the thumbnail to embed once the quilt is written.
+pub fn prepare_preview(
+    preview: Preview,
+    quilt_path: &Path,
+    base: &str,
+    settings: &QuiltSettings,
+    texture: &TextureImage,
+    heightmap: &DepthImage,
+    options: &RenderOptions,
+) -> Result<Option<RgbImage>, Box<dyn Error>> {
+    if preview == Preview::Exif {
+        if is_jpeg(quilt_path) {
+            return Ok(Some(render_preview(
+                settings,
+                texture,
+                heightmap,
+                options,
+                THUMBNAIL_SIZE,
+            )));
+        }
+        crate::output::warn(format!(
+            "{} is not a JPEG, so the preview goes beside it",
+            quilt_path.display()
+        ));
+    }
+    let path = format!("{}-preview.jpg", base);
+    let image = render_preview(settings, texture, heightmap, options, PREVIEW_SIZE);
+    save_preview(Path::new(&path), &image)?;
+    crate::status!("Saved preview as: {}", path);
+    Ok(None)
+}
+
+/// Whether `path` names a JPEG, which can hold an EXIF thumbnail.
+pub fn is_jpeg(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg"))
+}
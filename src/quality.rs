@@ -0,0 +1,94 @@
+//! Input checks for batch runs, so screenshots, thumbnails and shaken photos in a photo dump are
+//! skipped before they cost depth-model time.
+
+use image::imageops::FilterType;
+use image::GrayImage;
+use std::error::Error;
+use std::path::Path;
+
+/// Longer side images are scaled to before measuring sharpness, so the same threshold suits
+/// every resolution.
+const SHARPNESS_SIZE: u32 = 1024;
+
+#[derive(Clone, Debug, Default)]
+pub struct QualityGate {
+    /// Smallest acceptable size, in either orientation.
+    pub min_resolution: Option<(u32, u32)>,
+    /// Images with a `sharpness` below this are skipped as blurry.
+    pub blur_threshold: Option<f64>,
+}
+
+impl QualityGate {
+    /// Why the image at `path` should be skipped, or `None` if it passes. Only decodes the image
+    /// when the blur check is on.
+    pub fn check(&self, path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+        if let Some((min_width, min_height)) = self.min_resolution {
+            let (width, height) = image::image_dimensions(path)?;
+            // Compare long side with long side, so portrait photos pass a landscape minimum
+            let fits = |(a, b): (u32, u32)| {
+                a.max(b) >= min_width.max(min_height) && a.min(b) >= min_width.min(min_height)
+            };
+            if !fits((width, height)) {
+                return Ok(Some(format!(
+                    "{}x{} is below the minimum resolution {}x{}",
+                    width, height, min_width, min_height
+                )));
+            }
+        }
+        if let Some(threshold) = self.blur_threshold {
+            let sharpness = sharpness(&image::open(path)?.to_luma8());
+            if sharpness < threshold {
+                return Ok(Some(format!(
+                    "sharpness {:.1} is below {}",
+                    sharpness, threshold
+                )));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Variance of the Laplacian of the image scaled to `SHARPNESS_SIZE` on its longer side (smaller
+/// images as they are). Sharp photos have strong edges and score in the hundreds; blurry ones
+/// and flat graphics score low.
+pub fn sharpness(image: &GrayImage) -> f64 {
+    let (width, height) = image.dimensions();
+    let scale = SHARPNESS_SIZE as f64 / width.max(height) as f64;
+    let scaled;
+    let image = if scale < 1.0 {
+        scaled = image::imageops::resize(
+            image,
+            ((width as f64 * scale).round() as u32).max(1),
+            ((height as f64 * scale).round() as u32).max(1),
+            FilterType::Triangle,
+        );
+        &scaled
+    } else {
+        image
+    };
+
+    let (width, height) = image.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let at = |x: u32, y: u32| image.get_pixel(x, y)[0] as f64;
+    let (mut sum, mut sum_squares) = (0.0, 0.0);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian =
+                at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+            sum += laplacian;
+            sum_squares += laplacian * laplacian;
+        }
+    }
+    let count = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / count;
+    sum_squares / count - mean * mean
+}
+
+/// Parses `WxH`, e.g. `1280x720`.
+pub fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    s.split_once(['x', 'X'])
+        .and_then(|(width, height)| Some((width.trim().parse().ok()?, height.trim().parse().ok()?)))
+        .ok_or_else(|| format!("expected WxH, got '{}'", s))
+}
@@ -1,15 +1,126 @@
 use crate::{
-    camera::{self, Camera},
-    captions::{draw_caption, CaptionConfig},
-    debug::DebugFlags,
-    image_types::{DepthImage, TextureImage},
+    background::Background,
+    camera::{Camera, ViewSpacing, IMAGE_CENTER},
+    captions::{draw_caption, view_position, CaptionConfig, CaptionTarget},
+    debug::{DebugFlags, NullDebugFlags},
+    dither::{Dither, Ditherer},
+    image_types::{DepthImage, DepthSource, TextureImage, TextureSource},
+    metrics::METRICS,
+    panorama::{render_panorama_rows, Panorama},
+    postfx::{self, PostFx},
+    watermark::Watermark,
 };
 use image::Pixel;
 use image::{ImageBuffer, Rgb};
 use itertools::Itertools;
-use lazy_static::lazy_static;
 use nalgebra as na;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
+
+/// How colors blend across the gap between two samples that land apart, such as where a
+/// disocclusion opens.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    Linear,
+    Smoothstep,
+    /// A bezier that lingers on the darker color; the brighter end's control point is scaled by
+    /// `sharpness`, so lower values hold the darker color longer.
+    Luminance {
+        sharpness: f32,
+    },
+    /// No blending: each pixel takes the color of the nearer sample.
+    Hold,
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        Easing::Luminance { sharpness: 0.3333 }
+    }
+}
+
+impl FromStr for Easing {
+    type Err = String;
+
+    /// Parses `linear`, `smoothstep`, `hold`, or `luminance[:SHARPNESS]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, sharpness) = match s.split_once(':') {
+            Some((name, sharpness)) => (name, Some(sharpness)),
+            None => (s, None),
+        };
+        match (name, sharpness) {
+            ("linear", None) => Ok(Easing::Linear),
+            ("smoothstep", None) => Ok(Easing::Smoothstep),
+            ("hold", None) => Ok(Easing::Hold),
+            ("luminance", None) => Ok(Easing::default()),
+            ("luminance", Some(sharpness)) => sharpness
+                .parse()
+                .ok()
+                .filter(|s: &f32| (0.0..=1.0).contains(s))
+                .map(|sharpness| Easing::Luminance { sharpness })
+                .ok_or_else(|| format!("invalid sharpness '{}', expected 0 to 1", sharpness)),
+            _ => Err(format!(
+                "invalid easing '{}', expected linear, smoothstep, hold or luminance[:SHARPNESS]",
+                s
+            )),
+        }
+    }
+}
+
+/// An `Easing` set up for one pair of colors.
+enum EasingCurve {
+    Linear,
+    Smoothstep,
+    Bezier { w1: f32, w2: f32 },
+    Hold,
+}
+
+impl Easing {
+    fn curve(&self, start_color: Rgb<u8>, end_color: Rgb<u8>) -> EasingCurve {
+        match *self {
+            Easing::Linear => EasingCurve::Linear,
+            Easing::Smoothstep => EasingCurve::Smoothstep,
+            Easing::Hold => EasingCurve::Hold,
+            Easing::Luminance { sharpness } => {
+                let start_color_luminosity = rgb_to_lum(start_color);
+                let end_color_luminosity = rgb_to_lum(end_color);
+                let total = start_color_luminosity + end_color_luminosity;
+                if total <= 0.0 {
+                    return EasingCurve::Linear;
+                }
+                let mut w1 = start_color_luminosity / total;
+                let mut w2 = 1.0 - (end_color_luminosity / total);
+                if start_color_luminosity > end_color_luminosity {
+                    w2 *= sharpness;
+                } else {
+                    w1 *= sharpness;
+                }
+                EasingCurve::Bezier { w1, w2 }
+            }
+        }
+    }
+}
+
+impl EasingCurve {
+    fn ease(&self, t: f32) -> f32 {
+        match *self {
+            EasingCurve::Linear => t,
+            EasingCurve::Smoothstep => t * t * (3.0 - 2.0 * t),
+            EasingCurve::Bezier { w1, w2 } => ease_in_out(t, w1, w2),
+            EasingCurve::Hold => {
+                if t < 0.5 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+}
 
 fn ease_in_out(t: f32, w1: f32, w2: f32) -> f32 {
     // quadratic bezier
@@ -25,134 +136,522 @@ fn rgb_to_lum(rgb: Rgb<u8>) -> f32 {
     (0.2126 * rgb[0] as f32 + 0.7152 * rgb[1] as f32 + 0.0722 * rgb[2] as f32) / 255.0
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct QuiltSettings {
     pub columns: u32,
     pub rows: u32,
     pub resolution: (u32, u32),
+    /// Width of a display pixel over its height, for displays with non-square pixels.
+    #[serde(default = "default_pixel_aspect")]
+    pub pixel_aspect: f32,
 }
 
-lazy_static! {
-    pub static ref QUILT_SETTINGS: std::collections::HashMap<&'static str, QuiltSettings> = {
-        let mut m = std::collections::HashMap::new();
-        m.insert(
-            "Looking Glass Go",
-            QuiltSettings {
-                columns: 10,
-                rows: 6,
-                resolution: (4092, 4092),
-            },
-        );
-        m.insert(
-            "go",
-            QuiltSettings {
-                columns: 10,
-                rows: 6,
-                resolution: (4092, 4092),
-            },
-        );
-        m.insert(
-            "Looking Glass Portrait",
-            QuiltSettings {
-                columns: 8,
-                rows: 6,
-                resolution: (3360, 3360),
-            },
-        );
-        m.insert(
-            "portrait",
-            QuiltSettings {
-                columns: 8,
-                rows: 6,
-                resolution: (3360, 3360),
-            },
-        );
-        m.insert(
-            "Looking Glass 16\" Landscape",
-            QuiltSettings {
-                columns: 7,
-                rows: 7,
-                resolution: (5999, 5999),
-            },
-        );
-        m.insert(
-            "16l",
-            QuiltSettings {
-                columns: 7,
-                rows: 7,
-                resolution: (5999, 5999),
-            },
-        );
-        m.insert(
-            "Looking Glass 16\" Portrait",
-            QuiltSettings {
-                columns: 11,
-                rows: 6,
-                resolution: (5995, 6000),
-            },
-        );
-        m.insert(
-            "16p",
-            QuiltSettings {
-                columns: 11,
-                rows: 6,
-                resolution: (5995, 6000),
-            },
-        );
-        m.insert(
-            "Looking Glass 32\" Landscape",
-            QuiltSettings {
-                columns: 7,
-                rows: 7,
-                resolution: (8190, 8190),
-            },
-        );
-        m.insert(
-            "32l",
-            QuiltSettings {
-                columns: 7,
-                rows: 7,
-                resolution: (8190, 8190),
-            },
-        );
-        m.insert(
-            "Looking Glass 32\" Portrait",
-            QuiltSettings {
-                columns: 11,
-                rows: 6,
-                resolution: (8184, 8184),
-            },
-        );
-        m.insert(
-            "32p",
-            QuiltSettings {
-                columns: 11,
-                rows: 6,
-                resolution: (8184, 8184),
-            },
-        );
-        m.insert(
-            "Looking Glass 65\"",
-            QuiltSettings {
-                columns: 8,
-                rows: 9,
-                resolution: (8192, 8192),
-            },
-        );
-        m.insert(
-            "65",
-            QuiltSettings {
-                columns: 8,
-                rows: 9,
-                resolution: (8192, 8192),
-            },
-        );
-        m
+fn default_pixel_aspect() -> f32 {
+    1.0
+}
+
+impl Default for QuiltSettings {
+    fn default() -> Self {
+        Self {
+            columns: 0,
+            rows: 0,
+            resolution: (0, 0),
+            pixel_aspect: 1.0,
+        }
+    }
+}
+
+impl QuiltSettings {
+    /// The layout recorded in a quilt filename such as `photo_qs8x6a0.75.png`, for a quilt image
+    /// of `resolution`. Filenames don't record the pixel aspect, so it is taken to be 1.
+    pub fn from_filename(name: &str, resolution: (u32, u32)) -> Option<Self> {
+        let name = QuiltName::parse(name)?;
+        Some(Self {
+            columns: name.columns,
+            rows: name.rows,
+            resolution,
+            pixel_aspect: 1.0,
+        })
+    }
+}
+
+/// The `_qs<columns>x<rows>a<aspect>` tag Looking Glass software reads the layout of a quilt
+/// from, e.g. `_qs8x6a0.75`. The aspect is that of one view, width over height.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuiltName {
+    pub columns: u32,
+    pub rows: u32,
+    /// Some tools leave it out of the name.
+    pub aspect: Option<f32>,
+}
+
+impl QuiltName {
+    pub fn new(settings: &QuiltSettings, aspect: f32) -> Self {
+        Self {
+            columns: settings.columns,
+            rows: settings.rows,
+            aspect: Some(aspect),
+        }
+    }
+
+    /// Finds the last tag in a filename or path. `None` if there is none, or the layout has no
+    /// views.
+    pub fn parse(name: &str) -> Option<Self> {
+        let tag = &name[name.rfind("_qs")? + 3..];
+        let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+
+        let columns_end = digits(tag);
+        let columns = tag[..columns_end].parse().ok()?;
+        let rest = tag[columns_end..].strip_prefix('x')?;
+        let rows_end = digits(rest);
+        let rows = rest[..rows_end].parse().ok()?;
+        if columns == 0 || rows == 0 {
+            return None;
+        }
+
+        let aspect = rest[rows_end..].strip_prefix('a').and_then(|aspect| {
+            let end = aspect
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(aspect.len());
+            // The extension's dot follows the number directly
+            aspect[..end].trim_end_matches('.').parse().ok()
+        });
+        Some(Self {
+            columns,
+            rows,
+            aspect,
+        })
+    }
+
+    /// `base` with this tag and `extension` appended, e.g. `photo_qs8x6a0.75.png`.
+    pub fn filename(&self, base: &str, extension: &str) -> String {
+        match self.aspect {
+            Some(aspect) => format!(
+                "{}_qs{}x{}a{:.2}.{}",
+                base, self.columns, self.rows, aspect, extension
+            ),
+            None => format!("{}_qs{}x{}.{}", base, self.columns, self.rows, extension),
+        }
+    }
+}
+
+/// Render parameters a display looks best with, each used when the command line leaves it out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RenderDefaults {
+    pub zoom: Option<f32>,
+    pub scale: Option<f32>,
+    pub resize: Option<f32>,
+}
+
+impl RenderDefaults {
+    const NONE: RenderDefaults = RenderDefaults {
+        zoom: None,
+        scale: None,
+        resize: None,
     };
+
+    /// These values, with any left out taken from `defaults`.
+    pub fn or(self, defaults: RenderDefaults) -> RenderDefaults {
+        RenderDefaults {
+            zoom: self.zoom.or(defaults.zoom),
+            scale: self.scale.or(defaults.scale),
+            resize: self.resize.or(defaults.resize),
+        }
+    }
+}
+
+/// A display with a known quilt layout.
+#[derive(Clone, Copy, Debug)]
+pub struct DevicePreset {
+    /// Full name of the display.
+    pub name: &'static str,
+    /// Short names that `--device` accepts besides the full name.
+    pub aliases: &'static [&'static str],
+    pub settings: QuiltSettings,
+    /// Viewing angle of the display in degrees, as in its calibration.
+    pub view_cone: f32,
+    pub render: RenderDefaults,
+}
+
+impl DevicePreset {
+    /// Whether `device` is this preset's full name or an alias, ignoring case.
+    pub fn matches(&self, device: &str) -> bool {
+        self.name.eq_ignore_ascii_case(device)
+            || self
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(device))
+    }
+}
+
+const fn preset(
+    name: &'static str,
+    aliases: &'static [&'static str],
+    (columns, rows): (u32, u32),
+    resolution: (u32, u32),
+    view_cone: f32,
+) -> DevicePreset {
+    DevicePreset {
+        name,
+        aliases,
+        settings: QuiltSettings {
+            columns,
+            rows,
+            resolution,
+            pixel_aspect: 1.0,
+        },
+        view_cone,
+        render: RenderDefaults::NONE,
+    }
+}
+
+/// Known displays, then generic layouts named for their columns and rows.
+pub static DEVICE_PRESETS: &[DevicePreset] = &[
+    // The Go's small panel doubles strong depth, so it gets less of it and a closer crop
+    DevicePreset {
+        render: RenderDefaults {
+            zoom: Some(1.1),
+            scale: Some(0.8),
+            resize: None,
+        },
+        ..preset("Looking Glass Go", &["go"], (10, 6), (4092, 4092), 40.0)
+    },
+    DevicePreset {
+        render: RenderDefaults {
+            zoom: Some(1.0),
+            scale: Some(1.2),
+            resize: None,
+        },
+        ..preset(
+            "Looking Glass Portrait",
+            &["portrait"],
+            (8, 6),
+            (3360, 3360),
+            40.0,
+        )
+    },
+    preset(
+        "Looking Glass 16\" Landscape",
+        &["16l"],
+        (7, 7),
+        (5999, 5999),
+        40.0,
+    ),
+    preset(
+        "Looking Glass 16\" Portrait",
+        &["16p"],
+        (11, 6),
+        (5995, 6000),
+        40.0,
+    ),
+    preset(
+        "Looking Glass 27\"",
+        &["27", "27l"],
+        (7, 7),
+        (8190, 8190),
+        40.0,
+    ),
+    preset(
+        "Looking Glass 32\" Landscape",
+        &["32l"],
+        (7, 7),
+        (8190, 8190),
+        40.0,
+    ),
+    preset(
+        "Looking Glass 32\" Portrait",
+        &["32p"],
+        (11, 6),
+        (8184, 8184),
+        40.0,
+    ),
+    preset("Looking Glass 65\"", &["65"], (8, 9), (8192, 8192), 40.0),
+    // First generation displays
+    preset(
+        "Looking Glass 8.9\"",
+        &["8.9", "standard"],
+        (5, 9),
+        (4096, 4096),
+        35.0,
+    ),
+    preset(
+        "Looking Glass 15.6\"",
+        &["15.6", "pro"],
+        (5, 9),
+        (8192, 8192),
+        35.0,
+    ),
+    preset("5x9", &[], (5, 9), (4096, 4096), 35.0),
+    preset("8x6", &[], (8, 6), (4096, 4096), 40.0),
+    preset("8x12", &[], (8, 12), (8192, 8192), 40.0),
+    // No Prism: its quilt layout and view cone aren't published, so it is left to device files
+];
+
+/// A display in the user's device file. One named like a preset changes that preset, and only
+/// needs the fields it changes; any other adds a display and needs its whole layout.
+#[derive(Deserialize)]
+struct UserDevice {
+    name: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+    columns: Option<u32>,
+    rows: Option<u32>,
+    width: Option<u32>,
+    height: Option<u32>,
+    view_cone: Option<f32>,
+    #[serde(flatten)]
+    render: RenderDefaults,
+}
+
+impl UserDevice {
+    /// The preset for this entry. Its strings are leaked, as presets live for the whole run.
+    fn into_preset(self) -> Result<DevicePreset, Box<dyn Error>> {
+        let base = DEVICE_PRESETS
+            .iter()
+            .find(|preset| preset.matches(&self.name));
+        let field = |value: Option<u32>, base_value: Option<u32>, field: &str| {
+            value
+                .or(base_value)
+                .ok_or_else(|| format!("device '{}' needs {}", self.name, field))
+        };
+        let settings = QuiltSettings {
+            columns: field(self.columns, base.map(|p| p.settings.columns), "columns")?,
+            rows: field(self.rows, base.map(|p| p.settings.rows), "rows")?,
+            resolution: (
+                field(self.width, base.map(|p| p.settings.resolution.0), "width")?,
+                field(self.height, base.map(|p| p.settings.resolution.1), "height")?,
+            ),
+            pixel_aspect: base.map_or(1.0, |p| p.settings.pixel_aspect),
+        };
+        let leak = |s: String| -> &'static str { Box::leak(s.into_boxed_str()) };
+        let mut aliases: Vec<&'static str> = base.map(|p| p.aliases.to_vec()).unwrap_or_default();
+        aliases.extend(self.aliases.into_iter().map(leak));
+        Ok(DevicePreset {
+            name: base.map_or_else(|| leak(self.name), |p| p.name),
+            aliases: Box::leak(aliases.into_boxed_slice()),
+            settings,
+            view_cone: self.view_cone.or(base.map(|p| p.view_cone)).unwrap_or(40.0),
+            render: self.render.or(base.map(|p| p.render).unwrap_or_default()),
+        })
+    }
+}
+
+/// Where the user's device file is: `$QUILT_PAINTER_DEVICES`, or else
+/// `quilt-painter/devices.json` in the XDG config directory.
+pub fn user_devices_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("QUILT_PAINTER_DEVICES") {
+        return Some(path.into());
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join("quilt-painter").join("devices.json"))
+}
+
+/// Reads a device file: a JSON array of displays.
+pub fn load_user_devices(path: &Path) -> Result<Vec<DevicePreset>, Box<dyn Error>> {
+    let entries: Vec<UserDevice> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    entries.into_iter().map(UserDevice::into_preset).collect()
+}
+
+static USER_DEVICES: LazyLock<Vec<DevicePreset>> = LazyLock::new(|| match user_devices_path() {
+    Some(path) if path.exists() => load_user_devices(&path).unwrap_or_else(|e| {
+        crate::output::warn(format!("Ignoring {}: {}", path.display(), e));
+        Vec::new()
+    }),
+    _ => Vec::new(),
+});
+
+/// Quilt layouts by the full names and aliases of the presets.
+#[deprecated(note = "use find_device, which ignores case and reads the user's device file")]
+pub static QUILT_SETTINGS: LazyLock<std::collections::HashMap<&str, QuiltSettings>> =
+    LazyLock::new(|| {
+        DEVICE_PRESETS
+            .iter()
+            .flat_map(|preset| {
+                preset
+                    .aliases
+                    .iter()
+                    .chain([&preset.name])
+                    .map(|name| (*name, preset.settings))
+            })
+            .collect()
+    });
+
+/// The displays in the user's device file, then the presets, so the user's entries win.
+pub fn devices() -> impl Iterator<Item = &'static DevicePreset> {
+    USER_DEVICES.iter().chain(DEVICE_PRESETS)
+}
+
+/// The device named `device`, by its full name or an alias, ignoring case.
+pub fn find_device(device: &str) -> Option<&'static DevicePreset> {
+    devices().find(|preset| preset.matches(device))
+}
+
+/// The render defaults of `device`, or none for a custom layout.
+pub fn render_defaults(device: Option<&str>) -> RenderDefaults {
+    device
+        .and_then(find_device)
+        .map(|preset| preset.render)
+        .unwrap_or_default()
 }
 
 pub fn get_quilt_settings(device: &str) -> Option<&'static QuiltSettings> {
-    QUILT_SETTINGS.get(device)
+    find_device(device).map(|preset| &preset.settings)
+}
+
+/// Settings that decide how a quilt's views are rendered. The defaults match the painter's.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    /// Field of view in degrees.
+    pub fov_deg: f32,
+    /// How the views are spread across the field of view.
+    pub view_spacing: ViewSpacing,
+    /// Reverse the order of the views in the quilt, for displays or sources with mirrored
+    /// geometry.
+    pub mirror_views: bool,
+    /// Zoom factor.
+    pub zoom: f32,
+    /// Point of the image, as fractions of its size, to zoom towards.
+    pub zoom_center: [f32; 2],
+    /// Height scale factor, or depthiness: how far depth moves points between views.
+    pub scale: f32,
+    /// Depth value that stays on the display plane.
+    pub focus_depth: f32,
+    /// What pixels no texture sample lands on show.
+    pub background: Background,
+    /// Color of the letterbox bars around the texture, if not the background color.
+    pub matte: Option<Rgb<u8>>,
+    /// Optional limit in view pixels on how far depth moves a point.
+    pub max_parallax: Option<f32>,
+    /// How texture samples are placed on view pixels.
+    pub sampling: Sampling,
+    /// Pixels each texture sample covers, 1-3.
+    pub splat_radius: u32,
+    /// Dithering of blended colors.
+    pub dither: Dither,
+    /// Color blend across gaps between samples.
+    pub easing: Easing,
+    /// Which colors fill gaps between samples.
+    pub hole_fill: HoleFill,
+    /// Pixels of border around each view, which is rendered that much smaller to fit its tile.
+    pub tile_padding: u32,
+    /// Color of the border around each view.
+    pub border_color: Rgb<u8>,
+    /// Scene behind the main subject, shown where the subject moves aside.
+    pub back_layer: Option<Arc<BackLayer>>,
+    /// Render the input as an equirectangular panorama around the cameras instead of a flat
+    /// picture.
+    pub panorama: Option<Panorama>,
+    /// Histogram-match every view to the middle one after rendering.
+    pub stabilize_views: bool,
+    /// Effects applied to every view after rendering.
+    pub postfx: PostFx,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        let view = ViewOptions::default();
+        Self {
+            fov_deg: 60.0,
+            view_spacing: ViewSpacing::Linear,
+            mirror_views: false,
+            zoom: 1.0,
+            zoom_center: IMAGE_CENTER,
+            scale: 1.0,
+            focus_depth: 0.0,
+            background: view.background,
+            matte: view.matte,
+            max_parallax: None,
+            sampling: view.sampling,
+            splat_radius: view.splat_radius,
+            dither: view.dither,
+            easing: view.easing,
+            hole_fill: view.hole_fill,
+            tile_padding: 0,
+            border_color: Rgb([0, 0, 0]),
+            back_layer: view.back_layer,
+            panorama: view.panorama,
+            stabilize_views: false,
+            postfx: PostFx::default(),
+        }
+    }
+}
+
+impl RenderOptions {
+    /// The per-view part of the options.
+    pub fn view_options(&self) -> ViewOptions {
+        ViewOptions {
+            background: self.background,
+            matte: self.matte,
+            sampling: self.sampling,
+            splat_radius: self.splat_radius,
+            dither: self.dither,
+            easing: self.easing,
+            hole_fill: self.hole_fill,
+            back_layer: self.back_layer.clone(),
+            panorama: self.panorama,
+        }
+    }
+
+    /// Size of a rendered view: its tile in a quilt with `settings`, less the padding on each
+    /// side. At least one pixel however much padding is asked for.
+    pub fn view_size(&self, settings: &QuiltSettings) -> (u32, u32) {
+        let tile_width = settings.resolution.0 / settings.columns;
+        let tile_height = settings.resolution.1 / settings.rows;
+        let padding = 2 * self.tile_padding;
+        (
+            tile_width.saturating_sub(padding).max(1),
+            tile_height.saturating_sub(padding).max(1),
+        )
+    }
+
+    /// A rendered view on a tile of `tile_width` x `tile_height` filled with the border color.
+    /// Views are returned as they are without padding.
+    pub fn frame_view(
+        &self,
+        view: ImageBuffer<Rgb<u8>, Vec<u8>>,
+        tile_width: u32,
+        tile_height: u32,
+    ) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        if self.tile_padding == 0 {
+            return view;
+        }
+        let mut tile = ImageBuffer::from_pixel(tile_width, tile_height, self.border_color);
+        let padding = self.tile_padding as i64;
+        image::imageops::replace(&mut tile, &view, padding, padding);
+        tile
+    }
+
+    /// The camera for the view at `index` in a quilt with `settings`; with `mirror_views`, the
+    /// view from the other end of the cone.
+    pub fn camera(&self, settings: &QuiltSettings, index: u32) -> Camera {
+        let (view_width, view_height) = self.view_size(settings);
+        let view_count = settings.columns * settings.rows;
+        let index = if self.mirror_views {
+            view_count - 1 - index
+        } else {
+            index
+        };
+        Camera {
+            view_theta: self
+                .view_spacing
+                .view_theta(index, view_count, self.fov_deg),
+            max_parallax: self.max_parallax,
+            pixel_aspect: settings.pixel_aspect,
+            zoom_center: self.zoom_center,
+            focus_depth: self.focus_depth,
+            ..Camera::for_view(
+                index,
+                view_count,
+                self.fov_deg,
+                self.zoom,
+                self.scale,
+                view_width,
+                view_height,
+            )
+        }
+    }
 }
 
 /// Creates a quilt image from the input texture and heightmap
@@ -161,103 +660,251 @@ pub fn get_quilt_settings(device: &str) -> Option<&'static QuiltSettings> {
 /// * `settings` - The quilt settings for the target device
 /// * `texture` - The RGB texture image
 /// * `heightmap` - The grayscale heightmap image
-/// * `fov_deg` - Field of view in degrees
-/// * `zoom` - Zoom factor
-/// * `scale` - Height scale factor
-/// * `bg_color` - Background color
+/// * `options` - How the views are rendered
+/// * `caption` - Caption, drawn on every view or once on the quilt
+/// * `watermark` - Optional logo, drawn on every view or once on the quilt
 /// * `debug_kv` - Debug key-value pairs
 ///
 /// # Returns
 /// The generated quilt image
+pub fn render_quilt<T: TextureSource, H: DepthSource, D: DebugFlags>(
+    settings: &QuiltSettings,
+    texture: &T,
+    heightmap: &H,
+    options: &RenderOptions,
+    caption: CaptionConfig,
+    watermark: Option<&Watermark>,
+    debug_flags: &D,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let quilt = render_raw_quilt(settings, texture, heightmap, options, debug_flags);
+    composite_overlays(quilt, settings, caption, watermark)
+}
+
+/// `render_quilt` with each render option passed separately.
+#[deprecated(note = "use render_quilt with RenderOptions")]
+#[allow(clippy::too_many_arguments)]
 pub fn make_quilt<D: DebugFlags>(
     settings: &QuiltSettings,
     texture: &TextureImage,
     heightmap: &DepthImage,
     fov_deg: f32,
     zoom: f32,
+    zoom_center: [f32; 2],
     scale: f32,
+    focus_depth: f32,
     bg_color: Rgb<u8>,
     caption: CaptionConfig,
+    watermark: Option<&Watermark>,
+    max_parallax: Option<f32>,
+    sampling: Sampling,
+    splat_radius: u32,
+    dither: Dither,
+    easing: Easing,
+    hole_fill: HoleFill,
     debug_flags: &D,
 ) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-    let quilt_views = render_quilt_views(
-        settings.resolution.0,
-        settings.resolution.1,
-        settings.columns,
-        settings.rows,
-        texture,
-        heightmap,
-        zoom,
+    let options = RenderOptions {
         fov_deg,
+        view_spacing: ViewSpacing::Linear,
+        mirror_views: false,
+        zoom,
+        zoom_center,
         scale,
-        bg_color,
-        debug_flags,
+        focus_depth,
+        background: Background::Solid(bg_color),
+        max_parallax,
+        sampling,
+        splat_radius,
+        dither,
+        easing,
+        hole_fill,
+        ..Default::default()
+    };
+    render_quilt(
+        settings,
+        texture,
+        heightmap,
+        &options,
         caption,
-    );
-    stitch_quilt(&quilt_views, settings.columns, settings.rows)
+        watermark,
+        debug_flags,
+    )
+}
+
+/// Renders and stitches the views with their post effects but without any caption or watermark,
+/// for `composite_overlays` to finish.
+pub fn render_raw_quilt<T: TextureSource, H: DepthSource, D: DebugFlags>(
+    settings: &QuiltSettings,
+    texture: &T,
+    heightmap: &H,
+    options: &RenderOptions,
+    debug_flags: &D,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let mut quilt = render_base_quilt(settings, texture, heightmap, options, debug_flags);
+    apply_postfx(&mut quilt, settings, options);
+    quilt
+}
+
+/// Renders and stitches the views before their post effects, the expensive part that
+/// `apply_postfx` and `composite_overlays` finish. Keeping them apart lets callers cache this
+/// part, so changing only the post effects, caption or watermark doesn't render the views again.
+pub fn render_base_quilt<T: TextureSource, H: DepthSource, D: DebugFlags>(
+    settings: &QuiltSettings,
+    texture: &T,
+    heightmap: &H,
+    options: &RenderOptions,
+    debug_flags: &D,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let render_span = tracing::info_span!("render", views = settings.columns * settings.rows);
+    let mut quilt_views = render_span
+        .in_scope(|| render_quilt_views(settings, texture, heightmap, options, debug_flags));
+    if options.stabilize_views {
+        // Solid backgrounds and the matte stay as they are
+        let background = match options.background {
+            Background::Solid(color) => Some(color),
+            _ => None,
+        };
+        let ignore: Vec<Rgb<u8>> = [background, options.matte].into_iter().flatten().collect();
+        tracing::info_span!("stabilize")
+            .in_scope(|| postfx::stabilize_views(&mut quilt_views, &ignore));
+    }
+    tracing::info_span!("stitch").in_scope(|| {
+        let tile_width = settings.resolution.0 / settings.columns;
+        let tile_height = settings.resolution.1 / settings.rows;
+        let tiles: Vec<_> = quilt_views
+            .into_iter()
+            .map(|view| options.frame_view(view, tile_width, tile_height))
+            .collect();
+        stitch_quilt(&tiles, settings.columns, settings.rows)
+    })
+}
+
+/// Applies `options.postfx` to every view of a quilt from `render_base_quilt`, inside any tile
+/// padding.
+pub fn apply_postfx(
+    quilt: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+    settings: &QuiltSettings,
+    options: &RenderOptions,
+) {
+    if options.postfx.is_none() {
+        return;
+    }
+    tracing::info_span!("postfx").in_scope(|| {
+        let (columns, rows) = (settings.columns, settings.rows);
+        let tile_width = quilt.width() / columns;
+        let tile_height = quilt.height() / rows;
+        let (view_width, view_height) = options.view_size(settings);
+        let padding = options.tile_padding;
+        // Padding as wide as the tile leaves no view showing
+        if padding + view_width > tile_width || padding + view_height > tile_height {
+            return;
+        }
+        let source = &*quilt;
+        let views: Vec<_> = (0..columns * rows)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = tile_origin(i, columns, tile_width, tile_height);
+                let (x, y) = (x + padding, y + padding);
+                let view = image::imageops::crop_imm(source, x, y, view_width, view_height);
+                let mut view = view.to_image();
+                let height = view.height();
+                options
+                    .postfx
+                    .apply(&mut view, i, 0, height, &options.background);
+                (x, y, view)
+            })
+            .collect();
+        for (x, y, view) in views {
+            image::imageops::replace(quilt, &view, x as i64, y as i64);
+        }
+    });
+}
+
+/// Draws the caption and watermark onto a quilt from `render_raw_quilt`: on every view, or once
+/// on the whole quilt, as each is configured.
+pub fn composite_overlays(
+    mut quilt: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    settings: &QuiltSettings,
+    caption: CaptionConfig,
+    watermark: Option<&Watermark>,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let view_caption = !caption.is_empty() && caption.target == CaptionTarget::View;
+    let view_watermark = watermark.filter(|w| !w.config.once);
+    if view_caption || view_watermark.is_some() {
+        let (columns, rows) = (settings.columns, settings.rows);
+        let view_width = quilt.width() / columns;
+        let view_height = quilt.height() / rows;
+        let views: Vec<_> = (0..columns * rows)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = tile_origin(i, columns, view_width, view_height);
+                let view = image::imageops::crop_imm(&quilt, x, y, view_width, view_height);
+                let mut view = view.to_image();
+                if view_caption {
+                    view = draw_caption(view, caption.clone(), view_position(i, columns * rows));
+                }
+                if let Some(watermark) = view_watermark {
+                    watermark.draw(&mut view);
+                }
+                view
+            })
+            .collect();
+        quilt = stitch_quilt(&views, columns, rows);
+    }
+    if caption.target == CaptionTarget::Quilt {
+        quilt = draw_caption(quilt, caption, 0.0);
+    }
+    if let Some(watermark) = watermark.filter(|w| w.config.once) {
+        watermark.draw(&mut quilt);
+    }
+    quilt
 }
 
 /// Renders all views for the quilt
 ///
 /// # Arguments
-/// * `quilt_width` - Width of the final quilt
-/// * `quilt_height` - Height of the final quilt  
-/// * `columns` - Number of columns in the quilt
-/// * `rows` - Number of rows in the quilt
+/// * `settings` - The quilt settings for the target device
 /// * `texture` - The RGB texture image
 /// * `heightmap` - The grayscale heightmap image
-/// * `zoom` - Zoom factor
-/// * `fov_deg` - Field of view in degrees
-/// * `scale` - Height scale factor
-/// * `bg_color` - Background color
+/// * `options` - How the views are rendered
 /// * `debug_kv` - Debug key-value pairs
 ///
 /// # Returns
 /// Vector of rendered view images
-fn render_quilt_views<D: DebugFlags>(
-    quilt_width: u32,
-    quilt_height: u32,
-    columns: u32,
-    rows: u32,
-    texture: &TextureImage,
-    heightmap: &DepthImage,
-    zoom: f32,
-    fov_deg: f32,
-    scale: f32,
-    bg_color: Rgb<u8>,
+fn render_quilt_views<T: TextureSource, H: DepthSource, D: DebugFlags>(
+    settings: &QuiltSettings,
+    texture: &T,
+    heightmap: &H,
+    options: &RenderOptions,
     debug_flags: &D,
-    caption: CaptionConfig,
 ) -> Vec<ImageBuffer<Rgb<u8>, Vec<u8>>> {
-    let num_views = columns * rows;
-    let view_width = quilt_width / columns;
-    let view_height = quilt_height / rows;
-
-    // fov is centered at origin.
-    let fov_size = fov_deg / 360.0 * std::f32::consts::PI;
-    let fov_low = -fov_size / 2.0;
+    let num_views = settings.columns * settings.rows;
+    let opts = options.view_options();
 
     // Parallize over each view point. The smallest unit of parallelization we could do without
     // address conflicts should be a single y-line of an output image (not a input texture row) ,
     // but the image crate doesn't offer a way to slice out chunks of image like that, so lazily we
     // just do whole images.
+    let parent = tracing::Span::current();
     (0..num_views)
         .into_par_iter()
         .map(|i| {
-            let view_theta = fov_size * i as f32 / (num_views - 1) as f32 + fov_low;
-            log::debug!(
+            let _span = tracing::debug_span!(parent: &parent, "render_view", view = i).entered();
+            let camera = options.camera(settings, i);
+            tracing::debug!(
                 "Camera theta degrees: {:?}",
-                view_theta / std::f32::consts::PI * 360.0
+                camera.view_theta / std::f32::consts::PI * 360.0
             );
-            let camera = Camera {
-                zoom,
-                view_width,
-                view_height,
-                view_theta,
-                z_scale: scale,
-            };
-            let rotation = na::UnitComplex::from_angle(view_theta);
-            let view = render_view(texture, heightmap, camera, rotation, bg_color, debug_flags);
-            let view = draw_caption(view, caption.clone());
+            let started = std::time::Instant::now();
+            let view = render_view(
+                texture,
+                heightmap,
+                camera,
+                camera.rotation(),
+                &opts,
+                debug_flags,
+            );
+            METRICS.view_render_seconds.observe(started.elapsed());
             view
         })
         .collect()
@@ -272,7 +919,7 @@ fn render_quilt_views<D: DebugFlags>(
 ///
 /// # Returns
 /// The final stitched quilt image
-fn stitch_quilt(
+pub fn stitch_quilt(
     views: &[ImageBuffer<Rgb<u8>, Vec<u8>>],
     columns: u32,
     rows: u32,
@@ -283,10 +930,7 @@ fn stitch_quilt(
     let mut quilt = ImageBuffer::new(quilt_width, quilt_height);
 
     for (i, view) in views.iter().enumerate() {
-        let row = i as u32 / columns;
-        let col = columns - (i as u32 % columns) - 1;
-        let y_start = row * view_height;
-        let x_start = col * view_width;
+        let (x_start, y_start) = tile_origin(i as u32, columns, view_width, view_height);
 
         for (x, y, pixel) in view.enumerate_pixels() {
             quilt.put_pixel(x_start + x, y_start + y, *pixel);
@@ -296,6 +940,83 @@ fn stitch_quilt(
     quilt
 }
 
+/// Top-left pixel of view `index` within a quilt. Views are laid out right to left, top to
+/// bottom in render order, which puts the last view at the bottom left as devices expect.
+pub fn tile_origin(index: u32, columns: u32, view_width: u32, view_height: u32) -> (u32, u32) {
+    let row = index / columns;
+    let col = columns - (index % columns) - 1;
+    (col * view_width, row * view_height)
+}
+
+/// Depth difference within which subpixel samples count as the same surface and are averaged.
+const SPLAT_DEPTH_TOLERANCE: f32 = 2.0;
+
+/// Depth given up per pixel of distance by the footprint of a splat wider than one pixel.
+const SPLAT_FOOTPRINT_BIAS: f32 = 1.0;
+
+/// Weighted color sums for `Sampling::Subpixel`, resolved into the view once all samples are in.
+struct Coverage {
+    width: u32,
+    /// Premultiplied r, g, b and the total weight, per pixel.
+    sums: Vec<[f32; 4]>,
+}
+
+impl Coverage {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            sums: vec![[0.0; 4]; width as usize * height as usize],
+        }
+    }
+
+    /// Adds a sample covering `weight` of pixel (x, y). A sample in front of the surface seen so
+    /// far replaces it; one behind it is dropped.
+    fn splat(
+        &mut self,
+        x: u32,
+        y: u32,
+        z: f32,
+        color: Rgb<u8>,
+        weight: f32,
+        zbuffer: &mut na::DMatrix<f32>,
+    ) {
+        let front = &mut zbuffer[(x as usize, y as usize)];
+        let sum = &mut self.sums[(y * self.width + x) as usize];
+        if z > *front + SPLAT_DEPTH_TOLERANCE {
+            *sum = [0.0; 4];
+            *front = z;
+        } else if z < *front - SPLAT_DEPTH_TOLERANCE {
+            return;
+        } else {
+            *front = front.max(z);
+        }
+        for c in 0..3 {
+            sum[c] += color[c] as f32 * weight;
+        }
+        sum[3] += weight;
+    }
+
+    /// Forgets the samples at (x, y), after something in front of them was drawn there.
+    fn clear(&mut self, x: u32, y: u32) {
+        self.sums[(y * self.width + x) as usize] = [0.0; 4];
+    }
+
+    /// Writes the weighted average of each pixel's samples into `img`.
+    fn resolve(&self, img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>, ditherer: &Ditherer) {
+        for (i, (pixel, sum)) in img.pixels_mut().zip(&self.sums).enumerate() {
+            let weight = sum[3];
+            // Blending thinly covered pixels with the background would darken steep slopes,
+            // where samples in front keep replacing the ones behind, so always normalize.
+            if weight > 0.0 {
+                let (x, y) = (i as u32 % self.width, i as u32 / self.width);
+                *pixel = Rgb(std::array::from_fn(|c| {
+                    ditherer.quantize(sum[c] / weight, x, y)
+                }));
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 struct PrevRender {
     x: u32,
@@ -303,40 +1024,72 @@ struct PrevRender {
     color: Rgb<u8>,
 }
 
-fn render_px<D: DebugFlags>(
-    img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    texture: &TextureImage,
-    camera: &camera::Camera,
-    rot: &na::UnitComplex<f32>,
+/// One screen row of a view being drawn, and what every sample on it is drawn with.
+struct RowContext<'a, D> {
+    img: &'a mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+    zbuffer: &'a mut na::DMatrix<f32>,
+    coverage: Option<&'a mut Coverage>,
+    camera: &'a Camera,
+    scene_rotation: &'a na::UnitComplex<f32>,
+    opts: &'a ViewOptions,
+    ditherer: &'a Ditherer,
+    debug_flags: &'a D,
+    /// The row within the band being rendered.
+    band_y: u32,
+}
+
+fn render_px<T: TextureSource, D: DebugFlags>(
+    row: &mut RowContext<D>,
+    texture: &T,
     tex_y: u32,
     tex_x: u32,
-    screen_y: u32,
     height: f32,
-    zbuffer: &mut na::DMatrix<f32>,
     prev: Option<PrevRender>,
-    debug_flags: &D,
 ) -> Option<PrevRender> {
+    let (camera, rot, opts, ditherer, debug_flags) = (
+        row.camera,
+        row.scene_rotation,
+        row.opts,
+        row.ditherer,
+        row.debug_flags,
+    );
+    let screen_y = row.band_y;
+    let img = &mut *row.img;
+    let zbuffer = &mut *row.zbuffer;
+    let mut coverage = row.coverage.as_deref_mut();
     let (tex_width, _tex_height) = texture.dimensions();
-    let x_img = tex_x as f32 - (tex_width as f32) / 2.0;
+    let x_img = tex_x as f32 - tex_width as f32 * camera.view_center()[0];
     // let screen_x_0 = camera.view_width as f32 / 2.0;
 
     let z0 = 0.0;
-    let color = *texture.0.get_pixel(tex_x, tex_y);
+    let color = texture.texel(tex_x, tex_y);
 
     // We want to draw a line along the normal from the surface at (x,y,z0) (start_pt) to the displaced
     // height(x,y,z0+height). The surface is rotated by camera.rot around the y axis
-    let pt = rot * na::point!(z0 + (height) * camera.z_scale, x_img);
+    let depth = (height - camera.focus_depth) * camera.z_scale;
+    let depth = match camera.depth_limit(tex_width) {
+        Some(limit) => depth.clamp(-limit, limit),
+        None => depth,
+    };
+    let pt = rot * na::point!(z0 + depth, x_img);
     const EPSILON: f32 = 1e-5;
 
-    let screen_x = (pt[1] * camera.zoom * (camera.view_width as f32 / tex_width as f32)
-        + camera.view_width as f32 / 2.0)
-        .round();
-
-    if screen_x < 0.0 {
-        return None;
-    }
+    let exact_screen_x = pt[1] * camera.zoom_x() * (camera.view_width as f32 / tex_width as f32)
+        + camera.view_width as f32 / 2.0;
+    // Samples left of the view still count as the start of the gradient to the next sample, at
+    // the view edge (the u32 casts below saturate to 0), so zooming in leaves no gap there.
+    let screen_x = exact_screen_x.round();
 
-    if screen_x >= 0.0
+    if let Some(coverage) = coverage.as_deref_mut() {
+        // Pixel centers are at integer positions, so split between floor and floor + 1
+        let left = exact_screen_x.floor();
+        let fraction = exact_screen_x - left;
+        for (x, weight) in [(left, 1.0 - fraction), (left + 1.0, fraction)] {
+            if x >= 0.0 && x < camera.view_width as f32 && weight > 0.0 {
+                coverage.splat(x as u32, screen_y, pt[0], color, weight, zbuffer);
+            }
+        }
+    } else if screen_x >= 0.0
         && screen_x < camera.view_width as f32
         && pt[0] > zbuffer[(screen_x as usize, screen_y as usize)]
     {
@@ -344,6 +1097,33 @@ fn render_px<D: DebugFlags>(
         img.put_pixel(screen_x as u32, screen_y, color);
     }
 
+    if opts.splat_radius > 1 {
+        let reach = opts.splat_radius as i64 - 1;
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                let (x, y) = (screen_x as i64 + dx, screen_y as i64 + dy);
+                if (dx, dy) == (0, 0)
+                    || x < 0
+                    || y < 0
+                    || x >= camera.view_width as i64
+                    || y >= img.height() as i64
+                {
+                    continue;
+                }
+                // Push the footprint back a little so samples landing on their own pixel win
+                // over neighbors' footprints at the same depth.
+                let z = pt[0] - SPLAT_FOOTPRINT_BIAS * dx.abs().max(dy.abs()) as f32;
+                if z > zbuffer[(x as usize, y as usize)] {
+                    zbuffer[(x as usize, y as usize)] = z;
+                    img.put_pixel(x as u32, y as u32, color);
+                    if let Some(coverage) = coverage.as_deref_mut() {
+                        coverage.clear(x as u32, y as u32);
+                    }
+                }
+            }
+        }
+    }
+
     // Draw gradient from last
     if let Some(prev) = prev {
         let (start, start_z, start_color, end, end_z, end_color) = if prev.x > screen_x as u32 {
@@ -369,30 +1149,36 @@ fn render_px<D: DebugFlags>(
             }
             let min_x = start.min(end);
             let max_x = start.max(end);
-            let start_color_luminosity = rgb_to_lum(start_color);
-            let end_color_luminosity = rgb_to_lum(end_color);
-            let sharpness = 0.3333;
-            let mut w1 = start_color_luminosity / (start_color_luminosity + end_color_luminosity);
-            let mut w2 =
-                1.0 - (end_color_luminosity / (start_color_luminosity + end_color_luminosity));
-            if start_color_luminosity > end_color_luminosity {
-                w2 *= sharpness;
-            } else {
-                w1 *= sharpness;
-            }
+            let (start_color, end_color) = match opts.hole_fill {
+                HoleFill::Blend => (start_color, end_color),
+                HoleFill::Background => {
+                    let far_color = if start_z < end_z {
+                        start_color
+                    } else {
+                        end_color
+                    };
+                    (far_color, far_color)
+                }
+            };
+            let curve = opts.easing.curve(start_color, end_color);
             for draw_x in min_x..=max_x {
-                // Add epsilon to avoid floating point rounding errors
+                // Distance from the start sample, which is the right one. Add epsilon to avoid
+                // floating point rounding errors
                 let raw_t =
-                    ((draw_x as f32 - start as f32) / (len as f32 + EPSILON)).clamp(0.0, 1.0);
-                let eased_t = ease_in_out(raw_t, w1, w2);
+                    ((start as f32 - draw_x as f32) / (len as f32 + EPSILON)).clamp(0.0, 1.0);
+                let eased_t = curve.ease(raw_t);
                 if draw_x < camera.view_width && screen_y < camera.view_height {
                     let pt_color = start_color.map2(&end_color, |s, e| {
-                        ((e as f32 - s as f32) * eased_t + s as f32).clamp(0.0, 255.0) as u8
+                        let value = (e as f32 - s as f32) * eased_t + s as f32;
+                        ditherer.quantize_floor(value, draw_x, screen_y)
                     });
                     let z = start_z + (end_z - start_z) * raw_t;
                     if z > zbuffer[(draw_x as usize, screen_y as usize)] {
                         img.put_pixel(draw_x, screen_y, pt_color);
                         zbuffer[(draw_x as usize, screen_y as usize)] = z;
+                        if let Some(coverage) = coverage.as_deref_mut() {
+                            coverage.clear(draw_x, screen_y);
+                        }
                     }
                 }
             }
@@ -406,75 +1192,253 @@ fn render_px<D: DebugFlags>(
     })
 }
 
+/// How texture samples are placed on view pixels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sampling {
+    /// Each sample lands on the nearest pixel column. Fastest, but edges crawl between views.
+    #[default]
+    Nearest,
+    /// Each sample is split between the two nearest columns by its fractional position, and
+    /// samples at the same depth are averaged. Smoother edges and less crawling between views.
+    Subpixel,
+}
+
+/// How the gap between two samples that land apart is filled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HoleFill {
+    /// Blend from one sample's color to the other's, with the easing curve.
+    #[default]
+    Blend,
+    /// Repeat the color of the farther sample. Where a disocclusion opens, this continues the
+    /// background instead of smearing the foreground edge across it.
+    Background,
+}
+
+/// A second layer of the scene: what is behind the main subject, e.g. inpainted with the subject
+/// removed, with a depth map that continues the background through where the subject was. Main
+/// layer pixels nearer than `subject_depth` are drawn over it and the rest come from this layer,
+/// so disocclusions at the subject's edges reveal it instead of stretched edge pixels.
+#[derive(Clone)]
+pub struct BackLayer {
+    /// Same size as the main texture.
+    pub texture: TextureImage,
+    pub heightmap: DepthImage,
+    /// Main layer depths above this are the subject.
+    pub subject_depth: u8,
+}
+
+impl BackLayer {
+    /// Whether the main layer pixel with depth `height` is drawn over this layer.
+    fn is_subject(&self, height: u8) -> bool {
+        height > self.subject_depth
+    }
+}
+
+impl PartialEq for BackLayer {
+    fn eq(&self, other: &Self) -> bool {
+        self.subject_depth == other.subject_depth
+            && self.texture.0 == other.texture.0
+            && self.heightmap.0 == other.heightmap.0
+    }
+}
+
+impl std::fmt::Debug for BackLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BackLayer")
+            .field("size", &self.texture.dimensions())
+            .field("subject_depth", &self.subject_depth)
+            .finish()
+    }
+}
+
+/// Options for `render_single_view`.
+#[derive(Clone, Debug)]
+pub struct ViewOptions {
+    /// What pixels no texture sample lands on show, drawn per view.
+    pub background: Background,
+    /// Color of the letterbox bars: pixels nothing lands on outside the frame the texture covers
+    /// at the focus depth. The background when not set.
+    pub matte: Option<Rgb<u8>>,
+    pub sampling: Sampling,
+    /// Pixels each texture sample covers around where it lands: 1 is the landing pixel only, 2
+    /// a 3x3 square, 3 a 5x5 square. Larger splats fill pinholes where the texture is minified
+    /// or strongly displaced; the nearest sample still wins each pixel.
+    pub splat_radius: u32,
+    /// Dithering of colors blended from several samples.
+    pub dither: Dither,
+    /// Color blend across gaps between samples.
+    pub easing: Easing,
+    /// Which colors fill gaps between samples.
+    pub hole_fill: HoleFill,
+    /// Scene behind the main subject, drawn first.
+    pub back_layer: Option<Arc<BackLayer>>,
+    /// Where the cameras look in an equirectangular panorama, for panorama input.
+    pub panorama: Option<Panorama>,
+}
+
+impl Default for ViewOptions {
+    fn default() -> Self {
+        Self {
+            background: Background::default(),
+            matte: None,
+            sampling: Sampling::Nearest,
+            splat_radius: 1,
+            dither: Dither::None,
+            easing: Easing::default(),
+            hole_fill: HoleFill::default(),
+            back_layer: None,
+            panorama: None,
+        }
+    }
+}
+
+/// Renders one viewpoint of the scene, without captions or watermarks. Use
+/// `Camera::for_view` to get the camera the quilt renderer would use for a given view, or build
+/// one directly for viewpoints in between.
+pub fn render_single_view<T: TextureSource, H: DepthSource>(
+    camera: Camera,
+    texture: &T,
+    heightmap: &H,
+    opts: &ViewOptions,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    render_view(
+        texture,
+        heightmap,
+        camera,
+        camera.rotation(),
+        opts,
+        &NullDebugFlags,
+    )
+}
+
 /// Renders a single view from the given camera angle
-fn render_view<D: DebugFlags>(
-    texture: &TextureImage,
-    heightmap: &DepthImage,
+fn render_view<T: TextureSource, H: DepthSource, D: DebugFlags>(
+    texture: &T,
+    heightmap: &H,
     camera: Camera,
     scene_rotation: na::UnitComplex<f32>,
-    bg_color: Rgb<u8>,
+    opts: &ViewOptions,
+    debug_flags: &D,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    render_view_rows(
+        texture,
+        heightmap,
+        camera,
+        scene_rotation,
+        opts,
+        0..camera.view_height,
+        debug_flags,
+    )
+}
+
+/// Renders only the screen rows `rows` of a view, as an image `rows.len()` high. Rows near the
+/// band edges can miss the footprints of splats wider than one pixel landing outside the band.
+pub(crate) fn render_view_rows<T: TextureSource, H: DepthSource, D: DebugFlags>(
+    texture: &T,
+    heightmap: &H,
+    camera: Camera,
+    scene_rotation: na::UnitComplex<f32>,
+    opts: &ViewOptions,
+    rows: std::ops::Range<u32>,
     debug_flags: &D,
 ) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    if let Some(panorama) = &opts.panorama {
+        return render_panorama_rows(texture, heightmap, &camera, panorama, opts, rows);
+    }
     let (tex_width, tex_height) = texture.dimensions();
+    let band_height = rows.len() as u32;
 
-    let mut img = ImageBuffer::from_pixel(camera.view_width, camera.view_height, bg_color);
+    let mut img = opts
+        .background
+        .fill(camera.view_width, camera.view_height, rows.clone());
     let mut zbuffer: na::DMatrix<f32> = na::DMatrix::from_element(
         camera.view_width as usize,
-        camera.view_height as usize,
+        band_height as usize,
         f32::NEG_INFINITY,
     );
+    let mut coverage = (opts.sampling == Sampling::Subpixel)
+        .then(|| Coverage::new(camera.view_width, band_height));
+    let ditherer = Ditherer {
+        dither: opts.dither,
+        row_offset: rows.start,
+    };
+
+    // Texture row drawn in the middle of the view
+    let tex_center_y = tex_height as f32 * camera.view_center()[1];
+    // Rows of the band above or below the texture, for the matte
+    let mut outside_rows = vec![false; band_height as usize];
 
     // Iterate over output image rows
-    for screen_y in 0..camera.view_height {
+    for screen_y in rows.clone() {
+        let band_y = screen_y - rows.start;
         // Calculate texture y range that could map to this screen y
-        // Zoom the y around the center of the view.
-        let zoomed_screen_y = (screen_y as f32 - (camera.view_height as f32 / 2.0)) / camera.zoom;
-        let zoomed_screen_y_next = zoomed_screen_y + camera.zoom;
-        let tex_y_f = zoomed_screen_y * tex_height as f32 / camera.view_height as f32
-            + tex_height as f32 / 2.0;
-        let tex_y_next_f = (zoomed_screen_y_next) * tex_height as f32 / camera.view_height as f32
-            + tex_height as f32 / 2.0;
+        // Zoom the y around the center of the view; one screen row spans 1 / zoom unzoomed rows.
+        let zoomed_screen_y =
+            (screen_y as f32 - (camera.view_height as f32 / 2.0)) / camera.zoom_y();
+        let zoomed_screen_y_next = zoomed_screen_y + 1.0 / camera.zoom_y();
+        let tex_y_f =
+            zoomed_screen_y * tex_height as f32 / camera.view_height as f32 + tex_center_y;
+        let tex_y_next_f =
+            (zoomed_screen_y_next) * tex_height as f32 / camera.view_height as f32 + tex_center_y;
+
+        // Zoomed out, rows above and below the texture stay background
+        if tex_y_next_f <= 0.0 || tex_y_f >= tex_height as f32 {
+            outside_rows[band_y as usize] = true;
+            continue;
+        }
 
-        let tex_y_start = tex_y_f.floor() as u32;
-        let tex_y_end = tex_y_next_f.ceil() as u32;
+        // Every texture row the screen row overlaps, and at least one when zoomed in so far that
+        // the screen row falls within a single texture row
+        let tex_y_start = tex_y_f.floor().max(0.0) as u32;
+        let tex_y_end = ((tex_y_next_f.ceil() as u32).saturating_sub(1)).max(tex_y_start);
 
+        let mut row = RowContext {
+            img: &mut img,
+            zbuffer: &mut zbuffer,
+            coverage: coverage.as_mut(),
+            camera: &camera,
+            scene_rotation: &scene_rotation,
+            opts,
+            ditherer: &ditherer,
+            debug_flags,
+            band_y,
+        };
         // Process each texture y that maps to this screen y
         for tex_y in tex_y_start..=tex_y_end.min(tex_height - 1) {
-            let mut last = None;
-            if camera.view_theta < 0.0 {
-                for tex_x in 0..tex_width {
-                    let height_pixel = heightmap.0.get_pixel(tex_x, tex_y);
-                    last = render_px(
-                        &mut img,
-                        texture,
-                        &camera,
-                        &scene_rotation,
-                        tex_y,
-                        tex_x,
-                        screen_y,
-                        height_pixel[0] as f32,
-                        &mut zbuffer,
-                        last,
-                        debug_flags,
-                    )
-                }
-            } else {
-                for tex_x in (0..tex_width).rev() {
-                    let height_pixel = heightmap.0.get_pixel(tex_x, tex_y);
-                    last = render_px(
-                        &mut img,
-                        texture,
-                        &camera,
-                        &scene_rotation,
-                        tex_y,
-                        tex_x,
-                        screen_y,
-                        height_pixel[0] as f32,
-                        &mut zbuffer,
-                        last,
-                        debug_flags,
-                    )
-                }
+            // The back layer first, then only the subject of the main layer over it. Leaving
+            // out the rest breaks the gap fill at the subject's edges, where the back layer shows.
+            let back_layer = opts.back_layer.as_deref();
+            if let Some(back) = back_layer {
+                render_texture_row(&mut row, &back.texture, &back.heightmap, None, tex_y);
+            }
+            render_texture_row(&mut row, texture, heightmap, back_layer, tex_y);
+        }
+    }
+
+    if let Some(coverage) = coverage {
+        coverage.resolve(&mut img, &ditherer);
+    }
+
+    if let Some(matte) = opts
+        .matte
+        .filter(|matte| Background::Solid(*matte) != opts.background)
+    {
+        // Columns the texture spans at the focus depth, where depth moves nothing
+        let frame_x = |tex_x: f32| {
+            let x_img = tex_x - tex_width as f32 * camera.view_center()[0];
+            let pt = scene_rotation * na::point!(0.0, x_img);
+            pt[1] * camera.zoom_x() * (camera.view_width as f32 / tex_width as f32)
+                + camera.view_width as f32 / 2.0
+        };
+        let (left, right) = (frame_x(0.0), frame_x(tex_width as f32));
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let center_x = x as f32 + 0.5;
+            if zbuffer[(x as usize, y as usize)] == f32::NEG_INFINITY
+                && (outside_rows[y as usize] || center_x < left || center_x > right)
+            {
+                *pixel = matte;
             }
         }
     }
@@ -482,7 +1446,7 @@ fn render_view<D: DebugFlags>(
     // If texture=zbuffer debug mode is on, replace the output with zbuffer visualization
     if debug_flags.texture_mode() == Some("zbuffer") {
         // Create new image for zbuffer visualization
-        let mut zbuffer_img = ImageBuffer::new(camera.view_width, camera.view_height);
+        let mut zbuffer_img = ImageBuffer::new(camera.view_width, band_height);
 
         // Find min/max z values for normalization
         let (min_z, max_z) = zbuffer
@@ -493,7 +1457,7 @@ fn render_view<D: DebugFlags>(
             .unwrap();
 
         // Normalize and visualize zbuffer
-        for y in 0..camera.view_height {
+        for y in 0..band_height {
             for x in 0..camera.view_width {
                 let z = zbuffer[(x as usize, y as usize)];
                 if z == f32::NEG_INFINITY {
@@ -509,3 +1473,30 @@ fn render_view<D: DebugFlags>(
         img
     }
 }
+
+/// Draws texture row `tex_y` of one layer onto screen row `band_y`, sweeping the columns away
+/// from the camera so each sample's gap fill reaches back to the one before. With `subject_over`,
+/// only the pixels that are drawn over that back layer are.
+fn render_texture_row<T: TextureSource, H: DepthSource, D: DebugFlags>(
+    row: &mut RowContext<D>,
+    texture: &T,
+    heightmap: &H,
+    subject_over: Option<&BackLayer>,
+    tex_y: u32,
+) {
+    let tex_width = texture.dimensions().0;
+    let columns = if row.camera.view_theta < 0.0 {
+        itertools::Either::Left(0..tex_width)
+    } else {
+        itertools::Either::Right((0..tex_width).rev())
+    };
+    let mut last = None;
+    for tex_x in columns {
+        let height = heightmap.depth(tex_x, tex_y);
+        if subject_over.is_some_and(|back| !back.is_subject(height)) {
+            last = None;
+            continue;
+        }
+        last = render_px(row, texture, tex_y, tex_x, height as f32, last)
+    }
+}
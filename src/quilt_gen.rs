@@ -1,9 +1,36 @@
+use crate::autotune::auto_tune;
+pub use crate::background::parse_color;
+use crate::background::Background;
+use crate::camera::{ViewSpacing, IMAGE_CENTER};
 use crate::captions::CaptionConfig;
 use crate::debug::{CliDebugFlags, DebugFlags, NullDebugFlags};
+use crate::descriptor::QuiltDescriptor;
+use crate::dither::Dither;
+use crate::encode::save_image;
 use crate::image_types::{DepthImage, RgbdImage, TextureImage};
-use crate::quilt::{get_quilt_settings, make_quilt, QuiltSettings};
+use crate::ktx::save_ktx2;
+use crate::leia::save_leia;
+use crate::metrics::METRICS;
+use crate::output;
+use crate::panorama::Panorama;
+use crate::postfx::PostFx;
+use crate::preview::{embed_exif_thumbnail, prepare_preview, Preview};
+use crate::quilt::{
+    apply_postfx, composite_overlays, get_quilt_settings, render_base_quilt, BackLayer, Easing,
+    HoleFill, QuiltName, QuiltSettings, RenderOptions, Sampling,
+};
+use crate::render_cache::{RenderCache, RenderParams};
+use crate::saliency::SaliencyMap;
+use crate::tiled::{save_quilt_tiled, TiledOptions};
+use crate::undistort::LensDistortion;
+use crate::watermark::WatermarkConfig;
 use image::{ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct QuiltConfig {
     pub device: Option<String>,
     pub columns: Option<u32>,
@@ -12,117 +39,430 @@ pub struct QuiltConfig {
     pub height: Option<u32>,
     pub debug_mode: Option<String>,
     pub bg: String,
+    /// Color of the letterbox bars around the texture, when not `bg`.
+    #[serde(default)]
+    pub matte: Option<String>,
     pub fov: f32,
     pub zoom: f32,
     pub scale: f32,
     pub resize: f32,
     pub symlink_output: bool,
+    /// How `symlink_output` points the output name at the generated file.
+    #[serde(default)]
+    pub link_mode: LinkMode,
     pub caption: CaptionConfig,
+    #[serde(default)]
+    pub export_normals: Option<f32>,
+    /// Also write the four views as a Leia 2x2 lightfield image, `<name>_leia.jpg`.
+    #[serde(default)]
+    pub export_leia: bool,
+    #[serde(default)]
+    pub watermark: Option<WatermarkConfig>,
+    /// Also write a JSON layout descriptor next to the quilt.
+    #[serde(default)]
+    pub write_descriptor: bool,
+    /// Also write the center view as a flat preview, beside the quilt or as its EXIF thumbnail.
+    #[serde(default)]
+    pub preview: Option<Preview>,
+    /// Pick `scale` and `zoom` from the depth map instead of using the configured values.
+    #[serde(default)]
+    pub auto_tune: bool,
+    /// Auto-tune parallax target in view pixels. Defaults to a fraction of the view width.
+    #[serde(default)]
+    pub auto_tune_parallax: Option<f32>,
+    /// Limit in view pixels on how far depth moves a point in the outermost views.
+    #[serde(default)]
+    pub max_parallax: Option<f32>,
+    #[serde(default)]
+    pub sampling: Sampling,
+    /// Pixels each texture sample covers, 1-3.
+    #[serde(default = "default_splat_radius")]
+    pub splat_radius: u32,
+    /// Overrides the device's pixel aspect ratio (pixel width over height).
+    #[serde(default)]
+    pub pixel_aspect: Option<f32>,
+    /// Render and write the quilt in bands of this many view rows to bound memory.
+    #[serde(default)]
+    pub band_height: Option<u32>,
+    /// Where to cache rendered quilts before captions and watermarks, so re-runs that only
+    /// change those skip rendering.
+    #[serde(default)]
+    pub render_cache_dir: Option<std::path::PathBuf>,
+    /// Point to zoom towards. Defaults to the middle of the image.
+    #[serde(default)]
+    pub zoom_center: Option<ZoomCenter>,
+    /// Depth kept on the display plane. Defaults to the farthest depth, 0.
+    #[serde(default)]
+    pub focus_depth: Option<FocusDepth>,
+    /// How the views are spread across the field of view.
+    #[serde(default)]
+    pub view_spacing: ViewSpacing,
+    /// Mirror the texture and depth map left to right before rendering.
+    #[serde(default)]
+    pub flip_h: bool,
+    /// Mirror the texture and depth map top to bottom before rendering.
+    #[serde(default)]
+    pub flip_v: bool,
+    /// Reverse the order of the views in the quilt.
+    #[serde(default)]
+    pub mirror_views: bool,
+    /// Dithering of colors blended from several samples.
+    #[serde(default)]
+    pub dither: Dither,
+    /// Color blend across gaps between samples.
+    #[serde(default)]
+    pub easing: Easing,
+    /// Which colors fill gaps between samples.
+    #[serde(default)]
+    pub hole_fill: HoleFill,
+    /// Pixels of border around each view in its tile.
+    #[serde(default)]
+    pub tile_padding: u32,
+    /// Color of the border: black, sky, debug or an rgb triplet.
+    #[serde(default = "default_border_color")]
+    pub border_color: String,
+    /// Scene behind the subject from an inpainting pre-pass, at the input's size.
+    #[serde(skip)]
+    pub back_layer: Option<Arc<BackLayer>>,
+    /// Treat the input as an equirectangular panorama, seen from inside in this direction.
+    #[serde(default)]
+    pub panorama: Option<Panorama>,
+    /// Lens distortion to take out of the texture and depth map before anything else.
+    #[serde(default)]
+    pub undistort: Option<LensDistortion>,
+    /// Histogram-match every view to the middle one, against flicker between views.
+    #[serde(default)]
+    pub stabilize_views: bool,
+    /// Sharpening and other effects applied to every view.
+    #[serde(default)]
+    pub postfx: PostFx,
 }
 
-pub fn parse_color(arg: &str) -> Option<Rgb<u8>> {
-    match arg {
-        "black" => Some(Rgb([0, 0, 0])),
-        "sky" => Some(Rgb([128, (0.7 * 255.0) as u8, 255])),
-        "debug" => Some(Rgb([255, 0, 255])),
-        rgb => {
-            if rgb.contains(',') {
-                // parse 0,0,0
-                let parts: Vec<u8> = rgb
-                    .split(',')
-                    .map(|s| s.trim().parse::<u8>().unwrap_or(0))
-                    .collect();
-                if parts.len() == 3 {
-                    Some(Rgb([parts[0], parts[1], parts[2]]))
-                } else {
-                    Some(Rgb([0, 0, 0]))
+/// Where zooming in is centered, as given by `--zoom-center`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ZoomCenter {
+    /// A point as fractions of the image width and height, from the top left.
+    Point([f32; 2]),
+    /// The subject found by `SaliencyMap`, or the middle of the image if there is none.
+    Auto,
+}
+
+impl FromStr for ZoomCenter {
+    type Err = String;
+
+    /// Parses `auto` or `X,Y`, e.g. `0.5,0.33` for the middle of the upper third.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(ZoomCenter::Auto);
+        }
+        let point = s
+            .split_once(',')
+            .and_then(|(x, y)| Some([x.trim().parse().ok()?, y.trim().parse().ok()?]))
+            .filter(|point: &[f32; 2]| point.iter().all(|c| (0.0..=1.0).contains(c)));
+        point.map(ZoomCenter::Point).ok_or_else(|| {
+            format!(
+                "invalid zoom center '{}', expected auto or X,Y between 0 and 1",
+                s
+            )
+        })
+    }
+}
+
+/// Which depth stays on the display plane, as given by `--focus-depth`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FocusDepth {
+    /// A depth map value, 0 (farthest) to 255 (nearest).
+    Depth(f32),
+    /// The median depth of the subject found by `SaliencyMap`, or 0 if there is none.
+    Auto,
+}
+
+impl FromStr for FocusDepth {
+    type Err = String;
+
+    /// Parses `auto` or a depth from 0 to 255.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(FocusDepth::Auto);
+        }
+        s.parse()
+            .ok()
+            .filter(|depth| (0.0..=255.0).contains(depth))
+            .map(FocusDepth::Depth)
+            .ok_or_else(|| {
+                format!(
+                    "invalid focus depth '{}', expected auto or a depth from 0 to 255",
+                    s
+                )
+            })
+    }
+}
+
+impl FocusDepth {
+    /// The depth to keep on the display plane for this image.
+    pub fn resolve(&self, texture: &TextureImage, heightmap: &DepthImage) -> f32 {
+        match self {
+            FocusDepth::Depth(depth) => *depth,
+            FocusDepth::Auto => match SaliencyMap::new(texture, heightmap).subject_depth() {
+                Some(depth) => {
+                    crate::status!("Focusing on subject depth {:.0}", depth);
+                    depth
                 }
-            } else {
-                // parse hex #rrggbb or rrggbb
-                let s = rgb.trim_start_matches('#');
-
-                // Parse 6-digit hex code
-                if s.len() == 6 {
-                    let r = u8::from_str_radix(&s[0..2], 16).ok()?;
-                    let g = u8::from_str_radix(&s[2..4], 16).ok()?;
-                    let b = u8::from_str_radix(&s[4..6], 16).ok()?;
-                    Some(Rgb([r, g, b]))
-                } else {
-                    None
+                None => 0.0,
+            },
+        }
+    }
+}
+
+impl ZoomCenter {
+    /// The point to zoom towards in this image, as fractions of its width and height.
+    pub fn resolve(&self, texture: &TextureImage, heightmap: &DepthImage) -> [f32; 2] {
+        match self {
+            ZoomCenter::Point(point) => *point,
+            ZoomCenter::Auto => match SaliencyMap::new(texture, heightmap).subject_center() {
+                Some(center) => {
+                    crate::status!(
+                        "Zooming towards subject at {:.2},{:.2}",
+                        center[0],
+                        center[1]
+                    );
+                    center
                 }
-            }
+                None => IMAGE_CENTER,
+            },
         }
     }
 }
 
-pub fn generate_quilt(
-    mut texture: TextureImage,
-    mut heightmap: DepthImage,
-    output_base_name: String,
-    config: &QuiltConfig,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let custom_device: QuiltSettings;
+impl QuiltConfig {
+    /// The quilt layout: the named device's, or the custom columns, rows and size.
+    pub fn quilt_settings(&self) -> QuiltSettings {
+        let quilt_settings = if let Some(device) = &self.device {
+            *get_quilt_settings(device).expect("Unknown device")
+        } else {
+            QuiltSettings {
+                columns: self
+                    .columns
+                    .expect("Columns must be specified for custom settings"),
+                rows: self
+                    .rows
+                    .expect("Rows must be specified for custom settings"),
+                resolution: (
+                    self.width
+                        .expect("Width must be specified for custom settings"),
+                    self.height
+                        .expect("Height must be specified for custom settings"),
+                ),
+                pixel_aspect: 1.0,
+            }
+        };
+        QuiltSettings {
+            pixel_aspect: self.pixel_aspect.unwrap_or(quilt_settings.pixel_aspect),
+            ..quilt_settings
+        }
+    }
 
-    let quilt_settings = if let Some(device) = &config.device {
-        get_quilt_settings(device).expect("Unknown device")
+    /// Largest source size rendered into a tile of `quilt_settings`: the tile size times `resize`,
+    /// or for a panorama, enough to cover the tile that many times over at its field of view.
+    pub fn target_size(&self, quilt_settings: &QuiltSettings) -> (u32, u32) {
+        let tile_width = quilt_settings.resolution.0 / quilt_settings.columns;
+        let tile_height = quilt_settings.resolution.1 / quilt_settings.rows;
+        if let Some(panorama) = &self.panorama {
+            return panorama.target_size(tile_width, self.resize);
+        }
+        (
+            (tile_width as f32 * self.resize) as u32,
+            (tile_height as f32 * self.resize) as u32,
+        )
+    }
+}
+
+/// Downscales the texture and heightmap to fit within `target_width` x `target_height`,
+/// preserving the aspect ratio. Images that already fit are returned unchanged.
+pub fn fit_within(
+    texture: TextureImage,
+    heightmap: DepthImage,
+    target_width: u32,
+    target_height: u32,
+) -> (TextureImage, DepthImage) {
+    if texture.width() <= target_width && texture.height() <= target_height {
+        return (texture, heightmap);
+    }
+    let aspect_ratio = texture.width() as f32 / texture.height() as f32;
+    let (new_width, new_height) = if target_width as f32 / target_height as f32 > aspect_ratio {
+        // Height is the limiting factor
+        let new_height = target_height;
+        let new_width = (target_height as f32 * aspect_ratio) as u32;
+        (new_width, new_height)
     } else {
-        custom_device = QuiltSettings {
-            columns: config
-                .columns
-                .expect("Columns must be specified for custom settings"),
-            rows: config
-                .rows
-                .expect("Rows must be specified for custom settings"),
-            resolution: (
-                config
-                    .width
-                    .expect("Width must be specified for custom settings"),
-                config
-                    .height
-                    .expect("Height must be specified for custom settings"),
-            ),
-        };
-        &custom_device
+        // Width is the limiting factor
+        let new_width = target_width;
+        let new_height = (target_width as f32 / aspect_ratio) as u32;
+        (new_width, new_height)
     };
 
-    // Calculate target dimensions based on tile size and resize multiplier
-    let tile_width = quilt_settings.resolution.0 / quilt_settings.columns;
-    let tile_height = quilt_settings.resolution.1 / quilt_settings.rows;
-    let target_width = (tile_width as f32 * config.resize) as u32;
-    let target_height = (tile_height as f32 * config.resize) as u32;
-
-    // Resize if input is larger than target, preserving aspect ratio
-    if texture.width() > target_width || texture.height() > target_height {
-        let aspect_ratio = texture.width() as f32 / texture.height() as f32;
-        let (new_width, new_height) = if target_width as f32 / target_height as f32 > aspect_ratio {
-            // Height is the limiting factor
-            let new_height = target_height;
-            let new_width = (target_height as f32 * aspect_ratio) as u32;
-            (new_width, new_height)
-        } else {
-            // Width is the limiting factor
-            let new_width = target_width;
-            let new_height = (target_width as f32 / aspect_ratio) as u32;
-            (new_width, new_height)
-        };
-
-        texture = TextureImage(image::imageops::resize(
+    (
+        TextureImage(image::imageops::resize(
             &texture.0,
             new_width,
             new_height,
             image::imageops::FilterType::Lanczos3,
-        ));
-        heightmap = DepthImage(image::imageops::resize(
+        )),
+        DepthImage(image::imageops::resize(
             &heightmap.0,
             new_width,
             new_height,
             image::imageops::FilterType::Lanczos3,
-        ));
+        )),
+    )
+}
+
+/// Mirrors the texture and depth map together, left to right and/or top to bottom.
+pub fn flip_input(
+    mut texture: TextureImage,
+    mut heightmap: DepthImage,
+    horizontal: bool,
+    vertical: bool,
+) -> (TextureImage, DepthImage) {
+    if horizontal {
+        image::imageops::flip_horizontal_in_place(&mut texture.0);
+        image::imageops::flip_horizontal_in_place(&mut heightmap.0);
+    }
+    if vertical {
+        image::imageops::flip_vertical_in_place(&mut texture.0);
+        image::imageops::flip_vertical_in_place(&mut heightmap.0);
+    }
+    (texture, heightmap)
+}
+
+fn default_splat_radius() -> u32 {
+    1
+}
+
+fn default_border_color() -> String {
+    "black".to_string()
+}
+
+/// A rendered quilt that still has to be encoded and written, from `generate_quilt_deferred`.
+pub struct PendingSave {
+    pub filename: String,
+    pub image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    pub settings: QuiltSettings,
+    pub aspect: f32,
+    /// EXIF thumbnail to embed once the quilt is written.
+    pub thumbnail: Option<ImageBuffer<Rgb<u8>, Vec<u8>>>,
+}
+
+impl PendingSave {
+    /// Encodes the quilt in the format named by the file extension and writes it.
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let _span = tracing::info_span!("encode", file = %self.filename).entered();
+        let path = std::path::Path::new(&self.filename);
+        if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("ktx2"))
+        {
+            save_ktx2(
+                path,
+                &self.image,
+                self.settings.columns,
+                self.settings.rows,
+                self.aspect,
+            )?;
+        } else {
+            save_image(path, &self.image)?;
+        }
+        if let Some(thumbnail) = &self.thumbnail {
+            embed_exif_thumbnail(path, thumbnail)?;
+        }
+        crate::status!("Saved quilt image as: {}", self.filename);
+        Ok(())
     }
+}
 
-    let input_aspect_ratio = texture.width() as f32 / texture.height() as f32;
+pub fn generate_quilt(
+    texture: TextureImage,
+    heightmap: DepthImage,
+    output_base_name: String,
+    config: &QuiltConfig,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (filename, pending) =
+        generate_quilt_deferred(texture, heightmap, output_base_name, config)?;
+    if let Some(pending) = pending {
+        pending.save()?;
+    }
+    Ok(filename)
+}
 
-    let bg_color = parse_color(config.bg.as_str()).expect("valid --bg value");
+/// Like `generate_quilt`, but leaves encoding and writing the quilt to the caller, who can do it
+/// on another thread. Banded renders are written as they render, so return no `PendingSave`;
+/// the descriptor, normal map and symlink are always written before returning.
+pub fn generate_quilt_deferred(
+    mut texture: TextureImage,
+    mut heightmap: DepthImage,
+    output_base_name: String,
+    config: &QuiltConfig,
+) -> Result<(String, Option<PendingSave>), Box<dyn std::error::Error>> {
+    let quilt_settings = &config.quilt_settings();
+
+    if let Some(lens) = &config.undistort {
+        (texture, heightmap) = lens.undistort(texture, heightmap);
+    }
+    // Calculate target dimensions based on tile size and resize multiplier
+    let tile_width = quilt_settings.resolution.0 / quilt_settings.columns;
+    let (target_width, target_height) = config.target_size(quilt_settings);
+    (texture, heightmap) = fit_within(texture, heightmap, target_width, target_height);
+    (texture, heightmap) = flip_input(texture, heightmap, config.flip_h, config.flip_v);
+    // The back layer is undistorted, resized and flipped to stay aligned with the main layer
+    let back_layer = config.back_layer.as_deref().map(|layer| {
+        let (layer_texture, layer_heightmap) = match &config.undistort {
+            Some(lens) => lens.undistort(layer.texture.clone(), layer.heightmap.clone()),
+            None => (layer.texture.clone(), layer.heightmap.clone()),
+        };
+        let resize = |image: &ImageBuffer<Rgb<u8>, Vec<u8>>| {
+            if image.dimensions() == texture.dimensions() {
+                image.clone()
+            } else {
+                image::imageops::resize(
+                    image,
+                    texture.width(),
+                    texture.height(),
+                    image::imageops::FilterType::Lanczos3,
+                )
+            }
+        };
+        let (back_texture, back_heightmap) = flip_input(
+            TextureImage(resize(&layer_texture.0)),
+            DepthImage(resize(&layer_heightmap.0)),
+            config.flip_h,
+            config.flip_v,
+        );
+        Arc::new(BackLayer {
+            texture: back_texture,
+            heightmap: back_heightmap,
+            subject_depth: layer.subject_depth,
+        })
+    });
+
+    // A panorama fills each view, so it shows in the shape of the tiles on the display
+    let input_aspect_ratio = match config.panorama {
+        Some(_) => {
+            let tile_height = quilt_settings.resolution.1 / quilt_settings.rows;
+            tile_width as f32 * quilt_settings.pixel_aspect / tile_height as f32
+        }
+        None => texture.width() as f32 / texture.height() as f32,
+    };
+
+    let background: Background = config.bg.parse().expect("valid --bg value");
+    let matte = config
+        .matte
+        .as_deref()
+        .map(|matte| parse_color(matte).ok_or_else(|| format!("invalid matte color '{matte}'")))
+        .transpose()?;
+    let border_color = parse_color(&config.border_color)
+        .ok_or_else(|| format!("invalid border color '{}'", config.border_color))?;
 
     let debug_flags = if let Some(debug_str) = config.debug_mode.as_ref() {
         let mut flags = CliDebugFlags::default();
@@ -133,7 +473,7 @@ pub fn generate_quilt(
                     "texture" => flags.texture_mode = Some(value.to_string()),
                     "startpt" => flags.start_point_color = parse_color(value),
                     "endpt" => flags.end_point_color = parse_color(value),
-                    _ => eprintln!("Unknown debug flag: {}", flag),
+                    _ => output::warn(format!("Unknown debug flag: {}", flag)),
                 }
             }
         }
@@ -144,6 +484,8 @@ pub fn generate_quilt(
 
     let zero_heightmap = debug_flags.zero_heightmap();
     let texture_debug_mode = debug_flags.texture_mode();
+    // Debug renders show the main layer alone
+    let back_layer = back_layer.filter(|_| !zero_heightmap && texture_debug_mode.is_none());
 
     // If zero_heightmap is set, create a flat heightmap
     let heightmap = if zero_heightmap {
@@ -158,30 +500,56 @@ pub fn generate_quilt(
         _ => texture.0,
     });
 
-    let quilt_image = if config.debug_mode.is_some() {
-        make_quilt(
-            quilt_settings,
-            &texture_to_use,
+    let watermark = config.watermark.as_ref().map(|w| w.load()).transpose()?;
+
+    let focus_depth = config
+        .focus_depth
+        .map_or(0.0, |focus| focus.resolve(&texture_to_use, &heightmap));
+
+    let (zoom, scale) = if config.auto_tune {
+        let tuned = auto_tune(
             &heightmap,
             config.fov,
-            config.zoom,
-            config.scale,
-            bg_color,
-            config.caption.clone(),
-            &debug_flags,
-        )
+            tile_width,
+            config.auto_tune_parallax,
+            focus_depth,
+        );
+        crate::status!(
+            "Auto-tuned scale {:.3}, zoom {:.3}",
+            tuned.scale,
+            tuned.zoom
+        );
+        (tuned.zoom, tuned.scale)
     } else {
-        make_quilt(
-            quilt_settings,
-            &texture_to_use,
-            &heightmap,
-            config.fov,
-            config.zoom,
-            config.scale,
-            bg_color,
-            config.caption.clone(),
-            &NullDebugFlags {},
-        )
+        (config.zoom, config.scale)
+    };
+
+    let zoom_center = config.zoom_center.map_or(IMAGE_CENTER, |center| {
+        center.resolve(&texture_to_use, &heightmap)
+    });
+
+    let options = RenderOptions {
+        fov_deg: config.fov,
+        view_spacing: config.view_spacing.clone(),
+        mirror_views: config.mirror_views,
+        zoom,
+        zoom_center,
+        scale,
+        focus_depth,
+        background,
+        matte,
+        max_parallax: config.max_parallax,
+        sampling: config.sampling,
+        splat_radius: config.splat_radius,
+        dither: config.dither,
+        easing: config.easing,
+        hole_fill: config.hole_fill,
+        tile_padding: config.tile_padding,
+        border_color,
+        back_layer,
+        panorama: config.panorama,
+        stabilize_views: config.stabilize_views,
+        postfx: config.postfx,
     };
 
     // Extract extension from output_base_name or default to png
@@ -190,42 +558,269 @@ pub fn generate_quilt(
         .and_then(|ext| ext.to_str())
         .unwrap_or("png");
 
-    let filename = format!(
-        "{}_qs{}x{}a{:.2}.{}",
+    let filename = QuiltName::new(quilt_settings, input_aspect_ratio).filename(
         output_base_name.trim_end_matches(&format!(".{}", extension)),
-        quilt_settings.columns,
-        quilt_settings.rows,
-        input_aspect_ratio,
-        extension
+        extension,
     );
 
-    quilt_image.save(&filename)?;
-    println!("Saved quilt image as: {}", filename);
+    let thumbnail = match config.preview {
+        Some(preview) => prepare_preview(
+            preview,
+            Path::new(&filename),
+            output_base_name.trim_end_matches(&format!(".{}", extension)),
+            quilt_settings,
+            &texture_to_use,
+            &heightmap,
+            &options,
+        )?,
+        None => None,
+    };
 
-    // Create symlink if requested
-    if config.symlink_output {
-        let link_name = output_base_name;
-        // Remove existing symlink if it exists
-        if std::path::Path::new(&link_name).exists() {
-            std::fs::remove_file(&link_name).unwrap_or_else(|e| {
-                eprintln!("Warning: Failed to remove existing symlink: {}", e);
+    let pending = if let Some(band_height) = config.band_height {
+        let path = std::path::Path::new(&filename);
+        let tiled = TiledOptions {
+            band_height,
+            caption: config.caption.clone(),
+            watermark: watermark.as_ref(),
+        };
+        if config.debug_mode.is_some() {
+            save_quilt_tiled(
+                path,
+                quilt_settings,
+                &texture_to_use,
+                &heightmap,
+                &options,
+                &tiled,
+                &debug_flags,
+            )?;
+        } else {
+            save_quilt_tiled(
+                path,
+                quilt_settings,
+                &texture_to_use,
+                &heightmap,
+                &options,
+                &tiled,
+                &NullDebugFlags {},
+            )?;
+        }
+        if let Some(thumbnail) = &thumbnail {
+            embed_exif_thumbnail(path, thumbnail)?;
+        }
+        crate::status!("Saved quilt image as: {}", filename);
+        None
+    } else {
+        // Debug renders aren't cached, since the debug flags change the views
+        let render_cache = config
+            .render_cache_dir
+            .as_ref()
+            .filter(|_| config.debug_mode.is_none())
+            .map(|dir| {
+                (
+                    RenderCache::new(dir),
+                    RenderParams::new(quilt_settings, &options)
+                        .cache_key(&texture_to_use, &heightmap),
+                )
             });
+        let cached = render_cache
+            .as_ref()
+            .and_then(|(cache, key)| cache.load(key));
+        if render_cache.is_some() {
+            if cached.is_some() {
+                METRICS.render_cache_hits.inc();
+            } else {
+                METRICS.render_cache_misses.inc();
+            }
         }
 
-        #[cfg(unix)]
-        std::os::unix::fs::symlink(&filename, &link_name).unwrap_or_else(|e| {
-            eprintln!("Warning: Failed to create symlink: {}", e);
-        });
+        let mut raw_quilt = if let Some(quilt) = cached {
+            crate::status!(
+                "Reusing cached render; redrawing post effects, caption and watermark only"
+            );
+            quilt
+        } else {
+            let quilt = if config.debug_mode.is_some() {
+                render_base_quilt(
+                    quilt_settings,
+                    &texture_to_use,
+                    &heightmap,
+                    &options,
+                    &debug_flags,
+                )
+            } else {
+                render_base_quilt(
+                    quilt_settings,
+                    &texture_to_use,
+                    &heightmap,
+                    &options,
+                    &NullDebugFlags {},
+                )
+            };
+            if let Some((cache, key)) = &render_cache {
+                if let Err(e) = cache.save(key, &quilt) {
+                    output::warn(format!("Failed to cache render: {}", e));
+                }
+            }
+            quilt
+        };
+        apply_postfx(&mut raw_quilt, quilt_settings, &options);
+        let quilt_image = composite_overlays(
+            raw_quilt,
+            quilt_settings,
+            config.caption.clone(),
+            watermark.as_ref(),
+        );
+
+        Some(PendingSave {
+            filename: filename.clone(),
+            image: quilt_image,
+            settings: *quilt_settings,
+            aspect: input_aspect_ratio,
+            thumbnail,
+        })
+    };
 
-        #[cfg(windows)]
-        std::os::windows::fs::symlink_file(&filename, &link_name).unwrap_or_else(|e| {
-            eprintln!("Warning: Failed to create symlink: {}", e);
-        });
+    if config.write_descriptor {
+        let descriptor_path = QuiltDescriptor::new(quilt_settings, input_aspect_ratio, config.fov)
+            .save_beside(std::path::Path::new(&filename))?;
+        crate::status!("Saved quilt descriptor as: {}", descriptor_path.display());
+    }
 
-        println!("Created symlink: {} -> {}", link_name, filename);
+    if let Some(strength) = config.export_normals {
+        export_normals(
+            &heightmap,
+            strength,
+            output_base_name.trim_end_matches(&format!(".{}", extension)),
+        )?;
     }
 
-    Ok(filename)
+    if config.export_leia {
+        let leia_filename = format!(
+            "{}_leia.jpg",
+            output_base_name.trim_end_matches(&format!(".{}", extension))
+        );
+        save_leia(
+            Path::new(&leia_filename),
+            &texture_to_use,
+            &heightmap,
+            &options,
+            &debug_flags,
+        )?;
+        crate::status!("Saved Leia 2x2 image as: {}", leia_filename);
+    }
+
+    if config.symlink_output {
+        link_output(
+            Path::new(&filename),
+            Path::new(&output_base_name),
+            config.link_mode,
+        );
+    }
+
+    Ok((filename, pending))
+}
+
+/// How `--link-output` makes the output name refer to the generated file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkMode {
+    #[default]
+    Symlink,
+    Hardlink,
+    /// For filesystems without links, such as SMB shares and FAT SD cards.
+    Copy,
+}
+
+/// `target` as a path relative to the directory `link` is in, so the link survives moving or
+/// remounting the directory. Falls back to `target` if either directory can't be resolved.
+fn relative_target(target: &Path, link: &Path) -> PathBuf {
+    let dir = |path: &Path| {
+        path.parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .canonicalize()
+    };
+    let (Ok(target_dir), Ok(link_dir), Some(file_name)) =
+        (dir(target), dir(link), target.file_name())
+    else {
+        return target.to_path_buf();
+    };
+    let common = target_dir
+        .components()
+        .zip(link_dir.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in link_dir.components().skip(common) {
+        relative.push("..");
+    }
+    relative.extend(target_dir.components().skip(common));
+    relative.push(file_name);
+    relative
+}
+
+/// Saves the normal map of `heightmap`, for `--export-normals`, as `<stem>_normals.png`.
+pub fn export_normals(
+    heightmap: &DepthImage,
+    strength: f32,
+    stem: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let normals_filename = format!("{}_normals.png", stem);
+    heightmap.to_normal_map(strength).save(&normals_filename)?;
+    crate::status!("Saved normal map as: {}", normals_filename);
+    Ok(())
+}
+
+/// Points `link` at the generated file `target`. An existing `link` is only replaced if it is a
+/// symlink, so a real file that happens to have the output name is never deleted. Failures are
+/// warnings: the quilt itself has already been written.
+pub fn link_output(target: &Path, link: &Path, mode: LinkMode) {
+    match std::fs::symlink_metadata(link) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            if let Err(e) = std::fs::remove_file(link) {
+                output::warn(format!("Failed to remove existing symlink: {}", e));
+                return;
+            }
+        }
+        Ok(_) => {
+            output::warn(format!(
+                "Not replacing {}: it exists and is not a symlink",
+                link.display()
+            ));
+            return;
+        }
+        Err(_) => {}
+    }
+
+    let result = match mode {
+        LinkMode::Symlink => {
+            let relative = relative_target(target, link);
+            #[cfg(unix)]
+            let result = std::os::unix::fs::symlink(&relative, link);
+            #[cfg(windows)]
+            let result = std::os::windows::fs::symlink_file(&relative, link);
+            result.map(|_| {
+                format!(
+                    "Created symlink: {} -> {}",
+                    link.display(),
+                    relative.display()
+                )
+            })
+        }
+        LinkMode::Hardlink => std::fs::hard_link(target, link).map(|_| {
+            format!(
+                "Created hardlink: {} -> {}",
+                link.display(),
+                target.display()
+            )
+        }),
+        LinkMode::Copy => std::fs::copy(target, link)
+            .map(|_| format!("Copied {} to {}", target.display(), link.display())),
+    };
+    match result {
+        Ok(message) => crate::status!("{}", message),
+        Err(e) => output::warn(format!("Failed to link {}: {}", link.display(), e)),
+    }
 }
 
 pub fn split_rgbd_image(img: ImageBuffer<Rgb<u8>, Vec<u8>>) -> (TextureImage, DepthImage) {
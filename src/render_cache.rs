@@ -0,0 +1,211 @@
+//! Cache of rendered quilts before post effects, captions and watermarks are applied.
+//!
+//! Re-running a batch with new post effects, caption or watermark would otherwise render every
+//! view again. The quilt from `render_base_quilt` is stored under a hash of the texture, the depth map and
+//! every setting that affects rendering, so a later run with the same inputs only redraws the
+//! overlays.
+
+use crate::background::Background;
+use crate::camera::{ViewSpacing, IMAGE_CENTER};
+use crate::dither::Dither;
+use crate::image_types::{DepthImage, TextureImage};
+use crate::panorama::Panorama;
+use crate::quilt::{Easing, HoleFill, QuiltSettings, RenderOptions, Sampling};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{ImageBuffer, ImageEncoder, Rgb};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// Bump when a renderer change alters its output, so stale renders aren't reused.
+const RENDER_CACHE_VERSION: u32 = 2;
+
+/// Everything besides the texture and depth map that decides what `render_base_quilt` produces.
+#[derive(Clone, Debug, Serialize)]
+pub struct RenderParams {
+    pub settings: QuiltSettings,
+    pub fov_deg: f32,
+    /// Left out of the key when linear, like `zoom_center`.
+    #[serde(skip_serializing_if = "is_linear")]
+    pub view_spacing: ViewSpacing,
+    #[serde(skip_serializing_if = "is_false")]
+    pub mirror_views: bool,
+    pub zoom: f32,
+    /// Left out of the key when centered, so renders cached before it existed stay valid.
+    #[serde(skip_serializing_if = "is_image_center")]
+    pub zoom_center: [f32; 2],
+    pub scale: f32,
+    /// Likewise left out at the default, the farthest depth.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub focus_depth: f32,
+    /// Keyed as `bg_color`, its name from before gradients, so solid backgrounds keep their keys.
+    #[serde(rename = "bg_color")]
+    pub background: Background,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matte: Option<[u8; 3]>,
+    pub max_parallax: Option<f32>,
+    pub sampling: Sampling,
+    pub splat_radius: u32,
+    /// Left out of the key when off, like `zoom_center`.
+    #[serde(skip_serializing_if = "is_no_dither")]
+    pub dither: Dither,
+    /// Left out of the key at the default, like `zoom_center`.
+    #[serde(skip_serializing_if = "is_default_easing")]
+    pub easing: Easing,
+    #[serde(skip_serializing_if = "is_blend")]
+    pub hole_fill: HoleFill,
+    #[serde(skip_serializing_if = "is_no_padding")]
+    pub tile_padding: u32,
+    #[serde(skip_serializing_if = "is_black")]
+    pub border_color: [u8; 3],
+    /// Hash of the back layer's images and its subject depth.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub back_layer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub panorama: Option<Panorama>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub stabilize_views: bool,
+}
+
+fn is_linear(view_spacing: &ViewSpacing) -> bool {
+    *view_spacing == ViewSpacing::Linear
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_image_center(zoom_center: &[f32; 2]) -> bool {
+    *zoom_center == IMAGE_CENTER
+}
+
+fn is_zero(value: &f32) -> bool {
+    *value == 0.0
+}
+
+fn is_no_dither(dither: &Dither) -> bool {
+    *dither == Dither::None
+}
+
+fn is_default_easing(easing: &Easing) -> bool {
+    *easing == Easing::default()
+}
+
+fn is_blend(hole_fill: &HoleFill) -> bool {
+    *hole_fill == HoleFill::Blend
+}
+
+fn is_no_padding(padding: &u32) -> bool {
+    *padding == 0
+}
+
+fn is_black(color: &[u8; 3]) -> bool {
+    *color == [0, 0, 0]
+}
+
+impl RenderParams {
+    pub fn new(settings: &QuiltSettings, options: &RenderOptions) -> Self {
+        Self {
+            settings: *settings,
+            fov_deg: options.fov_deg,
+            view_spacing: options.view_spacing.clone(),
+            mirror_views: options.mirror_views,
+            zoom: options.zoom,
+            zoom_center: options.zoom_center,
+            scale: options.scale,
+            focus_depth: options.focus_depth,
+            background: options.background,
+            matte: options.matte.map(|matte| matte.0),
+            max_parallax: options.max_parallax,
+            sampling: options.sampling,
+            splat_radius: options.splat_radius,
+            dither: options.dither,
+            easing: options.easing,
+            hole_fill: options.hole_fill,
+            tile_padding: options.tile_padding,
+            border_color: options.border_color.0,
+            back_layer: options.back_layer.as_ref().map(|layer| {
+                let mut hasher = Sha256::new();
+                hasher.update([layer.subject_depth]);
+                hash_images(&mut hasher, &layer.texture, &layer.heightmap);
+                format!("{:x}", hasher.finalize())
+            }),
+            panorama: options.panorama,
+            stabilize_views: options.stabilize_views,
+        }
+    }
+
+    pub fn cache_key(&self, texture: &TextureImage, heightmap: &DepthImage) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(RENDER_CACHE_VERSION.to_le_bytes());
+        hasher.update(serde_json::to_string(self).unwrap_or_default().as_bytes());
+        hash_images(&mut hasher, texture, heightmap);
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+fn hash_images(hasher: &mut Sha256, texture: &TextureImage, heightmap: &DepthImage) {
+    for image in [&texture.0, &heightmap.0] {
+        hasher.update(image.width().to_le_bytes());
+        hasher.update(image.height().to_le_bytes());
+        hasher.update(image.as_raw());
+    }
+}
+
+pub struct RenderCache {
+    dir: PathBuf,
+}
+
+impl RenderCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}_quilt.png", key))
+    }
+
+    /// The raw quilt stored under `key`, if there is a readable one.
+    pub fn load(&self, key: &str) -> Option<ImageBuffer<Rgb<u8>, Vec<u8>>> {
+        let path = self.path(key);
+        if !path.exists() {
+            return None;
+        }
+        match image::open(&path) {
+            Ok(quilt) => {
+                tracing::debug!("Loaded cached render: {}", path.display());
+                Some(quilt.to_rgb8())
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Ignoring unreadable cached render {}: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Stores a raw quilt under `key`. Saved with fast PNG compression, since it is only read
+    /// back by this cache.
+    pub fn save(
+        &self,
+        key: &str,
+        quilt: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(key);
+        let file = std::io::BufWriter::new(fs::File::create(&path)?);
+        PngEncoder::new_with_quality(file, CompressionType::Fast, FilterType::Sub).write_image(
+            quilt.as_raw(),
+            quilt.width(),
+            quilt.height(),
+            image::ColorType::Rgb8,
+        )?;
+        tracing::debug!("Saved render to cache: {}", path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+//! A rough estimate of where the subject of an image is, for `--zoom-center auto` and
+//! `--focus-depth auto`.
+//!
+//! There's no face detection here: the subject is taken to be the detailed content standing out
+//! in front of the rest of the scene, near the middle of the frame. The image is analyzed on a
+//! coarse grid, so this stays cheap next to rendering.
+
+use crate::image_types::{DepthImage, TextureImage};
+
+/// Cells across the longer side of the image.
+const GRID_SIZE: u32 = 32;
+
+/// Spread of the preference for content near the middle of the frame, as a fraction of the
+/// image size.
+const CENTER_BIAS_SIGMA: f32 = 0.35;
+
+/// Weight a featureless cell keeps relative to a detailed one, so smooth skin still counts.
+const BASE_DETAIL: f32 = 0.25;
+
+/// Fraction of the subject's weight above the point chosen as its center. Faces sit near the
+/// top of a person, so the center is pulled up from the middle of the subject.
+const SUBJECT_Y_FRACTION: f32 = 0.3;
+
+/// Per-cell subject weights over a coarse grid of the image.
+pub struct SaliencyMap {
+    columns: u32,
+    rows: u32,
+    /// Mean depth of each cell.
+    depths: Vec<f32>,
+    weights: Vec<f32>,
+}
+
+impl SaliencyMap {
+    pub fn new(texture: &TextureImage, heightmap: &DepthImage) -> Self {
+        let (width, height) = heightmap.dimensions();
+        let cell = (width.max(height) / GRID_SIZE).max(1);
+        let columns = width.div_ceil(cell).max(1);
+        let rows = height.div_ceil(cell).max(1);
+
+        // Mean depth, and mean and spread of luminance, per cell
+        let mut depth = vec![0.0f32; (columns * rows) as usize];
+        let mut detail = vec![0.0f32; (columns * rows) as usize];
+        for row in 0..rows {
+            for column in 0..columns {
+                let (x0, y0) = (column * cell, row * cell);
+                let (x1, y1) = ((x0 + cell).min(width), (y0 + cell).min(height));
+                let (mut d_sum, mut l_sum, mut l_sq, mut n) = (0.0, 0.0, 0.0, 0.0);
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        let luminance = texture
+                            .0
+                            .get_pixel(x.min(texture.width() - 1), y.min(texture.height() - 1))
+                            .0
+                            .iter()
+                            .zip([0.299, 0.587, 0.114])
+                            .map(|(c, w)| *c as f32 * w)
+                            .sum::<f32>();
+                        d_sum += heightmap.0.get_pixel(x, y)[0] as f32;
+                        l_sum += luminance;
+                        l_sq += luminance * luminance;
+                        n += 1.0;
+                    }
+                }
+                let i = (row * columns + column) as usize;
+                depth[i] = d_sum / n;
+                let mean = l_sum / n;
+                let spread = (l_sq / n - mean * mean).max(0.0).sqrt();
+                detail[i] = (spread / 64.0).min(1.0);
+            }
+        }
+
+        // Only content nearer than the typical depth can be the subject
+        let mut sorted = depth.clone();
+        sorted.sort_by(f32::total_cmp);
+        let median = sorted[sorted.len() / 2];
+        let nearest = sorted[sorted.len() - 1];
+        let range = (nearest - median).max(1.0);
+
+        let weights = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let i = (row * columns + column) as usize;
+                let nearness = ((depth[i] - median) / range).max(0.0);
+                let (x, y) = Self::cell_center(column, row, columns, rows);
+                let distance = (x - 0.5).powi(2) + (y - 0.5).powi(2);
+                let bias = (-distance / (2.0 * CENTER_BIAS_SIGMA.powi(2))).exp();
+                nearness * nearness * (BASE_DETAIL + detail[i]) * bias
+            })
+            .collect();
+
+        Self {
+            columns,
+            rows,
+            depths: depth,
+            weights,
+        }
+    }
+
+    /// Center of a cell, as fractions of the image width and height.
+    fn cell_center(column: u32, row: u32, columns: u32, rows: u32) -> (f32, f32) {
+        (
+            (column as f32 + 0.5) / columns as f32,
+            (row as f32 + 0.5) / rows as f32,
+        )
+    }
+
+    /// Where the subject is, as fractions of the image width and height: its weighted center
+    /// across, and towards its top vertically. `None` when nothing stands out from the
+    /// background, such as for a flat depth map.
+    pub fn subject_center(&self) -> Option<[f32; 2]> {
+        let total: f32 = self.weights.iter().sum();
+        if total <= f32::EPSILON {
+            return None;
+        }
+
+        let mut x = 0.0;
+        let mut row_weights = vec![0.0f32; self.rows as usize];
+        for (i, weight) in self.weights.iter().enumerate() {
+            let (column, row) = (i as u32 % self.columns, i as u32 / self.columns);
+            x += Self::cell_center(column, row, self.columns, self.rows).0 * weight;
+            row_weights[row as usize] += weight;
+        }
+
+        let mut above = 0.0;
+        let mut y = 0.5;
+        for (row, weight) in row_weights.iter().enumerate() {
+            if above + weight >= total * SUBJECT_Y_FRACTION {
+                // Interpolate within the row for a smooth result on coarse grids
+                let within = (total * SUBJECT_Y_FRACTION - above) / weight.max(f32::EPSILON);
+                y = (row as f32 + within) / self.rows as f32;
+                break;
+            }
+            above += weight;
+        }
+        Some([x / total, y])
+    }
+
+    /// The median depth of the subject, weighted like `subject_center`. `None` when nothing
+    /// stands out from the background.
+    pub fn subject_depth(&self) -> Option<f32> {
+        let total: f32 = self.weights.iter().sum();
+        if total <= f32::EPSILON {
+            return None;
+        }
+        let mut cells: Vec<(f32, f32)> = self
+            .depths
+            .iter()
+            .copied()
+            .zip(self.weights.iter().copied())
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect();
+        cells.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut seen = 0.0;
+        for (depth, weight) in &cells {
+            seen += weight;
+            if seen >= total / 2.0 {
+                return Some(*depth);
+            }
+        }
+        cells.last().map(|(depth, _)| *depth)
+    }
+}
@@ -0,0 +1,65 @@
+//! Background quilt saving for batch runs.
+//!
+//! Depth generation waits on the network while encoding a quilt keeps the CPU busy, so running
+//! them one after the other leaves each idle half the time. `SaveQueue` encodes and writes quilts
+//! on its own thread; the channel is bounded so rendering can't run far ahead of the disk and
+//! pile finished quilts up in memory.
+
+use crate::quilt_gen::PendingSave;
+use std::error::Error;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::JoinHandle;
+
+/// Outcome of one save, with the tag it was queued under.
+pub type SaveResult<T> = (T, Result<(), String>);
+
+pub struct SaveQueue<T> {
+    sender: Option<SyncSender<(T, PendingSave)>>,
+    results: Receiver<SaveResult<T>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> SaveQueue<T> {
+    /// Starts the saving thread. `capacity` quilts may wait while one is being written.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, jobs) = mpsc::sync_channel::<(T, PendingSave)>(capacity);
+        let (done, results) = mpsc::channel();
+        let worker = std::thread::spawn(move || {
+            for (tag, pending) in jobs {
+                let result = pending.save().map_err(|e| e.to_string());
+                if done.send((tag, result)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            sender: Some(sender),
+            results,
+            worker: Some(worker),
+        }
+    }
+
+    /// Queues a quilt, blocking while the queue is full.
+    pub fn push(&self, tag: T, pending: PendingSave) -> Result<(), Box<dyn Error>> {
+        self.sender
+            .as_ref()
+            .and_then(|sender| sender.send((tag, pending)).ok())
+            .ok_or_else(|| "quilt saving thread stopped".into())
+    }
+
+    /// Saves that have finished since the last call, without waiting.
+    pub fn completed(&self) -> Vec<SaveResult<T>> {
+        self.results.try_iter().collect()
+    }
+
+    /// Waits for every queued quilt to be written and returns the remaining results.
+    pub fn finish(mut self) -> Vec<SaveResult<T>> {
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                tracing::error!("Quilt saving thread panicked");
+            }
+        }
+        self.results.try_iter().collect()
+    }
+}
@@ -0,0 +1,200 @@
+//! Per-image parameter scripts for batch runs, behind the `scripting` feature.
+//!
+//! A script is a [Rhai](https://rhai.rs) file evaluated once per image. It sees these variables:
+//!
+//! * `filename`, `path` - the input file
+//! * `exif` - map of EXIF tag name to text, e.g. `exif["Model"]`
+//! * `width`, `height`, `aspect` - texture size
+//! * `brightness`, `contrast` - mean and standard deviation of the luminance, 0-1
+//! * `depth_mean` - mean depth, 0 (far) to 1 (near)
+//! * `depth_histogram` - 16 bins of depth, as fractions of the pixels
+//! * `fov`, `zoom`, `scale`, `caption` - the settings the image would otherwise get
+//!
+//! and returns a map with any of `fov`, `zoom`, `scale` and `caption` to override them, or
+//! nothing to keep them:
+//!
+//! ```rhai
+//! if aspect > 1.6 && depth_mean < 0.3 {
+//!     #{ scale: scale * 1.5, fov: 70 }
+//! }
+//! ```
+
+use crate::image_types::{DepthImage, TextureImage};
+use crate::metadata::read_exif_fields;
+use std::error::Error;
+use std::path::Path;
+
+pub const DEPTH_HISTOGRAM_BINS: usize = 16;
+
+/// What a script knows about an image.
+#[derive(Clone, Debug, Default)]
+pub struct ImageFacts {
+    pub path: String,
+    pub filename: String,
+    pub exif: Vec<(String, String)>,
+    pub width: u32,
+    pub height: u32,
+    pub brightness: f32,
+    pub contrast: f32,
+    pub depth_mean: f32,
+    pub depth_histogram: [f32; DEPTH_HISTOGRAM_BINS],
+}
+
+impl ImageFacts {
+    pub fn gather(path: &Path, texture: &TextureImage, depth: &DepthImage) -> Self {
+        let pixel_count = (texture.width() as u64 * texture.height() as u64).max(1) as f32;
+        let luminance: Vec<f32> = texture
+            .0
+            .pixels()
+            .map(|p| (0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32) / 255.0)
+            .collect();
+        let brightness = luminance.iter().sum::<f32>() / pixel_count;
+        let variance = luminance
+            .iter()
+            .map(|l| (l - brightness) * (l - brightness))
+            .sum::<f32>()
+            / pixel_count;
+
+        let depth_count = (depth.width() as u64 * depth.height() as u64).max(1) as f32;
+        let mut depth_histogram = [0.0; DEPTH_HISTOGRAM_BINS];
+        let mut depth_total = 0.0;
+        for p in depth.0.pixels() {
+            depth_histogram[p[0] as usize * DEPTH_HISTOGRAM_BINS / 256] += 1.0 / depth_count;
+            depth_total += p[0] as f32 / 255.0;
+        }
+
+        Self {
+            path: path.to_string_lossy().into_owned(),
+            filename: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            exif: read_exif_fields(path),
+            width: texture.width(),
+            height: texture.height(),
+            brightness,
+            contrast: variance.sqrt(),
+            depth_mean: depth_total / depth_count,
+            depth_histogram,
+        }
+    }
+}
+
+/// Settings a script may change, as given to it and as it returns them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScriptParameters {
+    pub fov: f32,
+    pub zoom: f32,
+    pub scale: f32,
+    pub caption: Option<String>,
+}
+
+#[cfg(feature = "scripting")]
+pub struct ParameterScript {
+    engine: rhai::Engine,
+    ast: rhai::AST,
+}
+
+#[cfg(feature = "scripting")]
+impl ParameterScript {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut engine = rhai::Engine::new();
+        // A runaway script should fail its image rather than hang the batch
+        engine.set_max_operations(10_000_000);
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Self { engine, ast })
+    }
+
+    /// Runs the script for one image and returns `current` with its overrides applied.
+    pub fn evaluate(
+        &self,
+        facts: &ImageFacts,
+        current: &ScriptParameters,
+    ) -> Result<ScriptParameters, Box<dyn Error>> {
+        use rhai::{Dynamic, Map, Scope};
+
+        let mut exif = Map::new();
+        // Primary image tags come first; keep them over thumbnail duplicates
+        for (name, value) in facts.exif.iter().rev() {
+            exif.insert(name.as_str().into(), value.clone().into());
+        }
+        let histogram: rhai::Array = facts
+            .depth_histogram
+            .iter()
+            .map(|&fraction| Dynamic::from_float(fraction as f64))
+            .collect();
+
+        let mut scope = Scope::new();
+        scope.push("path", facts.path.clone());
+        scope.push("filename", facts.filename.clone());
+        scope.push("exif", exif);
+        scope.push("width", facts.width as i64);
+        scope.push("height", facts.height as i64);
+        scope.push("aspect", facts.width as f64 / facts.height.max(1) as f64);
+        scope.push("brightness", facts.brightness as f64);
+        scope.push("contrast", facts.contrast as f64);
+        scope.push("depth_mean", facts.depth_mean as f64);
+        scope.push("depth_histogram", histogram);
+        scope.push("fov", current.fov as f64);
+        scope.push("zoom", current.zoom as f64);
+        scope.push("scale", current.scale as f64);
+        scope.push(
+            "caption",
+            current.caption.clone().map_or(Dynamic::UNIT, Dynamic::from),
+        );
+
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| format!("script failed for {}: {}", facts.filename, e))?;
+        if result.is_unit() {
+            return Ok(current.clone());
+        }
+        let overrides = result
+            .try_cast::<Map>()
+            .ok_or("script must return a map such as #{ fov: 50 } or nothing")?;
+
+        let mut parameters = current.clone();
+        for (key, value) in overrides {
+            match key.as_str() {
+                "fov" => parameters.fov = number(&key, &value)?,
+                "zoom" => parameters.zoom = number(&key, &value)?,
+                "scale" => parameters.scale = number(&key, &value)?,
+                "caption" if value.is_unit() => parameters.caption = None,
+                "caption" => parameters.caption = Some(value.to_string()),
+                _ => return Err(format!("script returned unknown setting '{}'", key).into()),
+            }
+        }
+        Ok(parameters)
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn number(key: &str, value: &rhai::Dynamic) -> Result<f32, Box<dyn Error>> {
+    value
+        .as_float()
+        .or_else(|_| value.as_int().map(|i| i as f64))
+        .map(|v| v as f32)
+        .map_err(|_| format!("script setting '{}' must be a number", key).into())
+}
+
+#[cfg(not(feature = "scripting"))]
+pub struct ParameterScript;
+
+#[cfg(not(feature = "scripting"))]
+impl ParameterScript {
+    pub fn load(_path: &Path) -> Result<Self, Box<dyn Error>> {
+        Err("--script requires building with the scripting feature".into())
+    }
+
+    pub fn evaluate(
+        &self,
+        _facts: &ImageFacts,
+        current: &ScriptParameters,
+    ) -> Result<ScriptParameters, Box<dyn Error>> {
+        Ok(current.clone())
+    }
+}
@@ -0,0 +1,208 @@
+//! HTTP serving of an output directory, for `quilt-serve`.
+//!
+//! A Looking Glass Go on the same network can play a playlist URL instead of copying files over
+//! USB. The playlist is rebuilt on every request, so quilts that batch_depth adds while the server
+//! runs show up on the next refresh. Files are served with byte ranges, since the device seeks
+//! within large quilts rather than downloading them whole.
+
+use crate::http::{self, percent_decode, read_request, serve_connections};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+
+/// Extensions listed in the playlist. Other files in the directory, such as descriptors, are
+/// served but not listed.
+const QUILT_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "ktx2"];
+
+pub struct QuiltDirectory {
+    dir: PathBuf,
+}
+
+impl QuiltDirectory {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The playlist batch_depth writes for this directory: `<dir>.m3u` beside it.
+    pub fn batch_playlist_path(&self) -> PathBuf {
+        let name = self.dir.file_name().unwrap_or_default().to_string_lossy();
+        let parent = self.dir.parent().unwrap_or(&self.dir);
+        parent.join(format!("{name}.m3u"))
+    }
+
+    /// File names of the quilts to play, in order. Follows batch_depth's playlist when there is
+    /// one, with any quilts it doesn't list yet appended; otherwise every quilt, oldest first.
+    pub fn entries(&self) -> io::Result<Vec<String>> {
+        let mut quilts = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let is_quilt = Path::new(&name)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+                .is_some_and(|ext| QUILT_EXTENSIONS.contains(&ext.as_str()));
+            if is_quilt && !name.starts_with('.') && entry.file_type()?.is_file() {
+                let modified = entry.metadata()?.modified()?;
+                quilts.push((modified, name));
+            }
+        }
+        quilts.sort();
+        let mut quilts: Vec<String> = quilts.into_iter().map(|(_, name)| name).collect();
+
+        let Ok(batch_playlist) = std::fs::read_to_string(self.batch_playlist_path()) else {
+            return Ok(quilts);
+        };
+        let mut entries = Vec::new();
+        for line in batch_playlist.lines() {
+            let name = Path::new(line.trim())
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+            if let Some(position) = name.and_then(|name| quilts.iter().position(|q| *q == name)) {
+                entries.push(quilts.remove(position));
+            }
+        }
+        entries.append(&mut quilts);
+        Ok(entries)
+    }
+
+    /// An m3u playlist of the quilts, as URLs under `base_url`.
+    pub fn playlist(&self, base_url: &str) -> io::Result<String> {
+        Ok(self
+            .entries()?
+            .iter()
+            .map(|name| {
+                format!(
+                    "{}/{}\n",
+                    base_url.trim_end_matches('/'),
+                    percent_encode(name)
+                )
+            })
+            .collect())
+    }
+
+    /// The file a request path names, if it is a plain file directly in the directory.
+    pub fn file(&self, request_path: &str) -> Option<PathBuf> {
+        let name = percent_decode(request_path.trim_start_matches('/'))?;
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return None;
+        }
+        let path = self.dir.join(name);
+        path.is_file().then_some(path)
+    }
+}
+
+fn percent_encode(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Parses a `Range` header for a file of `len` bytes into the first and last byte to send.
+/// Returns `None` for ranges that can't be satisfied. Only single ranges are supported; for a
+/// list, the first is used.
+pub fn parse_range(header: &str, len: u64) -> Option<(u64, u64)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    let spec = spec.split(',').next()?.trim();
+    let (start, end) = spec.split_once('-')?;
+    let last = len.checked_sub(1)?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => (len.saturating_sub(suffix.parse().ok()?), last),
+        (start, "") => (start.parse().ok()?, last),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(last)),
+    };
+    (start <= end && start < len).then_some((start, end))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "ktx2" => "image/ktx2",
+        "json" => "application/json",
+        "m3u" => "audio/x-mpegurl",
+        _ => "application/octet-stream",
+    }
+}
+
+fn write_head(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    len: u64,
+    extra: &str,
+) -> io::Result<()> {
+    let extra = format!("Accept-Ranges: bytes\r\nCache-Control: no-cache\r\n{extra}");
+    http::write_head(stream, status, content_type, len, &extra)
+}
+
+fn respond(stream: &mut TcpStream, dir: &QuiltDirectory, fallback_host: &str) -> io::Result<()> {
+    let request = read_request(stream, 0)?;
+    let head_only = request.method == "HEAD";
+    if request.method != "GET" && !head_only {
+        return write_head(stream, "405 Method Not Allowed", "text/plain", 0, "");
+    }
+
+    if request.path == "/" || request.path.ends_with(".m3u") {
+        let host = request.header("host").unwrap_or(fallback_host);
+        let playlist = dir.playlist(&format!("http://{host}"))?;
+        write_head(
+            stream,
+            "200 OK",
+            content_type(Path::new("playlist.m3u")),
+            playlist.len() as u64,
+            "",
+        )?;
+        if !head_only {
+            stream.write_all(playlist.as_bytes())?;
+        }
+        return Ok(());
+    }
+
+    let Some(path) = dir.file(&request.path) else {
+        return write_head(stream, "404 Not Found", "text/plain", 0, "");
+    };
+    let mut file = File::open(&path)?;
+    let len = file.metadata()?.len();
+    let (status, start, end, extra) = match request.header("range") {
+        None => ("200 OK", 0, len.saturating_sub(1), String::new()),
+        Some(range) => match parse_range(range, len) {
+            Some((start, end)) => (
+                "206 Partial Content",
+                start,
+                end,
+                format!("Content-Range: bytes {start}-{end}/{len}\r\n"),
+            ),
+            None => {
+                let extra = format!("Content-Range: bytes */{len}\r\n");
+                return write_head(stream, "416 Range Not Satisfiable", "text/plain", 0, &extra);
+            }
+        },
+    };
+    let body_len = if len == 0 { 0 } else { end - start + 1 };
+    write_head(stream, status, content_type(&path), body_len, &extra)?;
+    if !head_only {
+        file.seek(SeekFrom::Start(start))?;
+        io::copy(&mut file.take(body_len), stream)?;
+    }
+    Ok(())
+}
+
+/// Answers requests on `listener` until the process exits, one thread per connection.
+/// `/` and any `*.m3u` path return the playlist; other paths name files in the directory.
+pub fn serve(listener: TcpListener, dir: QuiltDirectory) -> io::Result<()> {
+    let fallback_host = listener.local_addr()?.to_string();
+    serve_connections(listener, move |stream| {
+        Ok(respond(stream, &dir, &fallback_host)?)
+    });
+    Ok(())
+}
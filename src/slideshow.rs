@@ -0,0 +1,217 @@
+//! Slideshows of finished quilts: each quilt is held for a while, then replaced by the next with a
+//! cross-fade or a depth push, frame by frame into a `FrameSink`.
+
+use crate::captions::CaptionConfig;
+use crate::quilt::{QuiltName, QuiltSettings};
+use crate::serve::QuiltDirectory;
+use crate::subtitles::Subtitles;
+use crate::video::{FrameSink, VideoPreset};
+use image::{ImageBuffer, Rgb};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// How one quilt gives way to the next.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transition {
+    #[default]
+    Crossfade,
+    /// The outgoing quilt sinks back into the display as it fades, and the incoming one arrives
+    /// from in front of it.
+    DepthPush,
+}
+
+/// Disparity between the outermost views at the deepest point of a depth push, as a fraction of
+/// the view width.
+const PUSH_DISPARITY: f32 = 0.1;
+
+/// How a slideshow is timed, in seconds, captioned and encoded.
+#[derive(Debug, Clone)]
+pub struct Slideshow {
+    pub transition: Transition,
+    /// How long each quilt shows on its own.
+    pub hold: f32,
+    /// How long each transition takes.
+    pub transition_time: f32,
+    pub fps: f32,
+    pub video_preset: Option<VideoPreset>,
+    /// Timed captions, drawn as `caption` lays them out.
+    pub subtitles: Subtitles,
+    pub caption: CaptionConfig,
+}
+
+/// The quilts a playlist lists, in order. Entries are found as written, beside the playlist, or
+/// in the output directory batch_depth writes `<dir>.m3u` for.
+pub fn read_playlist(path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let parent = path.parent().unwrap_or(Path::new(""));
+    let dir = parent.join(path.file_stem().unwrap_or_default());
+    let mut quilts = Vec::new();
+    for line in std::fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = Path::new(line);
+        let candidates = [
+            entry.to_path_buf(),
+            parent.join(entry),
+            dir.join(entry.file_name().unwrap_or_default()),
+        ];
+        match candidates.into_iter().find(|candidate| candidate.is_file()) {
+            Some(quilt) => quilts.push(quilt),
+            None => crate::output::warn(format!("{} not found; skipping it", line)),
+        }
+    }
+    Ok(quilts)
+}
+
+/// The quilts of a playlist, or of a directory in its playlist order.
+pub fn slideshow_quilts(input: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if input.is_dir() {
+        let entries = QuiltDirectory::new(input).entries()?;
+        Ok(entries.into_iter().map(|name| input.join(name)).collect())
+    } else {
+        read_playlist(input)
+    }
+}
+
+/// Each view of `quilt` moved sideways in proportion to its distance from the middle view, which
+/// moves the whole picture in depth: behind the display for positive `disparity`, in front for
+/// negative. `disparity` is the total shift between the outermost views, in pixels.
+fn push_views(
+    quilt: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    settings: &QuiltSettings,
+    disparity: f32,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let view_width = settings.resolution.0 / settings.columns;
+    let view_height = settings.resolution.1 / settings.rows;
+    let views = settings.columns * settings.rows;
+    let middle = (views - 1) as f32 / 2.0;
+    let step = disparity / (views - 1).max(1) as f32;
+    ImageBuffer::from_fn(quilt.width(), quilt.height(), |x, y| {
+        let (column, row) = (x / view_width, y / view_height);
+        if column >= settings.columns || row >= settings.rows {
+            return *quilt.get_pixel(x, y);
+        }
+        // Devices number views from the bottom left, leftmost viewpoint first
+        let view = (settings.rows - 1 - row) * settings.columns + column;
+        let shift = ((view as f32 - middle) * step).round() as i64;
+        let source = (x % view_width) as i64 - shift;
+        if (0..view_width as i64).contains(&source) {
+            *quilt.get_pixel(column * view_width + source as u32, y)
+        } else {
+            Rgb([0, 0, 0])
+        }
+    })
+}
+
+/// `from` and `to` mixed with weight `t` on `to`.
+fn blend(
+    from: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    to: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    t: f32,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let mut mixed = from.clone();
+    mixed
+        .par_iter_mut()
+        .zip(to.as_raw().par_iter())
+        .for_each(|(a, &b)| *a = (*a as f32 * (1.0 - t) + b as f32 * t).round() as u8);
+    mixed
+}
+
+/// The frame `t` of the way (0 to 1) through the transition from `from` to `to`.
+pub fn transition_frame(
+    transition: Transition,
+    from: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    to: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    settings: &QuiltSettings,
+    t: f32,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let t = t * t * (3.0 - 2.0 * t);
+    match transition {
+        Transition::Crossfade => blend(from, to, t),
+        Transition::DepthPush => {
+            let view_width = settings.resolution.0 / settings.columns;
+            let disparity = PUSH_DISPARITY * view_width as f32;
+            blend(
+                &push_views(from, settings, t * disparity),
+                &push_views(to, settings, -(1.0 - t) * disparity),
+                t,
+            )
+        }
+    }
+}
+
+/// Loads a quilt, checking it has the layout and size of the first.
+fn load_quilt(
+    path: &Path,
+    settings: &QuiltSettings,
+) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, Box<dyn Error>> {
+    let quilt = image::open(path)?.to_rgb8();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let layout = QuiltSettings::from_filename(&name, quilt.dimensions());
+    let matches = layout.is_some_and(|layout| {
+        (layout.columns, layout.rows, layout.resolution)
+            == (settings.columns, settings.rows, settings.resolution)
+    });
+    if !matches {
+        return Err(format!(
+            "{} is not a {}x{} quilt of {}x{} like the first",
+            path.display(),
+            settings.columns,
+            settings.rows,
+            settings.resolution.0,
+            settings.resolution.1
+        )
+        .into());
+    }
+    Ok(quilt)
+}
+
+impl Slideshow {
+    /// Writes the slideshow of `quilts` to `output`, a video or numbered quilt name as for
+    /// `FrameSink`, and returns the name written. The quilts need the same layout and size.
+    pub fn render(&self, quilts: &[PathBuf], output: &str) -> Result<String, Box<dyn Error>> {
+        let first = quilts.first().ok_or("no quilts to show")?;
+        let first_name = first.file_name().unwrap_or_default().to_string_lossy();
+        let (width, height) = image::image_dimensions(first)?;
+        let settings = QuiltSettings::from_filename(&first_name, (width, height))
+            .ok_or_else(|| format!("{} has no quilt layout in its name", first.display()))?;
+        let aspect = QuiltName::parse(&first_name)
+            .and_then(|name| name.aspect)
+            .unwrap_or((width / settings.columns) as f32 / (height / settings.rows) as f32);
+
+        let mut sink = FrameSink::new(output, &settings, aspect, self.fps, self.video_preset)?;
+        let hold_frames = (self.hold * self.fps).round() as usize;
+        let transition_frames = (self.transition_time * self.fps).round() as usize;
+        let mut frames = 0;
+        let mut push = |quilt: &ImageBuffer<Rgb<u8>, Vec<u8>>| -> Result<(), Box<dyn Error>> {
+            let time = frames as f32 / self.fps;
+            frames += 1;
+            sink.push(&self.subtitles.draw(quilt, &settings, &self.caption, time))
+        };
+        let mut current = load_quilt(first, &settings)?;
+        for (index, next) in quilts.iter().skip(1).map(Some).chain([None]).enumerate() {
+            crate::status!("Showing {} of {}", index + 1, quilts.len());
+            for _ in 0..hold_frames.max(1) {
+                push(&current)?;
+            }
+            let Some(next) = next else { break };
+            let next = load_quilt(next, &settings)?;
+            for frame in 1..=transition_frames {
+                let t = frame as f32 / (transition_frames + 1) as f32;
+                push(&transition_frame(
+                    self.transition,
+                    &current,
+                    &next,
+                    &settings,
+                    t,
+                ))?;
+            }
+            current = next;
+        }
+        sink.finish()
+    }
+}
@@ -0,0 +1,122 @@
+//! Timed captions for quilt videos, read from SubRip (`.srt`) files and drawn with the caption
+//! renderer onto the frames they cover.
+
+use crate::captions::CaptionConfig;
+use crate::quilt::{composite_overlays, QuiltSettings};
+use image::{ImageBuffer, Rgb};
+use std::borrow::Cow;
+use std::error::Error;
+use std::path::Path;
+
+/// One numbered SRT entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subtitle {
+    /// Seconds from the start of the video.
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+}
+
+/// Parses an SRT timestamp, `HH:MM:SS,mmm`, into seconds. A `.` before the milliseconds is
+/// accepted too.
+fn parse_timestamp(s: &str) -> Option<f32> {
+    let (clock, millis) = s.trim().split_once([',', '.'])?;
+    let mut parts = clock.split(':').map(|part| part.parse::<u32>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+    let millis: u32 = millis.parse().ok()?;
+    Some((hours * 3600 + minutes * 60 + seconds) as f32 + millis as f32 / 1000.0)
+}
+
+/// Drops the markup SRT files commonly carry, `<i>`-style tags and `{\an8}`-style overrides,
+/// which the caption renderer would otherwise draw as text.
+fn strip_markup(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut closing = None;
+    for c in line.chars() {
+        match (closing, c) {
+            (None, '<') => closing = Some('>'),
+            (None, '{') => closing = Some('}'),
+            (Some(end), c) if c == end => closing = None,
+            (None, c) => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Subtitles in the order the file gives them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Subtitles(Vec<Subtitle>);
+
+impl Subtitles {
+    /// Parses SRT text: blank-line separated entries of an index line, a
+    /// `start --> end` line and one or more lines of text.
+    pub fn parse(srt: &str) -> Result<Self, String> {
+        let srt = srt.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+        let mut subtitles = Vec::new();
+        for entry in srt.split("\n\n").map(str::trim).filter(|e| !e.is_empty()) {
+            let mut lines = entry.lines();
+            let mut timing = lines.next().unwrap_or_default();
+            if !timing.contains("-->") {
+                // The index line
+                timing = lines.next().unwrap_or(timing);
+            }
+            let invalid = || format!("invalid subtitle timing '{}'", timing);
+            let (start, end) = timing.split_once("-->").ok_or_else(invalid)?;
+            // Players ignore position hints after the end time, e.g. `X1:100 X2:600`
+            let end = end.split_whitespace().next().unwrap_or_default();
+            let start = parse_timestamp(start).ok_or_else(invalid)?;
+            let end = parse_timestamp(end).ok_or_else(invalid)?;
+            let text: Vec<String> = lines.map(strip_markup).collect();
+            subtitles.push(Subtitle {
+                start,
+                end,
+                text: text.join("\n"),
+            });
+        }
+        Ok(Self(subtitles))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let srt = std::fs::read_to_string(path)?;
+        Self::parse(&srt).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    /// The text showing at `time`, with overlapping subtitles stacked in file order.
+    pub fn at(&self, time: f32) -> Option<String> {
+        let showing: Vec<&str> = self
+            .0
+            .iter()
+            .filter(|subtitle| (subtitle.start..subtitle.end).contains(&time))
+            .map(|subtitle| subtitle.text.as_str())
+            .collect();
+        (!showing.is_empty()).then(|| showing.join("\n"))
+    }
+
+    /// Draws the subtitle showing at `time` onto a frame of `settings`, laid out and placed by
+    /// `caption`. Frames with no subtitle showing are returned as they are.
+    pub fn draw<'a>(
+        &self,
+        frame: &'a ImageBuffer<Rgb<u8>, Vec<u8>>,
+        settings: &QuiltSettings,
+        caption: &CaptionConfig,
+        time: f32,
+    ) -> Cow<'a, ImageBuffer<Rgb<u8>, Vec<u8>>> {
+        match self.at(time) {
+            Some(text) => Cow::Owned(composite_overlays(
+                frame.clone(),
+                settings,
+                CaptionConfig {
+                    text: Some(text),
+                    captions: Vec::new(),
+                    ..caption.clone()
+                },
+                None,
+            )),
+            None => Cow::Borrowed(frame),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::image_types::{DepthImage, TextureImage};
+use image::{ImageBuffer, Rgb};
+
+/// Smooths depth maps across consecutive frames so quilts rendered from a sequence don't
+/// shimmer.
+///
+/// Each frame's depth is blended with the running average by exponential moving average. Instead
+/// of optical flow, the blend is gated per pixel by how much the texture changed: where the color
+/// moved by more than `motion_threshold` the new depth is taken as is, so moving objects don't
+/// leave depth trails. A frame whose mean color change exceeds `scene_cut_threshold`, or whose
+/// size differs from the previous one, restarts the average.
+pub struct TemporalDepthFilter {
+    /// Weight of the new frame, 0.0 (frozen) to 1.0 (no smoothing).
+    pub alpha: f32,
+    /// Per-pixel color difference (0-255, max over channels) treated as motion.
+    pub motion_threshold: u8,
+    /// Mean per-pixel color difference (0-255) treated as a scene cut.
+    pub scene_cut_threshold: f32,
+    previous: Option<PreviousFrame>,
+}
+
+/// What the next frame is blended with.
+struct PreviousFrame {
+    /// The last frame's texture, to detect motion and scene cuts against.
+    texture: TextureImage,
+    /// The running average of the depth, unrounded.
+    average: ImageBuffer<Rgb<f32>, Vec<f32>>,
+}
+
+impl TemporalDepthFilter {
+    pub fn new(alpha: f32) -> Self {
+        Self {
+            alpha: alpha.clamp(0.0, 1.0),
+            motion_threshold: 24,
+            scene_cut_threshold: 40.0,
+            previous: None,
+        }
+    }
+
+    /// Forgets the running average, e.g. between unrelated sequences.
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+
+    /// Returns the smoothed depth for the next frame and updates the running average.
+    pub fn filter(&mut self, texture: &TextureImage, depth: DepthImage) -> DepthImage {
+        let current = ImageBuffer::from_fn(depth.width(), depth.height(), |x, y| {
+            let p = depth.0.get_pixel(x, y);
+            Rgb([p[0] as f32, p[1] as f32, p[2] as f32])
+        });
+
+        let average = match self.previous.take() {
+            Some(PreviousFrame {
+                texture: previous_texture,
+                average: previous_average,
+            }) if previous_average.dimensions() == current.dimensions()
+                && previous_texture.dimensions() == texture.dimensions()
+                && texture.dimensions() == depth.dimensions()
+                && mean_difference(&previous_texture, texture) <= self.scene_cut_threshold =>
+            {
+                ImageBuffer::from_fn(depth.width(), depth.height(), |x, y| {
+                    let moved = max_channel_difference(
+                        previous_texture.0.get_pixel(x, y),
+                        texture.0.get_pixel(x, y),
+                    ) > self.motion_threshold;
+                    let alpha = if moved { 1.0 } else { self.alpha };
+                    let (old, new) = (previous_average.get_pixel(x, y), current.get_pixel(x, y));
+                    Rgb([0, 1, 2].map(|c| old[c] + (new[c] - old[c]) * alpha))
+                })
+            }
+            _ => current,
+        };
+
+        let smoothed = DepthImage(ImageBuffer::from_fn(
+            depth.width(),
+            depth.height(),
+            |x, y| {
+                let p = average.get_pixel(x, y);
+                Rgb([0, 1, 2].map(|c| p[c].round().clamp(0.0, 255.0) as u8))
+            },
+        ));
+        self.previous = Some(PreviousFrame {
+            texture: texture.clone(),
+            average,
+        });
+        smoothed
+    }
+}
+
+fn max_channel_difference(a: &Rgb<u8>, b: &Rgb<u8>) -> u8 {
+    (0..3).map(|c| a[c].abs_diff(b[c])).max().unwrap_or(0)
+}
+
+fn mean_difference(a: &TextureImage, b: &TextureImage) -> f32 {
+    let total: u64 =
+        a.0.pixels()
+            .zip(b.0.pixels())
+            .map(|(a, b)| max_channel_difference(a, b) as u64)
+            .sum();
+    total as f32 / (a.width() as u64 * a.height() as u64).max(1) as f32
+}
@@ -0,0 +1,248 @@
+//! Band-by-band quilt rendering for outputs too large to hold in memory.
+//!
+//! `render_quilt` keeps every view and the stitched quilt in memory at once, which for an 8192x8192
+//! quilt is several hundred megabytes. `save_quilt_tiled` instead renders the quilt top to bottom
+//! in horizontal bands, each the same rows of every view in one row of tiles, and streams each band
+//! to the PNG or JPEG encoder. Encoding runs on its own thread, so a band is compressed while the
+//! next one renders.
+
+use crate::{
+    captions::{draw_caption, view_position, CaptionConfig, CaptionTarget},
+    debug::DebugFlags,
+    encode::png_encoder,
+    image_types::{DepthSource, RgbImage, TextureSource},
+    quilt::{render_view_rows, tile_origin, QuiltSettings, RenderOptions},
+    watermark::Watermark,
+};
+use image::ImageBuffer;
+use rayon::prelude::*;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+enum BandWriter {
+    Png(Box<mtpng::encoder::Encoder<'static, BufWriter<File>>>),
+    Jpeg(Box<mozjpeg::compress::CompressStarted<BufWriter<File>>>),
+}
+
+impl BandWriter {
+    fn create(path: &Path, width: u32, height: u32) -> Result<Self, Box<dyn Error>> {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let file = BufWriter::new(File::create(path)?);
+        match extension.as_str() {
+            "png" => Ok(BandWriter::Png(Box::new(png_encoder(
+                file, width, height, true,
+            )?))),
+            "jpg" | "jpeg" => {
+                let mut compress = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+                compress.set_size(width as usize, height as usize);
+                compress.set_quality(100.0);
+                Ok(BandWriter::Jpeg(Box::new(compress.start_compress(file)?)))
+            }
+            _ => Err(format!(
+                "banded rendering writes PNG or JPEG, not '{}'",
+                path.display()
+            )
+            .into()),
+        }
+    }
+
+    /// Appends whole RGB8 rows.
+    fn write_rows(&mut self, rows: &[u8]) -> Result<(), Box<dyn Error>> {
+        match self {
+            BandWriter::Png(encoder) => encoder.write_image_rows(rows)?,
+            BandWriter::Jpeg(compress) => compress.write_scanlines(rows)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            BandWriter::Png(encoder) => encoder.finish()?.flush()?,
+            BandWriter::Jpeg(compress) => compress.finish()?.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// How `save_quilt_tiled` splits the quilt into bands, and the overlays it draws on each view.
+pub struct TiledOptions<'a> {
+    /// View rows rendered per band.
+    pub band_height: u32,
+    pub caption: CaptionConfig,
+    pub watermark: Option<&'a Watermark>,
+}
+
+/// Renders the quilt in bands of `tiled.band_height` view rows and writes it to `path` (PNG or JPEG) as
+/// it goes. The result matches `render_quilt`.
+///
+/// Captions and watermarks on every view need whole views, so with either of those the bands are
+/// one tile high. A caption or watermark drawn once on the whole quilt is not supported, nor is
+/// `stabilize_views`, which matches whole views to each other.
+pub fn save_quilt_tiled<T: TextureSource, H: DepthSource, D: DebugFlags>(
+    path: &Path,
+    settings: &QuiltSettings,
+    texture: &T,
+    heightmap: &H,
+    options: &RenderOptions,
+    tiled: &TiledOptions,
+    debug_flags: &D,
+) -> Result<(), Box<dyn Error>> {
+    let TiledOptions {
+        band_height,
+        ref caption,
+        watermark,
+    } = *tiled;
+    let has_caption = !caption.is_empty();
+    if (has_caption && caption.target == CaptionTarget::Quilt)
+        || watermark.is_some_and(|w| w.config.once)
+    {
+        return Err(
+            "banded rendering can't draw a caption or watermark once on the whole quilt".into(),
+        );
+    }
+    if options.stabilize_views {
+        return Err("banded rendering can't stabilize views, which needs every view whole".into());
+    }
+
+    let (columns, rows) = (settings.columns, settings.rows);
+    let num_views = columns * rows;
+    let view_width = settings.resolution.0 / columns;
+    let view_height = settings.resolution.1 / rows;
+    // The rendered part of each tile, inside the padding
+    let (inner_width, inner_height) = options.view_size(settings);
+    let padding = options.tile_padding;
+    let per_view_overlay = has_caption || watermark.is_some();
+    let band_height = if per_view_overlay {
+        view_height
+    } else {
+        band_height.clamp(1, view_height)
+    };
+    // Extra rows rendered around each band so wide splats from just outside it still land, and
+    // post effects see the same neighborhood as in a whole view
+    let margin = options.splat_radius.saturating_sub(1) + options.postfx.margin();
+    let opts = options.view_options();
+
+    let quilt_width = view_width * columns;
+    let quilt_height = view_height * rows;
+    let row_bytes = quilt_width as usize * 3;
+
+    std::thread::scope(|scope| {
+        // The JPEG encoder can't move between threads, so both are created on the encoding one
+        let (sender, receiver) = std::sync::mpsc::sync_channel::<Vec<u8>>(1);
+        let encode_span = tracing::info_span!("encode", file = %path.display());
+        let encoder = scope.spawn(move || -> Result<(), String> {
+            let _span = encode_span.entered();
+            let write = || -> Result<(), Box<dyn Error>> {
+                let mut writer = BandWriter::create(path, quilt_width, quilt_height)?;
+                for rows in receiver {
+                    writer.write_rows(&rows)?;
+                }
+                writer.finish()
+            };
+            write().map_err(|e| e.to_string())
+        });
+
+        let _span = tracing::info_span!("render", views = num_views).entered();
+        let parent = tracing::Span::current();
+        'bands: for tile_row in 0..rows {
+            for band_start in (0..view_height).step_by(band_height as usize) {
+                let band_end = (band_start + band_height).min(view_height);
+                // Rows of the rendered view within the band
+                let inner_start = band_start.saturating_sub(padding).min(inner_height);
+                let inner_end = band_end.saturating_sub(padding).min(inner_height);
+                let render_start = inner_start.saturating_sub(margin);
+                let render_end = (inner_end + margin).min(inner_height);
+
+                let views: Vec<(u32, RgbImage)> = (0..columns)
+                    .into_par_iter()
+                    .map(|column| {
+                        let i = tile_row * columns + column;
+                        let _span = tracing::debug_span!(
+                            parent: &parent,
+                            "render_view",
+                            view = i,
+                            rows = ?(band_start..band_end)
+                        )
+                        .entered();
+                        let band_rows = band_end - band_start;
+                        let mut band = if inner_start == inner_end {
+                            // All padding
+                            ImageBuffer::from_pixel(view_width, band_rows, options.border_color)
+                        } else {
+                            let camera = options.camera(settings, i);
+                            let mut rendered = render_view_rows(
+                                texture,
+                                heightmap,
+                                camera,
+                                camera.rotation(),
+                                &opts,
+                                render_start..render_end,
+                                debug_flags,
+                            );
+                            options.postfx.apply(
+                                &mut rendered,
+                                i,
+                                render_start,
+                                inner_height,
+                                &options.background,
+                            );
+                            let inner = image::imageops::crop_imm(
+                                &rendered,
+                                0,
+                                inner_start - render_start,
+                                inner_width,
+                                inner_end - inner_start,
+                            )
+                            .to_image();
+                            if padding == 0 {
+                                inner
+                            } else {
+                                let mut band = ImageBuffer::from_pixel(
+                                    view_width,
+                                    band_rows,
+                                    options.border_color,
+                                );
+                                let y = (inner_start + padding) as i64 - band_start as i64;
+                                image::imageops::replace(&mut band, &inner, padding as i64, y);
+                                band
+                            }
+                        };
+                        // Bands are whole views when there is an overlay
+                        if has_caption {
+                            band = draw_caption(band, caption.clone(), view_position(i, num_views));
+                        }
+                        if let Some(watermark) = watermark {
+                            watermark.draw(&mut band);
+                        }
+                        (i, band)
+                    })
+                    .collect();
+
+                let mut rows_buffer = vec![0u8; row_bytes * (band_end - band_start) as usize];
+                for (i, band) in &views {
+                    let x_start = tile_origin(*i, columns, view_width, view_height).0 as usize;
+                    for (y, row) in band.as_raw().chunks(view_width as usize * 3).enumerate() {
+                        let offset = y * row_bytes + x_start * 3;
+                        rows_buffer[offset..offset + row.len()].copy_from_slice(row);
+                    }
+                }
+                if sender.send(rows_buffer).is_err() {
+                    // The encoder failed; its error is returned below
+                    break 'bands;
+                }
+            }
+            tracing::debug!("Rendered tile row {} of {}", tile_row + 1, rows);
+        }
+
+        drop(sender);
+        encoder
+            .join()
+            .map_err(|_| "quilt encoder thread panicked")?
+            .map_err(|e| e.into())
+    })
+}
@@ -0,0 +1,47 @@
+//! Text-only title cards, e.g. to open a playlist. The caption is drawn onto a plain or gradient
+//! backdrop for the texture and, in the gray of its depth, onto a flat depth map, so the renderer
+//! lifts the lettering off the backdrop like any other scene.
+
+use crate::background::Background;
+use crate::captions::{draw_caption, CaptionConfig, CaptionSpec};
+use crate::image_types::{DepthImage, TextureImage};
+use image::{ImageBuffer, Rgb};
+
+/// A `width` x `height` card showing `caption` over `background`. The backdrop sits at depth 0,
+/// the farthest, and the lettering at `depth`.
+pub fn title_card(
+    caption: &CaptionConfig,
+    background: &Background,
+    depth: u8,
+    width: u32,
+    height: u32,
+) -> (TextureImage, DepthImage) {
+    let texture = draw_caption(
+        background.fill(width, height, 0..height),
+        caption.clone(),
+        0.0,
+    );
+    // The same glyphs without the box, outline and shadow, which belong to the backdrop
+    let gray = Some([depth, depth, depth, 255]);
+    let lettering = CaptionConfig {
+        color: gray,
+        background: None,
+        outline: 0,
+        shadow: false,
+        captions: caption
+            .captions
+            .iter()
+            .map(|spec| CaptionSpec {
+                color: gray,
+                ..spec.clone()
+            })
+            .collect(),
+        ..caption.clone()
+    };
+    let heightmap = draw_caption(
+        ImageBuffer::from_pixel(width, height, Rgb([0, 0, 0])),
+        lettering,
+        0.0,
+    );
+    (TextureImage(texture), DepthImage(heightmap))
+}
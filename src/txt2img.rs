@@ -0,0 +1,92 @@
+use crate::depth_gen::{run_workflow, DepthConfig};
+use serde_json::Value;
+use std::error::Error;
+use std::path::PathBuf;
+
+pub struct Txt2ImgConfig {
+    pub prompt: String,
+    pub negative_prompt: Option<String>,
+    pub seed: u64,
+    pub checkpoint: Option<String>,
+    /// ComfyUI API-format workflow to use instead of the bundled one.
+    pub workflow: Option<PathBuf>,
+}
+
+/// Follows a `[node_id, output_index]` link from `node`'s input `name`.
+fn linked_node_id(node: &Value, name: &str) -> Option<String> {
+    node["inputs"][name][0].as_str().map(str::to_string)
+}
+
+/// Fills the prompt, seed and checkpoint into a txt2img workflow. The prompt nodes are found by
+/// following the KSampler's `positive` and `negative` links, so custom workflows work as long as
+/// they use a single sampler.
+pub(crate) fn apply_txt2img_config(
+    workflow: &mut Value,
+    config: &Txt2ImgConfig,
+) -> Result<(), Box<dyn Error>> {
+    let nodes = workflow
+        .as_object_mut()
+        .ok_or("workflow is not a JSON object")?;
+
+    let sampler_id = nodes
+        .iter()
+        .find(|(_, node)| {
+            node["class_type"]
+                .as_str()
+                .is_some_and(|class| class.starts_with("KSampler"))
+        })
+        .map(|(id, _)| id.clone())
+        .ok_or("Could not find a KSampler node in workflow")?;
+    let sampler = &nodes[&sampler_id];
+    let positive_id =
+        linked_node_id(sampler, "positive").ok_or("KSampler has no positive prompt input")?;
+    let negative_id = linked_node_id(sampler, "negative");
+
+    if let Some(sampler) = nodes.get_mut(&sampler_id) {
+        if sampler["inputs"].get("noise_seed").is_some() {
+            sampler["inputs"]["noise_seed"] = config.seed.into();
+        } else {
+            sampler["inputs"]["seed"] = config.seed.into();
+        }
+    }
+
+    nodes
+        .get_mut(&positive_id)
+        .ok_or("KSampler positive prompt node is missing")?["inputs"]["text"] =
+        Value::String(config.prompt.clone());
+
+    if let (Some(negative_id), Some(negative_prompt)) = (negative_id, &config.negative_prompt) {
+        if let Some(node) = nodes.get_mut(&negative_id) {
+            node["inputs"]["text"] = Value::String(negative_prompt.clone());
+        }
+    }
+
+    if let Some(checkpoint) = &config.checkpoint {
+        for node in nodes.values_mut() {
+            if node["class_type"] == "CheckpointLoaderSimple" {
+                node["inputs"]["ckpt_name"] = Value::String(checkpoint.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Generates an image from a text prompt with a ComfyUI txt2img workflow and returns the
+/// encoded image bytes as sent by its SaveImageWebsocket node.
+pub fn generate_image(
+    config: &Txt2ImgConfig,
+    comfy: &DepthConfig,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut workflow: Value = match &config.workflow {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => serde_json::from_str(include_str!("../data/Txt2ImgWorkflow.json"))?,
+    };
+    apply_txt2img_config(&mut workflow, config)?;
+
+    tracing::debug!(
+        "Generating image for prompt {:?} with seed {}",
+        config.prompt,
+        config.seed
+    );
+    run_workflow(&workflow, comfy)
+}
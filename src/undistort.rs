@@ -0,0 +1,158 @@
+//! Lens undistortion for wide-angle and action camera photos, whose barrel distortion bends
+//! straight lines and breaks the renderer's assumption of a flat picture seen head on. The
+//! texture and depth map are resampled together through a Brown-Conrady lens model.
+
+use crate::image_types::{DepthImage, TextureImage};
+use image::imageops::interpolate_bilinear;
+use image::{ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Brown-Conrady distortion: radial coefficients `k1` to `k3` and tangential `p1` and `p2`, over
+/// coordinates centered on the middle of the image with half its shorter side as the unit.
+/// Barrel distortion, as in wide-angle lenses, has a negative `k1`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LensDistortion {
+    pub k1: f32,
+    pub k2: f32,
+    pub k3: f32,
+    pub p1: f32,
+    pub p2: f32,
+}
+
+/// Named distortion strengths to start from when a lens's own coefficients aren't known.
+const PROFILES: [(&str, LensDistortion); 3] = [
+    (
+        "wide",
+        LensDistortion {
+            k1: -0.08,
+            k2: 0.0,
+            k3: 0.0,
+            p1: 0.0,
+            p2: 0.0,
+        },
+    ),
+    (
+        "action",
+        LensDistortion {
+            k1: -0.25,
+            k2: 0.05,
+            k3: 0.0,
+            p1: 0.0,
+            p2: 0.0,
+        },
+    ),
+    (
+        "fisheye",
+        LensDistortion {
+            k1: -0.35,
+            k2: 0.1,
+            k3: 0.0,
+            p1: 0.0,
+            p2: 0.0,
+        },
+    ),
+];
+
+impl FromStr for LensDistortion {
+    type Err = String;
+
+    /// Parses a profile name, `wide`, `action` or `fisheye`, or up to five comma separated
+    /// coefficients `K1[,K2[,K3[,P1,P2]]]`, the missing ones 0.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, profile)) = PROFILES.iter().find(|(name, _)| *name == s) {
+            return Ok(*profile);
+        }
+        let coefficients: Option<Vec<f32>> = s.split(',').map(|c| c.trim().parse().ok()).collect();
+        match coefficients.as_deref() {
+            Some(c) if (1..=5).contains(&c.len()) && c.iter().all(|c| c.is_finite()) => {
+                let c = |i: usize| c.get(i).copied().unwrap_or(0.0);
+                Ok(LensDistortion {
+                    k1: c(0),
+                    k2: c(1),
+                    k3: c(2),
+                    p1: c(3),
+                    p2: c(4),
+                })
+            }
+            _ => Err(format!(
+                "invalid lens distortion '{}', expected wide, action, fisheye or K1[,K2[,K3[,P1,P2]]]",
+                s
+            )),
+        }
+    }
+}
+
+impl LensDistortion {
+    /// Where the lens put the point it should have imaged at `(x, y)`, in the normalized
+    /// coordinates described on the struct.
+    fn distort(&self, x: f32, y: f32) -> (f32, f32) {
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+        (
+            x * radial + 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x),
+            y * radial + self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y,
+        )
+    }
+
+    /// Largest magnification of the undistorted picture whose edges all come from inside the
+    /// photo, so the result has no empty corners and keeps as much of the photo as it can.
+    fn fill_scale(&self, half_width: f32, half_height: f32) -> f32 {
+        const STEPS: u32 = 64;
+        let inside = |scale: f32| {
+            (0..=STEPS).all(|i| {
+                let t = 2.0 * i as f32 / STEPS as f32 - 1.0;
+                [(t, 1.0), (t, -1.0), (1.0, t), (-1.0, t)]
+                    .into_iter()
+                    .all(|(u, v)| {
+                        let (x, y) = self.distort(u * half_width * scale, v * half_height * scale);
+                        x.abs() <= half_width && y.abs() <= half_height
+                    })
+            })
+        };
+        let (mut low, mut high) = (0.1, 10.0);
+        if inside(high) {
+            return high;
+        }
+        for _ in 0..32 {
+            let middle = (low + high) / 2.0;
+            if inside(middle) {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+
+    /// `image` undistorted at its size. The picture is magnified just enough to leave no empty
+    /// corners, so barrel corrections crop the photo's corners while keeping the middle of its
+    /// edges.
+    fn resample(&self, image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        let (width, height) = image.dimensions();
+        let unit = width.min(height) as f32 / 2.0;
+        let (half_width, half_height) = (width as f32 / 2.0 / unit, height as f32 / 2.0 / unit);
+        let scale = self.fill_scale(half_width, half_height);
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let (sx, sy) = self.distort(
+                ((x as f32 + 0.5) / unit - half_width) * scale,
+                ((y as f32 + 0.5) / unit - half_height) * scale,
+            );
+            let sx = ((sx + half_width) * unit - 0.5).clamp(0.0, (width - 1) as f32);
+            let sy = ((sy + half_height) * unit - 0.5).clamp(0.0, (height - 1) as f32);
+            interpolate_bilinear(image, sx, sy).unwrap_or(*image.get_pixel(sx as u32, sy as u32))
+        })
+    }
+
+    /// Undistorts the texture and depth map together.
+    pub fn undistort(
+        &self,
+        texture: TextureImage,
+        heightmap: DepthImage,
+    ) -> (TextureImage, DepthImage) {
+        (
+            TextureImage(self.resample(&texture.0)),
+            DepthImage(self.resample(&heightmap.0)),
+        )
+    }
+}
@@ -0,0 +1,218 @@
+//! Quilt sequences, written as numbered quilt images or as a video. Videos are encoded by piping
+//! raw frames to `ffmpeg`, which has to be on the PATH.
+
+use crate::encode::save_image;
+use crate::quilt::{QuiltName, QuiltSettings};
+use image::{ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+/// Extensions written as a video rather than as numbered quilts.
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mkv", "mov", "webm"];
+
+/// Whether `path` names a video file.
+pub fn is_video(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        VIDEO_EXTENSIONS
+            .iter()
+            .any(|video| ext.eq_ignore_ascii_case(video))
+    })
+}
+
+/// Encoder settings known to play back smoothly on a display, or to keep the most detail.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VideoPreset {
+    /// H.264 at 4096 pixels and 30 Mbit/s at most, which the Go decodes in real time.
+    Go,
+    /// H.264 at 4096 pixels and 40 Mbit/s at most, for the Portrait's player.
+    Portrait,
+    /// HEVC at full size and high quality, for keeping rather than playing.
+    Archive,
+}
+
+/// Encoder settings of a preset.
+struct Encoding {
+    codec: &'static str,
+    /// Capped bitrate in Mbit/s, or `None` for constant quality.
+    bitrate: Option<u32>,
+    /// Seconds between keyframes, for seeking and looping.
+    keyframe_interval: f32,
+    /// Longest side of the video; larger quilts are scaled down, tiles and all.
+    max_size: Option<u32>,
+}
+
+impl VideoPreset {
+    fn encoding(self) -> Encoding {
+        match self {
+            VideoPreset::Go => Encoding {
+                codec: "libx264",
+                bitrate: Some(30),
+                keyframe_interval: 1.0,
+                max_size: Some(4096),
+            },
+            VideoPreset::Portrait => Encoding {
+                codec: "libx264",
+                bitrate: Some(40),
+                keyframe_interval: 1.0,
+                max_size: Some(4096),
+            },
+            VideoPreset::Archive => Encoding {
+                codec: "libx265",
+                bitrate: None,
+                keyframe_interval: 10.0,
+                max_size: None,
+            },
+        }
+    }
+}
+
+/// The size of a quilt of `settings` no longer than `max_size` on either side, in whole tiles.
+fn capped_size(settings: &QuiltSettings, max_size: u32) -> (u32, u32) {
+    let (width, height) = settings.resolution;
+    let factor = (max_size as f32 / width.max(height) as f32).min(1.0);
+    let tile_width = ((width / settings.columns) as f32 * factor) as u32;
+    let tile_height = ((height / settings.rows) as f32 * factor) as u32;
+    (tile_width * settings.columns, tile_height * settings.rows)
+}
+
+enum Output {
+    /// `<base>_0000_qs...<extension>` onwards.
+    Frames {
+        base: String,
+        extension: String,
+    },
+    Ffmpeg {
+        child: Child,
+        stdin: ChildStdin,
+    },
+}
+
+/// Where the quilts of a sequence go, one frame at a time.
+pub struct FrameSink {
+    output: Output,
+    name: QuiltName,
+    frames: usize,
+    /// The video, or the first frame.
+    path: String,
+}
+
+impl FrameSink {
+    /// Starts a sequence of quilts of `settings` with views of `aspect`. `output` is a video name
+    /// such as `pan.mp4`, or a frame name such as `pan.png` for `pan_0000_qs...png` onwards; the
+    /// layout is added to the name either way. Videos are encoded with `preset`, or at a high
+    /// constant quality without one.
+    pub fn new(
+        output: &str,
+        settings: &QuiltSettings,
+        aspect: f32,
+        fps: f32,
+        preset: Option<VideoPreset>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let name = QuiltName::new(settings, aspect);
+        let extension = Path::new(output)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("png")
+            .to_string();
+        let base = output
+            .trim_end_matches(&format!(".{}", extension))
+            .to_string();
+        if !is_video(Path::new(output)) {
+            return Ok(Self {
+                path: name.filename(&format!("{base}_0000"), &extension),
+                output: Output::Frames { base, extension },
+                name,
+                frames: 0,
+            });
+        }
+
+        let path = name.filename(&base, &extension);
+        let (width, height) = settings.resolution;
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-y", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .args(["-s", &format!("{width}x{height}")])
+            .args(["-r", &fps.to_string(), "-i", "-"]);
+        // 4:2:0 chroma needs even sizes; padding keeps the tiles where they were
+        let pad = "pad=ceil(iw/2)*2:ceil(ih/2)*2";
+        match preset.map(VideoPreset::encoding) {
+            Some(encoding) => {
+                let filter = match encoding.max_size {
+                    Some(max_size) if width.max(height) > max_size => {
+                        let (width, height) = capped_size(settings, max_size);
+                        format!("scale={width}:{height},{pad}")
+                    }
+                    _ => pad.to_string(),
+                };
+                let keyframes = ((encoding.keyframe_interval * fps).round() as u32).max(1);
+                command
+                    .args(["-vf", &filter])
+                    .args(["-c:v", encoding.codec])
+                    .args(["-g", &keyframes.to_string(), "-pix_fmt", "yuv420p"]);
+                match encoding.bitrate {
+                    Some(rate) => command
+                        .args(["-profile:v", "high", "-level", "5.1"])
+                        .args(["-b:v", &format!("{rate}M"), "-maxrate", &format!("{rate}M")])
+                        .args(["-bufsize", &format!("{}M", rate * 2)]),
+                    // hvc1 so Apple players recognize HEVC in mp4 and mov
+                    None => command.args(["-crf", "16", "-preset", "slow", "-tag:v", "hvc1"]),
+                };
+            }
+            None => {
+                command
+                    .args(["-vf", pad])
+                    .args(["-c:v", "libx264", "-crf", "18", "-pix_fmt", "yuv420p"]);
+            }
+        }
+        let mut child = command
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to start ffmpeg: {e}"))?;
+        let stdin = child.stdin.take().ok_or("ffmpeg has no stdin")?;
+        Ok(Self {
+            output: Output::Ffmpeg { child, stdin },
+            name,
+            frames: 0,
+            path,
+        })
+    }
+
+    /// Adds the next quilt.
+    pub fn push(&mut self, quilt: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<(), Box<dyn Error>> {
+        match &mut self.output {
+            Output::Frames { base, extension } => {
+                let filename = self
+                    .name
+                    .filename(&format!("{base}_{:04}", self.frames), extension);
+                save_image(Path::new(&filename), quilt)?;
+                crate::status!("Saved frame {} as: {}", self.frames, filename);
+            }
+            Output::Ffmpeg { stdin, .. } => {
+                stdin.write_all(quilt.as_raw())?;
+                crate::status!("Encoded frame {}", self.frames);
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Ends the sequence, waiting for the video to be written, and returns the name of the video
+    /// or of the first frame.
+    pub fn finish(self) -> Result<String, Box<dyn Error>> {
+        if let Output::Ffmpeg { mut child, stdin } = self.output {
+            // Closing stdin ends the input
+            drop(stdin);
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(format!("ffmpeg failed ({}) writing {}", status, self.path).into());
+            }
+        }
+        Ok(self.path)
+    }
+}
@@ -0,0 +1,377 @@
+//! Heightfield quilts of 2D scalar data: a grid of values from a CSV or NumPy `.npy` file is
+//! colored by a colormap and raised by value, so heatmaps and surfaces show as holograms.
+//!
+//! The grid is sampled bilinearly at render time through `TextureSource` and `DepthSource`, so a
+//! small grid renders smoothly at quilt resolution without being upscaled into an image first.
+
+use crate::debug::NullDebugFlags;
+use crate::image_types::{DepthSource, TextureSource};
+use crate::quilt::{render_raw_quilt, QuiltSettings, RenderOptions};
+use image::{ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+
+/// Color of missing (NaN) values, which also sit at the farthest depth.
+const MISSING_COLOR: Rgb<u8> = Rgb([32, 32, 32]);
+
+/// A grid of values, row 0 at the top.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScalarField {
+    pub width: u32,
+    pub height: u32,
+    /// Row by row; NaN marks missing values.
+    pub values: Vec<f32>,
+}
+
+impl ScalarField {
+    pub fn new(width: u32, height: u32, values: Vec<f32>) -> Result<Self, Box<dyn Error>> {
+        if width == 0 || height == 0 || values.len() != width as usize * height as usize {
+            return Err(format!(
+                "a {}x{} field needs {} values, not {}",
+                width,
+                height,
+                width as u64 * height as u64,
+                values.len()
+            )
+            .into());
+        }
+        Ok(Self {
+            width,
+            height,
+            values,
+        })
+    }
+
+    /// Reads a `.npy` or a CSV file, by extension.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let is_npy = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("npy"));
+        let field = if is_npy {
+            Self::from_npy(&std::fs::read(path)?)
+        } else {
+            Self::from_csv(&std::fs::read_to_string(path)?)
+        };
+        field.map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    /// Parses rows of numbers separated by commas, semicolons, tabs or spaces. A first row that
+    /// isn't numeric is taken as a header and skipped, as are blank lines and `#` comments. Empty
+    /// cells and `nan` are missing values.
+    pub fn from_csv(csv: &str) -> Result<Self, Box<dyn Error>> {
+        let mut rows: Vec<Vec<f32>> = Vec::new();
+        for (number, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let cells: Vec<&str> = if line.contains([',', ';', '\t']) {
+                line.split([',', ';', '\t']).map(str::trim).collect()
+            } else {
+                line.split_whitespace().collect()
+            };
+            let row: Result<Vec<f32>, _> = cells
+                .iter()
+                .map(|cell| match *cell {
+                    "" => Ok(f32::NAN),
+                    cell => cell.parse::<f32>(),
+                })
+                .collect();
+            match row {
+                Ok(row) => rows.push(row),
+                Err(_) if rows.is_empty() => continue,
+                Err(_) => return Err(format!("line {} isn't all numbers", number + 1).into()),
+            }
+        }
+        let width = rows.first().ok_or("no rows of numbers")?.len();
+        if let Some(row) = rows.iter().find(|row| row.len() != width) {
+            return Err(format!("rows of {} and {} values", width, row.len()).into());
+        }
+        let height = rows.len();
+        Self::new(width as u32, height as u32, rows.concat())
+    }
+
+    /// Parses a NumPy `.npy` file holding a 2D array of floats or integers. A 1D array is one
+    /// row.
+    pub fn from_npy(npy: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let rest = npy.strip_prefix(b"\x93NUMPY").ok_or("not a .npy file")?;
+        let (header_len, rest) = match rest {
+            [1, _, a, b, rest @ ..] => (u16::from_le_bytes([*a, *b]) as usize, rest),
+            [2 | 3, _, a, b, c, d, rest @ ..] => {
+                (u32::from_le_bytes([*a, *b, *c, *d]) as usize, rest)
+            }
+            _ => return Err("unsupported .npy version".into()),
+        };
+        if rest.len() < header_len {
+            return Err(".npy header is cut short".into());
+        }
+        let (header, data) = rest.split_at(header_len);
+        let header = std::str::from_utf8(header)?;
+
+        // The header is a Python dict literal, e.g.
+        // {'descr': '<f8', 'fortran_order': False, 'shape': (3, 4), }
+        let value = |key: &str| {
+            let start = header.find(&format!("'{}':", key))? + key.len() + 3;
+            Some(header[start..].trim_start())
+        };
+        let descr = value("descr")
+            .and_then(|v| v.strip_prefix('\'')?.split('\'').next())
+            .ok_or("no dtype in .npy header")?;
+        let fortran_order = value("fortran_order").is_some_and(|v| v.starts_with("True"));
+        let shape: Vec<usize> = value("shape")
+            .and_then(|v| v.strip_prefix('(')?.split(')').next())
+            .ok_or("no shape in .npy header")?
+            .split(',')
+            .map(str::trim)
+            .filter(|dim| !dim.is_empty())
+            .map(|dim| dim.parse())
+            .collect::<Result<_, _>>()?;
+        let (height, width) = match shape[..] {
+            [width] => (1, width),
+            [height, width] => (height, width),
+            _ => return Err(format!("a {}-dimensional array, not 2", shape.len()).into()),
+        };
+
+        if descr.len() < 3 || !descr.is_ascii() {
+            return Err(format!("unsupported dtype '{}'", descr).into());
+        }
+        let (order, kind) = descr.split_at(1);
+        let big_endian = match order {
+            "<" | "|" => false,
+            ">" => true,
+            "=" => cfg!(target_endian = "big"),
+            _ => return Err(format!("unsupported dtype '{}'", descr).into()),
+        };
+        let size: usize = kind[1..]
+            .parse()
+            .map_err(|_| format!("unsupported dtype '{}'", descr))?;
+        let convert: fn(&[u8]) -> f32 = match kind {
+            "f4" => |b| f32::from_bits(u32::from_le_bytes(b.try_into().unwrap())),
+            "f8" => |b| f64::from_bits(u64::from_le_bytes(b.try_into().unwrap())) as f32,
+            "i1" => |b| b[0] as i8 as f32,
+            "u1" | "b1" => |b| b[0] as f32,
+            "i2" => |b| i16::from_le_bytes(b.try_into().unwrap()) as f32,
+            "u2" => |b| u16::from_le_bytes(b.try_into().unwrap()) as f32,
+            "i4" => |b| i32::from_le_bytes(b.try_into().unwrap()) as f32,
+            "u4" => |b| u32::from_le_bytes(b.try_into().unwrap()) as f32,
+            "i8" => |b| i64::from_le_bytes(b.try_into().unwrap()) as f32,
+            "u8" => |b| u64::from_le_bytes(b.try_into().unwrap()) as f32,
+            _ => return Err(format!("unsupported dtype '{}'", descr).into()),
+        };
+
+        let count = width * height;
+        let data = data
+            .get(..count * size)
+            .ok_or(".npy file is shorter than its shape")?;
+        let mut element = vec![0; size];
+        let read = |i: usize, element: &mut Vec<u8>| {
+            element.copy_from_slice(&data[i * size..(i + 1) * size]);
+            if big_endian {
+                element.reverse();
+            }
+            convert(element)
+        };
+        let values = (0..count)
+            .map(|i| {
+                // Fortran order stores columns one after another
+                let i = if fortran_order {
+                    (i % width) * height + i / width
+                } else {
+                    i
+                };
+                read(i, &mut element)
+            })
+            .collect();
+        Self::new(width as u32, height as u32, values)
+    }
+
+    /// Smallest and largest values, ignoring missing ones.
+    pub fn range(&self) -> Option<(f32, f32)> {
+        let finite = self.values.iter().copied().filter(|v| v.is_finite());
+        finite.fold(None, |range, v| match range {
+            None => Some((v, v)),
+            Some((min, max)) => Some((min.min(v), max.max(v))),
+        })
+    }
+
+    fn get(&self, x: u32, y: u32) -> f32 {
+        self.values[(y * self.width + x) as usize]
+    }
+}
+
+/// Colors for values from low to high.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Colormap {
+    /// Perceptually uniform dark blue through green to yellow.
+    #[default]
+    Viridis,
+    /// Perceptually uniform black through purple to pale yellow.
+    Magma,
+    /// Rainbow-like dark blue through green to dark red, for telling close values apart.
+    Turbo,
+    /// Diverging blue through light gray to red, for values around a midpoint.
+    Coolwarm,
+    Gray,
+}
+
+impl Colormap {
+    /// Evenly spaced colors the map blends between.
+    fn stops(self) -> &'static [[u8; 3]] {
+        match self {
+            Colormap::Viridis => &[
+                [0x44, 0x01, 0x54],
+                [0x47, 0x2d, 0x7b],
+                [0x3b, 0x52, 0x8b],
+                [0x2c, 0x72, 0x8e],
+                [0x21, 0x91, 0x8c],
+                [0x28, 0xae, 0x80],
+                [0x5e, 0xc9, 0x62],
+                [0xad, 0xdc, 0x30],
+                [0xfd, 0xe7, 0x25],
+            ],
+            Colormap::Magma => &[
+                [0x00, 0x00, 0x04],
+                [0x1c, 0x10, 0x44],
+                [0x4f, 0x12, 0x7b],
+                [0x81, 0x25, 0x81],
+                [0xb5, 0x36, 0x7a],
+                [0xe5, 0x50, 0x64],
+                [0xfb, 0x87, 0x61],
+                [0xfe, 0xc2, 0x87],
+                [0xfc, 0xfd, 0xbf],
+            ],
+            Colormap::Turbo => &[
+                [0x30, 0x12, 0x3b],
+                [0x46, 0x62, 0xd7],
+                [0x36, 0xaa, 0xf9],
+                [0x1a, 0xe4, 0xb6],
+                [0x72, 0xfe, 0x5e],
+                [0xc8, 0xef, 0x34],
+                [0xfa, 0xba, 0x39],
+                [0xf6, 0x6b, 0x19],
+                [0x7a, 0x04, 0x03],
+            ],
+            Colormap::Coolwarm => &[[0x3b, 0x4c, 0xc0], [0xdd, 0xdd, 0xdd], [0xb4, 0x04, 0x26]],
+            Colormap::Gray => &[[0, 0, 0], [255, 255, 255]],
+        }
+    }
+
+    /// The color of `t`, from 0 for the low end to 1 for the high end.
+    pub fn color(self, t: f32) -> Rgb<u8> {
+        let stops = self.stops();
+        let position = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+        let i = (position as usize).min(stops.len() - 2);
+        let f = position - i as f32;
+        Rgb(std::array::from_fn(|c| {
+            (stops[i][c] as f32 * (1.0 - f) + stops[i + 1][c] as f32 * f).round() as u8
+        }))
+    }
+}
+
+/// A scalar field as a texture and depth map: colored by `colormap` and raised from the
+/// farthest depth at the low end of `range` to the nearest at the high end. Both are sampled
+/// bilinearly at `width` by `height` texels.
+#[derive(Clone, Debug)]
+pub struct Heightfield {
+    pub field: ScalarField,
+    pub colormap: Colormap,
+    pub range: (f32, f32),
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Heightfield {
+    /// `field` over its own range, `longest_side` texels across its longer side.
+    pub fn new(field: ScalarField, colormap: Colormap, longest_side: u32) -> Self {
+        let scale = longest_side as f32 / field.width.max(field.height) as f32;
+        let width = ((field.width as f32 * scale).round() as u32).max(1);
+        let height = ((field.height as f32 * scale).round() as u32).max(1);
+        Self {
+            range: field.range().unwrap_or((0.0, 1.0)),
+            field,
+            colormap,
+            width,
+            height,
+        }
+    }
+
+    /// The field value at texel (x, y) mapped into 0 to 1 over `range`, or `None` where it is
+    /// missing.
+    fn sample(&self, x: u32, y: u32) -> Option<f32> {
+        let field = &self.field;
+        // Texel centers, in field cells
+        let fx = ((x as f32 + 0.5) * field.width as f32 / self.width as f32 - 0.5)
+            .clamp(0.0, (field.width - 1) as f32);
+        let fy = ((y as f32 + 0.5) * field.height as f32 / self.height as f32 - 0.5)
+            .clamp(0.0, (field.height - 1) as f32);
+        let (x0, y0) = (fx as u32, fy as u32);
+        let (x1, y1) = (
+            (x0 + 1).min(field.width - 1),
+            (y0 + 1).min(field.height - 1),
+        );
+        let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+        let corners = [
+            (field.get(x0, y0), (1.0 - tx) * (1.0 - ty)),
+            (field.get(x1, y0), tx * (1.0 - ty)),
+            (field.get(x0, y1), (1.0 - tx) * ty),
+            (field.get(x1, y1), tx * ty),
+        ];
+        // Blend the corners that have values, so missing ones don't spread past their cells
+        let (sum, weight) = corners
+            .iter()
+            .filter(|(value, _)| value.is_finite())
+            .fold((0.0, 0.0), |(sum, total), &(value, weight)| {
+                (sum + value * weight, total + weight)
+            });
+        let nearest = field.get(fx.round() as u32, fy.round() as u32);
+        if !nearest.is_finite() || weight <= 0.0 {
+            return None;
+        }
+        let (low, high) = self.range;
+        let span = if high > low { high - low } else { 1.0 };
+        Some(((sum / weight - low) / span).clamp(0.0, 1.0))
+    }
+}
+
+impl TextureSource for Heightfield {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn texel(&self, x: u32, y: u32) -> Rgb<u8> {
+        self.sample(x, y)
+            .map_or(MISSING_COLOR, |t| self.colormap.color(t))
+    }
+}
+
+impl DepthSource for Heightfield {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn depth(&self, x: u32, y: u32) -> u8 {
+        self.sample(x, y).map_or(0, |t| (t * 255.0).round() as u8)
+    }
+}
+
+/// Renders `field` as a heightfield quilt of `settings` with `colormap`, sampled at twice the
+/// tile size like the painters' default resize.
+pub fn render_field(
+    field: ScalarField,
+    colormap: Colormap,
+    settings: &QuiltSettings,
+    options: &RenderOptions,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let tile_size =
+        (settings.resolution.0 / settings.columns).max(settings.resolution.1 / settings.rows);
+    let heightfield = Heightfield::new(field, colormap, tile_size * 2);
+    render_raw_quilt(
+        settings,
+        &heightfield,
+        &heightfield,
+        options,
+        &NullDebugFlags,
+    )
+}
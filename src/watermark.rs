@@ -0,0 +1,129 @@
+use crate::captions::Position;
+use image::{ImageBuffer, Rgb, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Distance kept between the watermark and the edges of the image it is drawn on.
+const MARGIN: i64 = 10;
+
+/// Where and how to composite a logo image, as given by `--watermark`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WatermarkConfig {
+    pub path: PathBuf,
+    pub position: Position,
+    /// 0.0 (invisible) to 1.0 (the logo's own alpha).
+    pub opacity: f32,
+    /// Logo width as a fraction of the width of the image it is drawn on.
+    pub scale: f32,
+    /// Draw once onto the finished quilt instead of onto every view.
+    #[serde(default)]
+    pub once: bool,
+}
+
+impl FromStr for WatermarkConfig {
+    type Err = String;
+
+    /// Parses `PATH[:POSITION[:OPACITY[:SCALE]]]`, e.g. `logo.png:top-right:0.5:0.2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Options are peeled off the end so paths containing ':' still work.
+        let parts: Vec<&str> = s.split(':').collect();
+        for options in (0..parts.len().min(4)).rev() {
+            let (path, options) = parts.split_at(parts.len() - options);
+            if let Some(config) = Self::with_options(path.join(":"), options) {
+                return Ok(config);
+            }
+        }
+        Err(format!(
+            "invalid watermark '{}', expected PATH[:POSITION[:OPACITY[:SCALE]]]",
+            s
+        ))
+    }
+}
+
+impl WatermarkConfig {
+    fn with_options(path: String, options: &[&str]) -> Option<Self> {
+        if path.is_empty() {
+            return None;
+        }
+        let position = match options.first() {
+            Some(position) => clap::ValueEnum::from_str(position, true).ok()?,
+            None => Position::BottomRight,
+        };
+        let opacity = match options.get(1) {
+            Some(opacity) => opacity.parse().ok().filter(|o| (0.0..=1.0).contains(o))?,
+            None => 1.0,
+        };
+        let scale = match options.get(2) {
+            Some(scale) => scale.parse().ok().filter(|s: &f32| *s > 0.0)?,
+            None => 0.15,
+        };
+        Some(Self {
+            path: path.into(),
+            position,
+            opacity,
+            scale,
+            once: false,
+        })
+    }
+
+    pub fn load(&self) -> Result<Watermark, Box<dyn Error>> {
+        let image = image::open(&self.path)
+            .map_err(|e| format!("failed to load watermark {}: {}", self.path.display(), e))?
+            .to_rgba8();
+        Ok(Watermark {
+            image,
+            config: self.clone(),
+        })
+    }
+}
+
+/// A loaded watermark, ready to draw.
+#[derive(Clone, Debug)]
+pub struct Watermark {
+    image: RgbaImage,
+    pub config: WatermarkConfig,
+}
+
+impl Watermark {
+    /// Alpha-composites the logo onto `target`, scaled to `config.scale` of its width.
+    pub fn draw(&self, target: &mut ImageBuffer<Rgb<u8>, Vec<u8>>) {
+        let width = ((target.width() as f32 * self.config.scale).round() as u32).max(1);
+        let height =
+            ((self.image.height() as u64 * width as u64 / self.image.width() as u64) as u32).max(1);
+        let logo = image::imageops::resize(
+            &self.image,
+            width,
+            height,
+            image::imageops::FilterType::Triangle,
+        );
+
+        let (target_width, target_height) = (target.width() as i64, target.height() as i64);
+        let (width, height) = (width as i64, height as i64);
+        let (x, y) = match self.config.position {
+            Position::TopLeft => (MARGIN, MARGIN),
+            Position::TopCenter => ((target_width - width) / 2, MARGIN),
+            Position::TopRight => (target_width - width - MARGIN, MARGIN),
+            Position::BottomLeft => (MARGIN, target_height - height - MARGIN),
+            Position::BottomCenter => ((target_width - width) / 2, target_height - height - MARGIN),
+            Position::BottomRight => (
+                target_width - width - MARGIN,
+                target_height - height - MARGIN,
+            ),
+            Position::Center => ((target_width - width) / 2, (target_height - height) / 2),
+        };
+
+        for (lx, ly, pixel) in logo.enumerate_pixels() {
+            let (tx, ty) = (x + lx as i64, y + ly as i64);
+            if tx < 0 || ty < 0 || tx >= target_width || ty >= target_height {
+                continue;
+            }
+            let alpha = pixel[3] as f32 / 255.0 * self.config.opacity;
+            let out = target.get_pixel_mut(tx as u32, ty as u32);
+            for c in 0..3 {
+                out[c] = ((1.0 - alpha) * out[c] as f32 + alpha * pixel[c] as f32) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! `--caption-size` and `--caption-margin` lengths, in pixels or percent of the view height.
+
+use quilt_painter::captions::Length;
+
+fn length(s: &str) -> Length {
+    s.parse().unwrap()
+}
+
+#[test]
+fn parses_lengths() {
+    assert_eq!(length("16"), Length::Px(16));
+    assert_eq!(length("16px"), Length::Px(16));
+    assert_eq!(length(" 16 px "), Length::Px(16));
+    assert_eq!(length("4%"), Length::Percent(4.0));
+    assert_eq!(length("2.5 %"), Length::Percent(2.5));
+    assert_eq!(length("0%"), Length::Percent(0.0));
+}
+
+#[test]
+fn refuses_bad_lengths() {
+    for bad in [
+        "", "px", "%", "-1", "1.5", "16pt", "-4%", "inf%", "NaN%", "four%",
+    ] {
+        assert!(bad.parse::<Length>().is_err(), "{bad:?} parsed");
+    }
+}
+
+#[test]
+fn resolves_against_the_view_height() {
+    assert_eq!(Length::Px(16).to_pixels(2048), 16);
+    assert_eq!(Length::Percent(4.0).to_pixels(750), 30);
+    assert_eq!(Length::Percent(4.0).to_pixels(400), 16);
+    // Rounded to the nearest pixel
+    assert_eq!(Length::Percent(1.0).to_pixels(250), 3);
+}
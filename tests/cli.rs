@@ -0,0 +1,30 @@
+//! Colors are checked while the arguments are parsed, before anything is loaded or rendered.
+
+use std::process::Command;
+
+/// The error `painter` stops with for `args`, given an input that doesn't exist.
+fn painter_error(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_painter"))
+        .args(["missing.png", "out.png"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{args:?} accepted");
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn border_colors_are_checked() {
+    for color in ["1,2", "foo,bar,baz", "300,0,0", "aééb"] {
+        let error = painter_error(&["--tile-padding", "2", "--border-color", color]);
+        assert!(error.contains("invalid color"), "{color:?}: {error}");
+    }
+}
+
+#[test]
+fn matte_colors_are_checked() {
+    for color in ["999,0,0", "1,2", "#12345"] {
+        let error = painter_error(&["--matte", color]);
+        assert!(error.contains("invalid color"), "{color:?}: {error}");
+    }
+}
@@ -0,0 +1,47 @@
+//! Only files named after another image next to them are depth sidecars.
+
+use quilt_painter::depth_gen::DepthConfig;
+use std::path::Path;
+
+fn config(depth_suffix: Option<&str>) -> DepthConfig {
+    DepthConfig {
+        depth_suffix: depth_suffix.map(str::to_string),
+        ..DepthConfig::default()
+    }
+}
+
+#[test]
+fn sidecars_need_their_image() {
+    let dir = std::env::temp_dir().join(format!("quilt_sidecar_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in [
+        "photo.jpg",
+        "photo_depth.png",
+        "ocean_depth.jpg",
+        "lake_depth.png",
+        "hill.png",
+        "hill.depth.png",
+        "dune.jpeg",
+        "dune_d.png",
+        "reef_d.png",
+    ] {
+        std::fs::write(dir.join(name), b"").unwrap();
+    }
+    let sidecar = |config: &DepthConfig, name: &str| config.is_depth_sidecar(&dir.join(name));
+
+    let default = config(None);
+    assert!(sidecar(&default, "photo_depth.png"));
+    assert!(sidecar(&default, "hill.depth.png"));
+    assert!(!sidecar(&default, "photo.jpg"));
+    assert!(!sidecar(&default, "ocean_depth.jpg"));
+    assert!(!sidecar(&default, "lake_depth.png"));
+    assert!(!sidecar(&default, "dune_d.png"));
+
+    let custom = config(Some("_d"));
+    assert!(sidecar(&custom, "dune_d.png"));
+    assert!(!sidecar(&custom, "reef_d.png"));
+    assert!(!sidecar(&custom, "photo_depth.png"));
+    assert!(!custom.is_depth_sidecar(Path::new("missing/photo_d.png")));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,81 @@
+//! Golden-image check of the renderer against the synthetic fixtures.
+//!
+//! The first run saves the reference quilts under `tests/golden/`; later runs compare against
+//! them. Delete the directory (or set `QUILT_FIXTURES_BLESS=1`) to accept an intended change.
+#![cfg(feature = "fixtures")]
+
+use quilt_painter::debug::NullDebugFlags;
+use quilt_painter::fixtures::{self, Pattern, FIXTURE_QUILT};
+use quilt_painter::image_types::{ProceduralDepth, ProceduralTexture};
+use quilt_painter::quilt::{render_raw_quilt, RenderOptions};
+use std::path::PathBuf;
+
+/// Minimum SSIM per view before a fixture counts as changed.
+const MIN_SSIM: f32 = 0.999;
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+#[test]
+fn fixtures_are_deterministic() {
+    for pattern in Pattern::ALL {
+        let a = fixtures::Fixture::new(pattern, 32, 32).reference_quilt();
+        let b = fixtures::Fixture::new(pattern, 32, 32).reference_quilt();
+        assert!(a == b, "{} renders differently twice", pattern.name());
+    }
+}
+
+#[test]
+fn procedural_sources_render_like_their_images() {
+    for fixture in fixtures::all() {
+        let (width, height) = fixture.texture.dimensions();
+        let texture =
+            ProceduralTexture::new(width, height, |x, y| *fixture.texture.0.get_pixel(x, y));
+        let heightmap =
+            ProceduralDepth::new(width, height, |x, y| fixture.heightmap.0.get_pixel(x, y)[0]);
+        let procedural = render_raw_quilt(
+            &FIXTURE_QUILT,
+            &texture,
+            &heightmap,
+            &RenderOptions::default(),
+            &NullDebugFlags,
+        );
+        let image = render_raw_quilt(
+            &FIXTURE_QUILT,
+            &fixture.texture,
+            &fixture.heightmap,
+            &RenderOptions::default(),
+            &NullDebugFlags,
+        );
+        assert!(
+            procedural == image,
+            "{} renders differently procedurally",
+            fixture.pattern.name()
+        );
+    }
+}
+
+#[test]
+fn fixtures_match_golden_quilts() {
+    let dir = golden_dir();
+    let bless = std::env::var_os("QUILT_FIXTURES_BLESS").is_some();
+    if bless || !dir.exists() {
+        fixtures::write_references(&dir).unwrap();
+        eprintln!("Wrote reference quilts to {}", dir.display());
+        return;
+    }
+
+    for (pattern, diff) in fixtures::compare_to_references(&dir).unwrap() {
+        for view in &diff.views {
+            assert!(
+                view.ssim >= MIN_SSIM,
+                "{} view {} changed: ssim {:.5}, max difference {}",
+                pattern.name(),
+                view.view,
+                view.ssim,
+                view.max_difference
+            );
+        }
+    }
+}
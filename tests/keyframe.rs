@@ -0,0 +1,75 @@
+//! `--keyframe` parsing, and the values eased between keyframes.
+
+use approx::assert_relative_eq;
+use quilt_painter::keyframe::{Keyframe, Keyframes};
+
+fn keyframe(s: &str) -> Keyframe {
+    s.parse().unwrap()
+}
+
+#[test]
+fn parses_keyframes() {
+    assert_eq!(
+        keyframe("t=0:zoom=1.0,scale=1.5"),
+        Keyframe {
+            time: 0.0,
+            zoom: Some(1.0),
+            scale: Some(1.5),
+            focus: None,
+        }
+    );
+    assert_eq!(
+        keyframe("t=2.5:focus=128"),
+        Keyframe {
+            time: 2.5,
+            focus: Some(128.0),
+            ..Default::default()
+        }
+    );
+    // Negative scale flips the depth
+    assert_eq!(keyframe("t=1:scale=-1").scale, Some(-1.0));
+}
+
+#[test]
+fn refuses_bad_keyframes() {
+    for bad in [
+        "",
+        "t=1",
+        "t=1:",
+        "1:zoom=1",
+        "t=-1:zoom=1",
+        "t=x:zoom=1",
+        "t=1:zoom",
+        "t=1:zoom=x",
+        "t=1:zoom=0",
+        "t=1:focus=256",
+        "t=1:focus=-1",
+        "t=1:tilt=1",
+    ] {
+        assert!(bad.parse::<Keyframe>().is_err(), "{bad:?} parsed");
+    }
+}
+
+#[test]
+fn eases_between_keyframes() {
+    let keyframes = Keyframes::new(vec![
+        keyframe("t=4:zoom=2,focus=100"),
+        keyframe("t=0:zoom=1"),
+    ])
+    .unwrap();
+    assert_eq!(keyframes.duration(), 4.0);
+    assert_relative_eq!(keyframes.zoom(0.0).unwrap(), 1.0);
+    assert_relative_eq!(keyframes.zoom(2.0).unwrap(), 1.5);
+    // Smoothstep starts slowly
+    assert!(keyframes.zoom(1.0).unwrap() < 1.25);
+    assert_relative_eq!(keyframes.zoom(4.0).unwrap(), 2.0);
+    assert_relative_eq!(keyframes.zoom(9.0).unwrap(), 2.0);
+    // Held before the only keyframe that gives it
+    assert_eq!(keyframes.focus(0.0), Some(100.0));
+    assert_eq!(keyframes.scale(2.0), None);
+}
+
+#[test]
+fn refuses_two_keyframes_at_one_time() {
+    assert!(Keyframes::new(vec![keyframe("t=1:zoom=1"), keyframe("t=1:scale=2")]).is_err());
+}
@@ -0,0 +1,132 @@
+//! Mesh export and import: exported scenes read back as the same points, and imported points
+//! are resampled into a heightfield without holes.
+
+use image::{ImageBuffer, Rgb};
+use quilt_painter::image_types::{DepthImage, TextureImage};
+use quilt_painter::mesh::{
+    export_scene, rasterize_heightfield, read_mesh_obj, read_point_cloud_ply, ScenePoint,
+};
+
+const Z_SCALE: f32 = 0.5;
+
+fn scene() -> (TextureImage, DepthImage) {
+    let texture = ImageBuffer::from_fn(3, 2, |x, y| Rgb([x as u8 * 100, y as u8 * 200, 7]));
+    let depth = ImageBuffer::from_fn(3, 2, |x, y| {
+        let d = (x + 3 * y) as u8 * 40;
+        Rgb([d, d, d])
+    });
+    (TextureImage(texture), DepthImage(depth))
+}
+
+/// Scene space position of each texture pixel, row by row, as the exporters write them.
+fn expected_positions() -> Vec<(f32, f32, f32)> {
+    let mut positions = Vec::new();
+    for y in 0..2 {
+        for x in 0..3 {
+            let d = (x + 3 * y) as f32 * 40.0;
+            positions.push((x as f32 - 1.5, 1.0 - y as f32, d * Z_SCALE));
+        }
+    }
+    positions
+}
+
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("quilt_mesh_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn ply_round_trip() {
+    let dir = temp_dir("ply");
+    let path = dir.join("scene.ply");
+    let (texture, depth) = scene();
+    export_scene(&path, &texture, &depth, Z_SCALE).unwrap();
+
+    let points = read_point_cloud_ply(&path).unwrap();
+    let positions: Vec<_> = points.iter().map(|p| p.position).collect();
+    assert_eq!(positions, expected_positions());
+    let colors: Vec<_> = points.iter().map(|p| p.color).collect();
+    let expected: Vec<_> = texture.0.pixels().map(|p| p.0).collect();
+    assert_eq!(colors, expected);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn obj_round_trip() {
+    let dir = temp_dir("obj");
+    let path = dir.join("scene.obj");
+    let (texture, depth) = scene();
+    export_scene(&path, &texture, &depth, Z_SCALE).unwrap();
+    assert!(dir.join("scene.mtl").exists());
+    assert!(dir.join("scene_texture.png").exists());
+
+    let points = read_mesh_obj(&path).unwrap();
+    let positions: Vec<_> = points.iter().map(|p| p.position).collect();
+    assert_eq!(positions, expected_positions());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unknown_formats_are_refused() {
+    let (texture, depth) = scene();
+    let path = std::env::temp_dir().join("scene.gltf");
+    assert!(export_scene(&path, &texture, &depth, Z_SCALE).is_err());
+}
+
+#[test]
+fn holes_are_filled_from_neighbours() {
+    // Four corners of a 5x5 grid, with nothing in between
+    let corner = |x: f32, y: f32, z: f32, color: [u8; 3]| ScenePoint {
+        position: (x, y, z),
+        color,
+    };
+    let red = [255, 0, 0];
+    let green = [0, 255, 0];
+    let blue = [0, 0, 255];
+    let white = [255, 255, 255];
+    let points = [
+        corner(0.0, 4.0, 0.0, red),
+        corner(4.0, 4.0, 1.0, green),
+        corner(0.0, 0.0, 2.0, blue),
+        corner(4.0, 0.0, 4.0, white),
+    ];
+    let (texture, depth) = rasterize_heightfield(&points, 5);
+    assert_eq!(texture.dimensions(), (5, 5));
+
+    for (x, y, pixel) in texture.0.enumerate_pixels() {
+        assert!(
+            [red, green, blue, white].contains(&pixel.0),
+            "hole left at {x},{y}"
+        );
+    }
+    // Each hole takes the nearest filled cell
+    assert_eq!(texture.0.get_pixel(1, 0).0, red);
+    assert_eq!(texture.0.get_pixel(3, 0).0, green);
+    assert_eq!(texture.0.get_pixel(0, 3).0, blue);
+    assert_eq!(texture.0.get_pixel(4, 3).0, white);
+    assert_eq!(depth.0.get_pixel(1, 0).0, [0, 0, 0]);
+    assert_eq!(depth.0.get_pixel(3, 4).0, [255, 255, 255]);
+}
+
+#[test]
+fn the_point_nearest_the_viewer_wins() {
+    let points = [
+        ScenePoint {
+            position: (0.0, 0.0, 1.0),
+            color: [10, 10, 10],
+        },
+        ScenePoint {
+            position: (0.0, 0.0, 3.0),
+            color: [20, 20, 20],
+        },
+        ScenePoint {
+            position: (1.0, 0.0, 2.0),
+            color: [30, 30, 30],
+        },
+    ];
+    let (texture, _) = rasterize_heightfield(&points, 2);
+    assert_eq!(texture.0.get_pixel(0, 0).0, [20, 20, 20]);
+}
@@ -0,0 +1,29 @@
+//! The `/metrics` endpoint answers scrapes while another client sits on an unfinished request.
+
+use quilt_painter::metrics;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+#[test]
+fn stalled_clients_do_not_block_scrapes() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    metrics::serve(addr).unwrap();
+
+    let mut stalled = TcpStream::connect(addr).unwrap();
+    stalled.write_all(b"GET /metr").unwrap();
+
+    let mut scrape = TcpStream::connect(addr).unwrap();
+    scrape
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    scrape
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    scrape.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+    assert!(response.contains("# TYPE"), "{response}");
+}
@@ -0,0 +1,89 @@
+//! MPO files split into the JPEGs their MP index lists.
+
+use quilt_painter::mpo::split_mpo;
+
+/// Where the MP header starts: after SOI, the APP2 marker and length, and "MPF\0".
+const HEADER: usize = 10;
+
+/// An MPO of `images`, each a stand-in JPEG with no real image data, indexed big- or
+/// little-endian. The first is given an APP2 segment holding the MP index.
+fn mpo(images: &[&[u8]], little: bool) -> Vec<u8> {
+    let u16 = |v: u16| {
+        if little {
+            v.to_le_bytes()
+        } else {
+            v.to_be_bytes()
+        }
+    };
+    let u32 = |v: u32| {
+        if little {
+            v.to_le_bytes()
+        } else {
+            v.to_be_bytes()
+        }
+    };
+    // TIFF header, an IFD with only the MP Entry tag, then the table right after the IFD
+    let table_offset = 8 + 2 + 12 + 4;
+    let index_len = table_offset + 16 * images.len();
+    let first_len = HEADER + index_len + images[0].len() - 2;
+
+    let mut index = Vec::new();
+    index.extend_from_slice(if little { b"II" } else { b"MM" });
+    index.extend_from_slice(&u16(42));
+    index.extend_from_slice(&u32(8));
+    index.extend_from_slice(&u16(1));
+    index.extend_from_slice(&u16(0xB002));
+    index.extend_from_slice(&u16(7));
+    index.extend_from_slice(&u32(16 * images.len() as u32));
+    index.extend_from_slice(&u32(table_offset as u32));
+    index.extend_from_slice(&u32(0));
+    let mut next = first_len;
+    for (i, image) in images.iter().enumerate() {
+        let (size, offset) = if i == 0 {
+            (first_len, 0)
+        } else {
+            let offset = next - HEADER;
+            next += image.len();
+            (image.len(), offset)
+        };
+        index.extend_from_slice(&u32(0));
+        index.extend_from_slice(&u32(size as u32));
+        index.extend_from_slice(&u32(offset as u32));
+        index.extend_from_slice(&[0; 4]);
+    }
+
+    let mut data = vec![0xFF, 0xD8, 0xFF, 0xE2];
+    data.extend_from_slice(&(2 + 4 + index.len() as u16).to_be_bytes());
+    data.extend_from_slice(b"MPF\0");
+    data.extend_from_slice(&index);
+    // The rest of the first image after its SOI
+    data.extend_from_slice(&images[0][2..]);
+    for image in &images[1..] {
+        data.extend_from_slice(image);
+    }
+    data
+}
+
+const LEFT: &[u8] = &[0xFF, 0xD8, 0xFF, 0xDA, 1, 1, 0xFF, 0xD9];
+const RIGHT: &[u8] = &[0xFF, 0xD8, 0xFF, 0xDA, 2, 2, 2, 0xFF, 0xD9];
+
+#[test]
+fn splits_stereo_pair() {
+    for little in [true, false] {
+        let data = mpo(&[LEFT, RIGHT], little);
+        let images = split_mpo(&data).unwrap();
+        assert_eq!(images.len(), 2);
+        assert!(images[0].starts_with(&[0xFF, 0xD8, 0xFF, 0xE2]));
+        assert!(images[0].ends_with(&LEFT[2..]));
+        assert_eq!(images[1], RIGHT);
+    }
+}
+
+#[test]
+fn rejects_plain_jpeg_and_bad_offsets() {
+    assert!(split_mpo(LEFT).is_err());
+
+    let mut data = mpo(&[LEFT, RIGHT], true);
+    data.truncate(data.len() - 1);
+    assert!(split_mpo(&data).is_err());
+}
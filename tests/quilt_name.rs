@@ -0,0 +1,80 @@
+//! Quilt filenames written by the crate's naming code parse back to the same layout.
+
+use quilt_painter::quilt::{QuiltName, QuiltSettings, DEVICE_PRESETS};
+
+#[test]
+fn device_layouts_round_trip() {
+    for settings in DEVICE_PRESETS.iter().map(|preset| &preset.settings) {
+        let aspect = settings.resolution.0 as f32
+            / settings.columns as f32
+            / (settings.resolution.1 as f32 / settings.rows as f32);
+        let filename = QuiltName::new(settings, aspect).filename("photo", "png");
+        let parsed = QuiltSettings::from_filename(&filename, settings.resolution)
+            .unwrap_or_else(|| panic!("{} didn't parse", filename));
+        assert_eq!(
+            (parsed.columns, parsed.rows, parsed.resolution),
+            (settings.columns, settings.rows, settings.resolution),
+            "{}",
+            filename
+        );
+        let name = QuiltName::parse(&filename).unwrap();
+        assert!(
+            (name.aspect.unwrap() - aspect).abs() < 0.005,
+            "{}",
+            filename
+        );
+    }
+}
+
+#[test]
+fn round_trips_through_paths_and_odd_bases() {
+    let settings = QuiltSettings {
+        columns: 11,
+        rows: 6,
+        ..Default::default()
+    };
+    for base in ["out/photo", "my_qs_photo", "1.5x_zoom", "a_qs2x2a1.00_b"] {
+        let filename = QuiltName::new(&settings, 0.5625).filename(base, "jpg");
+        assert_eq!(
+            QuiltName::parse(&filename),
+            Some(QuiltName {
+                columns: 11,
+                rows: 6,
+                aspect: Some(0.56)
+            }),
+            "{}",
+            filename
+        );
+    }
+}
+
+#[test]
+fn aspect_is_optional() {
+    let name = QuiltName {
+        columns: 8,
+        rows: 6,
+        aspect: None,
+    };
+    let filename = name.filename("photo", "png");
+    assert_eq!(filename, "photo_qs8x6.png");
+    assert_eq!(QuiltName::parse(&filename), Some(name));
+    assert_eq!(
+        QuiltName::parse("photo_qs5x9a.png").map(|name| name.aspect),
+        Some(None)
+    );
+}
+
+#[test]
+fn rejects_names_without_a_layout() {
+    for name in [
+        "photo.png",
+        "photo_qs.png",
+        "photo_qs8.png",
+        "photo_qs8x.png",
+        "photo_qsx6a0.75.png",
+        "photo_qs0x6a0.75.png",
+        "photo_qs8x6a0.75_qs.png",
+    ] {
+        assert_eq!(QuiltName::parse(name), None, "{}", name);
+    }
+}
@@ -0,0 +1,53 @@
+//! Byte ranges and request paths of quilt-serve, which decide what leaves the served directory.
+
+use quilt_painter::serve::{parse_range, QuiltDirectory};
+
+#[test]
+fn ranges() {
+    assert_eq!(parse_range("bytes=0-4", 10), Some((0, 4)));
+    assert_eq!(parse_range("bytes=5-", 10), Some((5, 9)));
+    // The last bytes
+    assert_eq!(parse_range("bytes=-3", 10), Some((7, 9)));
+    assert_eq!(parse_range("bytes=-20", 10), Some((0, 9)));
+    // An end past the file is cut to it
+    assert_eq!(parse_range("bytes=8-100", 10), Some((8, 9)));
+    // Only the first of several
+    assert_eq!(parse_range("bytes=1-2, 4-5", 10), Some((1, 2)));
+}
+
+#[test]
+fn unsatisfiable_ranges() {
+    // A suffix of no bytes, which is answered with 416
+    assert_eq!(parse_range("bytes=-0", 10), None);
+    assert_eq!(parse_range("bytes=10-", 10), None);
+    assert_eq!(parse_range("bytes=10-12", 10), None);
+    assert_eq!(parse_range("bytes=5-2", 10), None);
+    assert_eq!(parse_range("bytes=0-", 0), None);
+    assert_eq!(parse_range("items=0-4", 10), None);
+    assert_eq!(parse_range("bytes=a-b", 10), None);
+}
+
+#[test]
+fn files_stay_in_the_directory() {
+    let root = std::env::temp_dir().join(format!("quilt_serve_{}", std::process::id()));
+    let dir = root.join("out");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a b.png"), b"").unwrap();
+    std::fs::write(dir.join(".hidden.png"), b"").unwrap();
+    std::fs::write(root.join("secret.png"), b"").unwrap();
+    let quilts = QuiltDirectory::new(&dir);
+
+    assert_eq!(quilts.file("/a%20b.png"), Some(dir.join("a b.png")));
+    assert_eq!(quilts.file("/missing.png"), None);
+    assert_eq!(quilts.file("/.hidden.png"), None);
+    assert_eq!(quilts.file("/../secret.png"), None);
+    assert_eq!(quilts.file("/..%2Fsecret.png"), None);
+    assert_eq!(quilts.file("/%2E%2E%2Fsecret.png"), None);
+    assert_eq!(quilts.file("/..%5Csecret.png"), None);
+    assert_eq!(quilts.file("/"), None);
+    // Malformed escapes
+    assert_eq!(quilts.file("/a%2"), None);
+    assert_eq!(quilts.file("/%FF.png"), None);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,41 @@
+//! SRT parsing, and which subtitles show when.
+
+use quilt_painter::subtitles::Subtitles;
+
+const SRT: &str = "\u{feff}1\r\n00:00:01,000 --> 00:00:03,500\r\nHello\r\n\r\n2\r\n00:00:03.000 --> 00:00:05,250 X1:100 X2:600\r\n<i>Two</i> {\\an8}lines\r\nof text\r\n\r\n\r\n00:01:00,000 --> 01:00:00,000\r\nNo index\r\n";
+
+#[test]
+fn parses_entries() {
+    let subtitles = Subtitles::parse(SRT).unwrap();
+    assert_eq!(subtitles.at(0.5), None);
+    assert_eq!(subtitles.at(1.0).as_deref(), Some("Hello"));
+    // Overlapping subtitles stack in file order
+    assert_eq!(
+        subtitles.at(3.2).as_deref(),
+        Some("Hello\nTwo lines\nof text")
+    );
+    // The end time is exclusive
+    assert_eq!(subtitles.at(5.25), None);
+    assert_eq!(subtitles.at(3599.0).as_deref(), Some("No index"));
+    assert_eq!(subtitles.at(3600.0), None);
+}
+
+#[test]
+fn empty_files_have_no_subtitles() {
+    let subtitles = Subtitles::parse("\n\n").unwrap();
+    assert_eq!(subtitles, Subtitles::default());
+}
+
+#[test]
+fn refuses_bad_timings() {
+    for bad in [
+        "1\n00:00:01,000 00:00:02,000\nNo arrow",
+        "1\n00:00:01 --> 00:00:02,000\nNo milliseconds",
+        "1\n00:61:00,000 --> 01:00:00,000\nMinutes past 59",
+        "1\n00:00:60,000 --> 01:00:00,000\nSeconds past 59",
+        "1\n0:0:0:1,000 --> 0:0:2,000\nToo many fields",
+        "1\n00:00:01,000 -->\nNo end",
+    ] {
+        assert!(Subtitles::parse(bad).is_err(), "{bad:?} parsed");
+    }
+}
@@ -0,0 +1,53 @@
+//! `--watermark PATH[:POSITION[:OPACITY[:SCALE]]]` parsing.
+
+use quilt_painter::captions::Position;
+use quilt_painter::watermark::WatermarkConfig;
+use std::path::Path;
+
+fn watermark(s: &str) -> WatermarkConfig {
+    s.parse().unwrap()
+}
+
+#[test]
+fn defaults() {
+    let config = watermark("logo.png");
+    assert_eq!(config.path, Path::new("logo.png"));
+    assert_eq!(config.position, Position::BottomRight);
+    assert_eq!(config.opacity, 1.0);
+    assert_eq!(config.scale, 0.15);
+    assert!(!config.once);
+}
+
+#[test]
+fn options() {
+    let config = watermark("logo.png:top-left:0.5:0.25");
+    assert_eq!(config.path, Path::new("logo.png"));
+    assert_eq!(config.position, Position::TopLeft);
+    assert_eq!(config.opacity, 0.5);
+    assert_eq!(config.scale, 0.25);
+
+    let config = watermark("logo.png:Center:0");
+    assert_eq!(config.position, Position::Center);
+    assert_eq!(config.opacity, 0.0);
+    assert_eq!(config.scale, 0.15);
+}
+
+#[test]
+fn paths_may_hold_colons() {
+    let config = watermark(r"C:\logos\logo.png:top-center");
+    assert_eq!(config.path, Path::new(r"C:\logos\logo.png"));
+    assert_eq!(config.position, Position::TopCenter);
+    // What isn't a valid option is part of the path
+    assert_eq!(watermark("a:b.png").path, Path::new("a:b.png"));
+    assert_eq!(
+        watermark("logo.png:top-left:2").path,
+        Path::new("logo.png:top-left:2")
+    );
+}
+
+#[test]
+fn refuses_an_empty_path() {
+    assert!("".parse::<WatermarkConfig>().is_err());
+    // Options without a path are taken as the path, which then fails to load
+    assert_eq!(watermark(":top-left").path, Path::new(":top-left"));
+}
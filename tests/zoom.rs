@@ -0,0 +1,197 @@
+//! Zoom sweeps over flat scenes: every screen row and column the texture covers must be drawn,
+//! and no texture row may be skipped or drawn beyond the screen rows it covers, at any zoom and
+//! zoom center.
+
+use image::{ImageBuffer, Rgb};
+use quilt_painter::camera::Camera;
+use quilt_painter::image_types::{DepthImage, TextureImage};
+use quilt_painter::quilt::{render_single_view, ViewOptions};
+
+const VIEW_WIDTH: u32 = 120;
+const VIEW_HEIGHT: u32 = 160;
+const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+
+fn render_flat(zoom: f32, tex_width: u32, tex_height: u32) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let texture = TextureImage(ImageBuffer::from_pixel(tex_width, tex_height, WHITE));
+    let heightmap = DepthImage(ImageBuffer::from_pixel(
+        tex_width,
+        tex_height,
+        Rgb([0, 0, 0]),
+    ));
+    // A single straight-on view
+    let camera = Camera::for_view(0, 1, 60.0, zoom, 1.0, VIEW_WIDTH, VIEW_HEIGHT);
+    render_single_view(camera, &texture, &heightmap, &ViewOptions::default())
+}
+
+/// Screen rows the texture covers, less one row of rounding slack at each edge.
+fn covered_rows(zoom: f32) -> std::ops::Range<u32> {
+    let half = (VIEW_HEIGHT as f32 * zoom / 2.0).min(VIEW_HEIGHT as f32 / 2.0);
+    let center = VIEW_HEIGHT as f32 / 2.0;
+    ((center - half).ceil() as u32 + 1)..((center + half).floor() as u32).saturating_sub(1)
+}
+
+fn assert_rows_filled(view: &ImageBuffer<Rgb<u8>, Vec<u8>>, zoom: f32) {
+    let center_x = VIEW_WIDTH / 2;
+    for y in covered_rows(zoom) {
+        assert_eq!(
+            *view.get_pixel(center_x, y),
+            WHITE,
+            "zoom {zoom}: gap at row {y}"
+        );
+    }
+}
+
+#[test]
+fn zoom_sweep_has_no_row_gaps() {
+    for zoom in [0.25, 0.33, 0.5, 0.75, 0.9, 1.0, 1.05, 1.5, 2.0, 3.0, 4.0] {
+        // Texture larger than the view, as after the default resize, and smaller than it
+        for (tex_width, tex_height) in [(240, 320), (60, 80)] {
+            assert_rows_filled(&render_flat(zoom, tex_width, tex_height), zoom);
+        }
+    }
+}
+
+#[test]
+fn zoomed_out_rows_outside_texture_stay_background() {
+    for zoom in [0.25, 0.5, 0.75] {
+        let view = render_flat(zoom, 240, 320);
+        let covered = covered_rows(zoom);
+        let center_x = VIEW_WIDTH / 2;
+        for y in (0..covered.start.saturating_sub(2)).chain(covered.end + 2..VIEW_HEIGHT) {
+            assert_eq!(
+                *view.get_pixel(center_x, y),
+                Rgb([0, 0, 0]),
+                "zoom {zoom}: texture drawn outside its rows at row {y}"
+            );
+        }
+    }
+}
+
+#[test]
+fn zoom_sweep_has_no_column_gaps() {
+    for zoom in [0.25, 0.5, 1.0, 2.0, 4.0] {
+        let view = render_flat(zoom, 60, 80);
+        let half = (VIEW_WIDTH as f32 * zoom / 2.0).min(VIEW_WIDTH as f32 / 2.0);
+        let center = VIEW_WIDTH as f32 / 2.0;
+        let y = VIEW_HEIGHT / 2;
+        for x in ((center - half).ceil() as u32 + 1)..((center + half).floor() as u32 - 1) {
+            assert_eq!(
+                *view.get_pixel(x, y),
+                WHITE,
+                "zoom {zoom}: gap at column {x}"
+            );
+        }
+    }
+}
+
+#[test]
+fn zoom_sweep_samples_every_texture_row() {
+    let (tex_width, tex_height) = (240, 320);
+    for zoom in [0.25, 0.33, 0.5, 0.75, 1.0, 2.0, 4.0] {
+        // Rows off center, so zooming in doesn't push them out of the view
+        let center = tex_height / 2;
+        for line in (center - 20..center + 20).step_by(3) {
+            // A one texel high white line, raised so it wins the depth test wherever it lands
+            let texture = TextureImage(ImageBuffer::from_fn(tex_width, tex_height, |_, y| {
+                if y == line {
+                    WHITE
+                } else {
+                    Rgb([40, 40, 40])
+                }
+            }));
+            let heightmap = DepthImage(ImageBuffer::from_fn(tex_width, tex_height, |_, y| {
+                Rgb([if y == line { 10 } else { 0 }; 3])
+            }));
+            let camera = Camera::for_view(0, 1, 60.0, zoom, 1.0, VIEW_WIDTH, VIEW_HEIGHT);
+            let view = render_single_view(camera, &texture, &heightmap, &ViewOptions::default());
+            assert!(
+                (0..VIEW_HEIGHT).any(|y| *view.get_pixel(VIEW_WIDTH / 2, y) == WHITE),
+                "zoom {zoom}: texture row {line} was skipped"
+            );
+        }
+    }
+}
+
+#[test]
+fn zoom_center_moves_towards_the_middle_of_the_view() {
+    let (tex_width, tex_height) = (240, 320);
+    // A white band a quarter of the way down, raised so it wins the depth test
+    let band = tex_height / 4 - 2..tex_height / 4 + 2;
+    let texture = TextureImage(ImageBuffer::from_fn(tex_width, tex_height, |_, y| {
+        if band.contains(&y) {
+            WHITE
+        } else {
+            Rgb([40, 40, 40])
+        }
+    }));
+    let heightmap = DepthImage(ImageBuffer::from_pixel(
+        tex_width,
+        tex_height,
+        Rgb([0, 0, 0]),
+    ));
+    let camera = Camera {
+        zoom_center: [0.5, 0.25],
+        ..Camera::for_view(0, 1, 60.0, 2.0, 1.0, VIEW_WIDTH, VIEW_HEIGHT)
+    };
+    let view = render_single_view(camera, &texture, &heightmap, &ViewOptions::default());
+    assert_eq!(*view.get_pixel(VIEW_WIDTH / 2, VIEW_HEIGHT / 2), WHITE);
+}
+
+#[test]
+fn zoom_center_never_uncovers_the_view_edges() {
+    for zoom in [1.0, 1.05, 1.5, 3.0] {
+        for zoom_center in [[0.0, 0.0], [1.0, 1.0], [0.0, 1.0]] {
+            let texture = TextureImage(ImageBuffer::from_pixel(240, 320, WHITE));
+            let heightmap = DepthImage(ImageBuffer::from_pixel(240, 320, Rgb([0, 0, 0])));
+            let camera = Camera {
+                zoom_center,
+                ..Camera::for_view(0, 1, 60.0, zoom, 1.0, VIEW_WIDTH, VIEW_HEIGHT)
+            };
+            let view = render_single_view(camera, &texture, &heightmap, &ViewOptions::default());
+            for (x, y) in [(1, 1), (VIEW_WIDTH - 2, VIEW_HEIGHT - 2)] {
+                assert_eq!(
+                    *view.get_pixel(x, y),
+                    WHITE,
+                    "zoom {zoom}, center {zoom_center:?}: background at ({x}, {y})"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn zoom_in_sweep_draws_each_texture_row_only_where_it_lands() {
+    // A raised one texel high line wins the depth test on every screen row that samples it, so
+    // sampling rows beyond the ones a screen row covers smears it into a stripe
+    let (tex_width, tex_height) = (VIEW_WIDTH, VIEW_HEIGHT);
+    let line = tex_height / 2 + 7;
+    let texture = TextureImage(ImageBuffer::from_fn(tex_width, tex_height, |_, y| {
+        if y == line {
+            WHITE
+        } else {
+            Rgb([40, 40, 40])
+        }
+    }));
+    let heightmap = DepthImage(ImageBuffer::from_fn(tex_width, tex_height, |_, y| {
+        Rgb([if y == line { 10 } else { 0 }; 3])
+    }));
+    for zoom in [1.0, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0] {
+        let camera = Camera::for_view(0, 1, 60.0, zoom, 1.0, VIEW_WIDTH, VIEW_HEIGHT);
+        let view = render_single_view(camera, &texture, &heightmap, &ViewOptions::default());
+        let rows = (0..VIEW_HEIGHT)
+            .filter(|&y| *view.get_pixel(VIEW_WIDTH / 2, y) == WHITE)
+            .count();
+        // One texture row spans `zoom` screen rows, plus one for each partly covered edge
+        assert!(
+            (1..=zoom.ceil() as usize + 1).contains(&rows),
+            "zoom {zoom}: texture row {line} drawn on {rows} screen rows"
+        );
+    }
+}
+
+#[test]
+fn nan_zoom_keeps_the_image_centered() {
+    let mut camera = Camera::for_view(0, 1, 60.0, f32::NAN, 1.0, VIEW_WIDTH, VIEW_HEIGHT);
+    camera.zoom_center = [0.1, 0.9];
+    assert_eq!(camera.view_center(), [0.5, 0.5]);
+}